@@ -21,7 +21,7 @@ import {
   // eslint-disable-next-line node/no-unpublished-import
 } from '../../../typescript_utilities/src/processor';
 import {fromJwk as createSignerFromJwk, Signer} from './signer';
-import {storageRead, storageWrite} from './storage';
+import {storageDelete, storageRead, storageWrite} from './storage';
 import {
   PAYLOAD_SIZE_LIMIT,
   readIntoArray,
@@ -29,6 +29,7 @@ import {
   // eslint-disable-next-line node/no-unpublished-import
 } from '../../../typescript_utilities/src/streams';
 import {
+  SxgStatus,
   WasmResponse,
   WasmRequest,
   WasmWorker,
@@ -66,7 +67,9 @@ addEventListener('scheduled', (event: ScheduledEvent) => {
 });
 
 function responseFromWasm(data: WasmResponse): Response {
-  return new Response(new Uint8Array(data.body), {
+  // A 204 or 304 response must not have a body, not even an empty one.
+  const body = data.body.length > 0 ? new Uint8Array(data.body) : null;
+  return new Response(body, {
     status: data.status,
     headers: data.headers,
   });
@@ -125,12 +128,34 @@ function createRuntime() {
     fetcher,
     storageRead,
     storageWrite,
+    storageDelete,
     sxgRawSigner: sxgSigner,
     sxgAsn1Signer: undefined,
     acmeRawSigner,
   };
 }
 
+// Returns the SxgStatus attached to an error thrown while generating the SXG.
+function sxgStatusOf(e: unknown): SxgStatus {
+  if (e instanceof Error && 'sxgStatus' in e) {
+    return (e as Error & {sxgStatus: SxgStatus}).sxgStatus;
+  }
+  return 'error';
+}
+
+// Adds the `x-sxg` header to the response if enabled in the config.
+async function withSxgStatus(response: Response, status: SxgStatus) {
+  const worker = await workerPromise;
+  const header = await worker.sxgStatusHeader(status);
+  if (!header) {
+    return response;
+  }
+  // The headers of a fetched response are immutable, so it is copied.
+  response = new Response(response.body, response);
+  response.headers.set(...header);
+  return response;
+}
+
 async function updateStateMachine() {
   const worker = await workerPromise;
   const runtime = createRuntime();
@@ -144,9 +169,12 @@ async function handleRequest(request: Request) {
   let fallback: Response | undefined;
   let response: Response | undefined;
   try {
+    // Cloudflare compresses responses at its edge, so the worker always
+    // returns an uncompressed body.
     const presetContent = await worker.servePresetContent(
       createRuntime(),
-      request.url
+      request.url,
+      undefined
     );
     let fallbackUrl: string;
     let certOrigin: string;
@@ -166,17 +194,27 @@ async function handleRequest(request: Request) {
         // `Accept: signed-exchange;v=b3`, we will throw an error.
         worker.createRequestHeaders('AcceptsSxg', Array.from(request.headers));
       }
+    } else if ((await worker.route(request.url)) !== 'Content') {
+      // Such as a certificate chain whose OCSP response is unavailable, which
+      // must not be signed as content.
+      throw new Error(`Failed to serve ${request.url}`);
     } else {
       [fallbackUrl, certOrigin] = fallbackUrlAndCertOrigin(request.url, true);
       const requestHeaders = await worker.createRequestHeaders(
         'PrefersSxg',
         Array.from(request.headers)
       );
-      [sxgPayload, fallback] = teeResponse(
-        await fetch(fallbackUrl, {
-          headers: requestHeaders,
-        })
-      );
+      // The origin response is buffered, so that it can be signed. If it is
+      // too slow or too large, this throws, and the catch block below passes
+      // the response through unsigned.
+      const originResponse = await worker.fetchOrigin(createRuntime(), {
+        body: [],
+        headers: requestHeaders,
+        method: 'Get',
+        url: fallbackUrl,
+      });
+      sxgPayload = responseFromWasm(originResponse);
+      fallback = responseFromWasm(originResponse);
     }
     sxgPayload = await processHTML(sxgPayload, [
       new PromoteLinkTagsToHeaders(),
@@ -186,11 +224,12 @@ async function handleRequest(request: Request) {
     // eslint-disable-next-line @typescript-eslint/no-explicit-any
   } catch (e: any) {
     console.error(e);
+    const sxgStatus = sxgStatusOf(e);
     sxgPayload?.body?.cancel();
     if (!fallback) {
-      // The error occurs before fetching from origin server, hence we need to
-      // fetch now. Since we are not generating SXG anyway in this case, we
-      // simply use all http headers from the user.
+      // The error occurs before the origin response is buffered, hence we
+      // need to fetch now. Since we are not generating SXG anyway in this
+      // case, we simply use all http headers from the user.
       fallback = await fetch(request);
     }
     let fallwayback;
@@ -205,7 +244,7 @@ async function handleRequest(request: Request) {
       fallback.body?.cancel();
       fallback = fallwayback;
     }
-    return fallback;
+    return await withSxgStatus(fallback, sxgStatus);
   }
   fallback.body?.cancel();
   return response;
@@ -221,7 +260,10 @@ async function generateSxgResponse(
   worker.validatePayloadHeaders(payloadHeaders);
   const payloadBody = await readIntoArray(payload.body, PAYLOAD_SIZE_LIMIT);
   if (!payloadBody) {
-    throw `The size of payload exceeds the limit ${PAYLOAD_SIZE_LIMIT}`;
+    throw Object.assign(
+      new Error(`The size of payload exceeds the limit ${PAYLOAD_SIZE_LIMIT}`),
+      {sxgStatus: 'passthrough-too-large'}
+    );
   }
   const {get: headerIntegrityGet, put: headerIntegrityPut} =
     await headerIntegrityCache();
@@ -238,7 +280,10 @@ async function generateSxgResponse(
   return responseFromWasm(sxg);
 }
 
-async function fetcher(request: WasmRequest): Promise<WasmResponse> {
+async function fetcher(
+  request: WasmRequest,
+  maxBodyBytes?: number
+): Promise<WasmResponse | null> {
   const requestInit: RequestInit = {
     headers: request.headers,
     method: request.method,
@@ -250,8 +295,14 @@ async function fetcher(request: WasmRequest): Promise<WasmResponse> {
 
   let body;
   if (response.body) {
-    body = await readIntoArray(response.body, PAYLOAD_SIZE_LIMIT);
+    body = await readIntoArray(
+      response.body,
+      maxBodyBytes ?? PAYLOAD_SIZE_LIMIT
+    );
     if (!body) {
+      if (maxBodyBytes !== undefined) {
+        return null;
+      }
       throw `The size of payload exceeds the limit ${PAYLOAD_SIZE_LIMIT}`;
     }
   } else {
@@ -18,6 +18,21 @@ export async function storageRead(k: string): Promise<string | null> {
   return await OCSP.get(k);
 }
 
-export async function storageWrite(k: string, v: string): Promise<void> {
-  await OCSP.put(k, v);
+export async function storageWrite(
+  k: string,
+  v: string,
+  ttlSeconds?: number
+): Promise<void> {
+  if (ttlSeconds === undefined) {
+    await OCSP.put(k, v);
+  } else {
+    // Workers KV rejects expiration TTLs shorter than 60 seconds.
+    await OCSP.put(k, v, {
+      expirationTtl: Math.max(60, Math.ceil(ttlSeconds)),
+    });
+  }
+}
+
+export async function storageDelete(k: string): Promise<void> {
+  await OCSP.delete(k);
 }
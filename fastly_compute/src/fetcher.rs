@@ -14,11 +14,14 @@
 
 use anyhow::{Error, Result};
 use async_trait::async_trait;
+use fastly::http::request::PollResult;
 use fastly::{Request as FastlyRequest, Response as FastlyResponse};
 use std::convert::TryInto;
+use std::io::Read;
 use sxg_rs::{
-    fetcher::Fetcher,
+    fetcher::{too_large_error, with_timeout, FetchLimits, Fetcher},
     http::{HttpRequest, HttpResponse},
+    SxgError,
 };
 
 /// A [`Fetcher`] implemented by
@@ -39,16 +42,65 @@ impl FastlyFetcher {
 
 #[async_trait(?Send)]
 impl Fetcher for FastlyFetcher {
-    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
-        let request: ::http::request::Request<Vec<u8>> = request.try_into()?;
-        let request = request.map(fastly::Body::from);
-        let request: FastlyRequest = request.try_into()?;
-        let response: FastlyResponse = request
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, SxgError> {
+        let response: FastlyResponse = to_fastly_request(request)?
             .send(self.backend_name)
-            .map_err(|e| Error::new(e).context("Failed to fetch from backend."))?;
+            .map_err(|e| {
+                SxgError::Network(Error::new(e).context("Failed to fetch from backend."))
+            })?;
 
         let response: ::http::response::Response<fastly::Body> = response.into();
         let response = response.map(|body| body.into_bytes());
-        response.try_into()
+        response.try_into().map_err(SxgError::Network)
     }
+    async fn fetch_with_limits(
+        &self,
+        request: HttpRequest,
+        limits: FetchLimits,
+    ) -> Result<HttpResponse, SxgError> {
+        let mut pending = to_fastly_request(request)?
+            .send_async(self.backend_name)
+            .map_err(|e| {
+                SxgError::Network(Error::new(e).context("Failed to fetch from backend."))
+            })?;
+        // `send` would block the only thread, so the request is polled instead,
+        // which lets the timer of `with_timeout` fire.
+        let wait = async move {
+            loop {
+                match pending.poll() {
+                    PollResult::Done(response) => {
+                        return response.map_err(|e| {
+                            SxgError::Network(
+                                Error::new(e).context("Failed to fetch from backend."),
+                            )
+                        })
+                    }
+                    PollResult::Pending(still_pending) => pending = still_pending,
+                }
+                tokio::task::yield_now().await;
+            }
+        };
+        let mut response = with_timeout(wait, limits.timeout).await?;
+        // Stops reading one byte past the limit, which tells that the body
+        // exceeds it.
+        let mut body = vec![];
+        response
+            .take_body()
+            .take(limits.max_body_bytes as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| SxgError::Network(e.into()))?;
+        if body.len() > limits.max_body_bytes {
+            return Err(too_large_error(limits.max_body_bytes));
+        }
+        let response: ::http::response::Response<fastly::Body> = response.into();
+        let response = response.map(|_| body);
+        response.try_into().map_err(SxgError::Network)
+    }
+}
+
+fn to_fastly_request(request: HttpRequest) -> Result<FastlyRequest, SxgError> {
+    let request: ::http::request::Request<Vec<u8>> =
+        request.try_into().map_err(SxgError::Network)?;
+    let request = request.map(fastly::Body::from);
+    request.try_into().map_err(|e| SxgError::Network(e.into()))
 }
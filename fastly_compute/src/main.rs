@@ -14,7 +14,7 @@
 
 mod fetcher;
 
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use fastly::{
     http::{StatusCode, Url},
     mime::Mime,
@@ -24,10 +24,14 @@ use fetcher::FastlyFetcher;
 use once_cell::sync::Lazy;
 use std::convert::TryInto;
 use sxg_rs::{
+    config::Route,
     crypto::CertificateChain,
     headers::{AcceptFilter, Headers},
-    http::HeaderFields,
-    PresetContent,
+    http::{HeaderFields, HttpRequest, Method},
+    http_cache::StoreHttpCache,
+    runtime::store::InMemoryStore,
+    signature::rust_signer::RustSigner,
+    PresetContent, ServeMode, SxgStatus,
 };
 
 pub static WORKER: Lazy<::sxg_rs::SxgWorker> = Lazy::new(|| {
@@ -41,6 +45,10 @@ pub static WORKER: Lazy<::sxg_rs::SxgWorker> = Lazy::new(|| {
     worker
 });
 
+// Parsing the private key is relatively slow, so the signer is created once
+// and cloned for each request.
+static SXG_SIGNER: Lazy<RustSigner> = Lazy::new(|| WORKER.create_rust_signer().unwrap());
+
 fn binary_response(status_code: StatusCode, content_type: Mime, body: &[u8]) -> Response {
     let mut response = Response::new();
     response.set_status(status_code);
@@ -53,6 +61,14 @@ fn text_response(body: &str) -> Response {
     binary_response(StatusCode::OK, fastly::mime::TEXT_PLAIN, body.as_bytes())
 }
 
+// Adds the `x-sxg` header if enabled in the config.
+fn with_sxg_status(mut response: Response, status: SxgStatus) -> Response {
+    if let Some((name, value)) = WORKER.sxg_status_header(status) {
+        response.set_header(name, value);
+    }
+    response
+}
+
 fn get_req_header_fields(req: &Request, accept_filter: AcceptFilter) -> Result<HeaderFields> {
     let mut fields: Vec<(String, String)> = vec![];
     for name in req.get_header_names() {
@@ -63,7 +79,7 @@ fn get_req_header_fields(req: &Request, accept_filter: AcceptFilter) -> Result<H
             fields.push((name.as_str().to_string(), value.to_string()))
         }
     }
-    WORKER.transform_request_headers(fields, accept_filter)
+    Ok(WORKER.transform_request_headers(fields, accept_filter)?)
 }
 
 fn get_rsp_header_fields(rsp: &Response) -> Result<Headers> {
@@ -76,7 +92,7 @@ fn get_rsp_header_fields(rsp: &Response) -> Result<Headers> {
             fields.push((name.as_str().to_string(), value.to_string()))
         }
     }
-    WORKER.transform_payload_headers(fields)
+    Ok(WORKER.transform_payload_headers(fields)?)
 }
 
 pub fn sxg_rs_response_to_fastly_response(
@@ -87,22 +103,54 @@ pub fn sxg_rs_response_to_fastly_response(
     Ok(rsp.into())
 }
 
-fn fetch_from_html_server(url: &Url, req_headers: Vec<(String, String)>) -> Result<Response> {
-    let mut req = Request::new("GET", url);
-    for (name, value) in req_headers {
-        req.append_header(name, value);
-    }
-    req.send("Origin HTML server")
-        .map_err(|err| Error::msg(format!(r#"Fetching "{}" leads to error "{}""#, url, err)))
+// Fetches the response to sign, giving up if the origin is too slow or the
+// response is too large to sign.
+async fn fetch_from_html_server(url: &Url, req_headers: HeaderFields) -> Result<Response> {
+    let runtime = sxg_rs::runtime::Runtime {
+        now: std::time::SystemTime::now(),
+        fetcher: Box::new(FastlyFetcher::new("Origin HTML server")),
+        ..Default::default()
+    };
+    let request = HttpRequest {
+        body: vec![],
+        headers: req_headers,
+        method: Method::Get,
+        url: url.to_string(),
+    };
+    let response = WORKER.fetch_origin(&runtime, request).await?;
+    sxg_rs_response_to_fastly_response(response)
 }
 
-async fn generate_sxg_response(fallback_url: &Url, payload: Response) -> Result<Response> {
+// Proxies the unsigned response.
+fn fetch_unsigned(req: Request, fallback_url: &Url) -> Result<Response> {
+    req.with_url(fallback_url)
+        .send("Origin HTML server")
+        .map_err(|err| {
+            Error::msg(format!(
+                r#"Fetching "{}" leads to error "{}""#,
+                fallback_url, err
+            ))
+        })
+}
+
+async fn generate_sxg_response(
+    fallback_url: &Url,
+    payload: Response,
+    amp_cache_transform: Option<String>,
+) -> Result<Response> {
+    let status_code = payload.get_status().as_u16();
+    if !WORKER.is_signable_status(status_code) {
+        return Ok(with_sxg_status(payload, SxgStatus::PassthroughUncacheable));
+    }
     let payload_headers = get_rsp_header_fields(&payload)?;
+    let unsigned_payload = payload.clone_without_body();
     let payload_body = payload.into_body_bytes();
     let cert_origin = fallback_url.origin().ascii_serialization();
+    let now = std::time::SystemTime::now();
     let runtime = sxg_rs::runtime::Runtime {
-        now: std::time::SystemTime::now(),
-        sxg_signer: Box::new(WORKER.create_rust_signer()?),
+        now,
+        storage: Box::new(InMemoryStore::new(now)),
+        sxg_signer: Box::new(SXG_SIGNER.clone()),
         fetcher: Box::new(FastlyFetcher::new("subresources")),
         ..Default::default()
     };
@@ -112,30 +160,51 @@ async fn generate_sxg_response(fallback_url: &Url, payload: Response) -> Result<
             payload_body: &payload_body,
             payload_headers,
             skip_process_link: false,
-            status_code: 200,
+            status_code,
             fallback_url: fallback_url.as_str(),
             cert_origin: &cert_origin,
             // The fastly crate provides only read access to dictionaries, so
-            // header integrities cannot be cached. However, I believe the
+            // header integrities are cached in `runtime.storage`, which only
+            // lasts as long as this request. However, I believe the
             // subresource_fetcher will go through the cache.
-            header_integrity_cache: sxg_rs::http_cache::NullCache {},
+            header_integrity_cache: StoreHttpCache::new(runtime.storage.as_ref()),
         },
     );
-    let sxg = sxg.await?;
-    sxg_rs_response_to_fastly_response(sxg)
+    let mut sxg = match sxg.await {
+        Ok(sxg) => sxg.into_response(),
+        Err(e) => {
+            // Serves the unsigned response, because the browser can still use it.
+            eprintln!("Failed to sign {}: {:#}", fallback_url, e);
+            return Ok(with_sxg_status(
+                unsigned_payload.with_body(payload_body),
+                e.status(),
+            ));
+        }
+    };
+    if let Some(amp_cache_transform) = amp_cache_transform {
+        sxg_rs::amp::set_response_header(&mut sxg, amp_cache_transform);
+    }
+    Ok(with_sxg_status(
+        sxg_rs_response_to_fastly_response(sxg)?,
+        SxgStatus::Signed,
+    ))
 }
 
 async fn handle_request(req: Request) -> Result<Response> {
     let runtime = sxg_rs::runtime::Runtime {
         now: std::time::SystemTime::now(),
-        sxg_signer: Box::new(WORKER.create_rust_signer()?),
+        sxg_signer: Box::new(SXG_SIGNER.clone()),
         fetcher: Box::new(FastlyFetcher::new("OCSP server")),
         ..Default::default()
     };
     let fallback_url: Url;
     let sxg_payload;
     let preset_content = WORKER
-        .serve_preset_content(&runtime, req.get_url_str())
+        .serve_preset_content(
+            &runtime,
+            req.get_url_str(),
+            req.get_header_str("accept-encoding"),
+        )
         .await;
     match preset_content {
         Some(PresetContent::Direct(response)) => {
@@ -146,13 +215,33 @@ async fn handle_request(req: Request) -> Result<Response> {
             sxg_payload = sxg_rs_response_to_fastly_response(payload)?;
             get_req_header_fields(&req, AcceptFilter::AcceptsSxg)?;
         }
+        None if WORKER.config().route(req.get_url_str()) != Route::Content => {
+            // Such as a certificate chain whose OCSP response is unavailable,
+            // which must not be signed as content.
+            return Err(anyhow!("Failed to serve {}", req.get_url_str()));
+        }
         None => {
             fallback_url = WORKER.get_fallback_url(req.get_url())?;
+            let accept = req.get_header_str("accept");
+            if sxg_rs::negotiate(accept, AcceptFilter::PrefersSxg) == ServeMode::Plain {
+                // Proxies the unsigned response without fetching it for signing.
+                return fetch_unsigned(req, &fallback_url);
+            }
             let req_headers = get_req_header_fields(&req, AcceptFilter::PrefersSxg)?;
-            sxg_payload = fetch_from_html_server(&fallback_url, req_headers)?;
+            sxg_payload = match fetch_from_html_server(&fallback_url, req_headers).await {
+                Ok(sxg_payload) => sxg_payload,
+                Err(e) => {
+                    // The response is still served, just unsigned.
+                    eprintln!("Failed to fetch {} for signing: {:#}", fallback_url, e);
+                    let status = SxgStatus::of_error(&e);
+                    return Ok(with_sxg_status(fetch_unsigned(req, &fallback_url)?, status));
+                }
+            };
         }
     };
-    generate_sxg_response(&fallback_url, sxg_payload).await
+    let amp_cache_transform =
+        WORKER.amp_cache_transform(req.get_header_str(sxg_rs::amp::AMP_CACHE_TRANSFORM));
+    generate_sxg_response(&fallback_url, sxg_payload, amp_cache_transform).await
 }
 
 #[fastly::main]
@@ -162,7 +251,10 @@ fn main(req: Request) -> Result<Response, std::convert::Infallible> {
         .unwrap()
         .block_on(async {
             let response = handle_request(req).await.unwrap_or_else(|msg| {
-                text_response(&format!("A message is gracefully thrown.\n{:?}", msg))
+                with_sxg_status(
+                    text_response(&format!("A message is gracefully thrown.\n{:?}", msg)),
+                    SxgStatus::of_error(&msg),
+                )
             });
             Ok(response)
         })
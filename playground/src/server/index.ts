@@ -84,6 +84,9 @@ export async function spawnSxgServer({
       async write(k: string, v: string) {
         m.set(k, v);
       },
+      async delete(k: string) {
+        m.delete(k);
+      },
     };
   })();
   function createRuntime() {
@@ -91,6 +94,7 @@ export async function spawnSxgServer({
       nowInSeconds: Date.now() / 1000,
       storageRead: storage.read,
       storageWrite: storage.write,
+      storageDelete: storage.delete,
       sxgAsn1Signer: undefined,
       sxgRawSigner: signer,
       acmeRawSigner: undefined, // Playground uses self-signed certificate, and does not use ACME.
@@ -186,7 +190,8 @@ export async function spawnSxgServer({
   fastify.get('/.well-known/sxg-certs/*', async (request, reply) => {
     const x = await worker.servePresetContent(
       createRuntime(),
-      `https://localhost:8443${request.url}`
+      `https://localhost:8443${request.url}`,
+      request.headers['accept-encoding']
     );
     assert(x?.kind === 'direct');
     x.headers.forEach(([k, v]) => reply.header(k, v));
@@ -0,0 +1,88 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Measures the cost of computing the signed headers and MICE-encoded payload
+//! of a response, which happens once per signed exchange.
+//! Run with `cargo bench -p sxg_rs --bench signed_headers`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::executor::block_on;
+use std::collections::BTreeSet;
+use sxg_rs::config::LinkHint;
+use sxg_rs::fetcher::NULL_FETCHER;
+use sxg_rs::header_integrity::new_fetcher;
+use sxg_rs::headers::Headers;
+use sxg_rs::http_cache::NullCache;
+use sxg_rs::utils::signed_headers_and_payload;
+use url::Url;
+
+fn response_headers() -> Headers {
+    let fields = vec![
+        ("content-type", "text/html; charset=utf-8"),
+        ("cache-control", "public, max-age=600"),
+        ("content-security-policy", "default-src 'self'"),
+        ("date", "Mon, 01 Aug 2022 00:00:00 GMT"),
+        ("etag", "\"0123456789abcdef\""),
+        ("last-modified", "Mon, 01 Aug 2022 00:00:00 GMT"),
+        (
+            "link",
+            "<https://example.org/style.css>;rel=preload;as=style,\
+             <https://example.org/script.js>;rel=preload;as=script",
+        ),
+        ("set-cookie", "id=abcdef"),
+        ("vary", "accept-encoding"),
+    ];
+    Headers::new(
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        &BTreeSet::new(),
+    )
+}
+
+fn bench_signed_headers(c: &mut Criterion) {
+    let fallback_url = Url::parse("https://example.org/index.html").unwrap();
+    let headers = response_headers();
+    let link_hints = vec![LinkHint {
+        url: "/font.woff2".to_string(),
+        rel: "preload".to_string(),
+        r#as: "font".to_string(),
+    }];
+    let strip_response_headers = BTreeSet::new();
+    let mut group = c.benchmark_group("signed headers and payload");
+    for body_size in [1024, 64 * 1024, 1024 * 1024] {
+        let body = vec![b'a'; body_size];
+        group.bench_with_input(BenchmarkId::from_parameter(body_size), &body, |b, body| {
+            b.iter(|| {
+                let mut fetcher = new_fetcher(&NULL_FETCHER, NullCache, &strip_response_headers);
+                block_on(signed_headers_and_payload(
+                    &fallback_url,
+                    200,
+                    &headers,
+                    body,
+                    &mut fetcher,
+                    false,
+                    &link_hints,
+                    &BTreeSet::new(),
+                ))
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_signed_headers);
+criterion_main!(benches);
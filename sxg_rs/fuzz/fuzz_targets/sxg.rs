@@ -0,0 +1,26 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(parts) = sxg_rs::sxg::parse(data) {
+        // Also parses the CBOR of the signed headers.
+        let _ = sxg_rs::sxg::read_response_map(parts.signed_headers);
+        if let Ok(signature) = std::str::from_utf8(parts.signature) {
+            let _ = sxg_rs::signature::SignatureHeader::parse(signature);
+        }
+    }
+});
@@ -15,12 +15,26 @@
 //! An ACME client handles authentication with the ACME server.
 
 use super::directory::Directory;
-use crate::crypto::EcPublicKey;
+use super::jws::Algorithm;
+use crate::crypto::JsonWebKey;
 use crate::fetcher::Fetcher;
 use crate::http::{HttpRequest, HttpResponse, Method};
+use crate::runtime::metrics::now_millis;
 use crate::signature::Signer;
+use crate::utils::{console_log, sleep};
+use crate::SxgError;
 use anyhow::{anyhow, Error, Result};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The number of times a request is retried after a `badNonce` error.
+const MAX_BAD_NONCE_RETRIES: u32 = 3;
+/// The number of times a request is retried after a `rateLimited` error.
+const MAX_RATE_LIMITED_RETRIES: u32 = 3;
+/// The longest time to wait before retrying a `rateLimited` request. If the
+/// server asks for a longer wait, the error is returned instead, and the
+/// state machine tries again on its next update.
+const MAX_RATE_LIMITED_BACKOFF: Duration = Duration::from_secs(10);
 
 pub struct Client<'a> {
     pub directory: &'a Directory,
@@ -29,8 +43,9 @@ pub struct Client<'a> {
 }
 
 pub enum AuthMethod {
-    JsonWebKey(EcPublicKey),
-    KeyId(String),
+    JsonWebKey(JsonWebKey),
+    /// The account URL, and the algorithm of the account key.
+    KeyId(String, Algorithm),
 }
 
 impl<'a> Client<'a> {
@@ -68,25 +83,77 @@ impl<'a> Client<'a> {
         self.post_impl(url, Some(payload), fetcher, acme_signer)
             .await
     }
-    /// Encapsulates the payload in JWS for authentication, connects to the ACME
-    /// server, saves `nonce` for next request, and returns the server response.
+    /// Sends the request by `post_once`, retrying it with a fresh nonce on
+    /// [`badNonce`](https://datatracker.ietf.org/doc/html/rfc8555#section-6.5)
+    /// errors, and after an exponential backoff on `rateLimited` errors.
     async fn post_impl<P: Serialize>(
         &mut self,
         url: String,
         payload: Option<P>,
         fetcher: &dyn Fetcher,
         acme_signer: &dyn Signer,
+    ) -> Result<HttpResponse> {
+        let mut bad_nonce_retries = 0;
+        let mut rate_limited_retries = 0;
+        loop {
+            let response = self
+                .post_once(&url, payload.as_ref(), fetcher, acme_signer)
+                .await?;
+            match problem_type(&response).as_deref() {
+                Some(BAD_NONCE) if bad_nonce_retries < MAX_BAD_NONCE_RETRIES => {
+                    bad_nonce_retries += 1;
+                    // The error response carries a fresh nonce, which
+                    // `post_once` has saved for the retry.
+                    console_log(&format!(
+                        "ACME server rejected the nonce for {}; retrying ({}/{}).",
+                        url, bad_nonce_retries, MAX_BAD_NONCE_RETRIES
+                    ));
+                }
+                Some(RATE_LIMITED) if rate_limited_retries < MAX_RATE_LIMITED_RETRIES => {
+                    let backoff = retry_after(&response)
+                        .unwrap_or_else(|| Duration::from_secs(1 << rate_limited_retries));
+                    if backoff > MAX_RATE_LIMITED_BACKOFF {
+                        return Ok(response);
+                    }
+                    rate_limited_retries += 1;
+                    console_log(&format!(
+                        "ACME server rate-limited {}; retrying in {:?} ({}/{}).",
+                        url, backoff, rate_limited_retries, MAX_RATE_LIMITED_RETRIES
+                    ));
+                    sleep(backoff).await;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+    /// Encapsulates the payload in JWS for authentication, connects to the ACME
+    /// server, saves `nonce` for next request, and returns the server response.
+    async fn post_once<P: Serialize>(
+        &mut self,
+        url: &str,
+        payload: Option<&P>,
+        fetcher: &dyn Fetcher,
+        acme_signer: &dyn Signer,
     ) -> Result<HttpResponse> {
         let nonce = self.take_nonce(fetcher).await?;
-        let (jwk, key_id) = match &self.auth_method {
-            AuthMethod::JsonWebKey(public_key) => (Some(public_key), None),
-            AuthMethod::KeyId(key_id) => (None, Some(key_id.as_str())),
+        let (alg, jwk, key_id) = match &self.auth_method {
+            AuthMethod::JsonWebKey(public_key) => {
+                (Algorithm::of_key(public_key), Some(public_key), None)
+            }
+            AuthMethod::KeyId(key_id, alg) => (*alg, None, Some(key_id.as_str())),
         };
-        let request_body =
-            super::jws::create_acme_request_body(jwk, key_id, nonce, &url, payload, acme_signer)
-                .await?;
+        let request_body = super::jws::create_acme_request_body(
+            alg,
+            jwk,
+            key_id,
+            nonce,
+            url,
+            payload,
+            acme_signer,
+        )
+        .await?;
         let request = HttpRequest {
-            url: url.clone(),
+            url: url.to_string(),
             method: Method::Post,
             headers: vec![(
                 "content-type".to_string(),
@@ -117,11 +184,41 @@ impl<'a> Client<'a> {
             body: vec![],
         };
         let response = fetcher.fetch(request).await?;
-        find_header(&response, "Replay-Nonce")
+        Ok(find_header(&response, "Replay-Nonce")?)
     }
 }
 
-pub fn find_header(response: &HttpResponse, header_name: &str) -> Result<String> {
+const BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+const RATE_LIMITED: &str = "urn:ietf:params:acme:error:rateLimited";
+
+/// Returns the `type` of the problem document in an error response.
+fn problem_type(response: &HttpResponse) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Problem {
+        r#type: String,
+    }
+    if response.status < 400 {
+        return None;
+    }
+    serde_json::from_slice::<Problem>(&response.body)
+        .ok()
+        .map(|problem| problem.r#type)
+}
+
+/// Parses the `Retry-After` header, which is either a number of seconds or
+/// an HTTP date.
+fn retry_after(response: &HttpResponse) -> Option<Duration> {
+    let value = find_header(response, "Retry-After").ok()?;
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let millis = date.timestamp_millis() as f64 - now_millis();
+    Some(Duration::from_millis(millis.max(0.0) as u64))
+}
+
+pub fn find_header(response: &HttpResponse, header_name: &str) -> Result<String, SxgError> {
     response
         .headers
         .iter()
@@ -132,22 +229,165 @@ pub fn find_header(response: &HttpResponse, header_name: &str) -> Result<String>
                 None
             }
         })
-        .ok_or_else(|| anyhow!("The response header does not contain {}", header_name))
+        .ok_or_else(|| {
+            SxgError::Acme(anyhow!(
+                "The response header does not contain {}",
+                header_name
+            ))
+        })
 }
 
 /// Parses response body as JSON of type `T`.
-pub fn parse_response_body<'a, T: Deserialize<'a>>(response: &'a HttpResponse) -> Result<T> {
+pub fn parse_response_body<'a, T: Deserialize<'a>>(
+    response: &'a HttpResponse,
+) -> Result<T, SxgError> {
     serde_json::from_slice(&response.body).map_err(|e| {
         let msg = if let Ok(s) = String::from_utf8(response.body.clone()) {
             format!("Body contains text: {}", s)
         } else {
             format!("Body contains bytes: {:?}", response.body)
         };
-        Error::new(e)
-            .context(format!(
-                "Failed to parse response body into type {}",
-                std::any::type_name::<T>()
-            ))
-            .context(msg)
+        SxgError::Acme(
+            Error::new(e)
+                .context(format!(
+                    "Failed to parse response body into type {}",
+                    std::any::type_name::<T>()
+                ))
+                .context(msg),
+        )
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{example_order_request, example_ready_order_response};
+    use super::*;
+    use crate::fetcher::mock_fetcher;
+    use crate::signature::mock_signer::MockSigner;
+
+    fn example_directory() -> Directory {
+        serde_json::from_value(serde_json::json!({
+            "newAccount": "https://acme.server/new-acct",
+            "newNonce": "https://acme.server/new-nonce",
+            "newOrder": "https://acme.server/new-order",
+            "revokeCert": "https://acme.server/revoke-cert",
+            "meta": {
+                "termsOfService": "https://acme.server/terms_of_service.pdf"
+            }
+        }))
+        .unwrap()
+    }
+    fn problem_response(r#type: &str, status: u16, headers: &[(&str, &str)]) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: serde_json::to_vec(&serde_json::json!({ "type": r#type })).unwrap(),
+        }
+    }
+    async fn post_order(client: &mut Client<'_>, fetcher: &dyn Fetcher) -> HttpResponse {
+        client
+            .post_as_get(
+                "https://acme.server/order/46540038".to_string(),
+                fetcher,
+                &MockSigner,
+            )
+            .await
+            .unwrap()
+    }
+    #[tokio::test]
+    async fn retries_bad_nonce_with_fresh_nonce() {
+        let directory = example_directory();
+        let (fetcher, mut server) = mock_fetcher::create();
+        let server_thread = async {
+            server
+                .handle_next_request(
+                    example_order_request("1").await,
+                    problem_response(BAD_NONCE, 400, &[("Replay-Nonce", "2")]),
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    example_order_request("2").await,
+                    example_ready_order_response("3"),
+                )
+                .await
+                .unwrap();
+        };
+        let client_thread = async {
+            let auth_method = AuthMethod::KeyId(
+                "https://acme.server/acct/123456".to_string(),
+                Algorithm::ES256,
+            );
+            let mut client = Client::new(&directory, auth_method, Some("1".to_string()));
+            let response = post_order(&mut client, &fetcher).await;
+            assert_eq!(response.status, 200);
+            assert_eq!(client.nonce.as_deref(), Some("3"));
+        };
+        tokio::join!(client_thread, server_thread);
+    }
+    #[tokio::test]
+    async fn retries_rate_limited_after_retry_after() {
+        let directory = example_directory();
+        let (fetcher, mut server) = mock_fetcher::create();
+        let server_thread = async {
+            server
+                .handle_next_request(
+                    example_order_request("1").await,
+                    problem_response(
+                        RATE_LIMITED,
+                        429,
+                        &[("Replay-Nonce", "2"), ("Retry-After", "0")],
+                    ),
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    example_order_request("2").await,
+                    example_ready_order_response("3"),
+                )
+                .await
+                .unwrap();
+        };
+        let client_thread = async {
+            let auth_method = AuthMethod::KeyId(
+                "https://acme.server/acct/123456".to_string(),
+                Algorithm::ES256,
+            );
+            let mut client = Client::new(&directory, auth_method, Some("1".to_string()));
+            assert_eq!(post_order(&mut client, &fetcher).await.status, 200);
+        };
+        tokio::join!(client_thread, server_thread);
+    }
+    #[tokio::test]
+    async fn gives_up_when_retry_after_exceeds_cap() {
+        let directory = example_directory();
+        let (fetcher, mut server) = mock_fetcher::create();
+        let server_thread = async {
+            server
+                .handle_next_request(
+                    example_order_request("1").await,
+                    problem_response(
+                        RATE_LIMITED,
+                        429,
+                        &[("Replay-Nonce", "2"), ("Retry-After", "3600")],
+                    ),
+                )
+                .await
+                .unwrap();
+        };
+        let client_thread = async {
+            let auth_method = AuthMethod::KeyId(
+                "https://acme.server/acct/123456".to_string(),
+                Algorithm::ES256,
+            );
+            let mut client = Client::new(&directory, auth_method, Some("1".to_string()));
+            assert_eq!(post_order(&mut client, &fetcher).await.status, 429);
+        };
+        tokio::join!(client_thread, server_thread);
+    }
+}
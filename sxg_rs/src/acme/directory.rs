@@ -16,7 +16,8 @@
 
 use crate::fetcher::Fetcher;
 use crate::http::HttpRequest;
-use anyhow::{Error, Result};
+use crate::SxgError;
+use anyhow::{Context, Error};
 use chrono::offset::FixedOffset;
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
@@ -41,17 +42,24 @@ pub struct Directory {
 impl Directory {
     /// Constructs an ACME directory by fetching the given ACME directory URL.
     /// The second item in return value is the `replay-nonce` header from server.
-    pub async fn from_url(url: &str, fetcher: &dyn Fetcher) -> Result<(Self, Option<String>)> {
+    pub async fn from_url(
+        url: &str,
+        fetcher: &dyn Fetcher,
+    ) -> Result<(Self, Option<String>), SxgError> {
         let request = HttpRequest {
             body: vec![],
             headers: vec![],
             method: crate::http::Method::Get,
             url: url.to_string(),
         };
-        let response = fetcher.fetch(request).await?;
+        let response = fetcher
+            .fetch(request)
+            .await
+            .context("Failed to fetch ACME directory")
+            .map_err(SxgError::Acme)?;
         let nonce = super::client::find_header(&response, "replay-nonce").ok();
         let directory = serde_json::from_slice(&response.body)
-            .map_err(|e| Error::new(e).context("Failed to parse ACME directory"))?;
+            .map_err(|e| SxgError::Acme(Error::new(e).context("Failed to parse ACME directory")))?;
         Ok((directory, nonce))
     }
 }
@@ -78,17 +86,43 @@ pub struct NewAccountRequestPayload {
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NewAccountResponsePayload {
-    pub status: Status,
+    pub status: AccountStatus,
+    /// The URL of the list of orders submitted by this account.
+    pub orders: Option<String>,
+}
+
+// https://datatracker.ietf.org/doc/html/rfc8555#section-7.3.6
+#[derive(Debug, Serialize)]
+pub struct DeactivateAccountRequest {
+    pub status: AccountStatus,
+}
+
+// https://datatracker.ietf.org/doc/html/rfc8555#section-7.1.6
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountStatus {
+    Valid,
+    Deactivated,
+    Revoked,
+}
+
+// https://datatracker.ietf.org/doc/html/rfc8555#section-7.1.2.1
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderList {
+    pub orders: Vec<String>,
 }
 
 // https://datatracker.ietf.org/doc/html/rfc8555#section-9.7.7
-#[derive(Debug, Serialize, Deserialize)]
+// https://datatracker.ietf.org/doc/html/rfc8738#section-3
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum IdentifierType {
     Dns,
+    Ip,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Identifier {
     pub r#type: IdentifierType,
@@ -100,7 +134,9 @@ pub struct Identifier {
 #[serde(rename_all = "camelCase")]
 pub struct NewOrderRequestPayload {
     pub identifiers: Vec<Identifier>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub not_before: Option<DateTime<FixedOffset>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub not_after: Option<DateTime<FixedOffset>>,
 }
 
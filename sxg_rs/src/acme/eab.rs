@@ -16,9 +16,9 @@
 //! [RFC-8555](https://datatracker.ietf.org/doc/html/rfc8555#section-7.3.4).
 
 use super::jws::{Algorithm, JsonWebSignature};
-use crate::crypto::EcPublicKey;
+use crate::crypto::JsonWebKey;
 use crate::signature::Signer;
-use anyhow::Result;
+use crate::SxgError;
 use serde::Serialize;
 
 /// The protected header which is used for External Account Binding.
@@ -36,9 +36,9 @@ pub async fn create_external_account_binding(
     alg: Algorithm,
     kid: &str,
     url: &str,
-    public_key: &EcPublicKey,
+    public_key: &JsonWebKey,
     hmac_signer: &dyn Signer,
-) -> Result<JsonWebSignature> {
+) -> Result<JsonWebSignature, SxgError> {
     let protected_header = EabProtectedHeader { alg, kid, url };
     JsonWebSignature::new(
         protected_header,
@@ -46,4 +46,5 @@ pub async fn create_external_account_binding(
         hmac_signer,
     )
     .await
+    .map_err(SxgError::Acme)
 }
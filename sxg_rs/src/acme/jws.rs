@@ -17,13 +17,14 @@
 //! [ACME spec](https://datatracker.ietf.org/doc/html/rfc8555#:~:text=payload%20in%20a%20JSON%20Web%20Signature)
 //! requires the request body to be encapsulated in JWS format for authentication.
 
-use crate::crypto::EcPublicKey;
+use crate::crypto::JsonWebKey;
 use crate::signature::{Format as SignatureFormat, Signer};
-use anyhow::{Error, Result};
-use serde::{Serialize, Serializer};
+use anyhow::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
 
-pub async fn create_acme_request_body<P: Serialize>(
-    jwk: Option<&EcPublicKey>,
+pub(crate) async fn create_acme_request_body<P: Serialize>(
+    alg: Algorithm,
+    jwk: Option<&JsonWebKey>,
     kid: Option<&str>,
     nonce: String,
     url: &str,
@@ -31,7 +32,7 @@ pub async fn create_acme_request_body<P: Serialize>(
     signer: &dyn Signer,
 ) -> Result<Vec<u8>> {
     let protected_header = AcmeProtectedHeader {
-        alg: Algorithm::ES256,
+        alg,
         nonce,
         url,
         jwk,
@@ -43,22 +44,24 @@ pub async fn create_acme_request_body<P: Serialize>(
 
 /// Cryptographic signing algorithms allowed in JWS, as defined in
 /// [RFC-7518](https://datatracker.ietf.org/doc/html/rfc7518#section-3.1).
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Algorithm {
     /// ECDSA using P-256 and SHA-256
+    #[default]
     ES256,
     /// HMAC using SHA-256
     HS256,
+    /// EdDSA using Ed25519, as defined in
+    /// [RFC-8037](https://datatracker.ietf.org/doc/html/rfc8037#section-3.1)
+    EdDSA,
 }
 
-impl Serialize for Algorithm {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            Algorithm::ES256 => serializer.serialize_str("ES256"),
-            Algorithm::HS256 => serializer.serialize_str("HS256"),
+impl Algorithm {
+    /// Returns the algorithm to sign with the private key of `jwk`.
+    pub fn of_key(jwk: &JsonWebKey) -> Self {
+        match jwk {
+            JsonWebKey::Ec(_) => Algorithm::ES256,
+            JsonWebKey::Okp(_) => Algorithm::EdDSA,
         }
     }
 }
@@ -70,7 +73,7 @@ struct AcmeProtectedHeader<'a> {
     alg: Algorithm,
     nonce: String,
     url: &'a str,
-    jwk: Option<&'a EcPublicKey>,
+    jwk: Option<&'a JsonWebKey>,
     kid: Option<&'a str>,
 }
 
@@ -86,7 +89,7 @@ impl JsonWebSignature {
     /// Constructs a signature from serialiable header and payload.
     /// If the given `payload` is `None`, it will be serialized into an empty
     /// string.
-    pub async fn new<H: Serialize, P: Serialize>(
+    pub(crate) async fn new<H: Serialize, P: Serialize>(
         protected_header: H,
         payload: Option<P>,
         signer: &dyn Signer,
@@ -102,7 +105,7 @@ impl JsonWebSignature {
         Self::new_from_serialized(&protected_header, &payload, signer).await
     }
     /// Constructs a signature from strings of serialized header and payload.
-    pub async fn new_from_serialized(
+    pub(crate) async fn new_from_serialized(
         protected_header: &str,
         payload: &str,
         signer: &dyn Signer,
@@ -114,7 +117,7 @@ impl JsonWebSignature {
         let signature = signer
             .sign(message.as_bytes(), SignatureFormat::Raw)
             .await
-            .map_err(|e| e.context("Failed to sign message"))?;
+            .context("Failed to sign message")?;
         let signature = base64::encode_config(&signature, base64::URL_SAFE_NO_PAD);
         Ok(JsonWebSignature {
             protected: protected_header,
@@ -126,6 +129,35 @@ impl JsonWebSignature {
 
 #[cfg(test)]
 mod tests {
+    #[tokio::test]
+    async fn eddsa_protected_header() {
+        use super::*;
+        use crate::crypto::OkpPublicKey;
+        let jwk = JsonWebKey::Okp(OkpPublicKey {
+            crv: "Ed25519".to_string(),
+            kty: "OKP".to_string(),
+            x: vec![1, 2, 3],
+        });
+        let body = create_acme_request_body::<()>(
+            Algorithm::of_key(&jwk),
+            Some(&jwk),
+            None,
+            "N".to_string(),
+            "https://acme.server/new-acct",
+            None,
+            &crate::signature::mock_signer::MockSigner,
+        )
+        .await
+        .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let protected_header =
+            base64::decode_config(body["protected"].as_str().unwrap(), base64::URL_SAFE_NO_PAD)
+                .unwrap();
+        assert_eq!(
+            String::from_utf8(protected_header).unwrap(),
+            r#"{"alg":"EdDSA","nonce":"N","url":"https://acme.server/new-acct","jwk":{"crv":"Ed25519","kty":"OKP","x":"AQID"},"kid":null}"#
+        );
+    }
     #[tokio::test]
     #[cfg(feature = "rust_signer")]
     async fn json_web_signature() {
@@ -0,0 +1,575 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-process ACME server, to test the whole flow of requesting a
+//! certificate without network. It is used as the [`Fetcher`] of the ACME
+//! client, and implements the endpoints used by [`super::create_account`] and
+//! [`super::state_machine`]. Every finalized order is issued the same given
+//! certificate. The JWS signatures are not verified.
+
+use super::directory::Status;
+use crate::crypto::JsonWebKey;
+use crate::fetcher::Fetcher;
+use crate::http::{HttpRequest, HttpResponse, Method};
+use crate::SxgError;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+const BASE_URL: &str = "https://acme.mock";
+pub const DIRECTORY_URL: &str = "https://acme.mock/directory";
+pub const TERMS_OF_SERVICE: &str = "https://acme.mock/terms_of_service.pdf";
+// The expiration time of every authorization.
+const AUTHORIZATION_EXPIRES: &str = "2099-01-01T00:00:00Z";
+
+/// A mock ACME server. The clones share the same state, so that one clone can
+/// be the fetcher of a `Runtime`, while another clone serves the challenges.
+#[derive(Clone)]
+pub struct MockAcmeServer {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    certificate_pem: String,
+    nonce_count: u64,
+    unused_nonces: BTreeSet<String>,
+    accounts: Vec<MockAccount>,
+    orders: Vec<MockOrder>,
+    // The HTTP-01 challenge files, as a map from tokens to answers.
+    challenge_files: BTreeMap<String, String>,
+}
+
+struct MockAccount {
+    public_key_thumbprint: String,
+    contact: Value,
+    deactivated: bool,
+}
+
+struct MockOrder {
+    account_id: usize,
+    identifiers: Value,
+    status: Status,
+    challenge_status: Status,
+    challenge_error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct JsonWebSignature {
+    protected: String,
+    payload: String,
+}
+
+#[derive(Deserialize)]
+struct ProtectedHeader {
+    nonce: String,
+    url: String,
+    jwk: Option<JsonWebKey>,
+    kid: Option<String>,
+}
+
+// A JSON response, or a problem document.
+// https://datatracker.ietf.org/doc/html/rfc8555#section-6.7
+type Reply =
+    std::result::Result<(u16, Vec<(String, String)>, Vec<u8>), (u16, &'static str, String)>;
+
+fn json_reply(status: u16, location: Option<String>, body: Value) -> Reply {
+    let mut headers = vec![("content-type".to_string(), "application/json".to_string())];
+    if let Some(location) = location {
+        headers.push(("location".to_string(), location));
+    }
+    Ok((status, headers, body.to_string().into_bytes()))
+}
+
+fn problem(status: u16, error_type: &'static str, detail: impl Into<String>) -> Reply {
+    Err((status, error_type, detail.into()))
+}
+
+fn decode_base64(input: &str) -> Result<Vec<u8>> {
+    Ok(base64::decode_config(input, base64::URL_SAFE_NO_PAD)?)
+}
+
+impl MockAcmeServer {
+    /// Creates a server that issues `certificate_pem` to every order.
+    pub fn new(certificate_pem: &str) -> Self {
+        MockAcmeServer {
+            state: Arc::new(Mutex::new(State {
+                certificate_pem: certificate_pem.to_string(),
+                nonce_count: 0,
+                unused_nonces: BTreeSet::new(),
+                accounts: vec![],
+                orders: vec![],
+                challenge_files: BTreeMap::new(),
+            })),
+        }
+    }
+    /// Serves `answer` as the HTTP-01 challenge file of `token`, which the
+    /// server checks when the client requests the challenge validation.
+    pub fn serve_challenge(&self, token: &str, answer: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .challenge_files
+            .insert(token.to_string(), answer.to_string());
+    }
+}
+
+impl State {
+    fn new_nonce(&mut self) -> String {
+        self.nonce_count += 1;
+        let nonce = format!("nonce-{}", self.nonce_count);
+        self.unused_nonces.insert(nonce.clone());
+        nonce
+    }
+    fn handle(&mut self, request: HttpRequest) -> Reply {
+        let path = match request.url.strip_prefix(BASE_URL) {
+            Some(path) => path.to_string(),
+            None => return problem(404, "malformed", format!("{} is not found", request.url)),
+        };
+        match request.method {
+            Method::Get => self.handle_get(&path),
+            Method::Post => self.handle_post(&request.url, &path, &request.body),
+        }
+    }
+    fn handle_get(&mut self, path: &str) -> Reply {
+        match path {
+            "/directory" => json_reply(
+                200,
+                None,
+                json!({
+                    "newNonce": format!("{}/new-nonce", BASE_URL),
+                    "newAccount": format!("{}/new-account", BASE_URL),
+                    "newOrder": format!("{}/new-order", BASE_URL),
+                    "revokeCert": format!("{}/revoke-cert", BASE_URL),
+                    "keyChange": format!("{}/key-change", BASE_URL),
+                    "meta": {
+                        "termsOfService": TERMS_OF_SERVICE,
+                    },
+                }),
+            ),
+            "/new-nonce" => Ok((200, vec![], vec![])),
+            // https://datatracker.ietf.org/doc/html/rfc8555#section-6.3
+            _ => problem(405, "malformed", "Use POST-as-GET instead"),
+        }
+    }
+    fn handle_post(&mut self, url: &str, path: &str, body: &[u8]) -> Reply {
+        let (header, payload) = match parse_jws(body) {
+            Ok(jws) => jws,
+            Err(e) => return problem(400, "malformed", e.to_string()),
+        };
+        if !self.unused_nonces.remove(&header.nonce) {
+            return problem(400, "badNonce", "The nonce is not issued or already used");
+        }
+        if header.url != url {
+            return problem(401, "unauthorized", "The JWS url does not match");
+        }
+        if path == "/new-account" {
+            return match header.jwk {
+                Some(jwk) => self.new_account(jwk, payload),
+                None => problem(400, "malformed", "newAccount requires jwk"),
+            };
+        }
+        let account_id = match header.kid.as_deref().and_then(parse_account_url) {
+            Some(account_id) if account_id < self.accounts.len() => account_id,
+            _ => return problem(400, "accountDoesNotExist", "Unknown kid"),
+        };
+        if self.accounts[account_id].deactivated {
+            return problem(401, "unauthorized", "The account is deactivated");
+        }
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["new-order"] => self.new_order(account_id, payload),
+            ["acct", id] if id.parse() == Ok(account_id) => {
+                self.update_account(account_id, payload)
+            }
+            ["acct", id, "orders"] if id.parse() == Ok(account_id) => {
+                let orders: Vec<String> = self
+                    .orders
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, order)| order.account_id == account_id)
+                    .map(|(order_id, _)| format!("{}/order/{}", BASE_URL, order_id))
+                    .collect();
+                json_reply(200, None, json!({ "orders": orders }))
+            }
+            [resource, id] => {
+                let order_id = match id.parse::<usize>() {
+                    Ok(order_id) if order_id < self.orders.len() => order_id,
+                    _ => return problem(404, "malformed", format!("{} is not found", url)),
+                };
+                if self.orders[order_id].account_id != account_id {
+                    return problem(403, "unauthorized", "The order belongs to another account");
+                }
+                match *resource {
+                    "order" => json_reply(200, None, self.order_json(order_id)),
+                    "authz" => json_reply(200, None, self.authorization_json(order_id)),
+                    "chall" => self.validate_challenge(order_id),
+                    "finalize" => self.finalize(order_id, payload),
+                    "cert" => self.certificate(order_id),
+                    _ => problem(404, "malformed", format!("{} is not found", url)),
+                }
+            }
+            _ => problem(404, "malformed", format!("{} is not found", url)),
+        }
+    }
+    fn account_json(&self, account_id: usize) -> Value {
+        let account = &self.accounts[account_id];
+        json!({
+            "status": if account.deactivated { "deactivated" } else { "valid" },
+            "contact": account.contact,
+            "orders": format!("{}/acct/{}/orders", BASE_URL, account_id),
+        })
+    }
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-7.3
+    fn new_account(&mut self, jwk: JsonWebKey, payload: Value) -> Reply {
+        if payload["termsOfServiceAgreed"] != json!(true) {
+            return problem(
+                403,
+                "userActionRequired",
+                "Please agree to the terms of service",
+            );
+        }
+        let public_key_thumbprint = match jwk.get_jwk_thumbprint() {
+            Ok(thumbprint) => base64::encode_config(thumbprint, base64::URL_SAFE_NO_PAD),
+            Err(e) => return problem(400, "badPublicKey", e.to_string()),
+        };
+        let existing_account = self
+            .accounts
+            .iter()
+            .position(|account| account.public_key_thumbprint == public_key_thumbprint);
+        let (status, account_id) = match existing_account {
+            Some(account_id) => (200, account_id),
+            None => {
+                self.accounts.push(MockAccount {
+                    public_key_thumbprint,
+                    contact: payload["contact"].clone(),
+                    deactivated: false,
+                });
+                (201, self.accounts.len() - 1)
+            }
+        };
+        json_reply(
+            status,
+            Some(format!("{}/acct/{}", BASE_URL, account_id)),
+            self.account_json(account_id),
+        )
+    }
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-7.3.6
+    fn update_account(&mut self, account_id: usize, payload: Value) -> Reply {
+        if payload["status"] == json!("deactivated") {
+            self.accounts[account_id].deactivated = true;
+        }
+        json_reply(200, None, self.account_json(account_id))
+    }
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-7.4
+    fn new_order(&mut self, account_id: usize, payload: Value) -> Reply {
+        let identifiers = payload["identifiers"].clone();
+        if !matches!(&identifiers, Value::Array(identifiers) if identifiers.len() == 1) {
+            return problem(
+                400,
+                "rejectedIdentifier",
+                "Exactly one identifier is supported",
+            );
+        }
+        self.orders.push(MockOrder {
+            account_id,
+            identifiers,
+            status: Status::Pending,
+            challenge_status: Status::Pending,
+            challenge_error: None,
+        });
+        let order_id = self.orders.len() - 1;
+        json_reply(
+            201,
+            Some(format!("{}/order/{}", BASE_URL, order_id)),
+            self.order_json(order_id),
+        )
+    }
+    fn order_json(&self, order_id: usize) -> Value {
+        let order = &self.orders[order_id];
+        let mut order_json = json!({
+            "status": order.status,
+            "identifiers": order.identifiers,
+            "authorizations": [format!("{}/authz/{}", BASE_URL, order_id)],
+            "finalize": format!("{}/finalize/{}", BASE_URL, order_id),
+        });
+        if order.status == Status::Valid {
+            order_json["certificate"] = json!(format!("{}/cert/{}", BASE_URL, order_id));
+        }
+        order_json
+    }
+    fn challenge_token(order_id: usize) -> String {
+        format!("token-{}", order_id)
+    }
+    fn challenge_json(&self, order_id: usize) -> Value {
+        let order = &self.orders[order_id];
+        let mut challenge_json = json!({
+            "type": "http-01",
+            "url": format!("{}/chall/{}", BASE_URL, order_id),
+            "token": Self::challenge_token(order_id),
+            "status": order.challenge_status,
+        });
+        if let Some(error) = &order.challenge_error {
+            challenge_json["error"] = error.clone();
+        }
+        challenge_json
+    }
+    fn authorization_json(&self, order_id: usize) -> Value {
+        let order = &self.orders[order_id];
+        json!({
+            "status": order.challenge_status,
+            "expires": AUTHORIZATION_EXPIRES,
+            "identifier": order.identifiers[0],
+            "challenges": [self.challenge_json(order_id)],
+        })
+    }
+    // Checks the served challenge file against the key authorization.
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-8.3
+    fn validate_challenge(&mut self, order_id: usize) -> Reply {
+        let token = Self::challenge_token(order_id);
+        let account_id = self.orders[order_id].account_id;
+        let key_authorization = format!(
+            "{}.{}",
+            token, self.accounts[account_id].public_key_thumbprint
+        );
+        let served = self.challenge_files.get(&token).cloned();
+        let order = &mut self.orders[order_id];
+        if order.challenge_status == Status::Pending {
+            if served.as_deref() == Some(key_authorization.as_str()) {
+                order.challenge_status = Status::Valid;
+                order.status = Status::Ready;
+            } else {
+                order.challenge_status = Status::Invalid;
+                order.status = Status::Invalid;
+                order.challenge_error = Some(json!({
+                    "type": "urn:ietf:params:acme:error:incorrectResponse",
+                    "detail": format!("The challenge file is {:?}", served),
+                }));
+            }
+        }
+        json_reply(200, None, self.challenge_json(order_id))
+    }
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-7.4
+    fn finalize(&mut self, order_id: usize, payload: Value) -> Reply {
+        if self.orders[order_id].status != Status::Ready {
+            return problem(403, "orderNotReady", "The order is not ready");
+        }
+        if !matches!(&payload["csr"], Value::String(csr) if decode_base64(csr).is_ok()) {
+            return problem(400, "badCSR", "The CSR is not base64url-encoded");
+        }
+        self.orders[order_id].status = Status::Valid;
+        json_reply(200, None, self.order_json(order_id))
+    }
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-7.4.2
+    fn certificate(&self, order_id: usize) -> Reply {
+        if self.orders[order_id].status != Status::Valid {
+            return problem(404, "malformed", "The certificate is not issued");
+        }
+        Ok((
+            200,
+            vec![(
+                "content-type".to_string(),
+                "application/pem-certificate-chain".to_string(),
+            )],
+            self.certificate_pem.clone().into_bytes(),
+        ))
+    }
+}
+
+// Returns the protected header and the payload. The payload of a POST-as-GET
+// request is `Value::Null`.
+fn parse_jws(body: &[u8]) -> Result<(ProtectedHeader, Value)> {
+    let jws: JsonWebSignature = serde_json::from_slice(body)?;
+    let header = serde_json::from_slice(&decode_base64(&jws.protected)?)?;
+    let payload = decode_base64(&jws.payload)?;
+    let payload = if payload.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&payload)?
+    };
+    Ok((header, payload))
+}
+
+fn parse_account_url(url: &str) -> Option<usize> {
+    url.strip_prefix(BASE_URL)?
+        .strip_prefix("/acct/")?
+        .parse()
+        .ok()
+}
+
+#[async_trait(?Send)]
+impl Fetcher for MockAcmeServer {
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, SxgError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| SxgError::Network(anyhow!("The mock ACME server panicked")))?;
+        let (status, mut headers, body) = match state.handle(request) {
+            Ok(reply) => reply,
+            Err((status, error_type, detail)) => (
+                status,
+                vec![(
+                    "content-type".to_string(),
+                    "application/problem+json".to_string(),
+                )],
+                json!({
+                    "type": format!("urn:ietf:params:acme:error:{}", error_type),
+                    "detail": detail,
+                })
+                .to_string()
+                .into_bytes(),
+            ),
+        };
+        headers.push(("replay-nonce".to_string(), state.new_nonce()));
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::state_machine::{
+        get_challenge_token_and_answer, read_current_state, update_state,
+    };
+    use crate::acme::{create_account, deactivate_account, get_account_status, AccountSetupParams};
+    use crate::acme::{directory::AccountStatus, Account};
+    use crate::runtime::Runtime;
+    use crate::utils::tests::SELF_SIGNED_CERT_PEM;
+    use std::time::{Duration, SystemTime};
+
+    async fn create_test_account(runtime: &Runtime) -> Result<Account> {
+        create_account(
+            AccountSetupParams {
+                directory_url: DIRECTORY_URL.to_string(),
+                agreed_terms_of_service: TERMS_OF_SERVICE,
+                external_account_binding: None,
+                emails: &["admin@example.com".to_string()],
+                domain: "example.com".to_string(),
+                public_key: crate::crypto::EcPublicKey {
+                    kty: "EC".to_string(),
+                    crv: "P-256".to_string(),
+                    x: vec![1],
+                    y: vec![2],
+                }
+                .into(),
+                cert_request_der: b"csr content".to_vec(),
+            },
+            runtime.fetcher.as_ref(),
+            runtime.acme_signer.as_ref(),
+        )
+        .await
+        .map_err(SxgError::into_inner)
+    }
+
+    // Runs the state machine in the same way as `apply_acme_cert` in the
+    // tools, and returns the issued certificate. If `corrupt_answer`, the
+    // challenge is answered wrongly.
+    async fn apply_certificate(corrupt_answer: bool) -> Result<String> {
+        let server = MockAcmeServer::new(SELF_SIGNED_CERT_PEM);
+        apply_certificate_with(&server, corrupt_answer).await
+    }
+    async fn apply_certificate_with(
+        server: &MockAcmeServer,
+        corrupt_answer: bool,
+    ) -> Result<String> {
+        let mut runtime = Runtime {
+            now: SystemTime::UNIX_EPOCH,
+            fetcher: Box::new(server.clone()),
+            ..Default::default()
+        };
+        let account = create_test_account(&runtime).await?;
+        update_state(&runtime, &account).await?;
+        let (token, answer) = get_challenge_token_and_answer(&runtime)
+            .await?
+            .ok_or_else(|| anyhow!("No challenge"))?;
+        if corrupt_answer {
+            server.serve_challenge(&token, "wrong answer");
+        } else {
+            server.serve_challenge(&token, &answer);
+        }
+        for _ in 0..10 {
+            runtime.now += Duration::from_secs(600);
+            update_state(&runtime, &account).await?;
+            if let Some(certificate) = read_current_state(&runtime).await?.certificates.pop() {
+                return Ok(certificate);
+            }
+        }
+        Err(anyhow!("No certificate after 10 updates"))
+    }
+
+    #[tokio::test]
+    async fn issues_certificate() {
+        assert_eq!(
+            apply_certificate(false).await.unwrap(),
+            SELF_SIGNED_CERT_PEM
+        );
+    }
+    #[tokio::test]
+    async fn rejects_wrong_challenge_answer() {
+        let error = apply_certificate(true).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("incorrectResponse"));
+    }
+    #[tokio::test]
+    async fn continues_existing_order() {
+        let server = MockAcmeServer::new(SELF_SIGNED_CERT_PEM);
+        let runtime = Runtime {
+            fetcher: Box::new(server.clone()),
+            ..Default::default()
+        };
+        let account = create_test_account(&runtime).await.unwrap();
+        update_state(&runtime, &account).await.unwrap();
+        // A rerun with new storage, such as after a crash, finds the same
+        // order, instead of placing a new one.
+        let rerun = Runtime {
+            fetcher: Box::new(server.clone()),
+            ..Default::default()
+        };
+        update_state(&rerun, &account).await.unwrap();
+        assert_eq!(
+            get_challenge_token_and_answer(&rerun).await.unwrap(),
+            get_challenge_token_and_answer(&runtime).await.unwrap()
+        );
+        assert_eq!(server.state.lock().unwrap().orders.len(), 1);
+    }
+    #[tokio::test]
+    async fn renews_with_new_order() {
+        let server = MockAcmeServer::new(SELF_SIGNED_CERT_PEM);
+        apply_certificate_with(&server, false).await.unwrap();
+        // The valid order of the first certificate is not reused.
+        apply_certificate_with(&server, false).await.unwrap();
+        assert_eq!(server.state.lock().unwrap().orders.len(), 2);
+    }
+    #[tokio::test]
+    async fn deactivates_account() {
+        let server = MockAcmeServer::new(SELF_SIGNED_CERT_PEM);
+        let runtime = Runtime {
+            fetcher: Box::new(server),
+            ..Default::default()
+        };
+        let account = create_test_account(&runtime).await.unwrap();
+        let fetcher = runtime.fetcher.as_ref();
+        let signer = runtime.acme_signer.as_ref();
+        assert_eq!(
+            get_account_status(&account, fetcher, signer).await.unwrap(),
+            AccountStatus::Valid
+        );
+        deactivate_account(&account, fetcher, signer).await.unwrap();
+        assert!(update_state(&runtime, &account).await.is_err());
+    }
+}
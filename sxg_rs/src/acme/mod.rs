@@ -20,22 +20,32 @@
 //!    challenge answer.
 //! 1. Call this module's `continue_challenge_validation_and_get_certificate`.
 
-pub mod client;
+mod client;
 pub mod directory;
 pub mod eab;
 pub mod jws;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock_server;
+pub mod retry_budget;
 pub mod state_machine;
 
-use crate::crypto::EcPublicKey;
+use crate::crypto::{certificate_chain, JsonWebKey};
 use crate::fetcher::Fetcher;
+use crate::http::{HttpRequest, HttpResponse, Method};
+use crate::http_parser::parse_link_header;
 use crate::signature::Signer;
-use anyhow::{anyhow, Error, Result};
+use crate::SxgError;
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
 use client::{parse_response_body, AuthMethod, Client};
 use directory::{
-    Authorization, Challenge, Directory, FinalizeRequest, Identifier, IdentifierType,
-    NewAccountRequestPayload, NewAccountResponsePayload, NewOrderRequestPayload, Order, Status,
+    AccountStatus, Authorization, Challenge, DeactivateAccountRequest, Directory, FinalizeRequest,
+    Identifier, IdentifierType, NewAccountRequestPayload, NewAccountResponsePayload,
+    NewOrderRequestPayload, Order, OrderList, Status,
 };
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::SystemTime;
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -46,6 +56,28 @@ pub struct Account {
     #[serde(with = "crate::serde_helpers::base64")]
     pub cert_request_der: Vec<u8>,
     pub public_key_thumbprint: String,
+    /// The algorithm of the account key. Defaults to ES256, which is the only
+    /// one supported before Ed25519 keys.
+    #[serde(default)]
+    pub key_algorithm: jws::Algorithm,
+}
+
+/// How the certificates of an account are ordered. Unlike the `Account`,
+/// these are not stored, and may change from one run to the next.
+#[derive(Clone, Debug, Default)]
+pub struct OrderOptions {
+    /// If `false`, the state machine reuses an unfinished order of the
+    /// account for the same domain, instead of placing a new order.
+    pub always_place_new_order: bool,
+    /// If set, the certificate chain whose topmost certificate is issued by
+    /// this common name, such as `ISRG Root X1`, is downloaded, among the
+    /// primary chain and the alternate chains offered by the ACME server. If
+    /// no chain matches, the primary chain is downloaded.
+    pub preferred_chain: Option<String>,
+    /// The `notBefore` requested in new orders. Some ACME servers ignore it.
+    pub not_before: Option<DateTime<FixedOffset>>,
+    /// The `notAfter` requested in new orders. Some ACME servers ignore it.
+    pub not_after: Option<DateTime<FixedOffset>>,
 }
 
 /// The runtime context of an ongoing ACME certificate request, which is
@@ -66,19 +98,137 @@ pub struct AccountSetupParams<'a> {
     /// This must be the same as what required by ACME server.
     pub agreed_terms_of_service: &'a str,
     pub external_account_binding: Option<jws::JsonWebSignature>,
-    pub email: &'a str,
+    /// The contact emails of the account, such as a shared alias and an
+    /// individual. At least one is required.
+    pub emails: &'a [String],
+    /// The domain name, or the IPv4 or IPv6 address, to request a certificate
+    /// for.
     pub domain: String,
-    pub public_key: EcPublicKey,
+    /// The public key of the account, which is either a P-256 or an Ed25519
+    /// key.
+    pub public_key: JsonWebKey,
     pub cert_request_der: Vec<u8>,
 }
 
+// The longest validity period that can be requested. SXG certificates are
+// valid for at most 90 days.
+const MAX_VALIDITY_DAYS: i64 = 90;
+
+impl OrderOptions {
+    /// Checks the requested validity period at `now`. See
+    /// `validate_validity_period`.
+    pub fn validate(&self, now: SystemTime) -> Result<(), SxgError> {
+        validate_validity_period(self.not_before, self.not_after, now).map_err(SxgError::Acme)
+    }
+}
+
+// Checks that the requested `notAfter` is after `notBefore`, and that the
+// period between them is not longer than `MAX_VALIDITY_DAYS`. If `notBefore`
+// is not requested, the certificate is valid from about `now`.
+fn validate_validity_period(
+    not_before: Option<DateTime<FixedOffset>>,
+    not_after: Option<DateTime<FixedOffset>>,
+    now: SystemTime,
+) -> Result<()> {
+    let not_before = not_before.unwrap_or_else(|| DateTime::<Utc>::from(now).into());
+    if let Some(not_after) = not_after {
+        if not_after <= not_before {
+            return Err(anyhow!(
+                "notAfter {} is not after notBefore {}.",
+                not_after.to_rfc3339(),
+                not_before.to_rfc3339()
+            ));
+        }
+        if not_after - not_before > Duration::days(MAX_VALIDITY_DAYS) {
+            return Err(anyhow!(
+                "The period from notBefore {} to notAfter {} is longer than {} days.",
+                not_before.to_rfc3339(),
+                not_after.to_rfc3339(),
+                MAX_VALIDITY_DAYS
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Checks that `email` is an `addr-spec` that can be put in a `mailto:` URL
+// without percent-encoding, with a domain name as the domain part. This is
+// stricter than RFC 5322, and rejects the quoted forms and the characters
+// `#%&?`, which ACME servers may not accept.
+fn validate_email(email: &str) -> Result<()> {
+    let is_valid = || {
+        let (local_part, domain) = email.rsplit_once('@')?;
+        let is_atext = |c: char| c.is_ascii_alphanumeric() || "!$'*+-/=^_`{|}~".contains(c);
+        let local_part_is_valid = local_part
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext));
+        let labels: Vec<&str> = domain.split('.').collect();
+        let domain_is_valid = labels.len() >= 2
+            && labels.iter().all(|label| {
+                !label.is_empty()
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        Some(local_part_is_valid && domain_is_valid)
+    };
+    if is_valid() == Some(true) {
+        Ok(())
+    } else {
+        Err(anyhow!("\"{}\" is not a valid email address.", email))
+    }
+}
+
+// Returns the ACME identifier of `domain`, which is an IP address identifier
+// if `domain` looks like an IPv4 or IPv6 address, and a DNS identifier
+// otherwise. No valid domain name consists of only digits and dots, or
+// contains a colon.
+// https://datatracker.ietf.org/doc/html/rfc8738#section-3
+fn create_identifier(domain: &str) -> Result<Identifier> {
+    let is_ip = domain.contains(':') || domain.chars().all(|c| c.is_ascii_digit() || c == '.');
+    if is_ip {
+        let ip: IpAddr = domain
+            .parse()
+            .map_err(|_| anyhow!("\"{}\" is not a valid IPv4 or IPv6 address.", domain))?;
+        // `IpAddr` formats IPv6 addresses in the canonical form of RFC 5952,
+        // as required by RFC 8738.
+        Ok(Identifier {
+            r#type: IdentifierType::Ip,
+            value: ip.to_string(),
+        })
+    } else {
+        Ok(Identifier {
+            r#type: IdentifierType::Dns,
+            value: domain.to_string(),
+        })
+    }
+}
+
 /// Connects to ACME server to request a certificate, stops after generating
 /// HTTP challenge answer, and returns the running context of this application.
 pub async fn create_account(
     params: AccountSetupParams<'_>,
     fetcher: &dyn Fetcher,
     acme_signer: &dyn Signer,
+) -> Result<Account, SxgError> {
+    create_account_impl(params, fetcher, acme_signer)
+        .await
+        .map_err(SxgError::Acme)
+}
+
+async fn create_account_impl(
+    params: AccountSetupParams<'_>,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
 ) -> Result<Account> {
+    if params.emails.is_empty() {
+        return Err(anyhow!("At least one contact email is required."));
+    }
+    for email in params.emails {
+        validate_email(email)?;
+    }
+    create_identifier(&params.domain)?;
+    let key_algorithm = jws::Algorithm::of_key(&params.public_key);
     let public_key_thumbprint = base64::encode_config(
         params.public_key.get_jwk_thumbprint()?,
         base64::URL_SAFE_NO_PAD,
@@ -104,7 +254,11 @@ pub async fn create_account(
     }
     let account_url: String = {
         let request_payload = NewAccountRequestPayload {
-            contact: vec![format!("mailto:{}", params.email)],
+            contact: params
+                .emails
+                .iter()
+                .map(|email| format!("mailto:{}", email))
+                .collect(),
             external_account_binding: params.external_account_binding,
             terms_of_service_agreed: true,
         };
@@ -117,7 +271,7 @@ pub async fn create_account(
             )
             .await?;
         let rsp_paylod: NewAccountResponsePayload = parse_response_body(&response)?;
-        if rsp_paylod.status != Status::Valid {
+        if rsp_paylod.status != AccountStatus::Valid {
             return Err(Error::msg("The account status is not valid"));
         }
         client::find_header(&response, "Location")?
@@ -128,28 +282,133 @@ pub async fn create_account(
         public_key_thumbprint,
         domain: params.domain,
         account_url,
+        key_algorithm,
     })
 }
 
-pub async fn place_new_order(
+/// Fetches the account, and returns its status.
+pub async fn get_account_status(
+    account: &Account,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<AccountStatus, SxgError> {
+    get_account_status_impl(account, fetcher, acme_signer)
+        .await
+        .map_err(SxgError::Acme)
+}
+
+async fn get_account_status_impl(
     account: &Account,
     fetcher: &dyn Fetcher,
     acme_signer: &dyn Signer,
+) -> Result<AccountStatus> {
+    let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
+    let mut client = Client::new(
+        &directory,
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
+        nonce,
+    );
+    let response = client
+        .post_as_get(account.account_url.clone(), fetcher, acme_signer)
+        .await?;
+    let account_payload: NewAccountResponsePayload = parse_response_body(&response)?;
+    Ok(account_payload.status)
+}
+
+/// Fetches the order at `order_url`, and returns its status.
+pub async fn get_order_status(
+    account: &Account,
+    order_url: &str,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<Status, SxgError> {
+    get_order_status_impl(account, order_url, fetcher, acme_signer)
+        .await
+        .map_err(SxgError::Acme)
+}
+
+async fn get_order_status_impl(
+    account: &Account,
+    order_url: &str,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<Status> {
+    let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
+    let mut client = Client::new(
+        &directory,
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
+        nonce,
+    );
+    let response = client
+        .post_as_get(order_url.to_string(), fetcher, acme_signer)
+        .await?;
+    let order: Order = parse_response_body(&response)?;
+    Ok(order.status)
+}
+
+/// Deactivates the account, after which the ACME server rejects its requests.
+/// A deactivated account can't be reactivated.
+// https://datatracker.ietf.org/doc/html/rfc8555#section-7.3.6
+pub async fn deactivate_account(
+    account: &Account,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<(), SxgError> {
+    deactivate_account_impl(account, fetcher, acme_signer)
+        .await
+        .map_err(SxgError::Acme)
+}
+
+async fn deactivate_account_impl(
+    account: &Account,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<()> {
+    let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
+    let mut client = Client::new(
+        &directory,
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
+        nonce,
+    );
+    let response = client
+        .post_with_payload(
+            account.account_url.clone(),
+            DeactivateAccountRequest {
+                status: AccountStatus::Deactivated,
+            },
+            fetcher,
+            acme_signer,
+        )
+        .await?;
+    let account_payload: NewAccountResponsePayload = parse_response_body(&response)?;
+    if account_payload.status != AccountStatus::Deactivated {
+        return Err(anyhow!(
+            "The account status is {:?} after deactivation",
+            account_payload.status
+        ));
+    }
+    Ok(())
+}
+
+/// Places a new order with the validity period in `options`, which should be
+/// checked by `OrderOptions::validate` first.
+pub(crate) async fn place_new_order(
+    account: &Account,
+    options: &OrderOptions,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
 ) -> Result<OngoingOrder> {
     let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
     let mut client = Client::new(
         &directory,
-        AuthMethod::KeyId(account.account_url.clone()),
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
         nonce,
     );
     let (order, order_url) = {
         let request_payload = NewOrderRequestPayload {
-            identifiers: vec![Identifier {
-                r#type: IdentifierType::Dns,
-                value: account.domain.clone(),
-            }],
-            not_before: None,
-            not_after: None,
+            identifiers: vec![create_identifier(&account.domain)?],
+            not_before: options.not_before,
+            not_after: options.not_after,
         };
         let response = client
             .post_with_payload(
@@ -160,17 +419,81 @@ pub async fn place_new_order(
             )
             .await?;
         let order: Order = parse_response_body(&response)?;
-        let order_url = client::find_header(&response, "location")
-            .map_err(|e| e.context("Failed to get order URL"))?;
+        let order_url =
+            client::find_header(&response, "location").context("Failed to get order URL")?;
         (order, order_url)
     };
+    create_ongoing_order(&mut client, account, order, order_url, fetcher, acme_signer).await
+}
+
+/// Looks up the orders of the account, and returns the first one that is for
+/// `account.domain` and is still pending, ready or processing, along with its
+/// status. This allows a rerun after a crash to continue an existing order,
+/// instead of hitting the rate limit of new orders. A valid order is not
+/// returned, since its certificate has already been issued, and a renewal
+/// needs a new one. Returns `None` if the server does not
+/// provide the order list, or if no such order exists. Only the first page of
+/// the order list is checked.
+pub(crate) async fn find_existing_order(
+    account: &Account,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<Option<(OngoingOrder, Status)>> {
+    let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
+    let mut client = Client::new(
+        &directory,
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
+        nonce,
+    );
+    let response = client
+        .post_as_get(account.account_url.clone(), fetcher, acme_signer)
+        .await?;
+    let account_payload: NewAccountResponsePayload = parse_response_body(&response)?;
+    let orders_url = match account_payload.orders {
+        Some(orders_url) => orders_url,
+        None => return Ok(None),
+    };
+    let response = client.post_as_get(orders_url, fetcher, acme_signer).await?;
+    let order_list: OrderList = parse_response_body(&response)?;
+    let identifier = create_identifier(&account.domain)?;
+    for order_url in order_list.orders {
+        let response = client
+            .post_as_get(order_url.clone(), fetcher, acme_signer)
+            .await?;
+        let order: Order = parse_response_body(&response)?;
+        let is_same_domain = order.identifiers.as_slice() == std::slice::from_ref(&identifier);
+        let is_unfinished = matches!(
+            order.status,
+            Status::Pending | Status::Ready | Status::Processing
+        );
+        if !is_same_domain || !is_unfinished {
+            continue;
+        }
+        let status = order.status;
+        let ongoing_order =
+            create_ongoing_order(&mut client, account, order, order_url, fetcher, acme_signer)
+                .await?;
+        return Ok(Some((ongoing_order, status)));
+    }
+    Ok(None)
+}
+
+/// Fetches the HTTP-01 challenge of `order`, and returns the context needed by
+/// the following steps.
+async fn create_ongoing_order(
+    client: &mut Client<'_>,
+    account: &Account,
+    order: Order,
+    order_url: String,
+    fetcher: &dyn Fetcher,
+    acme_signer: &dyn Signer,
+) -> Result<OngoingOrder> {
     let authorization_url: String = order
         .authorizations
-        .get(0)
+        .first()
         .ok_or_else(|| Error::msg("The order response does not contain authorizations"))?
         .to_owned();
-    let challenge =
-        get_http_challenge(&mut client, &authorization_url, fetcher, acme_signer).await?;
+    let challenge = get_http_challenge(client, &authorization_url, fetcher, acme_signer).await?;
 
     // https://datatracker.ietf.org/doc/html/rfc8555#section-8.1
     let challenge_answer = format!("{}.{}", challenge.token, account.public_key_thumbprint);
@@ -186,7 +509,7 @@ pub async fn place_new_order(
 }
 
 /// Notifies the server that the client is ready for HTTP challenge.
-pub async fn request_challenge_validation(
+pub(crate) async fn request_challenge_validation(
     account: &Account,
     challenge_url: String,
     fetcher: &dyn Fetcher,
@@ -195,7 +518,7 @@ pub async fn request_challenge_validation(
     let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
     let mut client = Client::new(
         &directory,
-        AuthMethod::KeyId(account.account_url.clone()),
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
         nonce,
     );
     // https://datatracker.ietf.org/doc/html/rfc8555#section-7.5.1
@@ -210,7 +533,7 @@ pub async fn request_challenge_validation(
 
 /// Checks the HTTP challenge status. Returns `true` if the challenge is successfully finished;
 /// returns `false` is the server is still processing.
-pub async fn check_challenge_finished(
+pub(crate) async fn check_challenge_finished(
     account: &Account,
     authorization_url: &str,
     fetcher: &dyn Fetcher,
@@ -219,7 +542,7 @@ pub async fn check_challenge_finished(
     let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
     let mut client = Client::new(
         &directory,
-        AuthMethod::KeyId(account.account_url.clone()),
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
         nonce,
     );
     let challenge =
@@ -241,6 +564,60 @@ pub async fn check_challenge_finished(
     }
 }
 
+/// Fetches the HTTP-01 challenge file of `domain` in the same way as the ACME
+/// server would do, and checks that it contains `challenge_answer`. This
+/// should be called before `request_challenge_validation`, because the ACME
+/// server does not report a helpful error if the domain is unreachable.
+pub async fn check_challenge_reachable(
+    domain: &str,
+    challenge_token: &str,
+    challenge_answer: &str,
+    fetcher: &dyn Fetcher,
+) -> Result<(), SxgError> {
+    check_challenge_reachable_impl(domain, challenge_token, challenge_answer, fetcher)
+        .await
+        .map_err(SxgError::Acme)
+}
+
+async fn check_challenge_reachable_impl(
+    domain: &str,
+    challenge_token: &str,
+    challenge_answer: &str,
+    fetcher: &dyn Fetcher,
+) -> Result<()> {
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-8.3
+    let host = match domain.parse::<Ipv6Addr>() {
+        Ok(ip) => format!("[{}]", ip),
+        Err(_) => domain.to_string(),
+    };
+    let url = format!(
+        "http://{}/.well-known/acme-challenge/{}",
+        host, challenge_token
+    );
+    let request = HttpRequest {
+        body: vec![],
+        headers: vec![],
+        method: Method::Get,
+        url: url.clone(),
+    };
+    let response = fetcher.fetch(request).await.with_context(|| {
+        format!(
+            "Failed to fetch {}, challenge not reachable from the public internet",
+            url
+        )
+    })?;
+    if response.status != 200 || String::from_utf8_lossy(&response.body).trim() != challenge_answer
+    {
+        return Err(anyhow!(
+            "{} responded with status {} and an unexpected body, \
+            challenge not reachable from the public internet",
+            url,
+            response.status
+        ));
+    }
+    Ok(())
+}
+
 /// Finalizes the order by submitting the CSR to the server.
 async fn finalize_signing_request(
     account: &Account,
@@ -251,7 +628,7 @@ async fn finalize_signing_request(
     let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
     let mut client = Client::new(
         &directory,
-        AuthMethod::KeyId(account.account_url.clone()),
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
         nonce,
     );
     client
@@ -278,7 +655,7 @@ async fn get_certificate_url(
     let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
     let mut client = Client::new(
         &directory,
-        AuthMethod::KeyId(account.account_url.clone()),
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
         nonce,
     );
     let response = client
@@ -301,9 +678,12 @@ async fn get_certificate_url(
     }
 }
 
-/// Downloads the certificate from URL, and returns it in PEM format.
+/// Downloads the certificate from URL, and returns it in PEM format. If
+/// `preferred_chain` is given, the alternate chains are also downloaded as
+/// needed to find the chain issued by it.
 async fn download_certificate(
     account: &Account,
+    preferred_chain: Option<&str>,
     certificate_url: String,
     fetcher: &dyn Fetcher,
     acme_signer: &dyn Signer,
@@ -311,16 +691,64 @@ async fn download_certificate(
     let (directory, nonce) = Directory::from_url(&account.server_directory_url, fetcher).await?;
     let mut client = Client::new(
         &directory,
-        AuthMethod::KeyId(account.account_url.clone()),
+        AuthMethod::KeyId(account.account_url.clone(), account.key_algorithm),
         nonce,
     );
-    let certificate = client
+    let response = client
         .post_as_get(certificate_url, fetcher, acme_signer)
         .await?;
-    let certificate = String::from_utf8(certificate.body).unwrap();
+    let certificate = String::from_utf8(response.body.clone())?;
+    let preferred_chain = match preferred_chain {
+        Some(preferred_chain) if !is_issued_by(&certificate, preferred_chain) => preferred_chain,
+        _ => return Ok(certificate),
+    };
+    // https://datatracker.ietf.org/doc/html/rfc8555#section-7.4.2
+    for alternate_url in alternate_urls(&response) {
+        let alternate = client
+            .post_as_get(alternate_url, fetcher, acme_signer)
+            .await?;
+        let alternate = String::from_utf8(alternate.body)?;
+        if is_issued_by(&alternate, preferred_chain) {
+            return Ok(alternate);
+        }
+    }
+    crate::utils::console_log(&format!(
+        "No certificate chain is issued by {:?}; using the primary chain.",
+        preferred_chain
+    ));
     Ok(certificate)
 }
 
+// Returns whether the topmost certificate of the PEM chain is issued by
+// `common_name`.
+fn is_issued_by(certificate_pem: &str, common_name: &str) -> bool {
+    let issuer = certificate_chain(certificate_pem)
+        .ok()
+        .and_then(|chain| chain.last()?.issuer_common_name().ok()?);
+    issuer.as_deref() == Some(common_name)
+}
+
+// Returns the URLs of the alternate certificate chains, which are the `Link`
+// headers with `rel="alternate"`.
+fn alternate_urls(response: &HttpResponse) -> Vec<String> {
+    response
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("link"))
+        .filter_map(|(_, value)| parse_link_header(value).ok())
+        .flatten()
+        .filter(|link| {
+            link.params.iter().any(|(name, value)| match value {
+                Some(rel) if name == "rel" => {
+                    rel.split_ascii_whitespace().any(|rel| rel == "alternate")
+                }
+                _ => false,
+            })
+        })
+        .map(|link| link.uri)
+        .collect()
+}
+
 /// Fetches `authorization_url` and returns the first `HTTP-01` challenge.
 async fn get_http_challenge(
     client: &mut Client<'_>,
@@ -344,7 +772,7 @@ async fn get_http_challenge(
 mod tests {
     use super::*;
     use crate::fetcher::mock_fetcher::MockServer;
-    use crate::http::{HttpRequest, HttpResponse, Method};
+    use crate::http::HttpResponse;
     use crate::runtime::Runtime;
     use crate::signature::mock_signer::MockSigner;
     use jws::JsonWebSignature;
@@ -380,7 +808,7 @@ mod tests {
         HttpRequest {
             body: serde_json::to_vec(&JsonWebSignature::new_from_serialized(
                 &format!(r#"{{"alg":"ES256","nonce":"{}","url":"https://acme.server/new-order","jwk":null,"kid":"https://acme.server/acct/123456"}}"#, nonce),
-                r#"{"identifiers":[{"type":"dns","value":"example.com"}],"notBefore":null,"notAfter":null}"#,
+                r#"{"identifiers":[{"type":"dns","value":"example.com"}]}"#,
                 &signer,
             ).await.unwrap()).unwrap(),
             method: Method::Post,
@@ -421,6 +849,90 @@ mod tests {
         }
     }
 
+    pub async fn example_account_request(nonce: &str) -> HttpRequest {
+        let signer = MockSigner;
+        HttpRequest {
+            body: serde_json::to_vec(&JsonWebSignature::new_from_serialized(
+                &format!(r#"{{"alg":"ES256","nonce":"{}","url":"https://acme.server/acct/123456","jwk":null,"kid":"https://acme.server/acct/123456"}}"#, nonce),
+                "",
+                &signer,
+            ).await.unwrap()).unwrap(),
+            method: Method::Post,
+            headers: vec![(
+                "content-type".to_string(),
+                "application/jose+json".to_string(),
+            )],
+            url: "https://acme.server/acct/123456".to_string(),
+        }
+    }
+
+    pub fn example_account_response(nonce: &str, orders_url: Option<&str>) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: vec![("Replay-Nonce".to_string(), nonce.to_string())],
+            body: serde_json::to_vec(&serde_json::json!({
+                "status": "valid",
+                "contact": ["mailto:admin@example.com"],
+                "orders": orders_url,
+            }))
+            .unwrap(),
+        }
+    }
+
+    pub async fn example_order_list_request(nonce: &str) -> HttpRequest {
+        let signer = MockSigner;
+        HttpRequest {
+            body: serde_json::to_vec(&JsonWebSignature::new_from_serialized(
+                &format!(r#"{{"alg":"ES256","nonce":"{}","url":"https://acme.server/acct/123456/orders","jwk":null,"kid":"https://acme.server/acct/123456"}}"#, nonce),
+                "",
+                &signer,
+            ).await.unwrap()).unwrap(),
+            method: Method::Post,
+            headers: vec![(
+                "content-type".to_string(),
+                "application/jose+json".to_string(),
+            )],
+            url: "https://acme.server/acct/123456/orders".to_string(),
+        }
+    }
+
+    pub fn example_order_list_response(nonce: &str) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: vec![("Replay-Nonce".to_string(), nonce.to_string())],
+            body: r#"{
+                "orders": [
+                    "https://acme.server/order/46540038"
+                ]
+            }"#
+            .to_string()
+            .into_bytes(),
+        }
+    }
+
+    pub fn example_ready_order_response(nonce: &str) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: vec![("Replay-Nonce".to_string(), nonce.to_string())],
+            body: r#"{
+                "status": "ready",
+                "expires": "2022-03-15T19:38:31Z",
+                "identifiers": [
+                    {
+                        "type": "dns",
+                        "value": "example.com"
+                    }
+                ],
+                "authorizations": [
+                    "https://acme.server/authz-v3/1866692048"
+                ],
+                "finalize": "https://acme.server/finalize/46540038/1977802858"
+            }"#
+            .to_string()
+            .into_bytes(),
+        }
+    }
+
     pub async fn example_authorization_request(nonce: &str) -> HttpRequest {
         let signer = MockSigner;
         HttpRequest {
@@ -619,10 +1131,18 @@ mod tests {
     }
 
     pub async fn example_certificate_request(nonce: &str) -> HttpRequest {
+        certificate_request(
+            nonce,
+            "https://acme.server/cert/fa7af446e23117a13137f4cf64f24c3cdb5b",
+        )
+        .await
+    }
+
+    async fn certificate_request(nonce: &str, url: &str) -> HttpRequest {
         let signer = MockSigner;
         HttpRequest {
                 body: serde_json::to_vec(&JsonWebSignature::new_from_serialized(
-                    &format!(r#"{{"alg":"ES256","nonce":"{}","url":"https://acme.server/cert/fa7af446e23117a13137f4cf64f24c3cdb5b","jwk":null,"kid":"https://acme.server/acct/123456"}}"#, nonce),
+                    &format!(r#"{{"alg":"ES256","nonce":"{}","url":"{}","jwk":null,"kid":"https://acme.server/acct/123456"}}"#, nonce, url),
                     "",
                     &signer,
                 ).await.unwrap()).unwrap(),
@@ -631,7 +1151,7 @@ mod tests {
                     "content-type".to_string(),
                     "application/jose+json".to_string(),
                 )],
-                url: "https://acme.server/cert/fa7af446e23117a13137f4cf64f24c3cdb5b".to_string(),
+                url: url.to_string(),
             }
     }
 
@@ -651,7 +1171,7 @@ mod tests {
             fetcher: Box::new(fetcher),
             ..Default::default()
         };
-        let public_key = EcPublicKey {
+        let public_key = crate::crypto::EcPublicKey {
             kty: "EC".to_string(),
             crv: "P-256".to_string(),
             x: vec![1],
@@ -664,9 +1184,12 @@ mod tests {
                     directory_url: "https://acme.server/".to_string(),
                     agreed_terms_of_service: "https://acme.server/terms_of_service.pdf",
                     external_account_binding: None,
-                    email: "admin@example.com",
+                    emails: &[
+                        "admin@example.com".to_string(),
+                        "ops@example.com".to_string(),
+                    ],
                     domain: "example.com".to_string(),
-                    public_key,
+                    public_key: public_key.into(),
                     cert_request_der: "csr content".to_string().into_bytes(),
                 },
                 runtime.fetcher.as_ref(),
@@ -676,6 +1199,7 @@ mod tests {
             .unwrap();
             let ongoing_certificate_request = place_new_order(
                 &acme_account,
+                &OrderOptions::default(),
                 runtime.fetcher.as_ref(),
                 runtime.acme_signer.as_ref(),
             )
@@ -717,6 +1241,7 @@ mod tests {
             .unwrap();
             let certificate_pem = download_certificate(
                 &acme_account,
+                None,
                 certificate_url,
                 runtime.fetcher.as_ref(),
                 runtime.acme_signer.as_ref(),
@@ -736,7 +1261,7 @@ mod tests {
             let req = HttpRequest {
                 body: serde_json::to_vec(&JsonWebSignature::new_from_serialized(
                     r#"{"alg":"ES256","nonce":"1","url":"https://acme.server/new-acct","jwk":{"crv":"P-256","kty":"EC","x":"AQ","y":"Ag"},"kid":null}"#,
-                    r#"{"contact":["mailto:admin@example.com"],"externalAccountBinding":null,"termsOfServiceAgreed":true}"#,
+                    r#"{"contact":["mailto:admin@example.com","mailto:ops@example.com"],"externalAccountBinding":null,"termsOfServiceAgreed":true}"#,
                     &signer,
                 ).await.unwrap()).unwrap(),
                 method: Method::Post,
@@ -763,7 +1288,8 @@ mod tests {
                         "y": "Ag"
                     },
                     "contact": [
-                        "mailto:admin@example.com"
+                        "mailto:admin@example.com",
+                        "mailto:ops@example.com"
                     ],
                     "initialIp": "2620:0:1000:0:0:0:0:0",
                     "createdAt": "2022-03-08T19:01:23.845700962Z",
@@ -844,4 +1370,338 @@ mod tests {
         };
         tokio::join!(client_thread, server_thread);
     }
+    // Generated with:
+    //   openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+    //     -keyout /dev/null -subj "/CN=Test Root X1" -days 365
+    const TEST_ROOT_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBgzCCASmgAwIBAgIUMasGRMTGHU0equDS7f3TK9NYsrgwCgYIKoZIzj0EAwIw
+FzEVMBMGA1UEAwwMVGVzdCBSb290IFgxMB4XDTI2MTAxNjEwNDY1OFoXDTI3MTAx
+NjEwNDY1OFowFzEVMBMGA1UEAwwMVGVzdCBSb290IFgxMFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAEbBF7ETw9XO8zbYtIJv+c62rR6I9OBJy7iAPnb+V5PZIU1uC1
+F4oGVrGm1G0T1f3l5Q6iA//iaThZlmrguiJ6JqNTMFEwHQYDVR0OBBYEFKhmxrCV
+8NKY9cdXfdBSURBs2ctWMB8GA1UdIwQYMBaAFKhmxrCV8NKY9cdXfdBSURBs2ctW
+MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIgPmH/fxkmkvHKpvoR
+fp98uL0W3KgdBjX/YQXgBfg/IV0CIQCMuDf0e+RkL7iwlj9GQXFzvghKOG4y6vGM
+F/UYBzdlcQ==
+-----END CERTIFICATE-----
+    ";
+    #[tokio::test]
+    async fn downloads_preferred_chain() {
+        const CERT_URL: &str = "https://acme.server/cert/fa7af446e23117a13137f4cf64f24c3cdb5b";
+        const ALTERNATE_URLS: [&str; 2] = [
+            "https://acme.server/cert/fa7af446e23117a13137f4cf64f24c3cdb5b/1",
+            "https://acme.server/cert/fa7af446e23117a13137f4cf64f24c3cdb5b/2",
+        ];
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let account = Account {
+            server_directory_url: "https://acme.server/".to_string(),
+            account_url: "https://acme.server/acct/123456".to_string(),
+            domain: "example.com".to_string(),
+            cert_request_der: vec![],
+            public_key_thumbprint: "key_thumbprint".to_string(),
+            key_algorithm: jws::Algorithm::ES256,
+        };
+        let client_thread = async {
+            let (account, fetcher) = (&account, &fetcher);
+            let download = |preferred_chain| async move {
+                download_certificate(
+                    account,
+                    Some(preferred_chain),
+                    CERT_URL.to_string(),
+                    fetcher,
+                    &MockSigner,
+                )
+                .await
+                .unwrap()
+            };
+            assert_eq!(download("Test Root X1").await, TEST_ROOT_CERT_PEM);
+            assert_eq!(
+                download("Unknown Root").await,
+                crate::utils::tests::SELF_SIGNED_CERT_PEM
+            );
+        };
+        let server_thread = async {
+            let primary_response = |nonce: &str| HttpResponse {
+                status: 200,
+                headers: vec![
+                    ("Replay-Nonce".to_string(), nonce.to_string()),
+                    (
+                        "Link".to_string(),
+                        "<https://acme.server/directory>;rel=index".to_string(),
+                    ),
+                    (
+                        "Link".to_string(),
+                        format!("<{}>;rel=\"alternate\"", ALTERNATE_URLS[0]),
+                    ),
+                    (
+                        "Link".to_string(),
+                        format!("<{}>;rel=\"alternate\"", ALTERNATE_URLS[1]),
+                    ),
+                ],
+                body: crate::utils::tests::SELF_SIGNED_CERT_PEM
+                    .as_bytes()
+                    .to_vec(),
+            };
+            let alternate_response = |nonce: &str, body: &str| HttpResponse {
+                status: 200,
+                headers: vec![("Replay-Nonce".to_string(), nonce.to_string())],
+                body: body.as_bytes().to_vec(),
+            };
+            // The first alternate is not issued by the preferred root, so the
+            // second is downloaded.
+            handle_server_directory(&mut server, "1").await;
+            for (request, response) in [
+                (
+                    certificate_request("1", CERT_URL).await,
+                    primary_response("2"),
+                ),
+                (
+                    certificate_request("2", ALTERNATE_URLS[0]).await,
+                    alternate_response("3", crate::utils::tests::NEAR_EXPIRY_CERT_PEM),
+                ),
+                (
+                    certificate_request("3", ALTERNATE_URLS[1]).await,
+                    alternate_response("4", TEST_ROOT_CERT_PEM),
+                ),
+            ] {
+                server.handle_next_request(request, response).await.unwrap();
+            }
+            // No chain is issued by the preferred root.
+            handle_server_directory(&mut server, "5").await;
+            for (request, response) in [
+                (
+                    certificate_request("5", CERT_URL).await,
+                    primary_response("6"),
+                ),
+                (
+                    certificate_request("6", ALTERNATE_URLS[0]).await,
+                    alternate_response("7", crate::utils::tests::NEAR_EXPIRY_CERT_PEM),
+                ),
+                (
+                    certificate_request("7", ALTERNATE_URLS[1]).await,
+                    alternate_response("8", TEST_ROOT_CERT_PEM),
+                ),
+            ] {
+                server.handle_next_request(request, response).await.unwrap();
+            }
+        };
+        tokio::join!(client_thread, server_thread);
+    }
+    #[tokio::test]
+    async fn manages_account_status() {
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let account = Account {
+            server_directory_url: "https://acme.server/".to_string(),
+            account_url: "https://acme.server/acct/123456".to_string(),
+            domain: "example.com".to_string(),
+            cert_request_der: vec![],
+            public_key_thumbprint: "key_thumbprint".to_string(),
+            key_algorithm: jws::Algorithm::ES256,
+        };
+        let client_thread = async {
+            assert_eq!(
+                get_account_status(&account, &fetcher, &MockSigner)
+                    .await
+                    .unwrap(),
+                AccountStatus::Valid
+            );
+            deactivate_account(&account, &fetcher, &MockSigner)
+                .await
+                .unwrap();
+            assert!(deactivate_account(&account, &fetcher, &MockSigner)
+                .await
+                .is_err());
+        };
+        let server_thread = async {
+            handle_server_directory(&mut server, "1").await;
+            server
+                .handle_next_request(
+                    example_account_request("1").await,
+                    example_account_response("2", None),
+                )
+                .await
+                .unwrap();
+            let deactivate_request = |nonce: &str| {
+                let nonce = nonce.to_string();
+                async move {
+                    HttpRequest {
+                        body: serde_json::to_vec(&JsonWebSignature::new_from_serialized(
+                            &format!(r#"{{"alg":"ES256","nonce":"{}","url":"https://acme.server/acct/123456","jwk":null,"kid":"https://acme.server/acct/123456"}}"#, nonce),
+                            r#"{"status":"deactivated"}"#,
+                            &MockSigner,
+                        ).await.unwrap()).unwrap(),
+                        method: Method::Post,
+                        headers: vec![(
+                            "content-type".to_string(),
+                            "application/jose+json".to_string(),
+                        )],
+                        url: "https://acme.server/acct/123456".to_string(),
+                    }
+                }
+            };
+            let account_response = |nonce: &str, status: &str| HttpResponse {
+                status: 200,
+                headers: vec![("Replay-Nonce".to_string(), nonce.to_string())],
+                body: serde_json::to_vec(&serde_json::json!({ "status": status })).unwrap(),
+            };
+            handle_server_directory(&mut server, "3").await;
+            server
+                .handle_next_request(
+                    deactivate_request("3").await,
+                    account_response("4", "deactivated"),
+                )
+                .await
+                .unwrap();
+            // A server that ignores the deactivation.
+            handle_server_directory(&mut server, "5").await;
+            server
+                .handle_next_request(
+                    deactivate_request("5").await,
+                    account_response("6", "valid"),
+                )
+                .await
+                .unwrap();
+        };
+        tokio::join!(client_thread, server_thread);
+    }
+    #[test]
+    fn validates_validity_period() {
+        let time = |s: &str| Some(DateTime::parse_from_rfc3339(s).unwrap());
+        let validate_validity_period = |not_before, not_after| {
+            let now = time("2022-01-01T00:00:00Z").unwrap();
+            validate_validity_period(not_before, not_after, now.into())
+        };
+        assert!(validate_validity_period(None, None).is_ok());
+        assert!(validate_validity_period(time("2022-01-01T00:00:00Z"), None).is_ok());
+        assert!(validate_validity_period(None, time("2022-04-01T00:00:00Z")).is_ok());
+        // Without a `notBefore`, the period starts now.
+        assert!(validate_validity_period(None, time("2022-04-01T00:00:01Z")).is_err());
+        assert!(validate_validity_period(None, time("2021-12-31T00:00:00Z")).is_err());
+        assert!(validate_validity_period(
+            time("2022-01-01T00:00:00Z"),
+            time("2022-04-01T00:00:00Z")
+        )
+        .is_ok());
+        assert!(validate_validity_period(
+            time("2022-01-01T00:00:00+08:00"),
+            time("2022-04-01T00:00:00+08:00")
+        )
+        .is_ok());
+        assert!(validate_validity_period(
+            time("2022-01-01T00:00:00Z"),
+            time("2022-04-01T00:00:01Z")
+        )
+        .is_err());
+        assert!(validate_validity_period(
+            time("2022-01-01T00:00:00Z"),
+            time("2022-01-01T00:00:00Z")
+        )
+        .is_err());
+        assert!(validate_validity_period(
+            time("2022-04-01T00:00:00Z"),
+            time("2022-01-01T00:00:00Z")
+        )
+        .is_err());
+    }
+    #[test]
+    fn places_order_with_validity_period() {
+        let payload = NewOrderRequestPayload {
+            identifiers: vec![create_identifier("example.com").unwrap()],
+            not_before: Some(DateTime::parse_from_rfc3339("2022-01-01T00:00:00Z").unwrap()),
+            not_after: Some(DateTime::parse_from_rfc3339("2022-04-01T00:00:00Z").unwrap()),
+        };
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            r#"{"identifiers":[{"type":"dns","value":"example.com"}],"notBefore":"2022-01-01T00:00:00Z","notAfter":"2022-04-01T00:00:00Z"}"#
+        );
+    }
+    #[test]
+    fn validates_email() {
+        for email in ["admin@example.com", "first.last+sxg@mail.example.co.uk"] {
+            assert!(validate_email(email).is_ok(), "{}", email);
+        }
+        for email in [
+            "",
+            "admin",
+            "admin@",
+            "@example.com",
+            "admin@localhost",
+            "admin@example..com",
+            "admin@-example.com",
+            "first..last@example.com",
+            "admin @example.com",
+            "admin@example.com,ops@example.com",
+            "\"admin\"@example.com",
+            "admin?subject=hi@example.com",
+        ] {
+            assert!(validate_email(email).is_err(), "{}", email);
+        }
+    }
+    #[test]
+    fn creates_identifiers() {
+        let identifier = |r#type, value: &str| Identifier {
+            r#type,
+            value: value.to_string(),
+        };
+        assert_eq!(
+            create_identifier("example.com").unwrap(),
+            identifier(IdentifierType::Dns, "example.com")
+        );
+        assert_eq!(
+            create_identifier("192.0.2.1").unwrap(),
+            identifier(IdentifierType::Ip, "192.0.2.1")
+        );
+        assert_eq!(
+            create_identifier("2001:DB8:0::1").unwrap(),
+            identifier(IdentifierType::Ip, "2001:db8::1")
+        );
+        for domain in ["192.0.2.256", "192.0.2", "2001:db8::g", "[2001:db8::1]"] {
+            assert!(create_identifier(domain).is_err(), "{}", domain);
+        }
+    }
+    #[tokio::test]
+    async fn challenge_reachable() {
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let request = || HttpRequest {
+            body: vec![],
+            method: Method::Get,
+            headers: vec![],
+            url: "http://example.com/.well-known/acme-challenge/token".to_string(),
+        };
+        let client_thread = async {
+            check_challenge_reachable("example.com", "token", "token.answer", &fetcher)
+                .await
+                .unwrap();
+            let err = check_challenge_reachable("example.com", "token", "token.answer", &fetcher)
+                .await
+                .unwrap_err();
+            assert!(format!("{}", err).contains("challenge not reachable"));
+        };
+        let server_thread = async {
+            server
+                .handle_next_request(
+                    request(),
+                    HttpResponse {
+                        status: 200,
+                        headers: vec![],
+                        body: b"token.answer".to_vec(),
+                    },
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    request(),
+                    HttpResponse {
+                        status: 404,
+                        headers: vec![],
+                        body: b"Not found".to_vec(),
+                    },
+                )
+                .await
+                .unwrap();
+        };
+        tokio::join!(client_thread, server_thread);
+    }
 }
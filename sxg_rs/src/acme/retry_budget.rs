@@ -0,0 +1,144 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A budget of requests and time shared by all the steps of an ACME issuance,
+//! which bounds the worst case regardless of where the retries happen.
+
+use crate::fetcher::Fetcher;
+use crate::http::{HttpRequest, HttpResponse};
+use crate::SxgError;
+use anyhow::Error;
+use async_trait::async_trait;
+use std::cell::Cell;
+use std::time::{Duration, SystemTime};
+
+/// Why a request to the ACME server was not sent. It is found in the chain of
+/// the error returned by the state machine.
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum RetryBudgetExceeded {
+    #[error("The ACME issuance has used up its budget of {0} requests.")]
+    Requests(u32),
+    #[error("The ACME issuance has used up its budget of {0:?}.")]
+    Time(Duration),
+}
+
+/// Counts down the requests to the ACME server, including retries and nonce
+/// fetches, and the time since it was created. Time is measured by the `now`
+/// of the runtime, like the rest of the state machine. Create one per
+/// issuance, and pass it to every update of the state machine.
+pub struct RetryBudget {
+    max_requests: u32,
+    remaining_requests: Cell<u32>,
+    max_duration: Duration,
+    // `None` if the deadline is too far to be represented.
+    deadline: Option<SystemTime>,
+}
+
+impl RetryBudget {
+    pub fn new(max_requests: u32, max_duration: Duration, now: SystemTime) -> Self {
+        RetryBudget {
+            max_requests,
+            remaining_requests: Cell::new(max_requests),
+            max_duration,
+            deadline: now.checked_add(max_duration),
+        }
+    }
+    /// A budget that never runs out.
+    pub fn unlimited() -> Self {
+        Self::new(u32::MAX, Duration::MAX, SystemTime::UNIX_EPOCH)
+    }
+    pub fn remaining_requests(&self) -> u32 {
+        self.remaining_requests.get()
+    }
+    /// Takes one request from the budget, or returns a `RetryBudgetExceeded`
+    /// in an `SxgError::Acme` if the requests or the time have run out.
+    pub fn take_attempt(&self, now: SystemTime) -> Result<(), SxgError> {
+        if matches!(self.deadline, Some(deadline) if now >= deadline) {
+            return Err(SxgError::Acme(Error::new(RetryBudgetExceeded::Time(
+                self.max_duration,
+            ))));
+        }
+        match self.remaining_requests.get().checked_sub(1) {
+            Some(remaining) => {
+                self.remaining_requests.set(remaining);
+                Ok(())
+            }
+            None => Err(SxgError::Acme(Error::new(RetryBudgetExceeded::Requests(
+                self.max_requests,
+            )))),
+        }
+    }
+    /// Wraps `fetcher`, so that each request takes an attempt from this
+    /// budget at `now` before it is sent.
+    pub fn fetcher<'a>(&'a self, fetcher: &'a dyn Fetcher, now: SystemTime) -> impl Fetcher + 'a {
+        BudgetedFetcher {
+            budget: self,
+            fetcher,
+            now,
+        }
+    }
+}
+
+struct BudgetedFetcher<'a> {
+    budget: &'a RetryBudget,
+    fetcher: &'a dyn Fetcher,
+    now: SystemTime,
+}
+
+#[async_trait(?Send)]
+impl<'a> Fetcher for BudgetedFetcher<'a> {
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, SxgError> {
+        self.budget.take_attempt(self.now)?;
+        self.fetcher.fetch(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetcher::NULL_FETCHER;
+    #[tokio::test]
+    async fn counts_down_requests() {
+        let budget = RetryBudget::new(2, Duration::from_secs(60), SystemTime::UNIX_EPOCH);
+        let fetcher = budget.fetcher(&NULL_FETCHER, SystemTime::UNIX_EPOCH);
+        for remaining in [1, 0] {
+            // `NULL_FETCHER` fails every request, which still takes an attempt.
+            let err = fetcher.get("https://acme.server/").await.unwrap_err();
+            assert!(matches!(err, SxgError::Network(_)));
+            assert_eq!(budget.remaining_requests(), remaining);
+        }
+        let err = fetcher.get("https://acme.server/").await.unwrap_err();
+        assert_eq!(
+            err.inner().downcast_ref::<RetryBudgetExceeded>(),
+            Some(&RetryBudgetExceeded::Requests(2))
+        );
+    }
+    #[test]
+    fn runs_out_of_time() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let budget = RetryBudget::new(2, Duration::from_secs(60), start);
+        assert!(budget.take_attempt(start + Duration::from_secs(59)).is_ok());
+        let err = budget
+            .take_attempt(start + Duration::from_secs(60))
+            .unwrap_err();
+        assert_eq!(
+            err.inner().downcast_ref::<RetryBudgetExceeded>(),
+            Some(&RetryBudgetExceeded::Time(Duration::from_secs(60)))
+        );
+        assert_eq!(budget.remaining_requests(), 1);
+        assert!(RetryBudget::unlimited()
+            .take_attempt(start + Duration::from_secs(60))
+            .is_ok());
+    }
+}
@@ -12,17 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Account, OngoingOrder};
+use super::directory::Status;
+use super::retry_budget::RetryBudget;
+use super::{Account, OngoingOrder, OrderOptions};
 use crate::crypto::CertificateChain;
 use crate::fetcher::Fetcher;
-use crate::runtime::Runtime;
+use crate::runtime::{store::Store, Runtime};
 use crate::signature::Signer;
 use crate::utils::console_log;
-use anyhow::Result;
+use crate::SxgError;
+use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, SystemTime};
 
 const ACME_STORAGE_KEY: &str = "ACME";
+// The prefix of the storage keys of HTTP-01 challenge answers, which are keyed
+// by token, so that any instance sharing the storage can answer the challenge.
+const CHALLENGE_STORAGE_KEY_PREFIX: &str = "ACME_CHALLENGE_";
+// ACME servers usually expire pending authorizations within a week.
+const CHALLENGE_ANSWER_TTL: Duration = Duration::from_secs(3600 * 24 * 7);
 
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
 pub struct AcmeStorageData {
@@ -30,6 +38,19 @@ pub struct AcmeStorageData {
     task: Option<Task>,
 }
 
+impl AcmeStorageData {
+    /// Returns the next step of the ongoing ACME order, or `None` if there is
+    /// no ongoing order.
+    pub fn next_step(&self) -> Option<&TaskStep> {
+        self.task.as_ref().map(|task| &task.schedule.next_step)
+    }
+    /// Returns the URL of the ongoing ACME order, or `None` if there is no
+    /// ongoing order.
+    pub fn order_url(&self) -> Option<&str> {
+        self.task.as_ref().map(|task| task.order.order_url.as_str())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Task {
     order: OngoingOrder,
@@ -55,8 +76,9 @@ impl Schedule {
     }
 }
 
+/// The next step of an ongoing ACME order.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
-enum TaskStep {
+pub enum TaskStep {
     RequestChallengeValidation,
     CheckChallengeFinished,
     FinalizeSigningRequest,
@@ -70,8 +92,8 @@ enum TaskStep {
 /// be parsed.
 /// We don't throw parsing error, because the parsing errors are likely to happen
 /// when a new version of `sxg-rs` changes the definition of `AcmeStorageData`.
-pub async fn read_current_state(runtime: &Runtime) -> Result<AcmeStorageData> {
-    match runtime.storage.read(ACME_STORAGE_KEY).await {
+pub async fn read_current_state(runtime: &Runtime) -> Result<AcmeStorageData, SxgError> {
+    match runtime.storage.get(ACME_STORAGE_KEY).await {
         Ok(Some(value)) => match serde_json::from_str(&value) {
             Ok(state) => Ok(state),
             Err(e) => {
@@ -83,29 +105,75 @@ pub async fn read_current_state(runtime: &Runtime) -> Result<AcmeStorageData> {
             console_log("No ACME state in storage");
             Ok(Default::default())
         }
-        Err(e) => Err(e.context("Failed to read ACME state in storage")),
+        Err(e) => Err(SxgError::Acme(
+            Error::new(e).context("Failed to read ACME state in storage"),
+        )),
     }
 }
 
 async fn write_state(runtime: &Runtime, state: &AcmeStorageData) -> Result<()> {
     let value = serde_json::to_string(state)?;
-    runtime.storage.write(ACME_STORAGE_KEY, &value).await?;
+    runtime.storage.put(ACME_STORAGE_KEY, &value).await?;
+    Ok(())
+}
+
+fn challenge_token(state: &AcmeStorageData) -> Option<&str> {
+    state
+        .task
+        .as_ref()
+        .map(|task| task.order.challenge_token.as_str())
+}
+
+// Writes the challenge answer of a new order into storage, keyed by its token,
+// and deletes the answer of a finished or replaced order.
+async fn write_challenge_answer(
+    runtime: &Runtime,
+    old_state: &AcmeStorageData,
+    new_state: &AcmeStorageData,
+) -> Result<()> {
+    let old_token = challenge_token(old_state);
+    let new_token = challenge_token(new_state);
+    if old_token == new_token {
+        return Ok(());
+    }
+    if let Some(task) = &new_state.task {
+        runtime
+            .storage
+            .put_with_ttl(
+                &challenge_storage_key(&task.order.challenge_token),
+                &task.order.challenge_answer,
+                CHALLENGE_ANSWER_TTL,
+            )
+            .await?;
+    }
+    if let Some(old_token) = old_token {
+        runtime
+            .storage
+            .delete(&challenge_storage_key(old_token))
+            .await?;
+    }
     Ok(())
 }
 
+fn challenge_storage_key(token: &str) -> String {
+    format!("{}{}", CHALLENGE_STORAGE_KEY_PREFIX, token)
+}
+
+/// How long before the expiration of the certificate a new one is requested.
+pub const RENEW_BEFORE_EXPIRATION: Duration = Duration::from_secs(3600 * 24 * 10);
+
 const MIN_SLEEP: Duration = Duration::from_secs(59);
 const MAX_SLEEP: Duration = Duration::from_secs(600);
 
 // Parses the certificate chain PEM, and returns the expiration time of the first certificate.
 fn get_certificate_expiration_time(certificate_pem: &str) -> Result<SystemTime> {
     let certificate_chain = CertificateChain::from_pem_files(&[certificate_pem])?;
-    let x509_cert = x509_parser::parse_x509_certificate(&certificate_chain.end_entity.der)?;
-    let timestamp = x509_cert.1.tbs_certificate.validity.not_after.timestamp();
-    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp as u64))
+    Ok(certificate_chain.end_entity.not_after()?)
 }
 
 async fn update_state_impl(
     account: &Account,
+    options: &OrderOptions,
     state: &mut AcmeStorageData,
     now: SystemTime,
     fetcher: &dyn Fetcher,
@@ -113,8 +181,7 @@ async fn update_state_impl(
 ) -> Result<()> {
     if let Some(certificate_pem) = state.certificates.last() {
         let expiration = get_certificate_expiration_time(certificate_pem)?;
-        const TEN_DAYS: Duration = Duration::from_secs(3600 * 24 * 10);
-        if now + TEN_DAYS < expiration {
+        if now + RENEW_BEFORE_EXPIRATION < expiration {
             // There is already a certificate, and it is far from expiration,
             // so we do nothing.
             return Ok(());
@@ -172,6 +239,7 @@ async fn update_state_impl(
                     task.order.certificate_url = Some(certificate_url);
                     let certificate_pem = super::download_certificate(
                         account,
+                        options.preferred_chain.as_deref(),
                         task.order.certificate_url.clone().unwrap(),
                         fetcher,
                         acme_signer,
@@ -185,34 +253,78 @@ async fn update_state_impl(
             }
         }
     } else {
-        let order = super::place_new_order(account, fetcher, acme_signer).await?;
+        let existing_order = if options.always_place_new_order {
+            None
+        } else {
+            super::find_existing_order(account, fetcher, acme_signer).await?
+        };
+        let (order, next_step) = match existing_order {
+            Some((order, status)) => {
+                let next_step = match status {
+                    Status::Ready => TaskStep::FinalizeSigningRequest,
+                    Status::Processing => TaskStep::DownloadCertificate,
+                    _ => TaskStep::RequestChallengeValidation,
+                };
+                (order, next_step)
+            }
+            None => {
+                options.validate(now)?;
+                let order = super::place_new_order(account, options, fetcher, acme_signer).await?;
+                (order, TaskStep::RequestChallengeValidation)
+            }
+        };
         state.task = Some(Task {
             order,
             schedule: Schedule {
                 updated_at: now,
                 wait_time: MIN_SLEEP,
-                next_step: TaskStep::RequestChallengeValidation,
+                next_step,
             },
         });
     }
     Ok(())
 }
 
-pub async fn update_state(runtime: &Runtime, account: &Account) -> Result<()> {
+pub async fn update_state(runtime: &Runtime, account: &Account) -> Result<(), SxgError> {
+    update_state_with_budget(
+        runtime,
+        account,
+        &OrderOptions::default(),
+        &RetryBudget::unlimited(),
+    )
+    .await
+}
+
+/// Like `update_state`, but orders certificates with `options`, and each
+/// request to the ACME server takes an attempt from `budget`, which is shared
+/// by all the updates of an issuance. Once it runs out, the update fails with
+/// the error of `RetryBudget::take_attempt` in its chain.
+pub async fn update_state_with_budget(
+    runtime: &Runtime,
+    account: &Account,
+    options: &OrderOptions,
+    budget: &RetryBudget,
+) -> Result<(), SxgError> {
     let mut old_state = read_current_state(runtime).await?;
     let mut new_state = old_state.clone();
     let result = update_state_impl(
         account,
+        options,
         &mut new_state,
         runtime.now,
-        runtime.fetcher.as_ref(),
+        &budget.fetcher(runtime.fetcher.as_ref(), runtime.now),
         runtime.acme_signer.as_ref(),
     )
     .await;
     match result {
         Ok(()) => {
             if old_state != new_state {
-                write_state(runtime, &new_state).await?;
+                write_challenge_answer(runtime, &old_state, &new_state)
+                    .await
+                    .map_err(SxgError::Acme)?;
+                write_state(runtime, &new_state)
+                    .await
+                    .map_err(SxgError::Acme)?;
             }
             Ok(())
         }
@@ -221,14 +333,18 @@ pub async fn update_state(runtime: &Runtime, account: &Account) -> Result<()> {
             // and double the wait time for next update.
             if let Some(task) = &mut old_state.task {
                 task.schedule.double_wait(runtime.now);
-                write_state(runtime, &old_state).await?;
+                write_state(runtime, &old_state)
+                    .await
+                    .map_err(SxgError::Acme)?;
             }
-            Err(e)
+            Err(SxgError::Acme(e))
         }
     }
 }
 
-pub async fn get_challenge_token_and_answer(runtime: &Runtime) -> Result<Option<(String, String)>> {
+pub async fn get_challenge_token_and_answer(
+    runtime: &Runtime,
+) -> Result<Option<(String, String)>, SxgError> {
     let state = read_current_state(runtime).await?;
     if let Some(task) = state.task {
         Ok(Some((
@@ -240,18 +356,39 @@ pub async fn get_challenge_token_and_answer(runtime: &Runtime) -> Result<Option<
     }
 }
 
+/// Returns the answer to the HTTP-01 challenge of `token`, which any instance
+/// sharing `storage` with the one running the state machine can serve. Returns
+/// `None` if there is no ongoing order with this token.
+pub async fn get_challenge_answer(
+    storage: &dyn Store,
+    token: &str,
+) -> Result<Option<String>, SxgError> {
+    // Tokens are base64url, so other characters can't address another key.
+    let is_base64url = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if token.is_empty() || !token.chars().all(is_base64url) {
+        return Ok(None);
+    }
+    storage
+        .get(&challenge_storage_key(token))
+        .await
+        .map_err(|e| SxgError::Acme(Error::new(e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::tests::{
-        example_approved_authorization_response, example_approved_order_response,
-        example_authorization_request, example_certificate_request, example_certificate_response,
-        example_challenge_request, example_finalize_request, example_new_order_request,
-        example_new_order_response, example_order_request, example_pending_authorization_response,
-        example_pending_challenge_response, example_pending_finalize_response,
-        example_pending_order_response, handle_server_directory,
+        example_account_request, example_account_response, example_approved_authorization_response,
+        example_approved_order_response, example_authorization_request,
+        example_certificate_request, example_certificate_response, example_challenge_request,
+        example_finalize_request, example_new_order_request, example_new_order_response,
+        example_order_list_request, example_order_list_response, example_order_request,
+        example_pending_authorization_response, example_pending_challenge_response,
+        example_pending_finalize_response, example_pending_order_response,
+        example_ready_order_response, handle_server_directory,
     };
     use super::*;
-    use crate::storage::{InMemoryStorage, Storage};
+    use crate::acme::retry_budget::RetryBudgetExceeded;
+    use crate::runtime::store::{InMemoryStore, Store};
     use std::time::UNIX_EPOCH;
     const ACCOUNT: &str = r#"{
         "serverDirectoryUrl": "https://acme.server/",
@@ -262,7 +399,7 @@ mod tests {
     }"#;
     #[tokio::test]
     async fn new_storage_returns_default_state() {
-        let storage = InMemoryStorage::new();
+        let storage = InMemoryStore::new(UNIX_EPOCH);
         let runtime = Runtime {
             storage: Box::new(storage),
             ..Default::default()
@@ -273,8 +410,8 @@ mod tests {
     }
     #[tokio::test]
     async fn storage_with_invalid_schema_returns_default_state() {
-        let storage = InMemoryStorage::new();
-        storage.write(ACME_STORAGE_KEY, "asdf").await.unwrap();
+        let storage = InMemoryStore::new(UNIX_EPOCH);
+        storage.put(ACME_STORAGE_KEY, "asdf").await.unwrap();
         let runtime = Runtime {
             storage: Box::new(storage),
             ..Default::default()
@@ -290,6 +427,14 @@ mod tests {
         let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
         let server_thread = async {
             handle_server_directory(&mut server, "1").await;
+            server
+                .handle_next_request(
+                    example_account_request("1").await,
+                    example_account_response("2", None),
+                )
+                .await
+                .unwrap();
+            handle_server_directory(&mut server, "1").await;
             server
                 .handle_next_request(
                     example_new_order_request("1").await,
@@ -306,7 +451,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH));
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH,
@@ -325,6 +470,28 @@ mod tests {
                     "0HORFRxrqEtAB-vUh9iSnFBHE66qWX4bbU1SBWxOr5o.key_thumbprint".to_string()
                 ),
             );
+            assert_eq!(
+                get_challenge_answer(
+                    runtime.storage.as_ref(),
+                    "0HORFRxrqEtAB-vUh9iSnFBHE66qWX4bbU1SBWxOr5o"
+                )
+                .await
+                .unwrap()
+                .unwrap(),
+                "0HORFRxrqEtAB-vUh9iSnFBHE66qWX4bbU1SBWxOr5o.key_thumbprint"
+            );
+            assert_eq!(
+                get_challenge_answer(runtime.storage.as_ref(), "other_token")
+                    .await
+                    .unwrap(),
+                None
+            );
+            assert_eq!(
+                get_challenge_answer(runtime.storage.as_ref(), "../ACME")
+                    .await
+                    .unwrap(),
+                None
+            );
             assert_eq!(
                 read_current_state(&runtime)
                     .await
@@ -341,6 +508,65 @@ mod tests {
         };
         tokio::join!(client_thread, server_thread);
     }
+    // When the account already has an unfinished order for the domain, the state
+    // machine continues that order instead of placing a new one.
+    #[tokio::test]
+    async fn reuse_existing_order() {
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let server_thread = async {
+            handle_server_directory(&mut server, "1").await;
+            server
+                .handle_next_request(
+                    example_account_request("1").await,
+                    example_account_response("2", Some("https://acme.server/acct/123456/orders")),
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    example_order_list_request("2").await,
+                    example_order_list_response("3"),
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    example_order_request("3").await,
+                    example_ready_order_response("4"),
+                )
+                .await
+                .unwrap();
+            server
+                .handle_next_request(
+                    example_authorization_request("4").await,
+                    example_approved_authorization_response("5"),
+                )
+                .await
+                .unwrap();
+        };
+        let client_thread = async {
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH));
+            let runtime = Runtime {
+                storage,
+                now: UNIX_EPOCH,
+                fetcher: Box::new(fetcher),
+                ..Default::default()
+            };
+            let account: Account = serde_json::from_str(ACCOUNT).unwrap();
+            update_state(&runtime, &account).await.unwrap();
+            let task = read_current_state(&runtime).await.unwrap().task.unwrap();
+            assert_eq!(task.order.order_url, "https://acme.server/order/46540038");
+            assert_eq!(
+                task.schedule,
+                Schedule {
+                    updated_at: UNIX_EPOCH,
+                    wait_time: Duration::from_secs(59),
+                    next_step: TaskStep::FinalizeSigningRequest,
+                }
+            );
+        };
+        tokio::join!(client_thread, server_thread);
+    }
     // When staring with `RequestChallengeValidation`, while not having waited long enough,
     // the state machine does nothing.
     #[tokio::test]
@@ -348,7 +574,7 @@ mod tests {
         let (fetcher, _server) = crate::fetcher::mock_fetcher::create();
         let server_thread = async {};
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(49)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -360,7 +586,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(49),
@@ -402,7 +628,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(61)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -414,7 +640,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(61),
@@ -457,7 +683,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(61)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -469,7 +695,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(61),
@@ -511,7 +737,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(61)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -523,7 +749,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(61),
@@ -565,7 +791,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(61)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -577,7 +803,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(61),
@@ -620,7 +846,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(61)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -632,7 +858,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(61),
@@ -683,7 +909,7 @@ mod tests {
                 .unwrap();
         };
         let client_thread = async {
-            let storage = Box::new(InMemoryStorage::new());
+            let storage = Box::new(InMemoryStore::new(UNIX_EPOCH + Duration::from_secs(61)));
             const VALUE: &str = r#"{
                 "certificates": [],
                 "task": {
@@ -695,7 +921,7 @@ mod tests {
                     }
                 }
             }"#;
-            storage.write(ACME_STORAGE_KEY, VALUE).await.unwrap();
+            storage.put(ACME_STORAGE_KEY, VALUE).await.unwrap();
             let runtime = Runtime {
                 storage,
                 now: UNIX_EPOCH + Duration::from_secs(61),
@@ -710,4 +936,27 @@ mod tests {
         };
         tokio::join!(client_thread, server_thread);
     }
+    #[tokio::test]
+    async fn stops_when_budget_is_exceeded() {
+        let runtime = Runtime {
+            storage: Box::new(InMemoryStore::new(UNIX_EPOCH)),
+            ..Default::default()
+        };
+        let account: Account = serde_json::from_str(ACCOUNT).unwrap();
+        let budget = RetryBudget::new(0, Duration::from_secs(60), runtime.now);
+        let err = update_state_with_budget(&runtime, &account, &Default::default(), &budget)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SxgError::Acme(_)));
+        let exceeded = err
+            .inner()
+            .chain()
+            .filter_map(|e| e.downcast_ref::<SxgError>())
+            .find_map(|e| e.inner().downcast_ref::<RetryBudgetExceeded>());
+        assert_eq!(exceeded, Some(&RetryBudgetExceeded::Requests(0)));
+        assert_eq!(
+            read_current_state(&runtime).await.unwrap(),
+            Default::default()
+        );
+    }
 }
@@ -0,0 +1,105 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The `AMP-Cache-Transform` header is defined in
+// https://github.com/ampproject/amphtml/blob/main/docs/spec/amp-cache-transform.md
+
+use crate::http::HttpResponse;
+use crate::http_parser::parse_amp_cache_transform_header;
+use std::ops::RangeInclusive;
+
+pub const AMP_CACHE_TRANSFORM: &str = "amp-cache-transform";
+
+// The AMP caches whose transforms we can satisfy. `any` means the requestor
+// accepts a signed exchange that is valid for any AMP cache.
+const SUPPORTED_IDENTIFIERS: &[&str] = &["any", "google"];
+
+// The versions of the AMP transforms that the signed documents conform to.
+const SUPPORTED_VERSIONS: RangeInclusive<u32> = 1..=1;
+
+/// Selects a transform from the `AMP-Cache-Transform` request header, and
+/// returns the value of the matching response header, such as
+/// `google;v="1"`. Returns `None` if the header is malformed, or if none of
+/// its members can be satisfied, in which case the signed exchange should not
+/// be served to the AMP cache.
+pub fn negotiate(request_header: &str) -> Option<String> {
+    let transforms = parse_amp_cache_transform_header(request_header.trim()).ok()?;
+    transforms.iter().find_map(|transform| {
+        if !SUPPORTED_IDENTIFIERS.contains(&transform.identifier) {
+            return None;
+        }
+        let requested_versions = match transform.parameters.iter().find(|p| p.name == "v") {
+            Some(param) => parse_version_ranges(&param.value)?,
+            // Without a `v` parameter, only version 1 is acceptable.
+            None => vec![1..=1],
+        };
+        let version = SUPPORTED_VERSIONS
+            .rev()
+            .find(|v| requested_versions.iter().any(|range| range.contains(v)))?;
+        Some(format!("{};v=\"{}\"", transform.identifier, version))
+    })
+}
+
+// Parses a list of versions like `1..3,5`.
+fn parse_version_ranges(input: &str) -> Option<Vec<RangeInclusive<u32>>> {
+    input
+        .split(',')
+        .map(|range| {
+            let range = range.trim();
+            if let Some((start, end)) = range.split_once("..") {
+                Some(start.parse().ok()?..=end.parse().ok()?)
+            } else {
+                let version = range.parse().ok()?;
+                Some(version..=version)
+            }
+        })
+        .collect()
+}
+
+/// Sets the `AMP-Cache-Transform` header on the response that carries the
+/// signed exchange.
+pub fn set_response_header(response: &mut HttpResponse, amp_cache_transform: String) {
+    response
+        .headers
+        .retain(|(name, _)| !name.eq_ignore_ascii_case(AMP_CACHE_TRANSFORM));
+    response
+        .headers
+        .push((AMP_CACHE_TRANSFORM.to_string(), amp_cache_transform));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn negotiates_transform() {
+        assert_eq!(negotiate("google"), Some(r#"google;v="1""#.to_string()));
+        assert_eq!(negotiate("any"), Some(r#"any;v="1""#.to_string()));
+        assert_eq!(
+            negotiate(r#"google;v="1..5""#),
+            Some(r#"google;v="1""#.to_string())
+        );
+        assert_eq!(
+            negotiate(r#"google;v="3,1""#),
+            Some(r#"google;v="1""#.to_string())
+        );
+        assert_eq!(
+            negotiate(r#"other, any;v="1""#),
+            Some(r#"any;v="1""#.to_string())
+        );
+        assert_eq!(negotiate(r#"google;v="2..4""#), None);
+        assert_eq!(negotiate("other"), None);
+        assert_eq!(negotiate(r#"google;v="x""#), None);
+        assert_eq!(negotiate("google;"), None);
+    }
+}
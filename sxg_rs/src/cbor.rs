@@ -14,10 +14,17 @@
 
 // https://tools.ietf.org/html/rfc7049
 
+use anyhow::{anyhow, Result};
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
+// The maximum nesting of arrays and maps accepted by `DataItem::parse`. The
+// formats parsed by this crate nest at most twice, and the limit prevents
+// malformed input from overflowing the stack.
+const MAX_NESTING_DEPTH: usize = 16;
+
+#[derive(Debug, Eq, PartialEq)]
 pub enum DataItem<'a> {
-    #[allow(dead_code)]
     UnsignedInteger(u64),
     ByteString(&'a [u8]),
     TextString(&'a str),
@@ -31,6 +38,63 @@ impl<'a> DataItem<'a> {
         self.append_binary_to(&mut result);
         result
     }
+    /// Parses a data item which spans the whole `input`. Only the types of
+    /// `DataItem` with definite lengths are supported.
+    pub fn parse(input: &'a [u8]) -> Result<Self> {
+        let (item, rest) = Self::parse_prefix(input, 0)?;
+        if rest.is_empty() {
+            Ok(item)
+        } else {
+            Err(anyhow!(
+                "{} bytes remain after the CBOR data item",
+                rest.len()
+            ))
+        }
+    }
+    fn parse_prefix(input: &'a [u8], depth: usize) -> Result<(Self, &'a [u8])> {
+        use DataItem::*;
+        if depth > MAX_NESTING_DEPTH {
+            return Err(anyhow!(
+                "CBOR is nested deeper than {} levels",
+                MAX_NESTING_DEPTH
+            ));
+        }
+        let (&initial_byte, input) = input
+            .split_first()
+            .ok_or_else(|| anyhow!("Unexpected end of CBOR"))?;
+        let (argument, mut input) = read_argument(initial_byte & 0x1f, input)?;
+        match initial_byte >> 5 {
+            0 => Ok((UnsignedInteger(argument), input)),
+            2 => {
+                let (bytes, input) = split_at(input, argument)?;
+                Ok((ByteString(bytes), input))
+            }
+            3 => {
+                let (bytes, input) = split_at(input, argument)?;
+                Ok((TextString(std::str::from_utf8(bytes)?), input))
+            }
+            4 => {
+                let mut items = vec![];
+                for _ in 0..argument {
+                    let (item, rest) = Self::parse_prefix(input, depth + 1)?;
+                    items.push(item);
+                    input = rest;
+                }
+                Ok((Array(items), input))
+            }
+            5 => {
+                let mut fields = vec![];
+                for _ in 0..argument {
+                    let (key, rest) = Self::parse_prefix(input, depth + 1)?;
+                    let (value, rest) = Self::parse_prefix(rest, depth + 1)?;
+                    fields.push((key, value));
+                    input = rest;
+                }
+                Ok((Map(fields), input))
+            }
+            major_type => Err(anyhow!("CBOR major type {} is not supported", major_type)),
+        }
+    }
     fn append_binary_to(&self, output: &mut Vec<u8>) {
         use DataItem::*;
         match self {
@@ -50,12 +114,17 @@ impl<'a> DataItem<'a> {
                 }
             }
             Map(fields) => {
-                let mut map = BTreeMap::<Vec<u8>, Vec<u8>>::new();
+                // Keys are sorted canonically, as in Section 3.9 of RFC 7049:
+                // shorter encoded keys first, then bytewise. SXG requires this
+                // ordering, and it makes the encoding independent of the
+                // order of `fields`.
+                let mut map = BTreeMap::<(usize, Vec<u8>), Vec<u8>>::new();
                 for (key, value) in fields {
-                    map.insert(key.serialize(), value.serialize());
+                    let key = key.serialize();
+                    map.insert((key.len(), key), value.serialize());
                 }
                 append_integer(output, 5, map.len() as u64);
-                for (mut key, mut value) in map.into_iter() {
+                for ((_, mut key), mut value) in map.into_iter() {
                     output.append(&mut key);
                     output.append(&mut value);
                 }
@@ -64,6 +133,31 @@ impl<'a> DataItem<'a> {
     }
 }
 
+// Reads the argument encoded by the low 5 bits of the initial byte and the
+// following bytes.
+fn read_argument(additional_info: u8, input: &[u8]) -> Result<(u64, &[u8])> {
+    let size = match additional_info {
+        0..=23 => return Ok((additional_info as u64, input)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(anyhow!("Indefinite lengths are not supported")),
+    };
+    let (bytes, input) = split_at(input, size)?;
+    let argument = bytes
+        .iter()
+        .fold(0u64, |argument, &byte| (argument << 8) | byte as u64);
+    Ok((argument, input))
+}
+
+fn split_at(input: &[u8], len: u64) -> Result<(&[u8], &[u8])> {
+    match usize::try_from(len) {
+        Ok(len) if len <= input.len() => Ok(input.split_at(len)),
+        _ => Err(anyhow!("Unexpected end of CBOR")),
+    }
+}
+
 fn append_integer(output: &mut Vec<u8>, major_type: u8, data: u64) {
     let major_type = major_type << 5;
     match data {
@@ -139,6 +233,63 @@ mod tests {
         );
     }
     #[test]
+    fn sorts_map_keys_canonically() {
+        use DataItem::*;
+        let fields = || {
+            vec![
+                (TextString("bb"), UnsignedInteger(1)),
+                (UnsignedInteger(1000), UnsignedInteger(2)),
+                (TextString("a"), UnsignedInteger(3)),
+                (ByteString(b"a"), UnsignedInteger(4)),
+                (UnsignedInteger(10), UnsignedInteger(5)),
+            ]
+        };
+        let expected = from_hex(concat!(
+            "a5",       // map(5)
+            "0a05",     // 10: 5
+            "416104",   // h'61': 4
+            "616103",   // "a": 3
+            "1903e802", // 1000: 2
+            "62626201", // "bb": 1
+        ));
+        assert_eq!(Map(fields()).serialize(), expected);
+        let mut reversed = fields();
+        reversed.reverse();
+        assert_eq!(Map(reversed).serialize(), expected);
+    }
+    #[test]
+    fn parses() {
+        use DataItem::*;
+        assert_eq!(
+            DataItem::parse(&from_hex("1b000000e8d4a51000")).unwrap(),
+            UnsignedInteger(1000000000000)
+        );
+        assert_eq!(
+            DataItem::parse(&from_hex("4401020304")).unwrap(),
+            ByteString(&[1, 2, 3, 4])
+        );
+        let item = Array(vec![
+            TextString("IETF"),
+            Map(vec![(UnsignedInteger(1), ByteString(&[2]))]),
+        ]);
+        assert_eq!(DataItem::parse(&item.serialize()).unwrap(), item);
+        // Truncated byte string.
+        assert!(DataItem::parse(&from_hex("44010203")).is_err());
+        // Trailing bytes.
+        assert!(DataItem::parse(&from_hex("0000")).is_err());
+        // Indefinite-length array.
+        assert!(DataItem::parse(&from_hex("9fff")).is_err());
+    }
+    // Without the depth limit, this input overflows the stack.
+    #[test]
+    fn rejects_deep_nesting() {
+        let input = include_bytes!("../fuzz/regressions/cert_chain/deep_nesting");
+        assert!(DataItem::parse(input).is_err());
+        let mut input = vec![0x81; MAX_NESTING_DEPTH];
+        input.push(0x00);
+        assert!(DataItem::parse(&input).is_ok());
+    }
+    #[test]
     fn map_keys_are_sorted() {
         use DataItem::*;
         assert_eq!(
@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::Result;
+use crate::headers::ResponseHeaderFilter;
+use crate::utils::{console_log, decode_text_file, trim_text_file};
+use crate::SxgError;
+use anyhow::{anyhow, Error, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 
@@ -20,33 +23,582 @@ use std::collections::BTreeSet;
 // a file (like `config.yaml`) to provide this config input.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
+    /// If true, `x-content-type-options: nosniff` is added to the signed
+    /// response headers unless the origin sets that header, as the Google
+    /// SXG cache requires. The outer response always carries it. Defaults to
+    /// true.
+    #[serde(default = "default_true")]
+    pub add_nosniff_header: bool,
+    /// If true, responses from the worker carry an `x-sxg` header describing
+    /// the outcome of signing, such as `signed` or `passthrough-uncacheable`.
+    /// This is meant for debugging why a page isn't served as SXG.
+    #[serde(default)]
+    pub add_sxg_status_header: bool,
+    /// If true, the worker still signs when the certificate has no OCSP
+    /// responder or the responder can't be reached, and omits the `ocsp`
+    /// entry from the cert-chain. Browsers reject such a
+    /// cert-chain, so this is only for development with certificates that
+    /// have no OCSP responder.
+    #[serde(default)]
+    pub allow_missing_ocsp: bool,
+    /// If true, signed exchanges are created even when the host of the
+    /// fallback URL is not covered by the `subjectAltName` of the latest
+    /// certificate. Browsers reject such signed exchanges, so this is only
+    /// for development with mismatched certificates.
+    #[serde(default)]
+    pub allow_uncovered_hosts: bool,
+    /// If true, `Config::lint` does not warn about a `reserved_path` that
+    /// doesn't start with a dot, such as when the site intentionally serves
+    /// signed exchanges under an unusual path.
+    #[serde(default)]
+    pub allow_unusual_reserved_path: bool,
+    /// The origins, such as `https://cdn.example.com`, of cross-origin
+    /// subresources that may be preloaded from a signed exchange. Preloads of
+    /// any other cross-origin URL are dropped, so that the signature never
+    /// vouches for third-party content. Same-origin subresources are always
+    /// allowed. Defaults to empty.
+    #[serde(default)]
+    pub allowed_subresource_origins: BTreeSet<String>,
+    /// Request headers forwarded to the origin in addition to
+    /// `forward_request_headers`, because many origins need them to render
+    /// correctly. Defaults to `host`. To stop forwarding one of them, list it
+    /// in `strip_request_headers`.
+    #[serde(default = "default_always_forward_request_headers")]
+    pub always_forward_request_headers: BTreeSet<String>,
+    /// If true, signed exchanges are served to AMP caches that negotiate a
+    /// transform with the `AMP-Cache-Transform` request header.
+    #[serde(default)]
+    pub amp_support: bool,
+    /// If set, the cert-chain is served with `cache-control: max-age` of this
+    /// many seconds, clamped to the time until the `nextUpdate` of the
+    /// stapled OCSP response, so that validators don't reuse a stale staple.
+    pub cert_chain_max_age: Option<u64>,
+    /// The directory the certificate chains are served under. Defaults to
+    /// `reserved_path`.
+    #[serde(default)]
     pub cert_url_dirname: String,
+    /// How `cert-url` is written in the `Signature` header.
+    #[serde(default)]
+    pub cert_url_form: UrlForm,
+    /// If true, signing fails when the signature would outlive the
+    /// certificate. By default, the signature expiry is clamped to the
+    /// certificate's `notAfter` with a warning.
+    #[serde(default)]
+    pub error_on_expiry_clamp: bool,
     pub forward_request_headers: BTreeSet<String>,
     pub html_host: String,
+    /// Link directives added to the `Link` header of every signed exchange.
+    /// Each `preload` is fetched to compute its `header-integrity`, and is
+    /// dropped if that fails, because the browser only uses a preload from a
+    /// signed exchange with a matching `allowed-alt-sxg`.
+    #[serde(default)]
+    pub link_hints: Vec<LinkHint>,
+    /// The largest origin response body that `SxgWorker::fetch_origin`
+    /// accepts. Larger responses are passed through unsigned. Defaults to
+    /// 8000000, which is also the largest payload that can be signed.
+    #[serde(default = "default_max_origin_body_bytes")]
+    pub max_origin_body_bytes: usize,
+    /// How long past the `nextUpdate` of the OCSP response the worker still
+    /// signs. Once this has passed, responses are served unsigned until a
+    /// fresh OCSP response is fetched. Defaults to 0.
+    #[serde(default)]
+    pub ocsp_grace_period_seconds: u64,
+    /// How long `SxgWorker::fetch_origin` waits for the origin response,
+    /// after which the response is passed through unsigned. By default, it
+    /// waits as long as the fetcher does.
+    pub origin_fetch_timeout_seconds: Option<u64>,
     // This field is only needed by Fastly, because Cloudflare uses secret
     // env variables to store private key.
     // TODO: check if Fastly edge dictionary is ok to store private key.
+    /// The 32-byte P-256 private key, in either standard or URL-safe base64,
+    /// with or without padding.
     pub private_key_base64: Option<String>,
+    /// If set, only requests carrying this header are signed, such as to roll
+    /// out signed exchanges to an experiment group tagged by the CDN. If the
+    /// value is also set, the header must equal it. Other requests are passed
+    /// through unsigned. For example, `["x-sxg-experiment", "on"]`.
+    pub require_request_header: Option<(String, Option<String>)>,
     pub reserved_path: String,
+    /// Whether `signed_response_headers` or `strip_response_headers` decides
+    /// which origin response headers are signed. Defaults to `denylist`.
+    #[serde(default)]
+    pub response_header_mode: ResponseHeaderMode,
+    /// If true, the worker serves diagnostic JSON at `<reserved_path>debug.json`,
+    /// such as the freshness of the OCSP response being stapled.
+    #[serde(default)]
+    pub respond_debug_info: bool,
+    /// The origin response status codes that are signed. Responses with other
+    /// status codes are passed through unsigned. Defaults to only 200. It
+    /// can't contain `UNSIGNABLE_STATUSES`.
+    #[serde(default = "default_signable_statuses")]
+    pub signable_statuses: Vec<u16>,
+    /// The label of the `Signature` header, which must be a structured-header
+    /// identifier. Defaults to `DEFAULT_SIGNATURE_LABEL`.
+    pub signature_label: Option<String>,
+    /// If set, the origin may shorten the signature of a response with this
+    /// response header, whose value is a number of seconds, such as
+    /// `x-sxg-max-age: 3600`. It can't lengthen the signature beyond what
+    /// `cache-control` allows, nor shorten it below 2 minutes. It is ignored
+    /// if malformed, and never signed.
+    pub signature_max_age_header: Option<String>,
+    /// In `allowlist` mode, the only response headers that are signed. Others
+    /// are dropped from the signed exchange, though still forwarded on unsigned
+    /// responses. It must include `content-type`.
+    #[serde(default)]
+    pub signed_response_headers: BTreeSet<String>,
     pub strip_request_headers: BTreeSet<String>,
+    /// In `denylist` mode, the response headers that are dropped from the
+    /// signed exchange.
     pub strip_response_headers: BTreeSet<String>,
+    /// If true, `Config::new` does not log the warnings found by
+    /// `Config::lint`.
+    #[serde(default)]
+    pub suppress_config_warnings: bool,
+    /// The directory the validity data is served under. Defaults to
+    /// `reserved_path`.
+    #[serde(default)]
     pub validity_url_dirname: String,
+    /// How `validity-url` is written in the `Signature` header.
+    #[serde(default)]
+    pub validity_url_form: UrlForm,
+    /// What to do with a response whose `Vary` header contains `cookie` or
+    /// `*`, which make the signed exchange unusable by caches. Defaults to
+    /// `refuse`.
+    #[serde(default)]
+    pub vary_mode: VaryMode,
 }
 
+/// How a URL is written in the `Signature` header.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlForm {
+    /// A full URL, such as `https://example.com/.well-known/sxg-certs/cert`.
+    #[default]
+    Absolute,
+    /// A path, such as `/.well-known/sxg-certs/cert`, which is resolved
+    /// against the signed URL. It requires the URL to be same-origin with the
+    /// signed URL.
+    Relative,
+    /// A `data:` URL embedding the cert-chain CBOR, so that the signed exchange
+    /// can be validated offline. It is only valid for `cert_url_form`, and is
+    /// only meant for testing.
+    #[cfg(feature = "test-utils")]
+    Data,
+}
+
+/// How a `Vary` header with `cookie` or `*` in the response is handled.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VaryMode {
+    /// The response is passed through unsigned.
+    #[default]
+    Refuse,
+    /// The tokens are removed from the `Vary` header, and the response is
+    /// signed. The header is dropped if no token remains.
+    Strip,
+    /// The response is signed with the `Vary` header as is, unless it
+    /// contains `*`, which browsers reject.
+    Keep,
+}
+
+/// Which responder of the worker a request is for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub enum Route {
+    /// A certificate chain under `cert_url_dirname`.
+    CertChain,
+    /// The validity data under `validity_url_dirname`.
+    Validity,
+    /// Any other name under `reserved_path`, `cert_url_dirname` or
+    /// `validity_url_dirname`, such as `test.html`, which the worker serves
+    /// itself, or answers with a 404 if the name is unknown. It is never
+    /// fetched from the origin.
+    Reserved,
+    /// Anything else, which is fetched from the origin and signed.
+    Content,
+}
+
+/// How the response headers to sign are configured.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseHeaderMode {
+    /// Only `signed_response_headers` are signed.
+    Allowlist,
+    /// All headers except `strip_response_headers` are signed.
+    #[default]
+    Denylist,
+}
+
+pub const DEFAULT_SIGNATURE_LABEL: &str = "sig";
+
+/// The status codes of responses that are always passed through unsigned,
+/// because they don't carry a complete resource: 206 Partial Content and 304
+/// Not Modified.
+pub const UNSIGNABLE_STATUSES: [u16; 2] = [206, 304];
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_always_forward_request_headers() -> BTreeSet<String> {
+    ["host"].iter().map(|name| name.to_string()).collect()
+}
+
+fn default_signable_statuses() -> Vec<u16> {
+    vec![200]
+}
+
+fn default_max_origin_body_bytes() -> usize {
+    crate::MAX_PAYLOAD_SIZE
+}
+
+/// A `Link` directive that hints the browser to fetch a resource, such as the
+/// page the user is likely to navigate to next.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct LinkHint {
+    /// An absolute URL, or a URL relative to the signed exchange.
+    pub url: String,
+    pub rel: String,
+    pub r#as: String,
+}
+
+impl LinkHint {
+    fn validate(&self) -> Result<()> {
+        // https://fetch.spec.whatwg.org/#concept-request-destination
+        // Only `prefetch` can fetch a document, which is used for
+        // navigations.
+        let allowed_as: &[&str] = match self.rel.as_str() {
+            "preload" => &["fetch", "font", "image", "script", "style"],
+            "prefetch" => &["document", "fetch", "font", "image", "script", "style"],
+            _ => {
+                return Err(anyhow!(
+                    "link_hints: rel \"{}\" is not supported; use \"preload\" or \"prefetch\".",
+                    self.rel
+                ))
+            }
+        };
+        if !allowed_as.contains(&self.r#as.as_str()) {
+            return Err(anyhow!(
+                "link_hints: rel \"{}\" does not support as \"{}\".",
+                self.rel,
+                self.r#as
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Request headers which usually carry user-specific or identifying data.
+// Forwarding them to the origin is rarely safe, because the signed exchange
+// will be cached and served to other users.
+const SENSITIVE_REQUEST_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "forwarded",
+    "proxy-authorization",
+    "x-forwarded-for",
+    "x-real-ip",
+];
+
 impl Config {
     pub fn normalize(&mut self) {
-        self.cert_url_dirname = to_url_prefix(&self.cert_url_dirname);
-        lowercase_all(&mut self.forward_request_headers);
         self.reserved_path = to_url_prefix(&self.reserved_path);
+        lowercase_all(&mut self.allowed_subresource_origins);
+        lowercase_all(&mut self.always_forward_request_headers);
+        self.cert_url_dirname = self.url_prefix_or_reserved_path(&self.cert_url_dirname);
+        lowercase_all(&mut self.forward_request_headers);
+        if let Some((name, _)) = &mut self.require_request_header {
+            name.make_ascii_lowercase();
+        }
+        if let Some(name) = &mut self.signature_max_age_header {
+            name.make_ascii_lowercase();
+        }
         lowercase_all(&mut self.strip_request_headers);
+        lowercase_all(&mut self.signed_response_headers);
         lowercase_all(&mut self.strip_response_headers);
-        self.validity_url_dirname = to_url_prefix(&self.validity_url_dirname);
+        self.validity_url_dirname = self.url_prefix_or_reserved_path(&self.validity_url_dirname);
+    }
+    // Returns `reserved_path` if `dirname` is not set. Must be called after
+    // `reserved_path` is normalized.
+    fn url_prefix_or_reserved_path(&self, dirname: &str) -> String {
+        if dirname.is_empty() {
+            self.reserved_path.clone()
+        } else {
+            to_url_prefix(dirname)
+        }
+    }
+    /// Returns all the problems that make the config invalid, of which `new`
+    /// reports the first. Must be called after `normalize`.
+    pub fn validation_errors(&self) -> Vec<Error> {
+        let mut errors: Vec<Error> = self
+            .link_hints
+            .iter()
+            .filter_map(|link_hint| link_hint.validate().err())
+            .collect();
+        for origin in &self.allowed_subresource_origins {
+            let serialized = url::Url::parse(origin)
+                .ok()
+                .map(|url| url.origin().ascii_serialization());
+            if serialized.as_ref() != Some(origin) {
+                errors.push(anyhow!(
+                    "allowed_subresource_origins: \"{}\" is not an origin, \
+                    such as \"https://cdn.example.com\".",
+                    origin
+                ));
+            }
+        }
+        #[cfg(feature = "test-utils")]
+        if self.validity_url_form == UrlForm::Data {
+            errors.push(anyhow!("validity_url_form can't be \"data\"."));
+        }
+        for status in &self.signable_statuses {
+            if UNSIGNABLE_STATUSES.contains(status) {
+                errors.push(anyhow!(
+                    "signable_statuses can't contain {}, which doesn't carry a \
+                    complete resource to sign.",
+                    status
+                ));
+            }
+        }
+        if let Some(label) = &self.signature_label {
+            if !crate::structured_header::is_identifier(label) {
+                errors.push(anyhow!(
+                    "signature_label \"{}\" is not a valid structured-header key; \
+                    use lowercase letters, digits, \"_\", \"-\", \"*\" or \"/\", \
+                    starting with a letter.",
+                    label
+                ));
+            }
+        }
+        if let Some(private_key_base64) = &self.private_key_base64 {
+            if let Err(error) = decode_private_key(private_key_base64) {
+                errors.push(error.context("private_key_base64 is invalid"));
+            }
+        }
+        if self.response_header_mode == ResponseHeaderMode::Allowlist
+            && !self.signed_response_headers.contains("content-type")
+        {
+            errors.push(anyhow!(
+                "signed_response_headers must contain \"content-type\", \
+                which is required in a signed exchange."
+            ));
+        }
+        errors
+    }
+    fn validate(&self) -> Result<()> {
+        match self.validation_errors().into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+    /// Returns which responder serves `request_url`, judging by its path
+    /// only. Must be called after `normalize`. Only `Content` may be fetched
+    /// from the origin and signed.
+    pub fn route(&self, request_url: &str) -> Route {
+        match url::Url::parse(request_url) {
+            Ok(url) => self.route_path(url.path()),
+            Err(_) => Route::Content,
+        }
+    }
+    pub(crate) fn route_path(&self, path: &str) -> Route {
+        if path.strip_prefix(&self.validity_url_dirname) == Some("validity") {
+            return Route::Validity;
+        }
+        match path.strip_prefix(&self.cert_url_dirname) {
+            Some(basename) if is_certificate_basename(basename) => return Route::CertChain,
+            _ => (),
+        }
+        let reserved = [
+            &self.reserved_path,
+            &self.cert_url_dirname,
+            &self.validity_url_dirname,
+        ];
+        if reserved
+            .iter()
+            .any(|dirname| path.starts_with(dirname.as_str()))
+        {
+            Route::Reserved
+        } else {
+            Route::Content
+        }
+    }
+    /// Returns the label of the `Signature` header.
+    pub fn signature_label(&self) -> &str {
+        self.signature_label
+            .as_deref()
+            .unwrap_or(DEFAULT_SIGNATURE_LABEL)
+    }
+    /// Returns which origin response headers are signed.
+    pub fn response_header_filter(&self) -> ResponseHeaderFilter<'_> {
+        match self.response_header_mode {
+            ResponseHeaderMode::Allowlist => {
+                ResponseHeaderFilter::Keep(&self.signed_response_headers)
+            }
+            ResponseHeaderMode::Denylist => {
+                ResponseHeaderFilter::Strip(&self.strip_response_headers)
+            }
+        }
+    }
+    /// Returns whether the request header `name`, in lowercase, is forwarded
+    /// to the origin, unless it is stripped by `strip_request_headers`.
+    pub fn forwards_request_header(&self, name: &str) -> bool {
+        self.forward_request_headers.contains(name)
+            || self.always_forward_request_headers.contains(name)
+    }
+    /// Returns warnings about settings that are valid but likely to be
+    /// mistakes. Must be called after `normalize`.
+    pub fn lint(&self) -> Vec<String> {
+        let forwarded = [
+            ("forward_request_headers", &self.forward_request_headers),
+            (
+                "always_forward_request_headers",
+                &self.always_forward_request_headers,
+            ),
+        ];
+        let mut warnings: Vec<String> = forwarded
+            .iter()
+            .flat_map(|(field, names)| names.iter().map(move |name| (field, name)))
+            .filter(|(_, name)| SENSITIVE_REQUEST_HEADERS.contains(&name.as_str()))
+            .map(|(field, name)| {
+                format!(
+                    "{} contains \"{}\", which may leak user-specific data \
+                    into publicly cached signed exchanges.",
+                    field, name
+                )
+            })
+            .collect();
+        // A path segment starting with a dot is unlikely to be used by the
+        // site's own content, while a word like "amp" may be.
+        if !self.reserved_path.starts_with("/.") && !self.allow_unusual_reserved_path {
+            warnings.push(format!(
+                "reserved_path \"{}\" may intercept URLs of the site's own content; \
+                consider \".well-known/sxg\" or \".sxg\" instead.",
+                self.reserved_path
+            ));
+        }
+        match self.response_header_mode {
+            ResponseHeaderMode::Allowlist if !self.strip_response_headers.is_empty() => {
+                warnings.push(
+                    "strip_response_headers is ignored because response_header_mode \
+                    is \"allowlist\"."
+                        .to_string(),
+                );
+            }
+            ResponseHeaderMode::Denylist if !self.signed_response_headers.is_empty() => {
+                warnings.push(
+                    "signed_response_headers is ignored because response_header_mode \
+                    is \"denylist\"."
+                        .to_string(),
+                );
+            }
+            _ => (),
+        }
+        if self.allow_missing_ocsp {
+            warnings.push(
+                "allow_missing_ocsp is set, so browsers may reject the signed exchanges."
+                    .to_string(),
+            );
+        }
+        if self.allow_uncovered_hosts {
+            warnings.push(
+                "allow_uncovered_hosts is set, so browsers may reject the signed exchanges."
+                    .to_string(),
+            );
+        }
+        warnings
+    }
+    fn log_warnings(&self) {
+        if self.suppress_config_warnings {
+            return;
+        }
+        for warning in self.lint() {
+            console_log(&format!("Config warning: {}", warning));
+        }
     }
     /// Creates config from text
-    pub fn new(input_yaml: &str) -> Result<Self> {
-        let mut input: Self = serde_yaml::from_str(input_yaml)?;
-        input.normalize();
-        Ok(input)
+    pub fn new(input_yaml: &str) -> Result<Self, SxgError> {
+        let input: Self = serde_yaml::from_str(trim_text_file(input_yaml))
+            .map_err(|e| SxgError::Config(e.into()))?;
+        input.finish()
+    }
+    /// Creates config from the content of a YAML file, which may be
+    /// compressed with gzip.
+    pub fn from_bytes(input: &[u8]) -> Result<Self, SxgError> {
+        Self::new(&decode_text_file(input)?)
+    }
+    /// Creates config from a base YAML and an overlay YAML, which typically
+    /// holds the few fields that differ between environments.
+    /// Each top-level field present in the overlay overrides the same field in
+    /// the base, including fields explicitly set to `null`. Fields absent from
+    /// the overlay are taken from the base. Header sets
+    /// (`always_forward_request_headers`, `forward_request_headers`,
+    /// `strip_request_headers` and `strip_response_headers`) present in both
+    /// files are merged according to `header_set_merge`.
+    pub fn new_with_overlay(
+        base_yaml: &str,
+        overlay_yaml: &str,
+        header_set_merge: HeaderSetMerge,
+    ) -> Result<Self, SxgError> {
+        let mut base = parse_mapping(base_yaml).map_err(SxgError::Config)?;
+        let overlay = parse_mapping(overlay_yaml).map_err(SxgError::Config)?;
+        for (key, overlay_value) in overlay {
+            let merged_value = match (header_set_merge, base.remove(&key), overlay_value) {
+                (
+                    HeaderSetMerge::Union,
+                    Some(serde_yaml::Value::Sequence(mut base_items)),
+                    serde_yaml::Value::Sequence(overlay_items),
+                ) if matches!(key.as_str(), Some(name) if is_header_set_field(name)) => {
+                    base_items.extend(overlay_items);
+                    serde_yaml::Value::Sequence(base_items)
+                }
+                (_, _, overlay_value) => overlay_value,
+            };
+            base.insert(key, merged_value);
+        }
+        let input: Self = serde_yaml::from_value(serde_yaml::Value::Mapping(base))
+            .map_err(|e| SxgError::Config(e.into()))?;
+        input.finish()
+    }
+    // Normalizes and validates the config deserialized from YAML.
+    fn finish(mut self) -> Result<Self, SxgError> {
+        self.normalize();
+        self.validate().map_err(SxgError::Config)?;
+        self.log_warnings();
+        Ok(self)
+    }
+}
+
+/// Specifies how `Config::new_with_overlay` merges a header set that is
+/// present in both the base and the overlay.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HeaderSetMerge {
+    /// The overlay's set is used, and the base's set is discarded. This makes
+    /// it possible to remove a header in one environment.
+    Replace,
+    /// The union of both sets is used. This makes it possible to add a header
+    /// in one environment without repeating the base's headers.
+    Union,
+}
+
+fn is_header_set_field(name: &str) -> bool {
+    matches!(
+        name,
+        "always_forward_request_headers"
+            | "forward_request_headers"
+            | "strip_request_headers"
+            | "strip_response_headers"
+    )
+}
+
+fn parse_mapping(yaml: &str) -> Result<serde_yaml::Mapping> {
+    let yaml = trim_text_file(yaml);
+    // An overlay without any overrides may be an empty file.
+    if yaml.is_empty() {
+        return Ok(serde_yaml::Mapping::new());
+    }
+    match serde_yaml::from_str(yaml)? {
+        serde_yaml::Value::Mapping(mapping) => Ok(mapping),
+        serde_yaml::Value::Null => Ok(serde_yaml::Mapping::new()),
+        _ => Err(anyhow!("Expecting the config YAML to be a mapping")),
     }
 }
 
@@ -58,6 +610,44 @@ fn lowercase_all(names: &mut BTreeSet<String>) {
         .collect();
 }
 
+/// Decodes a private key from standard or URL-safe base64, with or without
+/// padding, because secret stores differ in which one they emit.
+pub(crate) fn decode_private_key(text: &str) -> Result<Vec<u8>> {
+    // The size of a P-256 private key.
+    const KEY_SIZE: usize = 32;
+    let text = text.trim().trim_end_matches('=');
+    let is_standard = text.contains(['+', '/']);
+    let is_url_safe = text.contains(['-', '_']);
+    let alphabet = match (is_standard, is_url_safe) {
+        (true, true) => {
+            return Err(anyhow!(
+                "It mixes the standard and URL-safe base64 alphabets."
+            ))
+        }
+        (_, true) => base64::URL_SAFE_NO_PAD,
+        _ => base64::STANDARD_NO_PAD,
+    };
+    let key = base64::decode_config(text, alphabet)
+        .map_err(|e| Error::new(e).context("It is not base64."))?;
+    if key.len() != KEY_SIZE {
+        return Err(anyhow!(
+            "It decodes to {} bytes, but a P-256 private key has {} bytes.",
+            key.len(),
+            KEY_SIZE
+        ));
+    }
+    Ok(key)
+}
+
+// Returns whether `basename` has the form of `CertificateChain::basename`,
+// which is the base64url of a SHA-256 digest.
+fn is_certificate_basename(basename: &str) -> bool {
+    basename.len() == 43
+        && basename
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
 fn to_url_prefix(dirname: &str) -> String {
     format!("/{}/", dirname.trim_matches('/'))
 }
@@ -73,6 +663,7 @@ forward_request_headers:
   - "cf-IPCOUNTRY"
   - "USER-agent"
 html_host: my_domain.com
+require_request_header: ["X-SXG-Experiment", "on"]
 reserved_path: ".sxg"
 respond_debug_info: false
 strip_request_headers: ["Forwarded"]
@@ -89,6 +680,10 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 .collect()
         );
         assert_eq!(config.html_host, "my_domain.com".to_string());
+        assert_eq!(
+            config.require_request_header,
+            Some(("x-sxg-experiment".to_string(), Some("on".to_string())))
+        );
         assert_eq!(
             config.strip_request_headers,
             ["forwarded"].iter().map(|s| s.to_string()).collect()
@@ -103,4 +698,307 @@ validity_url_dirname: "//.well-known/sxg-validity"
         assert_eq!(config.reserved_path, "/.sxg/");
         assert_eq!(config.validity_url_dirname, "/.well-known/sxg-validity/");
     }
+    #[test]
+    fn routes_requests() {
+        let config = Config::new(BASE_YAML).unwrap();
+        let cert = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            crate::utils::tests::SELF_SIGNED_CERT_SHA256
+        );
+        assert_eq!(config.route(&cert), Route::CertChain);
+        assert_eq!(config.route(&format!("{}?x=1", cert)), Route::CertChain);
+        for url in [
+            "https://my_domain.com/.well-known/sxg-validity/validity",
+            "https://my_domain.com/.well-known/sxg-validity/validity?x=1#y",
+        ] {
+            assert_eq!(config.route(url), Route::Validity, "{}", url);
+        }
+        for url in [
+            "https://my_domain.com/.well-known/sxg-certs/",
+            "https://my_domain.com/.well-known/sxg-certs/test.html",
+            "https://my_domain.com/.well-known/sxg-validity/validity/more",
+            "https://my_domain.com/.sxg/test.html",
+            "https://my_domain.com/.sxg/unknown?x=1",
+        ] {
+            assert_eq!(config.route(url), Route::Reserved, "{}", url);
+        }
+        for url in [
+            "https://my_domain.com/",
+            "https://my_domain.com/index.html?x=/.well-known/sxg-validity/validity",
+            "https://my_domain.com/.sxg",
+            "https://my_domain.com/.well-known/acme-challenge/token",
+            "not a url",
+        ] {
+            assert_eq!(config.route(url), Route::Content, "{}", url);
+        }
+    }
+    const BASE_YAML: &str = r#"
+cert_url_dirname: ".well-known/sxg-certs"
+forward_request_headers: ["user-agent"]
+html_host: prod.example.com
+reserved_path: ".sxg"
+strip_request_headers: ["forwarded"]
+strip_response_headers: ["set-cookie"]
+validity_url_dirname: ".well-known/sxg-validity"
+    "#;
+    #[test]
+    fn defaults_url_dirnames_to_reserved_path() {
+        let base_yaml = r#"
+forward_request_headers: []
+html_host: example.com
+reserved_path: ".well-known/sxg"
+strip_request_headers: []
+strip_response_headers: []
+        "#;
+        for (overlay, cert_url_dirname, validity_url_dirname) in [
+            ("{}", "/.well-known/sxg/", "/.well-known/sxg/"),
+            ("cert_url_dirname: certs", "/certs/", "/.well-known/sxg/"),
+            (
+                "validity_url_dirname: validity",
+                "/.well-known/sxg/",
+                "/validity/",
+            ),
+            (
+                "{cert_url_dirname: certs, validity_url_dirname: validity}",
+                "/certs/",
+                "/validity/",
+            ),
+        ] {
+            let config =
+                Config::new_with_overlay(base_yaml, overlay, HeaderSetMerge::Replace).unwrap();
+            assert_eq!(config.cert_url_dirname, cert_url_dirname, "{}", overlay);
+            assert_eq!(
+                config.validity_url_dirname, validity_url_dirname,
+                "{}",
+                overlay
+            );
+        }
+    }
+    #[test]
+    fn parses_bom_prefixed_input() {
+        let config = Config::new(&format!("\u{feff}\n{}", BASE_YAML)).unwrap();
+        assert_eq!(config.html_host, "prod.example.com");
+        let overlay = "\u{feff}html_host: staging.example.com\n";
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Union).unwrap();
+        assert_eq!(config.html_host, "staging.example.com");
+    }
+    #[test]
+    fn parses_gzipped_input() {
+        // BASE_YAML compressed with gzip.
+        let config = Config::from_bytes(include_bytes!("static/config.yaml.gz")).unwrap();
+        assert_eq!(config.html_host, "prod.example.com");
+        assert_eq!(
+            Config::from_bytes(BASE_YAML.as_bytes()).unwrap().html_host,
+            "prod.example.com"
+        );
+        assert!(Config::from_bytes(&[0x1f, 0x8b, 0x08]).is_err());
+    }
+    #[test]
+    fn overlay_replaces_fields() {
+        let overlay = r#"
+html_host: staging.example.com
+reserved_path: ".sxg-staging"
+strip_response_headers: ["Strict-Transport-Security"]
+        "#;
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Replace).unwrap();
+        assert_eq!(config.html_host, "staging.example.com");
+        assert_eq!(config.reserved_path, "/.sxg-staging/");
+        assert_eq!(config.cert_url_dirname, "/.well-known/sxg-certs/");
+        assert_eq!(
+            config.forward_request_headers,
+            ["user-agent"].iter().map(|s| s.to_string()).collect()
+        );
+        assert_eq!(
+            config.strip_response_headers,
+            ["strict-transport-security"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+    }
+    #[test]
+    fn overlay_unions_header_sets() {
+        let overlay = r#"
+strip_response_headers: ["Strict-Transport-Security", "set-cookie"]
+        "#;
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Union).unwrap();
+        assert_eq!(config.html_host, "prod.example.com");
+        assert_eq!(
+            config.strip_response_headers,
+            ["set-cookie", "strict-transport-security"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+    }
+    #[test]
+    fn empty_overlay() {
+        let config = Config::new_with_overlay(BASE_YAML, "", HeaderSetMerge::Union).unwrap();
+        assert_eq!(config.html_host, "prod.example.com");
+    }
+    #[test]
+    fn lints_sensitive_headers() {
+        let config = Config::new(BASE_YAML).unwrap();
+        assert!(config.lint().is_empty());
+        let overlay = r#"
+forward_request_headers: ["Cookie", "user-agent", "X-Forwarded-For"]
+suppress_config_warnings: true
+        "#;
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Replace).unwrap();
+        let warnings = config.lint();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("\"cookie\""));
+        assert!(warnings[1].contains("\"x-forwarded-for\""));
+        let overlay = r#"
+always_forward_request_headers: ["host", "Authorization"]
+suppress_config_warnings: true
+        "#;
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Replace).unwrap();
+        let warnings = config.lint();
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with("always_forward_request_headers contains \"authorization\"")
+        );
+    }
+    #[test]
+    fn lints_reserved_path() {
+        for (reserved_path, expected_warnings) in [
+            ("amp", 1),
+            ("/sxg/test/", 1),
+            (".sxg", 0),
+            (".well-known/sxg", 0),
+        ] {
+            let overlay = format!("reserved_path: \"{}\"", reserved_path);
+            let config =
+                Config::new_with_overlay(BASE_YAML, &overlay, HeaderSetMerge::Replace).unwrap();
+            assert_eq!(config.lint().len(), expected_warnings, "{}", reserved_path);
+        }
+        let overlay = "reserved_path: amp\nallow_unusual_reserved_path: true";
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Replace).unwrap();
+        assert!(config.lint().is_empty());
+    }
+    #[test]
+    fn validates_response_header_mode() {
+        let config = Config::new(BASE_YAML).unwrap();
+        assert_eq!(config.response_header_mode, ResponseHeaderMode::Denylist);
+        let overlay = r#"
+response_header_mode: allowlist
+signed_response_headers: ["Content-Type", "Cache-Control"]
+        "#;
+        let config = Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Replace).unwrap();
+        assert_eq!(
+            config.signed_response_headers,
+            ["cache-control", "content-type"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect()
+        );
+        // BASE_YAML's strip_response_headers is unused in allowlist mode.
+        assert_eq!(config.lint().len(), 1);
+        let overlay = r#"
+response_header_mode: allowlist
+signed_response_headers: ["cache-control"]
+        "#;
+        assert!(Config::new_with_overlay(BASE_YAML, overlay, HeaderSetMerge::Replace).is_err());
+    }
+    #[test]
+    fn validates_link_hints() {
+        let link_hints = |rel: &str, r#as: &str| {
+            let overlay = format!(
+                "link_hints: [{{url: /next.html, rel: {}, as: {}}}]",
+                rel, r#as
+            );
+            Config::new_with_overlay(BASE_YAML, &overlay, HeaderSetMerge::Replace)
+                .map(|config| config.link_hints)
+        };
+        assert_eq!(
+            link_hints("prefetch", "document").unwrap(),
+            vec![LinkHint {
+                url: "/next.html".to_string(),
+                rel: "prefetch".to_string(),
+                r#as: "document".to_string(),
+            }]
+        );
+        assert!(link_hints("preload", "script").is_ok());
+        assert!(link_hints("preload", "document").is_err());
+        assert!(link_hints("prerender", "document").is_err());
+        assert!(Config::new(BASE_YAML).unwrap().link_hints.is_empty());
+    }
+    #[test]
+    fn validates_allowed_subresource_origins() {
+        let origins = |origin: &str| {
+            let overlay = format!("allowed_subresource_origins: [\"{}\"]", origin);
+            Config::new_with_overlay(BASE_YAML, &overlay, HeaderSetMerge::Replace)
+                .map(|config| config.allowed_subresource_origins)
+        };
+        assert_eq!(
+            origins("HTTPS://CDN.Example.com").unwrap(),
+            BTreeSet::from(["https://cdn.example.com".to_string()])
+        );
+        assert!(origins("https://cdn.example.com:8443").is_ok());
+        assert!(origins("https://cdn.example.com/").is_err());
+        assert!(origins("cdn.example.com").is_err());
+        assert!(origins("data:text/plain,").is_err());
+    }
+    #[test]
+    fn validates_signable_statuses() {
+        let statuses = |statuses: &str| {
+            let overlay = format!("signable_statuses: {}", statuses);
+            Config::new_with_overlay(BASE_YAML, &overlay, HeaderSetMerge::Replace)
+                .map(|config| config.signable_statuses)
+        };
+        assert_eq!(statuses("[200, 203]").unwrap(), [200, 203]);
+        assert!(statuses("[200, 206]").is_err());
+        assert!(statuses("[200, 304]").is_err());
+    }
+    #[test]
+    fn decodes_private_key_in_any_base64() {
+        let key: Vec<u8> = (0..32).map(|i| [0xfb, 0xff, 0xbf][i % 3]).collect();
+        for text in [
+            "+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/8=",
+            "+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/8",
+            "-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_8=",
+            "-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_8\n",
+        ] {
+            assert_eq!(decode_private_key(text).unwrap(), key);
+        }
+        assert!(decode_private_key("+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/+/-_8").is_err());
+        assert!(decode_private_key("!!!!").is_err());
+        // A 33-byte string decodes, but is not a P-256 private key.
+        assert!(decode_private_key(&base64::encode([1; 33])).is_err());
+        let private_key = |key: &str| {
+            let overlay = format!("private_key_base64: \"{}\"", key);
+            Config::new_with_overlay(BASE_YAML, &overlay, HeaderSetMerge::Replace)
+        };
+        assert!(private_key("-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_-_8").is_ok());
+        assert!(private_key("AAAA").is_err());
+    }
+    #[test]
+    fn validates_signature_label() {
+        let signature_label = |label: &str| {
+            let overlay = format!("signature_label: \"{}\"", label);
+            Config::new_with_overlay(BASE_YAML, &overlay, HeaderSetMerge::Replace)
+                .map(|config| config.signature_label().to_string())
+        };
+        assert_eq!(
+            Config::new(BASE_YAML).unwrap().signature_label(),
+            DEFAULT_SIGNATURE_LABEL
+        );
+        assert_eq!(signature_label("label-1").unwrap(), "label-1");
+        assert!(signature_label("Label").is_err());
+        assert!(signature_label("1abel").is_err());
+        assert!(signature_label("a b").is_err());
+    }
+    #[test]
+    fn returns_all_validation_errors() {
+        let mut config = Config::new(BASE_YAML).unwrap();
+        assert!(config.validation_errors().is_empty());
+        config.link_hints = vec![LinkHint {
+            url: "/next.html".to_string(),
+            rel: "prerender".to_string(),
+            r#as: "document".to_string(),
+        }];
+        config.signature_label = Some("Label".to_string());
+        assert_eq!(config.validation_errors().len(), 2);
+    }
 }
@@ -14,23 +14,87 @@
 
 #[cfg(feature = "rust_signer")]
 use crate::signature::rust_signer::RustSigner;
+use crate::SxgError;
 use anyhow::{anyhow, Error, Result};
 use der_parser::{
     ber::{BerObject, BerObjectContent},
+    oid,
     oid::Oid,
 };
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
 
-pub fn get_der_from_pem(pem_text: &str, expected_tag: &str) -> Result<Vec<u8>> {
-    for pem in ::pem::parse_many(pem_text).map_err(Error::new)? {
-        if pem.tag == expected_tag {
-            return Ok(pem.contents);
-        }
-    }
-    Err(anyhow!(
-        r#"The PEM file does not contains "{}" block"#,
-        expected_tag
-    ))
+/// A block in a PEM file, such as a certificate or a private key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PemBlock {
+    /// The label after "BEGIN", like `CERTIFICATE`.
+    pub tag: String,
+    pub der: Vec<u8>,
+}
+
+// Wraps `e` as the error of a certificate or a key that can't be parsed or
+// used.
+fn crypto_error(e: impl Into<Error>) -> SxgError {
+    SxgError::Crypto(e.into())
+}
+
+/// Parses all blocks in a PEM file, which may start with a UTF-8 byte order
+/// mark and may use Windows-style line endings.
+pub fn parse_pem_blocks(text: &str) -> Result<Vec<PemBlock>, SxgError> {
+    let blocks = ::pem::parse_many(crate::utils::trim_text_file(text)).map_err(crypto_error)?;
+    Ok(blocks
+        .into_iter()
+        .map(|block| PemBlock {
+            tag: block.tag,
+            der: block.contents,
+        })
+        .collect())
+}
+
+pub fn get_der_from_pem(pem_text: &str, expected_tag: &str) -> Result<Vec<u8>, SxgError> {
+    parse_pem_blocks(pem_text)?
+        .into_iter()
+        .find(|block| block.tag == expected_tag)
+        .map(|block| block.der)
+        .ok_or_else(|| {
+            crypto_error(anyhow!(
+                r#"The PEM file does not contains "{}" block"#,
+                expected_tag
+            ))
+        })
+}
+
+/// Returns the first certificate in a PEM file.
+pub fn first_certificate(pem_text: &str) -> Result<SingleCertificate, SxgError> {
+    let der = get_der_from_pem(pem_text, CertificateChain::TAG)?;
+    Ok(SingleCertificate { der })
+}
+
+/// Returns all certificates in a PEM file, in the same order as in the file.
+/// Returns an error if the file contains any block other than certificates.
+pub fn certificate_chain(pem_text: &str) -> Result<Vec<SingleCertificate>, SxgError> {
+    parse_pem_blocks(pem_text)?
+        .into_iter()
+        .map(|block| {
+            if block.tag == CertificateChain::TAG {
+                Ok(SingleCertificate { der: block.der })
+            } else {
+                Err(crypto_error(anyhow!(
+                    "Expecting {}, found {}",
+                    CertificateChain::TAG,
+                    block.tag
+                )))
+            }
+        })
+        .collect()
+}
+
+/// Returns the DER of the first EC private key in a PEM file, in
+/// [SEC1](https://www.secg.org/sec1-v2.pdf) format.
+pub fn private_key(pem_text: &str) -> Result<Vec<u8>, SxgError> {
+    get_der_from_pem(pem_text, "EC PRIVATE KEY")
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -43,6 +107,25 @@ pub struct EcPublicKey {
     pub y: Vec<u8>,
 }
 
+/// An Ed25519 public key, as an
+/// [Octet Key Pair](https://datatracker.ietf.org/doc/html/rfc8037#section-2).
+/// It is only used for ACME accounts, because SXG requires ECDSA.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct OkpPublicKey {
+    pub crv: String,
+    pub kty: String,
+    #[serde(with = "crate::serde_helpers::base64")]
+    pub x: Vec<u8>,
+}
+
+/// The public key of an ACME account.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum JsonWebKey {
+    Ec(EcPublicKey),
+    Okp(OkpPublicKey),
+}
+
 pub struct EcPrivateKey {
     // Only used when --feature=rust_signer.
     #[allow(dead_code)]
@@ -53,7 +136,7 @@ pub struct EcPrivateKey {
 impl EcPublicKey {
     /// Parses public key from DER-encoded
     /// [SPKI](https://datatracker.ietf.org/doc/html/rfc5480) format.
-    pub fn from_spki_der(der: &[u8]) -> Result<Self> {
+    pub fn from_spki_der(der: &[u8]) -> Result<Self, SxgError> {
         // https://datatracker.ietf.org/doc/html/rfc5480#section-2.2
         //   ECC public keys have the following syntax:
         //     ECPoint ::= OCTET STRING
@@ -62,10 +145,17 @@ impl EcPublicKey {
         //        by 0x04 and the compressed form is indicated by either 0x02 or
         //        0x03 (see 2.3.3 in [SEC1]).  The public key MUST be rejected if
         //        any other value is included in the first octet.
-        let octets = der_parser::parse_ber(der)?
+        let octets = der_parser::parse_ber(der)
+            .map_err(crypto_error)?
             .1
             .as_slice()
-            .map_err(|e| Error::new(e).context("Expecting ECPoint to be an OCTET STRING"))?;
+            .map_err(|e| {
+                crypto_error(Error::new(e).context("Expecting ECPoint to be an OCTET STRING"))
+            })?;
+        Self::from_ec_point(octets).map_err(crypto_error)
+    }
+    /// Parses public key from the octets of an ECPoint.
+    fn from_ec_point(octets: &[u8]) -> Result<Self> {
         const KEY_SIZE: usize = 32; // Both X and Y of the EC Point are 32 bytes (256 bit).
         if octets.len() != 1 + KEY_SIZE * 2 {
             return Err(Error::msg(format!("Expecting ECPoint to contain 1 octet of uncompression flag and {}*2 octets of point coordinates", KEY_SIZE)));
@@ -90,17 +180,65 @@ impl EcPublicKey {
     }
     /// Calculates the JWK thumbprint defined by
     /// [RFC7638](https://datatracker.ietf.org/doc/html/rfc7638#section-3).
-    pub fn get_jwk_thumbprint(&self) -> Result<Vec<u8>> {
-        let message = serde_json::to_string(&self)?;
+    pub fn get_jwk_thumbprint(&self) -> Result<Vec<u8>, SxgError> {
+        let message = serde_json::to_string(&self).map_err(crypto_error)?;
+        Ok(HashAlgorithm::Sha256.digest(message.as_bytes()))
+    }
+}
+
+impl OkpPublicKey {
+    /// Parses an Ed25519 public key from DER-encoded
+    /// [SPKI](https://datatracker.ietf.org/doc/html/rfc8410#section-4) format.
+    pub fn from_ed25519_spki_der(der: &[u8]) -> Result<Self, SxgError> {
+        // The SPKI of every Ed25519 key starts with the algorithm identifier
+        // 1.3.101.112 without parameters, followed by a BIT STRING of the
+        // 32-byte key.
+        const SPKI_PREFIX: [u8; 12] = [
+            0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+        ];
+        match der.strip_prefix(&SPKI_PREFIX[..]) {
+            Some(x) if x.len() == 32 => Ok(OkpPublicKey {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                x: x.to_vec(),
+            }),
+            _ => Err(crypto_error(anyhow!(
+                "Expecting the SPKI of an Ed25519 public key"
+            ))),
+        }
+    }
+}
+
+impl JsonWebKey {
+    /// Calculates the JWK thumbprint defined by
+    /// [RFC7638](https://datatracker.ietf.org/doc/html/rfc7638#section-3).
+    /// The fields of both key types are declared in the required order.
+    pub fn get_jwk_thumbprint(&self) -> Result<Vec<u8>, SxgError> {
+        let message = serde_json::to_string(&self).map_err(crypto_error)?;
         Ok(HashAlgorithm::Sha256.digest(message.as_bytes()))
     }
 }
 
+impl From<EcPublicKey> for JsonWebKey {
+    fn from(public_key: EcPublicKey) -> Self {
+        JsonWebKey::Ec(public_key)
+    }
+}
+
+impl From<OkpPublicKey> for JsonWebKey {
+    fn from(public_key: OkpPublicKey) -> Self {
+        JsonWebKey::Okp(public_key)
+    }
+}
+
 impl EcPrivateKey {
     /// Parses private key from DER-encoded
     /// [SEC1](https://www.secg.org/sec1-v2.pdf) format,
     /// which is also defined in [RFC5915](https://datatracker.ietf.org/doc/html/rfc5915).
-    pub fn from_sec1_der(der: &[u8]) -> Result<Self> {
+    pub fn from_sec1_der(der: &[u8]) -> Result<Self, SxgError> {
+        Self::parse_sec1_der(der).map_err(crypto_error)
+    }
+    fn parse_sec1_der(der: &[u8]) -> Result<Self> {
         let ec_private_key = der_parser::parse_ber(der)?.1;
         // https://datatracker.ietf.org/doc/html/rfc5915#section-3
         //   ECPrivateKey ::= SEQUENCE {
@@ -129,12 +267,14 @@ impl EcPrivateKey {
     }
     /// Parses private key from PEM-encoded
     /// [SEC1](https://www.secg.org/sec1-v2.pdf) format.
-    pub fn from_sec1_pem(pem: &str) -> Result<Self> {
-        let der = get_der_from_pem(pem, "EC PRIVATE KEY")?;
+    pub fn from_sec1_pem(pem: &str) -> Result<Self, SxgError> {
+        let der = private_key(pem)?;
         Self::from_sec1_der(&der)
     }
+    /// Creates a signer from the parsed key. The signer is cheap to clone,
+    /// so it should be created once and shared.
     #[cfg(feature = "rust_signer")]
-    pub fn create_signer(&self) -> Result<RustSigner> {
+    pub fn create_signer(&self) -> Result<RustSigner, SxgError> {
         RustSigner::new(&self.d)
     }
 }
@@ -171,12 +311,308 @@ impl Serialize for EcPrivateKey {
     }
 }
 
+/// The parsed fields of a certificate, for dashboards and health checks.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub subject_alt_names: Vec<String>,
+    pub serial_number: String,
+    pub issuer: String,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SingleCertificate {
     #[serde(with = "crate::serde_helpers::base64")]
     pub der: Vec<u8>,
 }
 
+impl SingleCertificate {
+    fn parse(&self) -> Result<x509_parser::certificate::X509Certificate<'_>, SxgError> {
+        Ok(x509_parser::parse_x509_certificate(&self.der)
+            .map_err(crypto_error)?
+            .1)
+    }
+    /// Returns all the fields in `CertificateInfo`.
+    pub fn info(&self) -> Result<CertificateInfo, SxgError> {
+        Ok(CertificateInfo {
+            subject: self.subject()?,
+            subject_alt_names: self.subject_alt_names()?,
+            serial_number: self.serial_number()?,
+            issuer: self.issuer()?,
+            not_before: self.not_before()?,
+            not_after: self.not_after()?,
+        })
+    }
+    /// Returns the time after which the certificate is no longer valid.
+    pub fn not_after(&self) -> Result<SystemTime, SxgError> {
+        let x509_cert = self.parse()?;
+        let timestamp = x509_cert.tbs_certificate.validity.not_after.timestamp();
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp as u64))
+    }
+    /// Returns the public key of the subject, which must be a P-256 key.
+    pub fn public_key(&self) -> Result<EcPublicKey, SxgError> {
+        let x509_cert = self.parse()?;
+        EcPublicKey::from_ec_point(x509_cert.public_key().subject_public_key.data)
+            .map_err(crypto_error)
+    }
+    /// Returns the serial number as colon-separated hex bytes, which is the
+    /// same format as displayed by `openssl x509 -text`.
+    pub fn serial_number(&self) -> Result<String, SxgError> {
+        let x509_cert = self.parse()?;
+        Ok(x509_cert.tbs_certificate.raw_serial_as_string())
+    }
+    /// Returns the time before which the certificate is not yet valid.
+    pub fn not_before(&self) -> Result<SystemTime, SxgError> {
+        let x509_cert = self.parse()?;
+        let timestamp = x509_cert.tbs_certificate.validity.not_before.timestamp();
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp as u64))
+    }
+    /// Returns the subject as a distinguished name, such as `CN=example.com`.
+    pub fn subject(&self) -> Result<String, SxgError> {
+        let x509_cert = self.parse()?;
+        Ok(x509_cert.tbs_certificate.subject.to_string())
+    }
+    /// Returns the DNS names and IP addresses in the `subjectAltName`
+    /// extension, in the order they appear.
+    pub fn subject_alt_names(&self) -> Result<Vec<String>, SxgError> {
+        use x509_parser::extensions::ParsedExtension;
+        let x509_cert = self.parse()?;
+        let mut names = vec![];
+        for extension in x509_cert.extensions() {
+            if let ParsedExtension::SubjectAlternativeName(san) = extension.parsed_extension() {
+                for name in &san.general_names {
+                    names.extend(general_name_to_string(name).map_err(crypto_error)?);
+                }
+            }
+        }
+        Ok(names)
+    }
+    /// Returns the issuer as a distinguished name.
+    pub fn issuer(&self) -> Result<String, SxgError> {
+        let x509_cert = self.parse()?;
+        Ok(x509_cert.tbs_certificate.issuer.to_string())
+    }
+    /// Returns the common name (CN) of the issuer, such as `ISRG Root X1`, or
+    /// `None` if the issuer has no common name.
+    pub fn issuer_common_name(&self) -> Result<Option<String>, SxgError> {
+        let x509_cert = self.parse()?;
+        let common_name = x509_cert.tbs_certificate.issuer.iter_common_name().next();
+        common_name
+            .map(|name| name.as_str().map(|name| name.to_string()))
+            .transpose()
+            .map_err(crypto_error)
+    }
+    /// Returns whether the certificate has the CanSignHttpExchanges extension,
+    /// without which browsers reject the signed exchanges.
+    /// https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#name-certificate-requirements
+    pub fn can_sign_http_exchanges(&self) -> Result<bool, SxgError> {
+        const CAN_SIGN_HTTP_EXCHANGES: Oid<'static> = oid!(1.3.6 .1 .4 .1 .11129 .2 .1 .22);
+        let x509_cert = self.parse()?;
+        Ok(x509_cert
+            .extensions()
+            .iter()
+            .any(|extension| extension.oid == CAN_SIGN_HTTP_EXCHANGES))
+    }
+    /// Returns the SCTs embedded in the certificate extension.
+    pub fn embedded_scts(&self) -> Result<Vec<SignedCertificateTimestamp>, SxgError> {
+        use x509_parser::extensions::ParsedExtension;
+        let x509_cert = self.parse()?;
+        let mut scts = vec![];
+        for extension in x509_cert.extensions() {
+            if let ParsedExtension::SCT(items) = extension.parsed_extension() {
+                scts.extend(items.iter().map(|sct| SignedCertificateTimestamp {
+                    log_id: sct.id.key_id.to_vec(),
+                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(sct.timestamp),
+                }));
+            }
+        }
+        Ok(scts)
+    }
+}
+
+// Returns a DNS name or IP address in `subjectAltName` as a string, or `None`
+// for other types of names.
+fn general_name_to_string(name: &x509_parser::extensions::GeneralName) -> Result<Option<String>> {
+    use x509_parser::extensions::GeneralName;
+    match name {
+        GeneralName::DNSName(domain) => Ok(Some(domain.to_string())),
+        GeneralName::IPAddress(ip) => {
+            let ip = match ip.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*ip)?),
+                16 => IpAddr::from(<[u8; 16]>::try_from(*ip)?),
+                _ => return Err(anyhow!("Invalid IP address in subjectAltName")),
+            };
+            Ok(Some(ip.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns the domain names requested by a certificate signing request, which
+/// are the common names in its subject and the DNS names and IP addresses in
+/// its `subjectAltName` extension, sorted and deduplicated.
+pub fn certificate_request_domains(cert_request_der: &[u8]) -> Result<Vec<String>, SxgError> {
+    parse_certificate_request_domains(cert_request_der).map_err(crypto_error)
+}
+
+fn parse_certificate_request_domains(cert_request_der: &[u8]) -> Result<Vec<String>> {
+    use x509_parser::{
+        certification_request::X509CertificationRequest, extensions::ParsedExtension,
+        prelude::FromDer,
+    };
+    let cert_request = X509CertificationRequest::from_der(cert_request_der)
+        .map_err(|e| Error::from(e).context("Failed to parse certificate request DER"))?
+        .1;
+    let mut domains = std::collections::BTreeSet::new();
+    for common_name in cert_request
+        .certification_request_info
+        .subject
+        .iter_common_name()
+    {
+        domains.insert(common_name.as_str()?.to_string());
+    }
+    for extension in cert_request.requested_extensions().into_iter().flatten() {
+        if let ParsedExtension::SubjectAlternativeName(san) = extension {
+            for name in &san.general_names {
+                domains.extend(general_name_to_string(name)?);
+            }
+        }
+    }
+    Ok(domains.into_iter().collect())
+}
+
+/// Returns the URL of the OCSP responder in the `authorityInfoAccess`
+/// extension of the certificate, or `None` if the certificate is unparsable
+/// or doesn't specify one, such as a self-signed certificate.
+/// https://datatracker.ietf.org/doc/html/rfc5280#section-4.2.2.1
+pub fn ocsp_responder_url(cert_der: &[u8]) -> Option<String> {
+    use x509_parser::extensions::{GeneralName, ParsedExtension};
+    // https://www.iana.org/assignments/smi-numbers/smi-numbers.xhtml#smi-numbers-1.3.6.1.5.5.7.48.1
+    const ID_AD_OCSP: Oid<'static> = oid!(1.3.6 .1 .5 .5 .7 .48 .1);
+    let cert = x509_parser::parse_x509_certificate(cert_der).ok()?.1;
+    cert.extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })?
+        .accessdescs
+        .iter()
+        .find_map(|access_desc| match access_desc.access_location {
+            GeneralName::URI(url) if access_desc.access_method == ID_AD_OCSP => {
+                Some(url.to_string())
+            }
+            _ => None,
+        })
+}
+
+/// The fields of a signed certificate timestamp which identify it.
+/// https://datatracker.ietf.org/doc/html/rfc6962#section-3.2
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SignedCertificateTimestamp {
+    pub log_id: Vec<u8>,
+    pub timestamp: SystemTime,
+}
+
+/// Parses a TLS-encoded `SignedCertificateTimestampList`, which is the format
+/// of the `sct` field in `application/cert-chain+cbor`.
+/// https://datatracker.ietf.org/doc/html/rfc6962#section-3.3
+pub fn parse_sct_list(input: &[u8]) -> Result<Vec<SignedCertificateTimestamp>, SxgError> {
+    parse_sct_list_impl(input).map_err(crypto_error)
+}
+
+fn parse_sct_list_impl(input: &[u8]) -> Result<Vec<SignedCertificateTimestamp>> {
+    fn split_u16_prefixed(input: &[u8]) -> Result<(&[u8], &[u8])> {
+        let len = match input {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+            _ => return Err(anyhow!("Unexpected end of SCT list")),
+        };
+        let input = &input[2..];
+        if len <= input.len() {
+            Ok(input.split_at(len))
+        } else {
+            Err(anyhow!("Unexpected end of SCT list"))
+        }
+    }
+    let (mut list, rest) = split_u16_prefixed(input)?;
+    if !rest.is_empty() {
+        return Err(anyhow!("Unexpected bytes after SCT list"));
+    }
+    let mut scts = vec![];
+    while !list.is_empty() {
+        let (sct, rest) = split_u16_prefixed(list)?;
+        list = rest;
+        // The SCT starts with a 1-byte version, a 32-byte log ID, and a
+        // 8-byte timestamp in milliseconds.
+        if sct.len() < 41 {
+            return Err(anyhow!("SCT is too short"));
+        }
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&sct[33..41]);
+        scts.push(SignedCertificateTimestamp {
+            log_id: sct[1..33].to_vec(),
+            timestamp: SystemTime::UNIX_EPOCH
+                + Duration::from_millis(u64::from_be_bytes(timestamp)),
+        });
+    }
+    Ok(scts)
+}
+
+/// A certificate in `application/cert-chain+cbor`, with the OCSP response and
+/// SCTs which are only present for the end-entity certificate.
+#[derive(Clone, Debug)]
+pub struct CertChainItem {
+    pub cert: SingleCertificate,
+    pub ocsp: Option<Vec<u8>>,
+    pub sct: Option<Vec<u8>>,
+}
+
+/// Parses `application/cert-chain+cbor`, which is created by
+/// `CertificateChain::create_cert_cbor`.
+/// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-certificate-chain-format
+pub fn parse_cert_cbor(cert_cbor: &[u8]) -> Result<Vec<CertChainItem>, SxgError> {
+    parse_cert_cbor_impl(cert_cbor).map_err(crypto_error)
+}
+
+fn parse_cert_cbor_impl(cert_cbor: &[u8]) -> Result<Vec<CertChainItem>> {
+    use crate::cbor::DataItem;
+    let items = match DataItem::parse(cert_cbor)? {
+        DataItem::Array(items) => items,
+        _ => return Err(anyhow!("The cert chain is not a CBOR array")),
+    };
+    let mut items = items.into_iter();
+    if items.next() != Some(DataItem::TextString("📜⛓")) {
+        return Err(anyhow!(
+            "The cert chain does not start with the magic string"
+        ));
+    }
+    items
+        .map(|item| {
+            let fields = match item {
+                DataItem::Map(fields) => fields,
+                _ => return Err(anyhow!("The cert chain item is not a CBOR map")),
+            };
+            let field = |name: &str| {
+                fields.iter().find_map(|(key, value)| match (key, value) {
+                    (DataItem::TextString(key), DataItem::ByteString(value)) if *key == name => {
+                        Some(value.to_vec())
+                    }
+                    _ => None,
+                })
+            };
+            Ok(CertChainItem {
+                cert: SingleCertificate {
+                    der: field("cert").ok_or_else(|| anyhow!("The cert chain item has no cert"))?,
+                },
+                ocsp: field("ocsp"),
+                sct: field("sct"),
+            })
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CertificateChain {
     pub end_entity: SingleCertificate,
@@ -193,17 +629,17 @@ impl CertificateChain {
     /// Parse `CertificateChain` from multiple PEM files.
     /// Each input file may contain multiple PEM certificates.
     /// Input files must be sorted like `[cert_pem, issuer_pem, root_pem]`.
-    pub fn from_pem_files(pem_files: &[&str]) -> Result<Self> {
-        let mut pem_items = vec![];
+    pub fn from_pem_files(pem_files: &[&str]) -> Result<Self, SxgError> {
+        let mut der_items = vec![];
         for current_file in pem_files {
-            let items_in_current_file = ::pem::parse_many(current_file).map_err(Error::new)?;
-            pem_items.extend_from_slice(&items_in_current_file);
+            der_items.extend(certificate_chain(current_file)?);
         }
-        let der_items = Self::take_certificate_contents(pem_items)?;
         let mut der_items = der_items.into_iter();
-        let end_entity = der_items
-            .next()
-            .ok_or_else(|| Error::msg("Expecting PEM files to contain at least one certificate"))?;
+        let end_entity = der_items.next().ok_or_else(|| {
+            crypto_error(anyhow!(
+                "Expecting PEM files to contain at least one certificate"
+            ))
+        })?;
         let issuers = der_items.collect();
         let end_entity_sha256 = HashAlgorithm::Sha256.digest(&end_entity.der);
         Ok(CertificateChain {
@@ -213,21 +649,22 @@ impl CertificateChain {
             end_entity_sha256,
         })
     }
-    pub fn create_cert_cbor(&self, end_entity_ocsp_der: &[u8]) -> Vec<u8> {
+    /// Serializes the `application/cert-chain+cbor`. The `ocsp` entry is
+    /// omitted if `end_entity_ocsp_der` is `None`, which tools can parse but
+    /// browsers reject.
+    pub fn create_cert_cbor(&self, end_entity_ocsp_der: Option<&[u8]>) -> Vec<u8> {
         use crate::cbor::DataItem;
-        let mut cert_cbor = vec![
-            DataItem::TextString("📜⛓"),
-            DataItem::Map(vec![
-                (
-                    DataItem::TextString("cert"),
-                    DataItem::ByteString(&self.end_entity.der),
-                ),
-                (
-                    DataItem::TextString("ocsp"),
-                    DataItem::ByteString(end_entity_ocsp_der),
-                ),
-            ]),
-        ];
+        let mut end_entity = vec![(
+            DataItem::TextString("cert"),
+            DataItem::ByteString(&self.end_entity.der),
+        )];
+        if let Some(end_entity_ocsp_der) = end_entity_ocsp_der {
+            end_entity.push((
+                DataItem::TextString("ocsp"),
+                DataItem::ByteString(end_entity_ocsp_der),
+            ));
+        }
+        let mut cert_cbor = vec![DataItem::TextString("📜⛓"), DataItem::Map(end_entity)];
         for issuer in self.issuers.iter() {
             cert_cbor.push(DataItem::Map(vec![(
                 DataItem::TextString("cert"),
@@ -238,21 +675,6 @@ impl CertificateChain {
         let cert_cbor = DataItem::Array(cert_cbor);
         cert_cbor.serialize()
     }
-    fn take_certificate_contents(pem_items: Vec<::pem::Pem>) -> Result<Vec<SingleCertificate>> {
-        pem_items
-            .into_iter()
-            .map(|pem_item| {
-                const TAG: &str = "CERTIFICATE";
-                if pem_item.tag == TAG {
-                    Ok(SingleCertificate {
-                        der: pem_item.contents,
-                    })
-                } else {
-                    Err(anyhow!("Expecting {}, found {}", Self::TAG, pem_item.tag))
-                }
-            })
-            .collect::<Result<_>>()
-    }
 }
 
 #[derive(Clone, Copy)]
@@ -262,16 +684,27 @@ pub enum HashAlgorithm {
 }
 
 impl HashAlgorithm {
+    pub fn oid(&self) -> Oid<'static> {
+        match self {
+            HashAlgorithm::Sha1 => Oid::from(&[1, 3, 14, 3, 2, 26]).unwrap(),
+            HashAlgorithm::Sha256 => Oid::from(&[2, 16, 840, 1, 101, 3, 4, 2, 1]).unwrap(),
+        }
+    }
+    /// Returns the algorithm identified by the OID, or `None` if it is not
+    /// supported.
+    pub fn from_oid(oid: &Oid) -> Option<Self> {
+        [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.oid() == *oid)
+    }
     // https://tools.ietf.org/html/rfc5280#section-4.1.1.2
     // AlgorithmIdentifier  ::=  SEQUENCE  {
     //      algorithm               OBJECT IDENTIFIER,
     //      parameters              ANY DEFINED BY algorithm OPTIONAL  }
     pub fn to_ber(&self) -> BerObject<'static> {
         BerObject::from_seq(vec![
-            BerObject::from_obj(BerObjectContent::OID(match self {
-                HashAlgorithm::Sha1 => Oid::from(&[1, 3, 14, 3, 2, 26]).unwrap(),
-                HashAlgorithm::Sha256 => Oid::from(&[2, 16, 840, 1, 101, 3, 4, 2, 1]).unwrap(),
-            })),
+            BerObject::from_obj(BerObjectContent::OID(self.oid())),
             BerObject::from_obj(BerObjectContent::Null),
         ])
     }
@@ -303,6 +736,34 @@ mod tests {
         assert_eq!(bytes.join(":"), hex);
     }
     #[test]
+    fn serializes_ed25519_jwk() {
+        // The example key in RFC 8037.
+        // https://datatracker.ietf.org/doc/html/rfc8037#appendix-A.2
+        let x = base64::decode_config(
+            "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo",
+            base64::URL_SAFE_NO_PAD,
+        )
+        .unwrap();
+        let mut spki = vec![
+            0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+        ];
+        spki.extend_from_slice(&x);
+        let jwk = JsonWebKey::from(OkpPublicKey::from_ed25519_spki_der(&spki).unwrap());
+        assert_eq!(
+            serde_json::to_string(&jwk).unwrap(),
+            r#"{"crv":"Ed25519","kty":"OKP","x":"11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}"#
+        );
+        // https://datatracker.ietf.org/doc/html/rfc8037#appendix-A.3
+        assert_eq!(
+            base64::encode_config(jwk.get_jwk_thumbprint().unwrap(), base64::URL_SAFE_NO_PAD),
+            "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k"
+        );
+        let parsed: JsonWebKey =
+            serde_json::from_str(&serde_json::to_string(&jwk).unwrap()).unwrap();
+        assert!(matches!(parsed, JsonWebKey::Okp(key) if key.x == x));
+        assert!(OkpPublicKey::from_ed25519_spki_der(&spki[1..]).is_err());
+    }
+    #[test]
     fn parse_ec_private_key() {
         // Generated with:
         //    KEY=`mktemp`
@@ -340,6 +801,204 @@ rS6wVo+rtspBMOwa/DK3LJE1W9nS6MqL4Q==
         );
     }
     #[test]
+    fn parses_cert_cbor() {
+        let chain = CertificateChain::from_pem_files(&[
+            crate::utils::tests::SELF_SIGNED_CERT_PEM,
+            crate::utils::tests::SELF_SIGNED_CERT_PEM,
+        ])
+        .unwrap();
+        let items = parse_cert_cbor(&chain.create_cert_cbor(Some(b"ocsp"))).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].cert.der, chain.end_entity.der);
+        assert_eq!(items[0].ocsp, Some(b"ocsp".to_vec()));
+        assert_eq!(items[0].sct, None);
+        assert_eq!(items[1].cert.der, chain.issuers[0].der);
+        assert_eq!(items[1].ocsp, None);
+        assert!(parse_cert_cbor(b"").is_err());
+    }
+    #[test]
+    fn creates_cert_cbor_without_ocsp() {
+        let chain =
+            CertificateChain::from_pem_files(&[crate::utils::tests::SELF_SIGNED_CERT_PEM]).unwrap();
+        let items = parse_cert_cbor(&chain.create_cert_cbor(None)).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].cert.der, chain.end_entity.der);
+        assert_eq!(items[0].ocsp, None);
+    }
+    #[test]
+    fn parses_certificate_request_domains() {
+        // Generated with:
+        //    openssl req -new -sha256 -key $KEY -subj "/CN=example.com/O=Test/C=US" \
+        //      -addext "subjectAltName=DNS:example.com,DNS:www.example.com"
+        const CSR_PEM: &str = "
+-----BEGIN CERTIFICATE REQUEST-----
+MIIBKDCBzgIBADAyMRQwEgYDVQQDDAtleGFtcGxlLmNvbTENMAsGA1UECgwEVGVz
+dDELMAkGA1UEBhMCVVMwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAAQpIwoOTL+U
+5AS6f009soWv8ZeZW65JNEwL4CVWii1bnfvRfu9VhQKEfBZvejT+LwCP4r9F/Y1Y
+7VXjpCaBZsguoDowOAYJKoZIhvcNAQkOMSswKTAnBgNVHREEIDAeggtleGFtcGxl
+LmNvbYIPd3d3LmV4YW1wbGUuY29tMAoGCCqGSM49BAMCA0kAMEYCIQCPDjun1Ub/
+KQtDmI13Gnaw2JBm2OuTB0XJjiIDEj1pygIhAPCqSvGv0BEoPVq0PMkWS7ad5E6j
+e+YTSbYqacD+Vi81
+-----END CERTIFICATE REQUEST-----";
+        let der = get_der_from_pem(CSR_PEM, "CERTIFICATE REQUEST").unwrap();
+        assert_eq!(
+            certificate_request_domains(&der).unwrap(),
+            vec!["example.com", "www.example.com"]
+        );
+        // Generated with:
+        //    openssl req -new -sha256 -key $KEY -subj "/CN=192.0.2.1" \
+        //      -addext "subjectAltName=IP:192.0.2.1,IP:2001:db8::1"
+        const IP_CSR_PEM: &str = "
+-----BEGIN CERTIFICATE REQUEST-----
+MIIBBDCBqgIBADAUMRIwEAYDVQQDDAkxOTIuMC4yLjEwWTATBgcqhkjOPQIBBggq
+hkjOPQMBBwNCAAQSbcieLVrCaWxp3iDCS8nNE2FYQfisYllJTpc7oDk8ySbFJi8n
+6VtXy+oUSfMsJ8rWsZ6kM4RmzV703hh7crI6oDQwMgYJKoZIhvcNAQkOMSUwIzAh
+BgNVHREEGjAYhwTAAAIBhxAgAQ24AAAAAAAAAAAAAAABMAoGCCqGSM49BAMCA0kA
+MEYCIQChQlXAlKxTTQziwvs9PuSpjTo2Xu9wClWeImC3ESFvkAIhAJsS/7Ofzsl2
+XuszBbureUCpE9tsbe3mcxWxSIvJFlj2
+-----END CERTIFICATE REQUEST-----";
+        let der = get_der_from_pem(IP_CSR_PEM, "CERTIFICATE REQUEST").unwrap();
+        assert_eq!(
+            certificate_request_domains(&der).unwrap(),
+            vec!["192.0.2.1", "2001:db8::1"]
+        );
+        assert!(certificate_request_domains(b"csr").is_err());
+    }
+    #[test]
+    fn parses_sct_list() {
+        let sct = [
+            vec![0],
+            vec![7; 32],
+            1_600_000_000_000u64.to_be_bytes().to_vec(),
+        ]
+        .concat();
+        let list = [
+            ((sct.len() + 2) as u16).to_be_bytes().to_vec(),
+            (sct.len() as u16).to_be_bytes().to_vec(),
+            sct,
+        ]
+        .concat();
+        assert_eq!(
+            parse_sct_list(&list).unwrap(),
+            vec![SignedCertificateTimestamp {
+                log_id: vec![7; 32],
+                timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+            }]
+        );
+        assert!(parse_sct_list(&list[..list.len() - 1]).is_err());
+    }
+    #[test]
+    fn parses_pem_blocks() {
+        let cert_pem = crate::utils::tests::SELF_SIGNED_CERT_PEM;
+        let cert_der = parse_pem_blocks(cert_pem).unwrap().remove(0).der;
+        let variants = [
+            format!("{}\n{}", cert_pem, cert_pem),
+            format!("{}\n{}", cert_pem, cert_pem).replace('\n', "\r\n"),
+            format!("\u{feff}{}\n{}", cert_pem, cert_pem),
+        ];
+        for text in variants.iter() {
+            let blocks = parse_pem_blocks(text).unwrap();
+            assert_eq!(blocks.len(), 2);
+            assert_eq!(blocks[0].tag, "CERTIFICATE");
+            assert_eq!(blocks[0].der, cert_der);
+            assert_eq!(first_certificate(text).unwrap().der, cert_der);
+            assert_eq!(certificate_chain(text).unwrap().len(), 2);
+        }
+    }
+    #[test]
+    fn parses_bom_prefixed_certificate_chain() {
+        let text = format!(
+            "\u{feff}\r\n{}\r\n",
+            crate::utils::tests::SELF_SIGNED_CERT_PEM.replace('\n', "\r\n")
+        );
+        let chain = CertificateChain::from_pem_files(&[&text]).unwrap();
+        assert_eq!(chain.basename, crate::utils::tests::SELF_SIGNED_CERT_SHA256);
+    }
+    #[test]
+    fn certificate_chain_rejects_other_blocks() {
+        let text = format!(
+            "{}\n-----BEGIN EC PARAMETERS-----\nBggqhkjOPQMBBw==\n-----END EC PARAMETERS-----",
+            crate::utils::tests::SELF_SIGNED_CERT_PEM
+        );
+        assert!(first_certificate(&text).is_ok());
+        assert!(certificate_chain(&text).is_err());
+        assert!(private_key(&text).is_err());
+    }
+    #[test]
+    fn certificate_metadata() {
+        let certificate =
+            CertificateChain::from_pem_files(&[crate::utils::tests::SELF_SIGNED_CERT_PEM])
+                .unwrap()
+                .end_entity;
+        assert_eq!(
+            certificate.not_after().unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1637194670)
+        );
+        assert_eq!(
+            certificate.serial_number().unwrap(),
+            "2f:f0:fa:b7:f9:77:3a:b4:91:08:8d:0a:94:23:fb:cc:7d:54:e7:fb"
+        );
+        assert_eq!(
+            certificate.subject_alt_names().unwrap(),
+            vec!["example.org"]
+        );
+        assert_eq!(
+            certificate.info().unwrap(),
+            CertificateInfo {
+                subject: "CN=example.org, O=Test, C=US".to_string(),
+                subject_alt_names: vec!["example.org".to_string()],
+                serial_number: certificate.serial_number().unwrap(),
+                issuer: "CN=example.org, O=Test, C=US".to_string(),
+                not_before: SystemTime::UNIX_EPOCH + Duration::from_secs(1629418670),
+                not_after: certificate.not_after().unwrap(),
+            }
+        );
+        let public_key = certificate.public_key().unwrap();
+        assert_bytes_eq_hex(
+            public_key.x,
+            "de:38:9b:4f:27:02:93:db:62:7d:31:60:e8:2c:a2:52:
+             2a:dd:4a:56:e5:a8:ba:28:7c:40:bb:07:42:38:20:ef",
+        );
+        assert_bytes_eq_hex(
+            public_key.y,
+            "40:e7:67:f0:0d:88:d9:7f:01:92:76:ef:fa:8e:2d:0c:
+             43:5f:b5:f4:eb:eb:dd:df:43:bd:74:9a:ee:93:75:8f",
+        );
+    }
+    #[test]
+    fn can_sign_http_exchanges() {
+        let certificate = first_certificate(crate::utils::tests::SELF_SIGNED_CERT_PEM).unwrap();
+        assert!(certificate.can_sign_http_exchanges().unwrap());
+        // Generated with:
+        //   openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+        //     -keyout /dev/null -subj /CN=example.org -days 365
+        const TLS_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBgjCCASegAwIBAgIULFhzHVqkyvCFYTlvWGuwHYMFut8wCgYIKoZIzj0EAwIw
+FjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcwHhcNMjYxMDE2MDkzNzUxWhcNMjcxMDE2
+MDkzNzUxWjAWMRQwEgYDVQQDDAtleGFtcGxlLm9yZzBZMBMGByqGSM49AgEGCCqG
+SM49AwEHA0IABIdfEPK1hEed5hKF9Dy0EhvuTxCmsDU+zNFD6lfTM3Zspmj6WLkP
+E8kvSxP2Hfdcc67avSHvXQYRIKrZmxUaZQWjUzBRMB0GA1UdDgQWBBSUO3wCZE31
+uGkZoBA2F9/F8XOaeDAfBgNVHSMEGDAWgBSUO3wCZE31uGkZoBA2F9/F8XOaeDAP
+BgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0kAMEYCIQDUeYrlL92tn1hAI6DY
+OgPetNtFO+s9UJ+SdzcgLkZObwIhALunWDRr7jZzR3AdpQrVVg0mu+uXNos9kZOX
+E8GdDh/U
+-----END CERTIFICATE-----";
+        let certificate = first_certificate(TLS_CERT_PEM).unwrap();
+        assert!(!certificate.can_sign_http_exchanges().unwrap());
+    }
+    #[test]
+    fn parses_ocsp_responder_url() {
+        let certificate = first_certificate(crate::utils::tests::AIA_CERT_PEM).unwrap();
+        assert_eq!(
+            ocsp_responder_url(&certificate.der).as_deref(),
+            Some("http://ocsp.example.org")
+        );
+        let certificate = first_certificate(crate::utils::tests::SELF_SIGNED_CERT_PEM).unwrap();
+        assert_eq!(ocsp_responder_url(&certificate.der), None);
+        assert_eq!(ocsp_responder_url(b"not a certificate"), None);
+    }
+    #[test]
     fn returns_err_on_invalid_input() {
         const INVALID_PRIVKEY: &str = "
 -----BEGIN EC PARAMETERS-----
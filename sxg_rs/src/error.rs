@@ -0,0 +1,127 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::SxgStatus;
+use std::time::Duration;
+
+/// The error returned by the public entry points of this crate, such as the
+/// methods of `SxgWorker`. The variant tells which subsystem failed, so that
+/// embedders can handle each kind of failure differently. The wrapped
+/// `anyhow::Error` keeps the chain of context messages, which is displayed as
+/// is.
+///
+/// The traits implemented by embedders report their failures as `Network`
+/// for a `Fetcher`, `Storage` for a `Store` or an `HttpCache`, and `Crypto`
+/// for a `Signer`. When such a failure stops a subsystem, such as `Acme` or
+/// `Ocsp`, it is reported as that subsystem, and the original `SxgError` is
+/// found in the chain of the wrapped error.
+#[derive(Debug, thiserror::Error)]
+pub enum SxgError {
+    /// The config can't be parsed or is invalid.
+    #[error(transparent)]
+    Config(anyhow::Error),
+    /// A certificate or a private key can't be parsed or used.
+    #[error(transparent)]
+    Crypto(anyhow::Error),
+    /// The ACME state can't be read or updated, or the ACME server rejects
+    /// a request.
+    #[error(transparent)]
+    Acme(anyhow::Error),
+    /// The OCSP response can't be fetched, parsed or verified.
+    #[error(transparent)]
+    Ocsp(anyhow::Error),
+    /// A request sent by a `Fetcher` failed, such as to the origin or to a
+    /// subresource.
+    #[error(transparent)]
+    Network(anyhow::Error),
+    /// A `Store` or an `HttpCache` can't be read or written.
+    #[error(transparent)]
+    Storage(anyhow::Error),
+    /// The request or response can't be served as a signed exchange. `status`
+    /// tells whether it should be passed through unsigned.
+    #[error(transparent)]
+    Sxg(anyhow::Error),
+}
+
+impl SxgError {
+    /// Returns the wrapped error, for downcasting or adding context.
+    pub fn inner(&self) -> &anyhow::Error {
+        match self {
+            SxgError::Config(e)
+            | SxgError::Crypto(e)
+            | SxgError::Acme(e)
+            | SxgError::Ocsp(e)
+            | SxgError::Network(e)
+            | SxgError::Storage(e)
+            | SxgError::Sxg(e) => e,
+        }
+    }
+    /// Consumes the error and returns the wrapped error, dropping which
+    /// subsystem failed. Converting with `?` or `anyhow::Error::from` instead
+    /// keeps the `SxgError` in the chain, where it can be downcast.
+    pub fn into_inner(self) -> anyhow::Error {
+        match self {
+            SxgError::Config(e)
+            | SxgError::Crypto(e)
+            | SxgError::Acme(e)
+            | SxgError::Ocsp(e)
+            | SxgError::Network(e)
+            | SxgError::Storage(e)
+            | SxgError::Sxg(e) => e,
+        }
+    }
+    /// Returns the `SxgStatus` that the pipeline attached to this error, or
+    /// `SxgStatus::Error` if it didn't attach one.
+    pub fn status(&self) -> SxgStatus {
+        SxgStatus::of_error(self.inner())
+    }
+}
+
+/// Why `SxgWorker::fetch_origin` gave up on the origin response. It is found
+/// in the chain of the returned `SxgError::Network`.
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum OriginFetchError {
+    #[error("The origin did not respond within {0:?}.")]
+    Timeout(Duration),
+    #[error("The origin response body exceeds the limit of {limit} bytes.")]
+    TooLarge { limit: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn keeps_context() {
+        let e = SxgError::Config(anyhow!("root cause").context("Failed to parse config"));
+        assert_eq!(e.to_string(), "Failed to parse config");
+        assert_eq!(format!("{:#}", e), "Failed to parse config: root cause");
+        assert_eq!(e.status(), SxgStatus::Error);
+        let e = anyhow::Error::from(e);
+        assert!(matches!(
+            e.downcast_ref::<SxgError>(),
+            Some(SxgError::Config(_))
+        ));
+    }
+    #[test]
+    fn finds_status() {
+        let e = SxgError::Sxg(SxgStatus::PassthroughTooLarge.error("Too large".into()));
+        assert_eq!(e.status(), SxgStatus::PassthroughTooLarge);
+        assert_eq!(
+            SxgStatus::of_error(&anyhow::Error::from(e)),
+            SxgStatus::PassthroughTooLarge
+        );
+    }
+}
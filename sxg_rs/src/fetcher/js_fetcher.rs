@@ -12,10 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::Fetcher;
+use super::{too_large_error, with_timeout, FetchLimits, Fetcher};
 use crate::http::{HttpRequest, HttpResponse};
 use crate::utils::await_js_promise;
-use anyhow::{Error, Result};
+use crate::SxgError;
+use anyhow::Error;
 use async_trait::async_trait;
 use js_sys::Function as JsFunction;
 use wasm_bindgen::JsValue;
@@ -29,8 +30,13 @@ impl JsFetcher {
     /// [Promise](https://developer.mozilla.org/en-US/docs/Web/JavaScript/Reference/Global_Objects/Promise)
     /// of [`HttpResponse`].
     /// ```typescript
-    /// function js_function(req: HttpRequest): Promise<HttpResponse> {...}
+    /// function js_function(
+    ///   req: HttpRequest,
+    ///   maxBodyBytes?: number
+    /// ): Promise<HttpResponse | null> {...}
     /// ```
+    /// If `maxBodyBytes` is given, the function stops reading the body once
+    /// it exceeds `maxBodyBytes`, and resolves to `null`.
     /// # Panics
     /// Panics if `js_function` throws an error.
     pub fn new(js_function: JsFunction) -> Self {
@@ -40,13 +46,41 @@ impl JsFetcher {
 
 #[async_trait(?Send)]
 impl Fetcher for JsFetcher {
-    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
-        let request = JsValue::from_serde(&request)
-            .map_err(|e| Error::new(e).context("Failed to parse request."))?;
-        let response = await_js_promise(self.0.call1(&JsValue::NULL, &request)).await?;
-        let response: HttpResponse = response
-            .into_serde()
-            .map_err(|e| Error::new(e).context("Failed to serialize response."))?;
-        Ok(response)
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, SxgError> {
+        let request = serialize_request(&request)?;
+        let response = await_js_promise(self.0.call1(&JsValue::NULL, &request))
+            .await
+            .map_err(SxgError::Network)?;
+        parse_response(response)
     }
+    async fn fetch_with_limits(
+        &self,
+        request: HttpRequest,
+        limits: FetchLimits,
+    ) -> Result<HttpResponse, SxgError> {
+        let request = serialize_request(&request)?;
+        let max_body_bytes = JsValue::from_f64(limits.max_body_bytes as f64);
+        let fetch = async {
+            let response =
+                await_js_promise(self.0.call2(&JsValue::NULL, &request, &max_body_bytes))
+                    .await
+                    .map_err(SxgError::Network)?;
+            if response.is_null() {
+                return Err(too_large_error(limits.max_body_bytes));
+            }
+            parse_response(response)
+        };
+        with_timeout(fetch, limits.timeout).await
+    }
+}
+
+fn serialize_request(request: &HttpRequest) -> Result<JsValue, SxgError> {
+    JsValue::from_serde(request)
+        .map_err(|e| SxgError::Network(Error::new(e).context("Failed to parse request.")))
+}
+
+fn parse_response(response: JsValue) -> Result<HttpResponse, SxgError> {
+    response
+        .into_serde()
+        .map_err(|e| SxgError::Network(Error::new(e).context("Failed to serialize response.")))
 }
@@ -16,7 +16,8 @@
 
 use super::Fetcher;
 use crate::http::{HttpRequest, HttpResponse};
-use anyhow::{anyhow, Result};
+use crate::SxgError;
+use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
 use std::sync::Arc;
 use std::time::Duration;
@@ -55,19 +56,22 @@ pub fn create() -> (MockFetcher, MockServer) {
 
 #[async_trait(?Send)]
 impl Fetcher for MockFetcher {
-    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse> {
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, SxgError> {
         let request_url = request.url.clone();
-        self.request_sender.send(request).await?;
+        self.request_sender
+            .send(request)
+            .await
+            .map_err(|e| SxgError::Network(Error::new(e)))?;
         timeout(self.time_limit, self.response_receiver.lock().await.recv())
             .await
             .map_err(|_e| {
-                anyhow!(
+                SxgError::Network(anyhow!(
                     "Failed to get response for URL \"{}\" within time limit, \
                     did you set up \"handle_next_request\" on the MockServer side?",
                     request_url,
-                )
+                ))
             })?
-            .ok_or_else(|| anyhow!("No more message"))
+            .ok_or_else(|| SxgError::Network(anyhow!("No more message")))
     }
 }
 
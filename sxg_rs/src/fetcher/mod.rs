@@ -17,15 +17,29 @@ pub mod js_fetcher;
 pub mod mock_fetcher;
 
 use crate::http::{HttpRequest, HttpResponse};
-use anyhow::{anyhow, Result};
+use crate::{OriginFetchError, SxgError, SxgStatus};
+use anyhow::{anyhow, Error};
 use async_trait::async_trait;
+use std::future::Future;
+use std::time::Duration;
 
-/// An interface for fetching resources from network.
+/// The limits that `Fetcher::fetch_with_limits` enforces.
+#[derive(Clone, Copy, Debug)]
+pub struct FetchLimits {
+    /// How long to wait for the response. `None` waits as long as the
+    /// fetcher does.
+    pub timeout: Option<Duration>,
+    /// The largest response body to read.
+    pub max_body_bytes: usize,
+}
+
+/// An interface for fetching resources from network. Failures are reported as
+/// `SxgError::Network`.
 #[async_trait(?Send)]
 pub trait Fetcher {
-    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse>;
+    async fn fetch(&self, request: HttpRequest) -> Result<HttpResponse, SxgError>;
     /// Uses `Get` method and returns response body.
-    async fn get(&self, url: &str) -> Result<Vec<u8>> {
+    async fn get(&self, url: &str) -> Result<Vec<u8>, SxgError> {
         let request = HttpRequest {
             body: vec![],
             headers: vec![],
@@ -35,6 +49,51 @@ pub trait Fetcher {
         let response = self.fetch(request).await?;
         Ok(response.body)
     }
+    /// Like `fetch`, but gives up once `limits` are exceeded, with an error
+    /// that contains an `OriginFetchError`. The default implementation races
+    /// `fetch` against a timer, and checks the body size once `fetch` has
+    /// read all of it. Fetchers that can stop reading a body early, or whose
+    /// `fetch` blocks the thread, should override it.
+    async fn fetch_with_limits(
+        &self,
+        request: HttpRequest,
+        limits: FetchLimits,
+    ) -> Result<HttpResponse, SxgError> {
+        let response = with_timeout(self.fetch(request), limits.timeout).await?;
+        if response.body.len() > limits.max_body_bytes {
+            return Err(too_large_error(limits.max_body_bytes));
+        }
+        Ok(response)
+    }
+}
+
+/// Awaits `future`, unless `timeout` passes first, in which case returns an
+/// error that contains `OriginFetchError::Timeout`.
+pub async fn with_timeout<T>(
+    future: impl Future<Output = Result<T, SxgError>>,
+    timeout: Option<Duration>,
+) -> Result<T, SxgError> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return future.await,
+    };
+    futures::pin_mut!(future);
+    let sleep = crate::utils::sleep(timeout);
+    futures::pin_mut!(sleep);
+    match futures::future::select(future, sleep).await {
+        futures::future::Either::Left((output, _)) => output,
+        futures::future::Either::Right(_) => Err(SxgError::Network(Error::new(
+            OriginFetchError::Timeout(timeout),
+        ))),
+    }
+}
+
+/// Returns the error of a response body that exceeds `limit` bytes, which
+/// should be passed through unsigned.
+pub fn too_large_error(limit: usize) -> SxgError {
+    SxgError::Network(
+        Error::new(SxgStatus::PassthroughTooLarge).context(OriginFetchError::TooLarge { limit }),
+    )
 }
 
 pub const NULL_FETCHER: NullFetcher = NullFetcher {};
@@ -43,7 +102,7 @@ pub struct NullFetcher;
 
 #[async_trait(?Send)]
 impl Fetcher for NullFetcher {
-    async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse> {
-        Err(anyhow!("Not found"))
+    async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse, SxgError> {
+        Err(SxgError::Network(anyhow!("Not found")))
     }
 }
@@ -14,7 +14,7 @@
 
 use crate::crypto::HashAlgorithm;
 use crate::fetcher::{Fetcher, NULL_FETCHER};
-use crate::headers::Headers;
+use crate::headers::{Headers, ResponseHeaderFilter};
 use crate::http::{HttpRequest, HttpResponse, Method};
 use crate::http_cache::{HttpCache, NullCache};
 use crate::utils::signed_headers_and_payload;
@@ -29,22 +29,22 @@ pub trait HeaderIntegrityFetcher {
     async fn fetch(&self, url: &str) -> Result<String>;
 }
 
-pub fn new_fetcher<'a, C: HttpCache>(
-    subresource_fetcher: &'a dyn Fetcher,
+pub fn new_fetcher<'a, C: HttpCache, F: Fetcher + ?Sized>(
+    subresource_fetcher: &'a F,
     header_integrity_cache: C,
-    strip_response_headers: &'a BTreeSet<String>,
-) -> HeaderIntegrityFetcherImpl<'a, C> {
+    response_header_filter: impl Into<ResponseHeaderFilter<'a>>,
+) -> HeaderIntegrityFetcherImpl<'a, C, F> {
     HeaderIntegrityFetcherImpl {
         subresource_fetcher,
         header_integrity_cache,
-        strip_response_headers,
+        response_header_filter: response_header_filter.into(),
     }
 }
 
-pub struct HeaderIntegrityFetcherImpl<'a, C: HttpCache> {
-    subresource_fetcher: &'a dyn Fetcher,
+pub struct HeaderIntegrityFetcherImpl<'a, C: HttpCache, F: ?Sized = dyn Fetcher> {
+    subresource_fetcher: &'a F,
     header_integrity_cache: C,
-    strip_response_headers: &'a BTreeSet<String>,
+    response_header_filter: ResponseHeaderFilter<'a>,
 }
 
 // A synthesized error response that can be cached, to prevent overloading the
@@ -56,7 +56,9 @@ static ERROR_RESPONSE: Lazy<HttpResponse> = Lazy::new(|| HttpResponse {
 });
 
 #[async_trait(?Send)]
-impl<'a, C: HttpCache> HeaderIntegrityFetcher for HeaderIntegrityFetcherImpl<'a, C> {
+impl<'a, C: HttpCache, F: Fetcher + ?Sized> HeaderIntegrityFetcher
+    for HeaderIntegrityFetcherImpl<'a, C, F>
+{
     async fn fetch(&self, url: &str) -> Result<String> {
         let integrity_response = match self.cache_get(url).await {
             // Use cached header-integrity.
@@ -95,12 +97,12 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcher for HeaderIntegrityFetcherImpl<'a,
     }
 }
 
-impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
+impl<'a, C: HttpCache, F: Fetcher + ?Sized> HeaderIntegrityFetcherImpl<'a, C, F> {
     async fn cache_get(&self, url: &str) -> Result<HttpResponse> {
-        self.header_integrity_cache.get(url).await
+        Ok(self.header_integrity_cache.get(url).await?)
     }
     async fn cache_put(&self, url: &str, response: &HttpResponse) -> Result<()> {
-        self.header_integrity_cache.put(url, response).await
+        Ok(self.header_integrity_cache.put(url, response).await?)
     }
     /// Fetches unsigned subresource from URL.
     async fn fetch_subresource(&self, url: &str) -> Result<HttpResponse> {
@@ -116,7 +118,7 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
             method: Method::Get,
             url: url.into(),
         };
-        self.subresource_fetcher.fetch(request).await
+        Ok(self.subresource_fetcher.fetch(request).await?)
     }
     // Computes header-integrity of the given unsigned response, per the definition at
     // https://github.com/WICG/webpackage/blob/main/explainers/signed-exchange-subresource-substitution.md#identifying-exactly-one-version-of-a-signed-exchange
@@ -131,12 +133,17 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
         response: &HttpResponse,
         skip_process_link: bool,
     ) -> Result<Vec<u8>> {
+        // Only a 200 response can be substituted by its signed exchange.
+        if response.status != 200 {
+            return Err(anyhow!("The resource status code is {}.", response.status));
+        }
         let fallback_base =
             Url::parse(url).map_err(|e| Error::new(e).context("parsing fallback URL"))?;
         // TODO: Figure out how to reduce the amount of data cloned.
-        let payload_headers = Headers::new(response.headers.clone(), self.strip_response_headers);
+        let payload_headers =
+            Headers::new_filtered(response.headers.clone(), self.response_header_filter);
         let mut header_integrity_fetcher =
-            new_fetcher(&NULL_FETCHER, NullCache, self.strip_response_headers);
+            new_fetcher(&NULL_FETCHER, NullCache, self.response_header_filter);
         let (signed_headers, _) = signed_headers_and_payload(
             &fallback_base,
             response.status,
@@ -144,6 +151,8 @@ impl<'a, C: HttpCache> HeaderIntegrityFetcherImpl<'a, C> {
             &response.body,
             &mut header_integrity_fetcher,
             skip_process_link,
+            &[],
+            &BTreeSet::new(),
         )
         .await?;
         Ok([
@@ -173,9 +182,10 @@ pub mod tests {
     use super::*;
     use crate::fetcher::NULL_FETCHER;
     use crate::http_cache::NullCache;
+    use crate::SxgError;
     use anyhow::{anyhow, Result};
     use std::cell::RefCell;
-    use std::collections::HashMap;
+    use std::collections::{BTreeSet, HashMap};
 
     static EMPTY_SET: Lazy<BTreeSet<String>> = Lazy::new(BTreeSet::new);
 
@@ -199,7 +209,7 @@ pub mod tests {
 
     #[async_trait(?Send)]
     impl<'a> Fetcher for FakeFetcher<'a> {
-        async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse> {
+        async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse, SxgError> {
             Ok(self.0.clone())
         }
     }
@@ -229,16 +239,18 @@ pub mod tests {
 
     #[async_trait(?Send)]
     impl HttpCache for InMemoryCache<'_> {
-        async fn get(&self, url: &str) -> Result<HttpResponse> {
+        async fn get(&self, url: &str) -> Result<HttpResponse, SxgError> {
             self.0
-                .try_borrow()?
+                .try_borrow()
+                .map_err(|e| SxgError::Storage(Error::new(e)))?
                 .get(url)
                 .cloned()
-                .ok_or_else(|| anyhow!("not found"))
+                .ok_or_else(|| SxgError::Storage(anyhow!("not found")))
         }
-        async fn put(&self, url: &str, response: &HttpResponse) -> Result<()> {
+        async fn put(&self, url: &str, response: &HttpResponse) -> Result<(), SxgError> {
             self.0
-                .try_borrow_mut()?
+                .try_borrow_mut()
+                .map_err(|e| SxgError::Storage(Error::new(e)))?
                 .insert(url.into(), response.clone());
             Ok(())
         }
@@ -311,14 +323,16 @@ pub mod tests {
             future::BoxFuture,
             stream::{self, StreamExt},
         };
-        struct OutOfOrderCache<F: Fn() -> BoxFuture<'static, Result<HttpResponse>>>(F);
+        struct OutOfOrderCache<F: Fn() -> BoxFuture<'static, Result<HttpResponse, SxgError>>>(F);
         #[async_trait(?Send)]
-        impl<F: Fn() -> BoxFuture<'static, Result<HttpResponse>>> HttpCache for OutOfOrderCache<F> {
-            async fn get(&self, url: &str) -> Result<HttpResponse> {
+        impl<F: Fn() -> BoxFuture<'static, Result<HttpResponse, SxgError>>> HttpCache
+            for OutOfOrderCache<F>
+        {
+            async fn get(&self, url: &str) -> Result<HttpResponse, SxgError> {
                 println!("get: url = {}", url);
                 self.0().await
             }
-            async fn put(&self, url: &str, _response: &HttpResponse) -> Result<()> {
+            async fn put(&self, url: &str, _response: &HttpResponse) -> Result<(), SxgError> {
                 println!("put: url = {}", url);
                 Ok(())
             }
@@ -12,14 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::{LinkHint, VaryMode};
 use crate::header_integrity::HeaderIntegrityFetcher;
 use crate::http::HeaderFields;
 use crate::http_parser::{
     media_type::MediaType, parse_accept_header, parse_cache_control_header,
     parse_content_type_header, parse_vary_header,
 };
-use crate::link::process_link_header;
-use crate::MAX_PAYLOAD_SIZE;
+use crate::link::{
+    drop_disallowed_hints, link_hints_not_in, process_link_header, serialize_link_hints,
+    subresource_preload,
+};
+use crate::utils::console_log;
+use crate::{SxgError, SxgStatus, MAX_PAYLOAD_SIZE, SXG_CONTENT_TYPE, SXG_VERSION};
 use anyhow::{anyhow, Result};
 use once_cell::sync::Lazy;
 use serde::Deserialize;
@@ -27,7 +32,39 @@ use std::collections::{hash_map, BTreeSet, HashMap, HashSet};
 use std::time::Duration;
 use url::Url;
 
-pub struct Headers(HashMap<String, String>);
+// https://github.com/google/webpackager/blob/main/docs/cache_requirements.md
+pub(crate) const MIN_SIGNATURE_DURATION: Duration = Duration::from_secs(120);
+
+pub struct Headers(
+    HashMap<String, String>,
+    // The most specific cache-control header, remembered by `retain` for
+    // `signature_duration` in case the filter drops it.
+    Option<String>,
+);
+
+/// Which response headers are kept in the payload of a signed exchange.
+#[derive(Clone, Copy, Debug)]
+pub enum ResponseHeaderFilter<'a> {
+    /// Keeps all headers except these.
+    Strip(&'a BTreeSet<String>),
+    /// Keeps only these headers.
+    Keep(&'a BTreeSet<String>),
+}
+
+impl<'a> ResponseHeaderFilter<'a> {
+    pub(crate) fn keeps(&self, name: &str) -> bool {
+        match self {
+            ResponseHeaderFilter::Strip(names) => !names.contains(name),
+            ResponseHeaderFilter::Keep(names) => names.contains(name),
+        }
+    }
+}
+
+impl<'a> From<&'a BTreeSet<String>> for ResponseHeaderFilter<'a> {
+    fn from(strip_headers: &'a BTreeSet<String>) -> Self {
+        ResponseHeaderFilter::Strip(strip_headers)
+    }
+}
 
 // Which requestors to serve an SXG to.
 #[derive(Deserialize)]
@@ -43,12 +80,42 @@ pub enum AcceptFilter {
 // A default mobile user agent, for when the upstream request doesn't include one.
 const USER_AGENT: &str = "Mozilla/5.0 (Linux; Android 6.0.1; Nexus 5X Build/MMB29P) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/41.0.2272.96 Mobile Safari/537.36";
 
+// Sorts header fields in the canonical order of CBOR map keys, which SXG
+// requires for the signed headers: shorter names first, then bytewise. The
+// fields come from a `HashMap`, so their order is otherwise unstable.
+// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#section-3.4
+pub(crate) fn sort_canonically(fields: &mut [(&str, &str)]) {
+    fields.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+}
+
+/// Returns the header fields with lowercase names, in the order they are
+/// serialized into the signed headers.
+pub fn canonicalize_headers(headers: Vec<(&str, &str)>) -> Vec<(String, String)> {
+    let lowercase_names: Vec<String> = headers
+        .iter()
+        .map(|(name, _)| name.to_ascii_lowercase())
+        .collect();
+    let mut fields: Vec<(&str, &str)> = lowercase_names
+        .iter()
+        .zip(headers)
+        .map(|(name, (_, value))| (name.as_str(), value))
+        .collect();
+    sort_canonically(&mut fields);
+    fields
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
 impl Headers {
     pub fn new(data: HeaderFields, strip_headers: &BTreeSet<String>) -> Self {
-        let mut headers = Headers(HashMap::new());
+        Self::new_filtered(data, strip_headers.into())
+    }
+    pub fn new_filtered(data: HeaderFields, filter: ResponseHeaderFilter) -> Self {
+        let mut headers = Headers(HashMap::new(), None);
         for (mut k, v) in data {
             k.make_ascii_lowercase();
-            if !strip_headers.contains(&k) {
+            if filter.keeps(&k) {
                 match headers.0.entry(k) {
                     hash_map::Entry::Occupied(o) => {
                         let o = o.into_mut();
@@ -69,10 +136,45 @@ impl Headers {
     pub fn into_inner(self) -> HashMap<String, String> {
         self.0
     }
+    /// Drops the headers whose lowercase name doesn't satisfy `keeps`. The
+    /// cache-control headers still determine the `signature_duration`.
+    pub(crate) fn retain(&mut self, keeps: impl Fn(&str) -> bool) {
+        if self.1.is_none() {
+            self.1 = self.cache_control().cloned();
+        }
+        self.0.retain(|name, _| keeps(name));
+    }
+    /// Adds the `name` header, which must be lowercase, replacing any
+    /// existing value.
+    pub(crate) fn insert(&mut self, name: &str, value: String) {
+        self.0.insert(name.to_string(), value);
+    }
+    /// Removes the `name` header, and returns its value as the signature
+    /// duration requested by the origin. A value that is not a number of
+    /// seconds is ignored with a warning.
+    pub(crate) fn take_signature_max_age(&mut self, name: &str) -> Option<Duration> {
+        let value = self.0.remove(name)?;
+        match value.trim().parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => {
+                console_log(&format!(
+                    "Warning: Ignoring the malformed {} header \"{}\".",
+                    name, value
+                ));
+                None
+            }
+        }
+    }
+    /// Updates the `content-length` header, if present, to `length`.
+    pub(crate) fn update_content_length(&mut self, length: usize) {
+        if let Some(value) = self.0.get_mut("content-length") {
+            *value = length.to_string();
+        }
+    }
     pub fn forward_to_origin_server(
         self,
         accept_filter: AcceptFilter,
-        forwarded_header_names: &BTreeSet<String>,
+        is_forwarded: impl Fn(&str) -> bool,
     ) -> Result<HeaderFields> {
         if self.0.contains_key("authorization") {
             // We should not sign personalized content, but we cannot anonymize this request per
@@ -95,7 +197,7 @@ impl Headers {
             .0
             .into_iter()
             .filter_map(|(k, v)| {
-                let v = if forwarded_header_names.contains(&k) {
+                let v = if is_forwarded(&k) {
                     v
                 } else if k == "via" {
                     format!("{}, {}", v, via)
@@ -113,10 +215,50 @@ impl Headers {
         }
         Ok(new_headers.into_iter().collect())
     }
-    pub fn validate_as_sxg_payload(&self) -> Result<()> {
+    /// Applies `mode` to the `cookie` and `*` tokens in the `vary` header.
+    /// Caches can't serve a signed exchange that varies by cookie, and
+    /// browsers reject one that varies by anything (https://crbug.com/1250532).
+    /// An unparsable `vary` header is left as is.
+    pub fn apply_vary_mode(&mut self, mode: VaryMode) -> Result<()> {
+        let vary = match self.0.get("vary") {
+            Some(vary) => vary,
+            None => return Ok(()),
+        };
+        let tokens = match parse_vary_header(vary) {
+            Ok(tokens) => tokens,
+            Err(_) => return Ok(()),
+        };
+        let is_disallowed =
+            |token: &&str| DISALLOWED_VARY_TOKENS.contains(&token.to_ascii_lowercase().as_str());
+        if !tokens.iter().any(is_disallowed) {
+            return Ok(());
+        }
+        match mode {
+            VaryMode::Refuse => Err(SxgStatus::PassthroughUncacheable.error(format!(
+                r#"The response varies by cookie or anything, because its "vary" header is "{}"."#,
+                vary
+            ))),
+            VaryMode::Strip => {
+                let kept: Vec<&str> = tokens.into_iter().filter(|t| !is_disallowed(t)).collect();
+                if kept.is_empty() {
+                    self.0.remove("vary");
+                } else {
+                    let kept = kept.join(", ");
+                    self.0.insert("vary".to_string(), kept);
+                }
+                Ok(())
+            }
+            VaryMode::Keep => Ok(()),
+        }
+    }
+    pub fn validate_as_sxg_payload(&self) -> Result<(), SxgError> {
+        self.validate_as_sxg_payload_impl().map_err(SxgError::Sxg)
+    }
+    fn validate_as_sxg_payload_impl(&self) -> Result<()> {
         for (k, v) in self.0.iter() {
             if DONT_SIGN_RESPONSE_HEADERS.contains(k.as_str()) {
-                return Err(anyhow!(r#"A stateful header "{}" is found."#, k));
+                return Err(SxgStatus::PassthroughUncacheable
+                    .error(format!(r#"A stateful header "{}" is found."#, k)));
             }
             if CACHE_CONTROL_HEADERS_SET.contains(k.as_str()) {
                 // `private` and `no-store` are disallowed by
@@ -127,19 +269,22 @@ impl Headers {
                     || v.contains("no-cache")
                     || v.contains("max-age=0")
                 {
-                    return Err(anyhow!(r#"The {} header is "{}"."#, k, v));
+                    return Err(SxgStatus::PassthroughUncacheable
+                        .error(format!(r#"The {} header is "{}"."#, k, v)));
                 }
             }
-            // TODO: Remove this section once https://crbug.com/1250532 is fixed in most clients.
-            if let Some(vary) = self.0.get("vary") {
-                if let Ok(directives) = parse_vary_header(vary) {
-                    if directives.contains(&"*") {
-                        return Err(anyhow!(
-                            "The response may vary by anything,\
-                            because its \"vary\" header is \"{}\".",
-                            vary
-                        ));
-                    }
+        }
+        // Browsers reject a signed exchange that varies by anything, whatever
+        // the `VaryMode`.
+        // TODO: Remove this section once https://crbug.com/1250532 is fixed in most clients.
+        if let Some(vary) = self.0.get("vary") {
+            if let Ok(directives) = parse_vary_header(vary) {
+                if directives.contains(&"*") {
+                    return Err(SxgStatus::PassthroughUncacheable.error(format!(
+                        "The response may vary by anything, \
+                        because its \"vary\" header is \"{}\".",
+                        vary
+                    )));
                 }
             }
         }
@@ -147,11 +292,10 @@ impl Headers {
         if let Some(size) = self.0.get("content-length") {
             if let Ok(size) = size.parse::<usize>() {
                 if size > MAX_PAYLOAD_SIZE {
-                    return Err(anyhow!(
+                    return Err(SxgStatus::PassthroughTooLarge.error(format!(
                         "The content-length header is {}, which exceeds the limit {}.",
-                        size,
-                        MAX_PAYLOAD_SIZE
-                    ));
+                        size, MAX_PAYLOAD_SIZE
+                    )));
                 }
             }
         }
@@ -162,10 +306,83 @@ impl Headers {
         }
         Ok(())
     }
+    // Returns the value of the signed link header: the origin's link header
+    // (with preloads given header-integrity unless skip_process_link), followed
+    // by the configured link_hints that the origin didn't already emit. This
+    // is the only part of the signed headers that may need to fetch
+    // subresources, and it doesn't depend on the payload body, so callers may
+    // run it concurrently with the MICE encoding.
+    pub(crate) async fn get_signed_link(
+        &self,
+        fallback_url: &Url,
+        header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
+        skip_process_link: bool,
+        link_hints: &[LinkHint],
+        allowed_origins: &BTreeSet<String>,
+    ) -> String {
+        if skip_process_link {
+            return self.get_unprocessed_signed_link(fallback_url, link_hints, allowed_origins);
+        }
+        let origin_link = self.0.get("link").map(String::as_str).unwrap_or_default();
+        let origin_link = if origin_link.is_empty() {
+            String::new()
+        } else {
+            process_link_header(
+                origin_link,
+                fallback_url,
+                header_integrity_fetcher,
+                allowed_origins,
+            )
+            .await
+        };
+        let (preloads, other_hints): (Vec<&LinkHint>, Vec<&LinkHint>) =
+            link_hints_not_in(link_hints, &origin_link, fallback_url)
+                .into_iter()
+                .partition(|hint| hint.rel == "preload");
+        join_link_values(&[
+            &origin_link,
+            &subresource_preload(
+                preloads,
+                fallback_url,
+                header_integrity_fetcher,
+                allowed_origins,
+            )
+            .await,
+            &drop_disallowed_hints(
+                &serialize_link_hints(other_hints, fallback_url),
+                fallback_url,
+                allowed_origins,
+            ),
+        ])
+    }
+    // Like get_signed_link with skip_process_link, which needs no I/O. Only
+    // the preloads and prefetches outside allowed_origins are dropped.
+    pub(crate) fn get_unprocessed_signed_link(
+        &self,
+        fallback_url: &Url,
+        link_hints: &[LinkHint],
+        allowed_origins: &BTreeSet<String>,
+    ) -> String {
+        let origin_link = self.0.get("link").map(String::as_str).unwrap_or_default();
+        let link = join_link_values(&[
+            origin_link,
+            &serialize_link_hints(
+                link_hints_not_in(link_hints, origin_link, fallback_url),
+                fallback_url,
+            ),
+        ]);
+        if link.is_empty() {
+            return link;
+        }
+        drop_disallowed_hints(&link, fallback_url, allowed_origins)
+    }
     // Returns the signed headers via the serializer callback instead of return
-    // value, because it contains a mix of &str and String. This makes it easy
-    // to test the intermediate Vec<(&str, &str)> without sacrificing
-    // performance by copying it into a Vec<(String, String)>.
+    // value, because it contains a mix of &str and String. The serializer also
+    // receives the status code, which is signed as the `:status`
+    // pseudo-header. This makes it easy to test the intermediate
+    // Vec<(&str, &str)> without sacrificing performance by copying it into a
+    // Vec<(String, String)>.
+    #[allow(clippy::too_many_arguments)]
     async fn get_signed_headers<O, S>(
         &self,
         fallback_url: &Url,
@@ -174,26 +391,42 @@ impl Headers {
         header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
         serializer: S,
         skip_process_link: bool,
+        link_hints: &[LinkHint],
+        allowed_origins: &BTreeSet<String>,
     ) -> O
     where
-        S: Fn(Vec<(&str, &str)>) -> O,
+        S: Fn(u16, Vec<(&str, &str)>) -> O,
+    {
+        let link = self
+            .get_signed_link(
+                fallback_url,
+                header_integrity_fetcher,
+                skip_process_link,
+                link_hints,
+                allowed_origins,
+            )
+            .await;
+        self.get_signed_headers_with_link(&link, status_code, mice_digest, serializer)
+    }
+    // Like get_signed_headers, but with the link header already computed by
+    // get_signed_link.
+    fn get_signed_headers_with_link<O, S>(
+        &self,
+        link: &str,
+        status_code: u16,
+        mice_digest: &[u8],
+        serializer: S,
+    ) -> O
+    where
+        S: Fn(u16, Vec<(&str, &str)>) -> O,
     {
         let connection = self.connection_headers();
-        let mut fields: Vec<(&str, &str)> = vec![];
-        let html = self.0.get("content-type").map_or(false, |t|
-            matches!(parse_content_type_header(t),
-                     Ok(MediaType {primary_type, sub_type, ..})
-                         if primary_type.eq_ignore_ascii_case("text") && sub_type.eq_ignore_ascii_case("html")));
-        let link;
-        match (skip_process_link, self.0.get("link")) {
-            (false, Some(value)) => {
-                link = process_link_header(value, fallback_url, header_integrity_fetcher).await;
-                if !link.is_empty() {
-                    fields.push(("link", &link));
-                }
-            }
-            (true, Some(value)) if !value.is_empty() => fields.push(("link", value)),
-            _ => (),
+        let mut fields: Vec<(&str, &str)> = Vec::with_capacity(self.0.len() + 4);
+        let html = matches!(self.0.get("content-type").map(|t| parse_content_type_header(t)),
+                            Some(Ok(MediaType {primary_type, sub_type, ..}))
+                                if primary_type.eq_ignore_ascii_case("text") && sub_type.eq_ignore_ascii_case("html"));
+        if !link.is_empty() {
+            fields.push(("link", link));
         }
         for (k, v) in self.0.iter() {
             if STRIP_RESPONSE_HEADERS.contains(k.as_str())
@@ -214,13 +447,12 @@ impl Headers {
             }
             fields.push((k, v));
         }
-        let status_code = status_code.to_string();
-        let digest = format!("mi-sha256-03={}", ::base64::encode(&mice_digest));
-        fields.push((":status", &status_code));
+        let digest = crate::mice::digest_header_value(mice_digest);
         fields.push(("content-encoding", "mi-sha256-03"));
         fields.push(("digest", &digest));
-        serializer(fields)
+        serializer(status_code, fields)
     }
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_signed_headers_bytes(
         &self,
         fallback_url: &Url,
@@ -228,31 +460,33 @@ impl Headers {
         mice_digest: &[u8],
         header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
         skip_process_link: bool,
+        link_hints: &[LinkHint],
+        allowed_origins: &BTreeSet<String>,
     ) -> Vec<u8> {
         self.get_signed_headers(
             fallback_url,
             status_code,
             mice_digest,
             header_integrity_fetcher,
-            |fields| {
-                use crate::cbor::DataItem;
-                let cbor_data = DataItem::Map(
-                    fields
-                        .iter()
-                        .map(|(key, value)| {
-                            (
-                                DataItem::ByteString(key.as_bytes()),
-                                DataItem::ByteString(value.as_bytes()),
-                            )
-                        })
-                        .collect(),
-                );
-                cbor_data.serialize()
-            },
+            |status_code, fields| crate::sxg::build_response_map(status_code, &fields),
             skip_process_link,
+            link_hints,
+            allowed_origins,
         )
         .await
     }
+    /// Like `get_signed_headers_bytes`, but with the link header already
+    /// computed by `get_signed_link`.
+    pub(crate) fn get_signed_headers_bytes_with_link(
+        &self,
+        link: &str,
+        status_code: u16,
+        mice_digest: &[u8],
+    ) -> Vec<u8> {
+        self.get_signed_headers_with_link(link, status_code, mice_digest, |status_code, fields| {
+            crate::sxg::build_response_map(status_code, &fields)
+        })
+    }
     // Connection-specific headers per
     // https://datatracker.ietf.org/doc/html/rfc7230#section-6.1.
     // These headers should be removed before signing per
@@ -273,14 +507,9 @@ impl Headers {
         // Default to 7 days unless a cache-control directive lowers it.
         // Only look at the most specific cache-control header present. This follows the requirement
         // in https://datatracker.ietf.org/doc/html/draft-cdn-control-header-01#section-2.1.
-        let value = CACHE_CONTROL_HEADERS
-            .iter()
-            .find_map(|name| self.0.get(*name));
-        if let Some(value) = value {
+        if let Some(value) = self.cache_control() {
             if let Ok(duration) = parse_cache_control_header(value) {
-                // https://github.com/google/webpackager/blob/main/docs/cache_requirements.md
-                const MIN_DURATION: Duration = Duration::from_secs(120);
-                return if duration >= MIN_DURATION {
+                return if duration >= MIN_SIGNATURE_DURATION {
                     Ok(duration)
                 } else {
                     Err(anyhow!("Validity duration is too short."))
@@ -289,6 +518,13 @@ impl Headers {
         }
         Ok(Duration::MAX)
     }
+    fn cache_control(&self) -> Option<&String> {
+        self.1.as_ref().or_else(|| {
+            CACHE_CONTROL_HEADERS
+                .iter()
+                .find_map(|name| self.0.get(*name))
+        })
+    }
 }
 
 // These headers are always stripped before signing, but preserved when serving unsigned (e.g.
@@ -320,6 +556,9 @@ static STRIP_RESPONSE_HEADERS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     .collect()
 });
 
+// The tokens of the `vary` header that `VaryMode` applies to.
+const DISALLOWED_VARY_TOKENS: [&str; 2] = ["cookie", "*"];
+
 // These headers don't affect the semantics of the response inside an
 // SXG, but they vary frequently. This prevents the SXG from being used
 // as a subresource due to the header-integrity requirement:
@@ -387,48 +626,73 @@ static CACHE_CONTROL_HEADERS: Lazy<Vec<&'static str>> = Lazy::new(|| {
 static CACHE_CONTROL_HEADERS_SET: Lazy<HashSet<&'static str>> =
     Lazy::new(|| CACHE_CONTROL_HEADERS.clone().into_iter().collect());
 
+// Joins the non-empty values into a single link header.
+fn join_link_values(values: &[&str]) -> String {
+    let mut link = String::new();
+    for value in values.iter().filter(|value| !value.is_empty()) {
+        if !link.is_empty() {
+            link.push(',');
+        }
+        link.push_str(value);
+    }
+    link
+}
+
 // Checks whether to serve SXG based on the Accept header of the HTTP request.
-// Returns Ok iff the input string has a `application/signed-exchange;v=b3`,
-// and either accept_filter != PrefersSxg or its `q` value is 1.
-fn validate_accept_header(accept: &str, accept_filter: AcceptFilter) -> Result<()> {
+// Returns Ok iff the input string has a `application/signed-exchange` of
+// version SXG_VERSION, and either accept_filter != PrefersSxg or its `q` value
+// is 1. Other versions are ignored, because this crate can't create them.
+pub(crate) fn validate_accept_header(accept: &str, accept_filter: AcceptFilter) -> Result<()> {
     let accept = accept.trim();
     let accept = parse_accept_header(accept)?;
     if accept.is_empty() {
         return Err(anyhow!("Accept header is empty"));
     }
-    let q_sxg = accept
+    // The q values of each version of SXG, where an absent version is "".
+    let sxg_versions: Vec<(&str, u16)> = accept
         .iter()
-        .filter_map(|t| {
-            if t.media_range
+        .filter(|t| {
+            t.media_range
                 .primary_type
                 .eq_ignore_ascii_case("application")
                 && t.media_range
                     .sub_type
                     .eq_ignore_ascii_case("signed-exchange")
-            {
-                let mut v = "";
-                for param in &t.media_range.parameters {
-                    if param.name.eq_ignore_ascii_case("v") {
-                        v = &param.value;
-                    }
-                }
-                if v == "b3" {
-                    Some(t.q_millis)
-                } else {
-                    None
+        })
+        .map(|t| {
+            let mut v = "";
+            for param in &t.media_range.parameters {
+                if param.name.eq_ignore_ascii_case("v") {
+                    v = &param.value;
                 }
-            } else {
-                None
             }
+            (v, t.q_millis)
         })
+        .collect();
+    let q_sxg = sxg_versions
+        .iter()
+        .filter(|(v, _)| *v == SXG_VERSION)
+        .map(|(_, q)| *q)
         .max()
         .unwrap_or(0);
-    const SXG: &str = "application/signed-exchange;v=b3";
     if q_sxg == 0 {
-        Err(anyhow!(
-            "The request accept header does not contain {}.",
-            SXG
-        ))
+        let unsupported_versions: Vec<&str> = sxg_versions
+            .iter()
+            .filter(|(v, q)| *v != SXG_VERSION && *q > 0)
+            .map(|(v, _)| if v.is_empty() { "(none)" } else { v })
+            .collect();
+        if unsupported_versions.is_empty() {
+            Err(anyhow!(
+                "The request accept header does not contain {}.",
+                SXG_CONTENT_TYPE
+            ))
+        } else {
+            Err(anyhow!(
+                "The request accept header asks for SXG version {}, but only {} is supported.",
+                unsupported_versions.join(", "),
+                SXG_VERSION
+            ))
+        }
     } else {
         match accept_filter {
             AcceptFilter::PrefersSxg => {
@@ -437,7 +701,7 @@ fn validate_accept_header(accept: &str, accept_filter: AcceptFilter) -> Result<(
                 } else {
                     Err(anyhow!(
                         "The q value of {} is less than 1 in request Accept header.",
-                        SXG
+                        SXG_CONTENT_TYPE
                     ))
                 }
             }
@@ -458,6 +722,17 @@ mod tests {
             .map(|(k, v)| (k.to_string(), v.to_string()))
             .collect()
     }
+    // Serializes the signed headers as the signing path does, and reads them
+    // back, including `:status`.
+    fn response_fields<T: FromIterator<(String, String)>>(
+        status_code: u16,
+        fields: Vec<(&str, &str)>,
+    ) -> T {
+        crate::sxg::parse_signed_headers(&crate::sxg::build_response_map(status_code, &fields))
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
     fn headers(pairs: Vec<(&str, &str)>) -> Headers {
         Headers::new(header_fields(pairs), &BTreeSet::new())
     }
@@ -492,7 +767,7 @@ mod tests {
     fn basic_request_headers() {
         assert_eq!(
             headers(vec![("accept", "application/signed-exchange;v=b3")])
-                .forward_to_origin_server(AcceptFilter::PrefersSxg, &BTreeSet::new())
+                .forward_to_origin_server(AcceptFilter::PrefersSxg, |_| false)
                 .unwrap()
                 .into_iter()
                 .collect::<HashMap<String, String>>(),
@@ -506,7 +781,7 @@ mod tests {
                 ("accept", "application/signed-exchange;v=b3"),
                 ("authorization", "x")
             ])
-            .forward_to_origin_server(AcceptFilter::PrefersSxg, &BTreeSet::new())
+            .forward_to_origin_server(AcceptFilter::PrefersSxg, |_| false)
             .unwrap_err()
             .to_string(),
             "The request contains an Authorization header."
@@ -569,6 +844,26 @@ mod tests {
         .is_err());
     }
     #[test]
+    fn explains_unsupported_versions() {
+        let error = |accept| {
+            validate_accept_header(accept, AcceptFilter::AcceptsSxg)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("application/signed-exchange;v=b2"),
+            "The request accept header asks for SXG version b2, but only b3 is supported."
+        );
+        assert_eq!(
+            error("application/signed-exchange"),
+            "The request accept header asks for SXG version (none), but only b3 is supported."
+        );
+        assert_eq!(
+            error("text/html,application/signed-exchange;v=b2;q=0"),
+            "The request accept header does not contain application/signed-exchange;v=b3."
+        );
+    }
+    #[test]
     fn accepts_sxg() {
         // Same list as above, but some more are ok.
         assert!(validate_accept_header(
@@ -689,6 +984,53 @@ mod tests {
         .is_err());
     }
 
+    // === apply_vary_mode ===
+    fn vary_after(vary: &str, mode: VaryMode) -> Result<Option<String>> {
+        let mut headers = headers(vec![("content-type", "text/html"), ("vary", vary)]);
+        headers.apply_vary_mode(mode)?;
+        Ok(headers.0.get("vary").cloned())
+    }
+    #[test]
+    fn vary_allowed_tokens() {
+        for mode in [VaryMode::Refuse, VaryMode::Strip, VaryMode::Keep] {
+            assert_eq!(
+                vary_after("Accept-Encoding", mode).unwrap().as_deref(),
+                Some("Accept-Encoding")
+            );
+        }
+    }
+    #[test]
+    fn vary_cookie() {
+        let err = vary_after("accept-encoding, Cookie", VaryMode::Refuse).unwrap_err();
+        assert_eq!(SxgStatus::of_error(&err), SxgStatus::PassthroughUncacheable);
+        assert_eq!(
+            vary_after("accept-encoding, Cookie", VaryMode::Strip)
+                .unwrap()
+                .as_deref(),
+            Some("accept-encoding")
+        );
+        assert_eq!(
+            vary_after("accept-encoding, Cookie", VaryMode::Keep)
+                .unwrap()
+                .as_deref(),
+            Some("accept-encoding, Cookie")
+        );
+    }
+    #[test]
+    fn vary_anything() {
+        let err = vary_after("*", VaryMode::Refuse).unwrap_err();
+        assert_eq!(SxgStatus::of_error(&err), SxgStatus::PassthroughUncacheable);
+        assert_eq!(vary_after("*", VaryMode::Strip).unwrap(), None);
+        assert_eq!(
+            vary_after("*", VaryMode::Keep).unwrap().as_deref(),
+            Some("*")
+        );
+        let err = headers(vec![("content-type", "text/html"), ("vary", "*")])
+            .validate_as_sxg_payload()
+            .unwrap_err();
+        assert_eq!(err.status(), SxgStatus::PassthroughUncacheable);
+    }
+
     // === connection_headers ===
     #[test]
     fn no_connection_headers() {
@@ -802,8 +1144,10 @@ mod tests {
                 200,
                 &[],
                 &mut null_integrity_fetcher(),
-                header_fields,
+                response_fields,
                 false,
+                &[],
+                &BTreeSet::new(),
             )
             .await,
             header_fields::<HashMap<String, String>>(vec![
@@ -824,8 +1168,10 @@ mod tests {
                 200,
                 &[],
                 &mut null_integrity_fetcher(),
-                header_fields,
+                response_fields,
                 false,
+                &[],
+                &BTreeSet::new(),
             )
             .await,
             header_fields::<HashMap<String, String>>(vec![
@@ -850,8 +1196,10 @@ mod tests {
                 200,
                 &[],
                 &mut null_integrity_fetcher(),
-                header_fields,
+                response_fields,
                 false,
+                &[],
+                &BTreeSet::new(),
             )
             .await,
             header_fields::<HashMap<String, String>>(vec![
@@ -872,8 +1220,10 @@ mod tests {
                 200,
                 &[],
                 &mut null_integrity_fetcher(),
-                header_fields,
-                false
+                response_fields,
+                false,
+                &[],
+                &BTreeSet::new(),
             )
             .await,
             header_fields::<HashMap<String, String>>(vec![
@@ -884,12 +1234,120 @@ mod tests {
             ])
         );
     }
+    #[tokio::test]
+    async fn merges_origin_and_generated_preloads() {
+        use crate::link::tests::FakeIntegrityFetcher;
+        let url = Url::parse("https://foo.com").unwrap();
+        let headers = headers(vec![
+            ("content-type", "text/html"),
+            ("link", "</a.js>;rel=preload;as=script"),
+        ]);
+        let link_hints = vec![
+            LinkHint {
+                url: "/a.js".to_string(),
+                rel: "preload".to_string(),
+                r#as: "script".to_string(),
+            },
+            LinkHint {
+                url: "/b.css".to_string(),
+                rel: "preload".to_string(),
+                r#as: "style".to_string(),
+            },
+        ];
+        let mut fetcher = FakeIntegrityFetcher(Ok("sha256-blah".into()));
+        assert_eq!(
+            headers
+                .get_signed_link(&url, &mut fetcher, false, &link_hints, &BTreeSet::new())
+                .await,
+            concat!(
+                "<https://foo.com/a.js>;rel=preload;as=script,",
+                "<https://foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah,",
+                "<https://foo.com/b.css>;rel=preload;as=style,",
+                "<https://foo.com/b.css>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+            )
+        );
+        assert_eq!(
+            headers
+                .get_signed_link(&url, &mut fetcher, true, &link_hints, &BTreeSet::new())
+                .await,
+            "</a.js>;rel=preload;as=script,<https://foo.com/b.css>;rel=preload;as=style"
+        );
+    }
+    #[tokio::test]
+    async fn drops_disallowed_prefetch_hints() {
+        let url = Url::parse("https://foo.com").unwrap();
+        let headers = headers(vec![("content-type", "text/html")]);
+        let link_hints = vec![
+            LinkHint {
+                url: "/next.html".to_string(),
+                rel: "prefetch".to_string(),
+                r#as: "document".to_string(),
+            },
+            LinkHint {
+                url: "https://bar.com/next.html".to_string(),
+                rel: "prefetch".to_string(),
+                r#as: "document".to_string(),
+            },
+        ];
+        for skip_process_link in [false, true] {
+            assert_eq!(
+                headers
+                    .get_signed_link(
+                        &url,
+                        &mut null_integrity_fetcher(),
+                        skip_process_link,
+                        &link_hints,
+                        &BTreeSet::new()
+                    )
+                    .await,
+                "<https://foo.com/next.html>;rel=prefetch;as=document"
+            );
+        }
+    }
+    // The `digest` header is required whenever the payload is encoded with
+    // `mi-sha256-03`, so it is always signed, and is the MICE root digest.
+    #[test]
+    fn digest_is_mice_root() {
+        let input = "When I grow up, I want to be a watermelon".as_bytes();
+        let (mice_digest, _) = crate::mice::calculate(input, 16384);
+        let fields = headers(vec![("content-type", "text/plain")])
+            .get_signed_headers_with_link::<HashMap<String, String>, _>(
+                "",
+                200,
+                &mice_digest,
+                response_fields,
+            );
+        assert_eq!(fields["content-encoding"], "mi-sha256-03");
+        assert_eq!(
+            fields["digest"],
+            "mi-sha256-03=dcRDgR2GM35DluAV13PzgnG6+pvQwPywfFvAu1UeFrs="
+        );
+    }
 
     // === get_signed_headers_bytes ===
     #[tokio::test]
     async fn get_signed_headers_bytes() {
         let url = Url::parse("https://foo.com").unwrap();
-        assert_eq!(headers(vec![("content-type", "image/jpeg")]).get_signed_headers_bytes(&url, 200, &[], &mut null_integrity_fetcher(), false).await,
+        assert_eq!(headers(vec![("content-type", "image/jpeg")]).get_signed_headers_bytes(&url, 200, &[], &mut null_integrity_fetcher(), false, &[], &BTreeSet::new()).await,
                    b"\xA4FdigestMmi-sha256-03=G:statusC200Lcontent-typeJimage/jpegPcontent-encodingLmi-sha256-03");
     }
+    #[test]
+    fn canonicalizes_headers() {
+        assert_eq!(
+            canonicalize_headers(vec![
+                ("Content-Type", "text/html"),
+                ("digest", "mi-sha256-03=x"),
+                ("X-B", "2"),
+                (":status", "200"),
+                ("x-a", "1"),
+            ]),
+            vec![
+                ("x-a".to_string(), "1".to_string()),
+                ("x-b".to_string(), "2".to_string()),
+                ("digest".to_string(), "mi-sha256-03=x".to_string()),
+                (":status".to_string(), "200".to_string()),
+                ("content-type".to_string(), "text/html".to_string()),
+            ]
+        );
+    }
 }
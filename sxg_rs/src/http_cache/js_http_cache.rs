@@ -14,6 +14,7 @@
 
 use super::HttpCache;
 use crate::http::HttpResponse;
+use crate::SxgError;
 use anyhow::{anyhow, Error, Result};
 use async_trait::async_trait;
 use js_sys::Function as JsFunction;
@@ -26,7 +27,18 @@ pub struct JsHttpCache {
 
 #[async_trait(?Send)]
 impl HttpCache for JsHttpCache {
-    async fn get(&self, url: &str) -> Result<HttpResponse> {
+    async fn get(&self, url: &str) -> Result<HttpResponse, SxgError> {
+        self.call_get(url).await.map_err(SxgError::Storage)
+    }
+    async fn put(&self, url: &str, response: &HttpResponse) -> Result<(), SxgError> {
+        self.call_put(url, response)
+            .await
+            .map_err(SxgError::Storage)
+    }
+}
+
+impl JsHttpCache {
+    async fn call_get(&self, url: &str) -> Result<HttpResponse> {
         let url = JsValue::from_serde(&url)
             .map_err(|e| Error::new(e).context("serializing url to JS"))?;
         let this = JsValue::null();
@@ -43,7 +55,7 @@ impl HttpCache for JsHttpCache {
             .map_err(|e| Error::new(e).context("parsing response from JS"))?;
         Ok(response)
     }
-    async fn put(&self, url: &str, response: &HttpResponse) -> Result<()> {
+    async fn call_put(&self, url: &str, response: &HttpResponse) -> Result<()> {
         let url = JsValue::from_serde(&url)
             .map_err(|e| Error::new(e).context("serializing url to JS"))?;
         let response = JsValue::from_serde(&response)
@@ -55,8 +67,7 @@ impl HttpCache for JsHttpCache {
             .map_err(|_| anyhow!("Error invoking JS put"))?;
         let ret = wasm_bindgen_futures::JsFuture::from(js_sys::Promise::from(ret));
         let ret = ret.await.map_err(|_| anyhow!("Error returned by JS put"))?;
-        let _ret = ret
-            .into_serde()
+        ret.into_serde::<()>()
             .map_err(|e| Error::new(e).context("parsing ack from JS"))?;
         Ok(())
     }
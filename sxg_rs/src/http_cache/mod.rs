@@ -16,24 +16,99 @@
 pub mod js_http_cache;
 
 use crate::http::HttpResponse;
-use anyhow::{anyhow, Result};
+use crate::http_parser::parse_cache_control_header;
+use crate::runtime::store::Store;
+use crate::SxgError;
+use anyhow::{anyhow, Error};
 use async_trait::async_trait;
 
-/// An interface for storing HTTP responses in a cache.
+/// An interface for storing HTTP responses in a cache. Failures, including
+/// cache misses, are reported as `SxgError::Storage`.
 #[async_trait(?Send)]
 pub trait HttpCache {
-    async fn get(&self, url: &str) -> Result<HttpResponse>;
-    async fn put(&self, url: &str, response: &HttpResponse) -> Result<()>;
+    async fn get(&self, url: &str) -> Result<HttpResponse, SxgError>;
+    async fn put(&self, url: &str, response: &HttpResponse) -> Result<(), SxgError>;
 }
 
 pub struct NullCache;
 
 #[async_trait(?Send)]
 impl HttpCache for NullCache {
-    async fn get(&self, _url: &str) -> Result<HttpResponse> {
-        Err(anyhow!("No cache entry found in NullCache"))
+    async fn get(&self, _url: &str) -> Result<HttpResponse, SxgError> {
+        Err(SxgError::Storage(anyhow!(
+            "No cache entry found in NullCache"
+        )))
     }
-    async fn put(&self, _url: &str, _response: &HttpResponse) -> Result<()> {
+    async fn put(&self, _url: &str, _response: &HttpResponse) -> Result<(), SxgError> {
         Ok(())
     }
 }
+
+/// Caches responses in a `Store`, for platforms that have a key-value store
+/// but no HTTP cache. Responses without a freshness lifetime in their
+/// `cache-control` header are not cached.
+pub struct StoreHttpCache<'a> {
+    store: &'a dyn Store,
+}
+
+impl<'a> StoreHttpCache<'a> {
+    pub fn new(store: &'a dyn Store) -> Self {
+        StoreHttpCache { store }
+    }
+}
+
+const STORE_KEY_PREFIX: &str = "http-cache:";
+
+#[async_trait(?Send)]
+impl<'a> HttpCache for StoreHttpCache<'a> {
+    async fn get(&self, url: &str) -> Result<HttpResponse, SxgError> {
+        let key = format!("{}{}", STORE_KEY_PREFIX, url);
+        let value = self
+            .store
+            .get(&key)
+            .await?
+            .ok_or_else(|| SxgError::Storage(anyhow!("No cache entry found for {}", url)))?;
+        serde_json::from_str(&value).map_err(|e| SxgError::Storage(Error::new(e)))
+    }
+    async fn put(&self, url: &str, response: &HttpResponse) -> Result<(), SxgError> {
+        let ttl = response
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("cache-control"))
+            .and_then(|(_, value)| parse_cache_control_header(value).ok());
+        if let Some(ttl) = ttl {
+            let key = format!("{}{}", STORE_KEY_PREFIX, url);
+            let value =
+                serde_json::to_string(response).map_err(|e| SxgError::Storage(Error::new(e)))?;
+            self.store.put_with_ttl(&key, &value, ttl).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::store::InMemoryStore;
+    #[tokio::test]
+    async fn store_http_cache() {
+        let store = InMemoryStore::new(std::time::SystemTime::UNIX_EPOCH);
+        let cache = StoreHttpCache::new(&store);
+        let response = HttpResponse {
+            body: b"body".to_vec(),
+            headers: vec![("cache-control".into(), "max-age=3600".into())],
+            status: 200,
+        };
+        cache.put("https://foo.com/a", &response).await.unwrap();
+        let cached = cache.get("https://foo.com/a").await.unwrap();
+        assert_eq!(cached.body, response.body);
+        assert_eq!(cached.headers, response.headers);
+        assert!(cache.get("https://foo.com/b").await.is_err());
+        let uncacheable = HttpResponse {
+            headers: vec![],
+            ..response
+        };
+        cache.put("https://foo.com/c", &uncacheable).await.unwrap();
+        assert!(cache.get("https://foo.com/c").await.is_err());
+    }
+}
@@ -50,7 +50,7 @@ pub fn accept(input: &str) -> IResult<&str, Accept<'_>> {
     })(input)
 }
 
-fn parse_q_millis(s: &str) -> Option<u16> {
+pub fn parse_q_millis(s: &str) -> Option<u16> {
     let x = s.parse::<f64>().ok()?;
     if (0.0..=1.0).contains(&x) {
         Some((x * 1000.0) as u16)
@@ -0,0 +1,79 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// https://datatracker.ietf.org/doc/html/rfc7231#section-5.3.4
+
+use super::accept::parse_q_millis;
+use super::base::{ows, token};
+use super::media_type::parameter;
+use nom::{
+    character::complete::char as char1,
+    combinator::map,
+    multi::many0,
+    sequence::{preceded, separated_pair, tuple},
+    IResult,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct AcceptEncoding<'a> {
+    pub coding: &'a str,
+    pub q_millis: u16,
+}
+
+// Each member of the `Accept-Encoding` header is a content coding, or `*`,
+// optionally followed by a weight, such as `gzip;q=0.8`.
+pub fn accept_encoding(input: &str) -> IResult<&str, AcceptEncoding<'_>> {
+    map(
+        tuple((
+            token,
+            many0(preceded(separated_pair(ows, char1(';'), ows), parameter)),
+        )),
+        |(coding, parameters)| AcceptEncoding {
+            coding,
+            q_millis: parameters
+                .iter()
+                .find(|param| param.name.eq_ignore_ascii_case("q"))
+                .and_then(|param| parse_q_millis(&param.value))
+                .unwrap_or(1000),
+        },
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn it_works() {
+        assert_eq!(
+            accept_encoding("gzip").unwrap(),
+            (
+                "",
+                AcceptEncoding {
+                    coding: "gzip",
+                    q_millis: 1000,
+                }
+            )
+        );
+        assert_eq!(
+            accept_encoding("br ; q=0.5").unwrap(),
+            (
+                "",
+                AcceptEncoding {
+                    coding: "br",
+                    q_millis: 500,
+                }
+            )
+        );
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::base::{ows, token};
+use super::media_type::{parameter, Parameter};
+use nom::{
+    character::complete::char as char1,
+    combinator::map,
+    multi::many0,
+    sequence::{preceded, separated_pair, tuple},
+    IResult,
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct AmpCacheTransform<'a> {
+    pub identifier: &'a str,
+    pub parameters: Vec<Parameter<'a>>,
+}
+
+// https://github.com/ampproject/amphtml/blob/main/docs/spec/amp-cache-transform.md
+// Each member of the `AMP-Cache-Transform` header is an identifier followed by
+// parameters, such as `google;v="1..2"`.
+pub fn amp_cache_transform(input: &str) -> IResult<&str, AmpCacheTransform<'_>> {
+    map(
+        tuple((
+            token,
+            many0(preceded(separated_pair(ows, char1(';'), ows), parameter)),
+        )),
+        |(identifier, parameters)| AmpCacheTransform {
+            identifier,
+            parameters,
+        },
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn it_works() {
+        assert_eq!(
+            amp_cache_transform(r#"google;v="1..2""#).unwrap(),
+            (
+                "",
+                AmpCacheTransform {
+                    identifier: "google",
+                    parameters: vec![Parameter {
+                        name: "v",
+                        value: "1..2".to_string(),
+                    }],
+                }
+            )
+        );
+        assert_eq!(
+            amp_cache_transform("any").unwrap(),
+            (
+                "",
+                AmpCacheTransform {
+                    identifier: "any",
+                    parameters: vec![],
+                }
+            )
+        );
+    }
+}
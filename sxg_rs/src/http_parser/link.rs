@@ -97,14 +97,14 @@ fn uri_ref(input: &str) -> IResult<&str, &str> {
     })(input)
 }
 
-fn link_param<'a>(input: &'a str) -> IResult<&str, (Cow<'a, str>, Option<String>)> {
+fn link_param<'a>(input: &'a str) -> IResult<&'a str, (Cow<'a, str>, Option<String>)> {
     pair(
         map(terminated(token, ows), Cow::Borrowed),
         opt(preceded(pair(char('='), ows), parameter_value)),
     )(input)
 }
 
-pub fn link(input: &str) -> IResult<&str, Link> {
+pub fn link(input: &str) -> IResult<&str, Link<'_>> {
     map(
         pair(
             delimited(char('<'), uri_ref, char('>')),
@@ -56,7 +56,7 @@ pub struct Parameter<'a> {
 // https://tools.ietf.org/html/rfc7231#section-3.1.1.1
 // `parameter` is defined as
 //   parameter  = token "=" ( token / quoted-string )
-fn parameter(input: &str) -> IResult<&str, Parameter<'_>> {
+pub fn parameter(input: &str) -> IResult<&str, Parameter<'_>> {
     map(
         separated_pair(token, char1('='), parameter_value),
         |(name, value)| Parameter { name, value },
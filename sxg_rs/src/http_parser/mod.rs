@@ -13,6 +13,8 @@
 // limitations under the License.
 
 mod accept;
+pub mod accept_encoding;
+pub mod amp_cache_transform;
 mod base;
 mod cache_control;
 pub mod link;
@@ -20,6 +22,7 @@ pub mod media_type;
 pub mod srcset;
 
 use anyhow::{Error, Result};
+pub(crate) use base::is_tchar;
 use base::ows;
 use nom::{
     branch::alt,
@@ -49,10 +52,22 @@ where
     .map_err(format_nom_err)
 }
 
-pub fn parse_accept_header(input: &str) -> Result<Vec<accept::Accept>> {
+pub fn parse_accept_header(input: &str) -> Result<Vec<accept::Accept<'_>>> {
     parse_vec(input, accept::accept)
 }
 
+pub fn parse_accept_encoding_header(
+    input: &str,
+) -> Result<Vec<accept_encoding::AcceptEncoding<'_>>> {
+    parse_vec(input, accept_encoding::accept_encoding)
+}
+
+pub fn parse_amp_cache_transform_header(
+    input: &str,
+) -> Result<Vec<amp_cache_transform::AmpCacheTransform<'_>>> {
+    parse_vec(input, amp_cache_transform::amp_cache_transform)
+}
+
 // Returns the freshness lifetime for a shared cache.
 pub fn parse_cache_control_header(input: &str) -> Result<Duration> {
     let directives = parse_vec(input, cache_control::directive)?;
@@ -60,13 +75,13 @@ pub fn parse_cache_control_header(input: &str) -> Result<Duration> {
         .ok_or_else(|| Error::msg("Freshness lifetime is implicit"))
 }
 
-pub fn parse_content_type_header(input: &str) -> Result<media_type::MediaType> {
+pub fn parse_content_type_header(input: &str) -> Result<media_type::MediaType<'_>> {
     terminated(media_type::media_type, eof)(input)
         .map(|(_, output)| output)
         .map_err(format_nom_err)
 }
 
-pub fn parse_link_header(input: &str) -> Result<Vec<link::Link>> {
+pub fn parse_link_header(input: &str) -> Result<Vec<link::Link<'_>>> {
     parse_vec(input, link::link)
 }
 
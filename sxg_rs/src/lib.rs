@@ -13,42 +13,59 @@
 // limitations under the License.
 
 pub mod acme;
+pub mod amp;
 mod cbor;
 pub mod config;
 pub mod crypto;
+mod error;
 pub mod fetcher;
-mod header_integrity;
+pub mod header_integrity;
 pub mod headers;
 pub mod http;
 pub mod http_cache;
 mod http_parser;
 mod id_headers;
 mod link;
-mod mice;
-mod ocsp;
+pub mod mice;
+pub mod ocsp;
 pub mod process_html;
 pub mod runtime;
 pub mod serde_helpers;
 pub mod signature;
 pub mod storage;
-mod structured_header;
-mod sxg;
+pub mod structured_header;
+pub mod sxg;
 pub mod utils;
+#[cfg(feature = "rust_signer")]
+pub mod verify;
 #[cfg(feature = "wasm")]
 mod wasm_worker;
 
 use crate::http::{HeaderFields, HttpResponse};
 use anyhow::{anyhow, Error, Result};
-use config::Config;
-use crypto::CertificateChain;
-use headers::{AcceptFilter, Headers};
+use chrono::{DateTime, Utc};
+use config::{Config, Route, UrlForm};
+use crypto::{CertificateChain, CertificateInfo};
+pub use error::{OriginFetchError, SxgError};
+use fetcher::{FetchLimits, Fetcher};
+use headers::{AcceptFilter, Headers, ResponseHeaderFilter};
 use http_cache::HttpCache;
-use runtime::Runtime;
-use serde::Serialize;
-use std::collections::VecDeque;
-use std::time::Duration;
+use runtime::{metrics, GenericRuntime, Runtime};
+use serde::{Deserialize, Serialize};
+use signature::Signer;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::{Duration, SystemTime};
 use url::Url;
 
+/// Creates signed exchanges and serves the resources they refer to.
+///
+/// Methods that take a `Runtime` may perform I/O through it, such as fetching
+/// subresources, OCSP responses and ACME orders, or calling an async signer.
+/// The other methods are I/O-free and synchronous, including
+/// `create_signed_exchange_blocking`, `create_cert_cbor`,
+/// `transform_request_headers`, `transform_payload_headers`, `process_html`,
+/// `get_fallback_url` and `amp_cache_transform`.
 #[derive(Debug)]
 pub struct SxgWorker {
     config: Config,
@@ -68,6 +85,101 @@ pub enum PresetContent {
     },
 }
 
+/// Diagnostic information served at `<reserved_path>debug.json` when
+/// `respond_debug_info` is enabled in the config.
+#[derive(Serialize, Debug, Default)]
+pub struct DebugInfo {
+    pub certificate_basename: Option<String>,
+    pub ocsp_this_update: Option<DateTime<Utc>>,
+    pub ocsp_next_update: Option<DateTime<Utc>>,
+    /// Seconds until the stapled OCSP response expires. Negative if it has
+    /// already expired.
+    pub seconds_until_ocsp_expiry: Option<i64>,
+    /// Why the OCSP fields are missing, if they are.
+    pub ocsp_error: Option<String>,
+}
+
+/// The name of the response header that describes the `SxgStatus`, when
+/// `add_sxg_status_header` is enabled in the config.
+pub const SXG_STATUS_HEADER: &str = "x-sxg";
+
+/// The version of the signed exchange format created by this crate, as in the
+/// `v` parameter of its media type. Requestors that accept only other versions
+/// are served the plain response.
+pub const SXG_VERSION: &str = "b3";
+
+/// The media type of the signed exchanges created by this crate.
+pub const SXG_CONTENT_TYPE: &str = "application/signed-exchange;v=b3";
+
+/// The outcome of trying to serve a response as a signed exchange.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SxgStatus {
+    Signed,
+    /// The response can't be cached as an SXG, such as because of its status
+    /// code or its `cache-control` header.
+    PassthroughUncacheable,
+    /// The response exceeds `MAX_PAYLOAD_SIZE`.
+    PassthroughTooLarge,
+    Error,
+}
+
+impl SxgStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SxgStatus::Signed => "signed",
+            SxgStatus::PassthroughUncacheable => "passthrough-uncacheable",
+            SxgStatus::PassthroughTooLarge => "passthrough-too-large",
+            SxgStatus::Error => "error",
+        }
+    }
+    /// Returns the status that the pipeline attached to `error`, or `Error` if
+    /// it didn't attach one.
+    pub fn of_error(error: &Error) -> Self {
+        error
+            .chain()
+            .find_map(|e| {
+                e.downcast_ref::<SxgStatus>()
+                    .copied()
+                    .or_else(|| e.downcast_ref::<SxgError>().map(SxgError::status))
+            })
+            .unwrap_or(SxgStatus::Error)
+    }
+    // Creates an error with the given message, which is classified as `self`
+    // by `of_error`.
+    pub(crate) fn error(self, message: String) -> Error {
+        Error::new(self).context(message)
+    }
+}
+
+impl std::fmt::Display for SxgStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::error::Error for SxgStatus {}
+
+/// Whether to serve a requestor the signed exchange or the plain response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServeMode {
+    Sxg,
+    Plain,
+}
+
+/// Returns `Sxg` iff `accept_header` contains `SXG_CONTENT_TYPE` with a q
+/// value allowed by `accept_filter`. Other versions of SXG are served
+/// `Plain`. Call this before fetching the origin response, so that no signing
+/// work is done for requestors that would get the plain response anyway.
+pub fn negotiate(accept_header: Option<&str>, accept_filter: AcceptFilter) -> ServeMode {
+    match accept_header {
+        Some(accept) if headers::validate_accept_header(accept, accept_filter).is_ok() => {
+            ServeMode::Sxg
+        }
+        _ => ServeMode::Plain,
+    }
+}
+
 // To avoid issues with clock skew, backdate the start time by an hour. Don't backdate the
 // expiration because it goes against the origin's cache-control header. (e.g. For max-age
 // <1h, an SXG would be instantly invalid; this would be confusing.)
@@ -76,7 +188,7 @@ const BACKDATING: Duration = Duration::from_secs(60 * 60);
 pub(crate) const MAX_PAYLOAD_SIZE: usize = 8_000_000;
 
 impl SxgWorker {
-    pub fn new(config_yaml: &str) -> Result<Self> {
+    pub fn new(config_yaml: &str) -> Result<Self, SxgError> {
         let config = Config::new(config_yaml)?;
         Ok(Self::from_parsed(config))
     }
@@ -86,11 +198,18 @@ impl SxgWorker {
             certificates: VecDeque::new(),
         }
     }
+    /// Adds a certificate, which becomes the latest one. While certificates
+    /// are rotated, each signed exchange is signed with the certificate that
+    /// covers its host and expires last, so all the certificates must share
+    /// the key of `sxg_signer`.
     pub fn add_certificate(&mut self, certificate: CertificateChain) {
         self.certificates.push_back(certificate);
     }
     /// Reads ACME storage, and adds all ACME certificates to worker.
-    pub async fn add_acme_certificates_from_storage(&mut self, runtime: &Runtime) -> Result<()> {
+    pub async fn add_acme_certificates_from_storage(
+        &mut self,
+        runtime: &Runtime,
+    ) -> Result<(), SxgError> {
         let acme_state = acme::state_machine::read_current_state(runtime).await?;
         for certificate_pem in acme_state.certificates {
             let certificate = CertificateChain::from_pem_files(&[&certificate_pem])?;
@@ -101,6 +220,27 @@ impl SxgWorker {
     pub fn config(&self) -> &Config {
         &self.config
     }
+    // Returns the certificate to sign `url` with at `now`: among the
+    // certificates that are valid and cover its host, the one that expires
+    // last, so that an expiring certificate is phased out as soon as its
+    // replacement is added. If none qualifies, returns the latest certificate,
+    // so that the caller reports why it can't be used.
+    fn select_certificate(&self, url: &Url, now: SystemTime) -> Option<&CertificateChain> {
+        let is_usable = |certificate: &&CertificateChain| {
+            let end_entity = &certificate.end_entity;
+            matches!(end_entity.not_before(), Ok(not_before) if not_before <= now)
+                && matches!(end_entity.not_after(), Ok(not_after) if now < not_after)
+                && self.check_host_covered(certificate, url).is_ok()
+        };
+        self.certificates
+            .iter()
+            .filter(is_usable)
+            .max_by_key(|certificate| certificate.end_entity.not_after().ok())
+            .or_else(|| self.certificates.back())
+    }
+    fn is_latest_certificate(&self, certificate: &CertificateChain) -> bool {
+        matches!(self.certificates.back(), Some(latest) if latest.basename == certificate.basename)
+    }
     fn find_certificate_by_basename(&self, basename: &str) -> Option<&CertificateChain> {
         self.certificates
             .iter()
@@ -109,9 +249,55 @@ impl SxgWorker {
     pub fn latest_certificate_basename(&self) -> Option<&str> {
         Some(&self.certificates.back()?.basename)
     }
+    /// Returns the basename of the certificate that signs `fallback_url` at
+    /// `now`, whose OCSP response is to be passed to
+    /// `create_signed_exchange_blocking`.
+    pub fn signing_certificate_basename(
+        &self,
+        fallback_url: &Url,
+        now: SystemTime,
+    ) -> Option<&str> {
+        Some(&self.select_certificate(fallback_url, now)?.basename)
+    }
+    /// Returns the subject, SANs, serial number, issuer and validity of the
+    /// latest certificate, or `None` if there is no certificate, or an error
+    /// if any of them can't be parsed.
+    pub fn certificate_info(&self) -> Option<Result<CertificateInfo, SxgError>> {
+        self.certificates
+            .back()
+            .map(|certificate| certificate.end_entity.info())
+    }
+    /// Returns the DNS names and IP addresses that the latest certificate
+    /// covers, as listed in its `subjectAltName` extension.
+    pub fn certificate_sans(&self) -> Vec<String> {
+        self.certificates
+            .back()
+            .and_then(|certificate| certificate.end_entity.subject_alt_names().ok())
+            .unwrap_or_default()
+    }
+    // Returns an error if the host of `url` is not covered by `certificate`,
+    // because browsers reject signed exchanges for such URLs.
+    fn check_host_covered(&self, certificate: &CertificateChain, url: &Url) -> Result<()> {
+        if self.config.allow_uncovered_hosts {
+            return Ok(());
+        }
+        let host = url
+            .host()
+            .ok_or_else(|| anyhow!("The fallback URL {} has no host.", url))?;
+        let sans = certificate.end_entity.subject_alt_names()?;
+        if sans.iter().any(|san| san_covers_host(san, &host)) {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "The certificate does not cover host {}; it covers [{}].",
+                host,
+                sans.join(", ")
+            ))
+        }
+    }
     pub fn create_cert_cbor(&self, cert_basename: &str, ocsp_der: &[u8]) -> Vec<u8> {
         if let Some(certificate) = self.find_certificate_by_basename(cert_basename) {
-            certificate.create_cert_cbor(ocsp_der)
+            certificate.create_cert_cbor(Some(ocsp_der))
         } else {
             cbor::DataItem::Array(vec![]).serialize()
         }
@@ -120,236 +306,789 @@ impl SxgWorker {
         &self,
         input: HttpResponse,
         option: process_html::ProcessHtmlOption,
-    ) -> Result<HttpResponse> {
+    ) -> Result<HttpResponse, SxgError> {
         process_html::process_html(input, option)
     }
-    pub async fn create_signed_exchange<C: HttpCache>(
+    /// Returns whether a response with `status_code` from the origin should be
+    /// signed. Otherwise, the response should be passed through unsigned.
+    /// Partial and not-modified responses are never signed.
+    pub fn is_signable_status(&self, status_code: u16) -> bool {
+        !config::UNSIGNABLE_STATUSES.contains(&status_code)
+            && self.config.signable_statuses.contains(&status_code)
+    }
+    /// Returns the header that describes `status`, if `add_sxg_status_header`
+    /// is enabled in the config.
+    pub fn sxg_status_header(&self, status: SxgStatus) -> Option<(&'static str, &'static str)> {
+        if self.config.add_sxg_status_header {
+            Some((SXG_STATUS_HEADER, status.as_str()))
+        } else {
+            None
+        }
+    }
+    /// Fetches the origin response to sign with `runtime.fetcher`, giving up
+    /// after `origin_fetch_timeout_seconds` or once the body exceeds
+    /// `max_origin_body_bytes`. In either case, the returned `SxgError::Network`
+    /// contains an `OriginFetchError`, and the response should be passed
+    /// through unsigned.
+    /// A chunked response is returned with a `content-length` instead of its
+    /// `transfer-encoding`, because the body is fully buffered.
+    pub async fn fetch_origin<S: ?Sized, F: Fetcher + ?Sized>(
         &self,
-        runtime: &Runtime,
+        runtime: &GenericRuntime<S, F>,
+        request: http::HttpRequest,
+    ) -> Result<HttpResponse, SxgError> {
+        let limits = FetchLimits {
+            timeout: self
+                .config
+                .origin_fetch_timeout_seconds
+                .map(Duration::from_secs),
+            max_body_bytes: self.config.max_origin_body_bytes,
+        };
+        let response = runtime
+            .fetcher
+            .fetch_with_limits(request, limits)
+            .await
+            .map_err(|e| {
+                SxgError::Network(
+                    e.into_inner()
+                        .context("Failed to fetch the origin response"),
+                )
+            })?;
+        Ok(dechunk(response))
+    }
+    /// Creates a signed exchange, and reports the outcome to `runtime.metrics`.
+    pub async fn create_signed_exchange<C: HttpCache, S: Signer + ?Sized, F: Fetcher + ?Sized>(
+        &self,
+        runtime: &GenericRuntime<S, F>,
+        params: CreateSignedExchangeParams<'_, C>,
+    ) -> Result<SignedExchange, SxgError> {
+        let fallback_url = params.fallback_url;
+        let parts = self.sign_with_metrics(runtime, params).await?;
+        let mut bytes = vec![];
+        parts
+            .write(fallback_url, &mut bytes)
+            .map_err(SxgError::Sxg)?;
+        cache_head_response(runtime, fallback_url, parts.expires);
+        Ok(SignedExchange {
+            bytes,
+            expires: parts.expires,
+        })
+    }
+    /// Like `create_signed_exchange`, but writes the signed exchange to
+    /// `writer` instead of returning its bytes, and returns the time when the
+    /// signature expires. The MICE-encoded payload is written as is, without
+    /// another copy of the whole exchange in memory, which reduces the peak
+    /// memory for large pages.
+    pub async fn create_signed_exchange_to_writer<
+        C: HttpCache,
+        W: std::io::Write,
+        S: Signer + ?Sized,
+        F: Fetcher + ?Sized,
+    >(
+        &self,
+        runtime: &GenericRuntime<S, F>,
+        params: CreateSignedExchangeParams<'_, C>,
+        writer: &mut W,
+    ) -> Result<SystemTime, SxgError> {
+        let fallback_url = params.fallback_url;
+        let parts = self.sign_with_metrics(runtime, params).await?;
+        parts.write(fallback_url, writer).map_err(SxgError::Sxg)?;
+        cache_head_response(runtime, fallback_url, parts.expires);
+        Ok(parts.expires)
+    }
+    async fn sign_with_metrics<C: HttpCache, S: Signer + ?Sized, F: Fetcher + ?Sized>(
+        &self,
+        runtime: &GenericRuntime<S, F>,
+        params: CreateSignedExchangeParams<'_, C>,
+    ) -> Result<SignedParts, SxgError> {
+        if !self.is_signable_status(params.status_code) {
+            runtime.metrics.incr(metrics::PASSED_THROUGH);
+            return Err(SxgError::Sxg(unsignable_status_error(params.status_code)));
+        }
+        let start = metrics::now_millis();
+        let parts = self.sign(runtime, params).await?;
+        runtime.metrics.incr(metrics::SIGNED);
+        runtime
+            .metrics
+            .observe(metrics::SIGNING_LATENCY_MS, metrics::now_millis() - start);
+        Ok(parts)
+    }
+    async fn sign<C: HttpCache, S: Signer + ?Sized, F: Fetcher + ?Sized>(
+        &self,
+        runtime: &GenericRuntime<S, F>,
         params: CreateSignedExchangeParams<'_, C>,
-    ) -> Result<HttpResponse> {
+    ) -> Result<SignedParts, SxgError> {
         let CreateSignedExchangeParams {
             fallback_url,
             cert_origin,
             payload_body,
-            payload_headers,
+            mut payload_headers,
             skip_process_link,
             status_code,
             header_integrity_cache,
         } = params;
+        let payload_body = match &runtime.payload_transform {
+            Some(payload_transform) => {
+                let payload_body = payload_transform(payload_body, &payload_headers);
+                payload_headers.update_content_length(payload_body.len());
+                payload_body
+            }
+            None => Cow::Borrowed(payload_body),
+        };
         if payload_body.len() > MAX_PAYLOAD_SIZE {
-            return Err(anyhow!(
-                "Payload body size is {}, which exceeds the limit {}.",
-                payload_body.len(),
-                MAX_PAYLOAD_SIZE
-            ));
+            return count_failure(
+                runtime,
+                "payload_too_large",
+                Err(SxgError::Sxg(payload_too_large_error(payload_body.len()))),
+            );
         }
-
-        let latest_certificate = self
-            .certificates
-            .back()
-            .ok_or_else(|| Error::msg("Can't create signed exchange without certificate chain."))?;
+        let _signing_permit = match &runtime.signing_limiter {
+            Some(signing_limiter) => Some(count_failure(
+                runtime,
+                "overloaded",
+                signing_limiter.try_acquire(),
+            )?),
+            None => None,
+        };
 
         let fallback_base = Url::parse(fallback_url)
-            .map_err(|e| Error::new(e).context("Failed to parse fallback URL"))?;
-        let cert_base = Url::parse(cert_origin)
-            .map_err(|e| Error::new(e).context("Failed to parse cert origin"))?;
+            .map_err(|e| SxgError::Sxg(Error::new(e).context("Failed to parse fallback URL")))?;
+        let certificate = count_failure(
+            runtime,
+            "no_certificate",
+            self.select_certificate(&fallback_base, runtime.now)
+                .ok_or_else(|| {
+                    SxgError::Crypto(Error::msg(
+                        "Can't create signed exchange without certificate chain.",
+                    ))
+                }),
+        )?;
+
+        let ocsp_der = count_failure(
+            runtime,
+            "ocsp",
+            self.check_ocsp(runtime, certificate)
+                .await
+                .map_err(SxgError::Ocsp),
+        )?;
+
+        count_failure(
+            runtime,
+            "uncovered_host",
+            self.check_host_covered(certificate, &fallback_base)
+                .map_err(SxgError::Crypto),
+        )?;
+        let max_age = self.take_signature_max_age(&mut payload_headers);
         let mut header_integrity_fetcher = header_integrity::new_fetcher(
             runtime.fetcher.as_ref(),
             header_integrity_cache,
-            &self.config.strip_response_headers,
+            self.config.response_header_filter(),
         );
-        let (signed_headers, payload_body) = utils::signed_headers_and_payload(
+        let signed_headers_and_payload = utils::signed_headers_and_payload(
             &fallback_base,
             status_code,
             &payload_headers,
-            payload_body,
+            &payload_body,
             &mut header_integrity_fetcher,
             skip_process_link,
+            &self.config.link_hints,
+            &self.config.allowed_subresource_origins,
         )
-        .await?;
-        let cert_url = cert_base
-            .join(&format!(
-                "{}{}",
-                &self.config.cert_url_dirname, &latest_certificate.basename
-            ))
-            .map_err(|e| Error::new(e).context("Failed to parse cert_url_dirname"))?;
-        let validity_url = fallback_base
-            .join(&format!(
-                "{}{}",
-                &self.config.validity_url_dirname, "validity"
-            ))
-            .map_err(|e| Error::new(e).context("Failed to parse validity_url_dirname"))?;
-        let date = runtime
-            .now
-            .checked_sub(BACKDATING)
-            .ok_or_else(|| anyhow!("Failed to construct date"))?;
-        let expires = runtime
-            .now
-            .checked_add(payload_headers.signature_duration()?);
+        .await;
+        let (signed_headers, payload_body) = count_failure(
+            runtime,
+            "signed_headers",
+            signed_headers_and_payload.map_err(SxgError::Sxg),
+        )?;
+        let (cert_url, validity_url) = self
+            .signature_urls(
+                &fallback_base,
+                cert_origin,
+                certificate,
+                ocsp_der.as_deref(),
+            )
+            .map_err(SxgError::Config)?;
+        let (date, expires) =
+            self.signature_validity(runtime.now, &payload_headers, max_age, certificate)?;
         let signature = signature::Signature::new(signature::SignatureParams {
-            cert_url: cert_url.as_str(),
-            cert_sha256: &latest_certificate.end_entity_sha256,
+            cert_url: &cert_url,
+            cert_sha256: &certificate.end_entity_sha256,
             date,
             expires,
             headers: &signed_headers,
-            id: "sig",
+            id: self.config.signature_label(),
             request_url: fallback_url,
             signer: runtime.sxg_signer.as_ref(),
-            validity_url: validity_url.as_str(),
+            validity_url: &validity_url,
         })
         .await;
 
-        let signature = signature.map_err(|e| e.context("Failed to create signature."))?;
-        let sxg_body = sxg::build(
+        let signature = count_failure(
+            runtime,
+            "signature",
+            signature.map_err(|e| SxgError::Crypto(e.context("Failed to create signature."))),
+        )?;
+        let (signature, expires) = (signature.serialize(), signature.expires());
+        Ok(SignedParts {
+            signature,
+            signed_headers,
+            payload_body,
+            expires,
+        })
+    }
+    /// Like `create_signed_exchange`, but synchronous and I/O-free, for
+    /// embedders that sign inside a synchronous request handler. The caller
+    /// provides the OCSP response to staple, such as one kept up to date by
+    /// `update_oscp_in_storage`, and a signer that needs no I/O. The `link`
+    /// header is signed as with `skip_process_link`, because computing the
+    /// header integrity of preloads requires fetching them. No metrics are
+    /// reported, and the signed exchange is not cached for `HEAD` requests.
+    pub fn create_signed_exchange_blocking(
+        &self,
+        params: CreateSignedExchangeBlockingParams<'_>,
+    ) -> Result<SignedExchange, SxgError> {
+        self.sign_blocking(params)
+    }
+    /// Signs an origin response that the caller already has, such as one
+    /// from its own cache, without any network I/O. The response headers are
+    /// checked and filtered as by `transform_payload_headers`, and the rest is
+    /// as in `create_signed_exchange_blocking`.
+    pub fn create_signed_exchange_from_response(
+        &self,
+        params: CreateSignedExchangeFromResponseParams<'_>,
+    ) -> Result<SignedExchange, SxgError> {
+        let CreateSignedExchangeFromResponseParams {
+            now,
             fallback_url,
-            &signature.serialize(),
-            &signed_headers,
-            &payload_body,
-        )
-        .map_err(|e| e.context("Failed to create SXG."))?;
-        Ok(HttpResponse {
-            body: sxg_body,
-            headers: vec![
-                (
-                    "content-type".into(),
-                    "application/signed-exchange;v=b3".into(),
-                ),
-                ("x-content-type-options".into(), "nosniff".into()),
-            ],
-            status: 200,
+            cert_origin,
+            response,
+            ocsp_der,
+            signer,
+        } = params;
+        let payload_headers = self.transform_payload_headers(response.headers.clone())?;
+        self.create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+            now,
+            fallback_url,
+            cert_origin,
+            payload_body: &response.body,
+            payload_headers,
+            status_code: response.status,
+            ocsp_der,
+            signer,
+        })
+    }
+    fn sign_blocking(
+        &self,
+        params: CreateSignedExchangeBlockingParams<'_>,
+    ) -> Result<SignedExchange, SxgError> {
+        let CreateSignedExchangeBlockingParams {
+            now,
+            fallback_url,
+            cert_origin,
+            payload_body,
+            mut payload_headers,
+            status_code,
+            ocsp_der,
+            signer,
+        } = params;
+        if !self.is_signable_status(status_code) {
+            return Err(SxgError::Sxg(unsignable_status_error(status_code)));
+        }
+        if payload_body.len() > MAX_PAYLOAD_SIZE {
+            return Err(SxgError::Sxg(payload_too_large_error(payload_body.len())));
+        }
+        let fallback_base = Url::parse(fallback_url)
+            .map_err(|e| SxgError::Sxg(Error::new(e).context("Failed to parse fallback URL")))?;
+        let certificate = self
+            .select_certificate(&fallback_base, now)
+            .ok_or_else(|| {
+                SxgError::Crypto(Error::msg(
+                    "Can't create signed exchange without certificate chain.",
+                ))
+            })?;
+        self.check_ocsp_freshness(ocsp_der, now)
+            .map_err(SxgError::Ocsp)?;
+        self.check_host_covered(certificate, &fallback_base)
+            .map_err(SxgError::Crypto)?;
+        let max_age = self.take_signature_max_age(&mut payload_headers);
+        let (signed_headers, payload_body) = utils::signed_headers_and_payload_blocking(
+            &fallback_base,
+            status_code,
+            &payload_headers,
+            payload_body,
+            &self.config.link_hints,
+            &self.config.allowed_subresource_origins,
+        );
+        let (cert_url, validity_url) = self
+            .signature_urls(&fallback_base, cert_origin, certificate, Some(ocsp_der))
+            .map_err(SxgError::Config)?;
+        let (date, expires) =
+            self.signature_validity(now, &payload_headers, max_age, certificate)?;
+        let signature = signature::Signature::new_blocking(signature::SignatureParams {
+            cert_url: &cert_url,
+            cert_sha256: &certificate.end_entity_sha256,
+            date,
+            expires,
+            headers: &signed_headers,
+            id: self.config.signature_label(),
+            request_url: fallback_url,
+            signer,
+            validity_url: &validity_url,
         })
+        .map_err(|e| SxgError::Crypto(e.context("Failed to create signature.")))?;
+        build_signed_exchange(fallback_url, &signature, &signed_headers, &payload_body)
+            .map_err(SxgError::Sxg)
+    }
+    // Removes the `signature_max_age_header` from the payload headers, so that
+    // it is not signed, and returns the signature duration it requests.
+    fn take_signature_max_age(&self, payload_headers: &mut Headers) -> Option<Duration> {
+        let name = self.config.signature_max_age_header.as_deref()?;
+        payload_headers.take_signature_max_age(name)
+    }
+    // Returns the `date` and `expires` of a signature created at `now`, which
+    // lasts as long as cache-control allows, or `max_age` if the origin
+    // requested shorter. The browser rejects a signature that outlives the
+    // certificate, so `expires` is clamped to the certificate's `notAfter`,
+    // unless `error_on_expiry_clamp` is set. An expired certificate is an
+    // error.
+    fn signature_validity(
+        &self,
+        now: SystemTime,
+        payload_headers: &Headers,
+        max_age: Option<Duration>,
+        certificate: &CertificateChain,
+    ) -> Result<(SystemTime, Option<SystemTime>), SxgError> {
+        let date = now
+            .checked_sub(BACKDATING)
+            .ok_or_else(|| SxgError::Sxg(anyhow!("Failed to construct date")))?;
+        // `Signature` clamps the duration to 7 days after `date`.
+        let duration = payload_headers
+            .signature_duration()
+            .map_err(SxgError::Sxg)?;
+        let duration = match max_age {
+            Some(max_age) if max_age < headers::MIN_SIGNATURE_DURATION => {
+                return Err(SxgError::Sxg(anyhow!("Validity duration is too short.")));
+            }
+            Some(max_age) => max_age.min(duration),
+            None => duration,
+        };
+        let expires = now.checked_add(duration);
+        let not_after = certificate.end_entity.not_after()?;
+        if not_after <= date {
+            return Err(SxgError::Crypto(anyhow!(
+                "The certificate expired at {}.",
+                DateTime::<Utc>::from(not_after)
+            )));
+        }
+        if signature::effective_expires(date, expires).map_err(SxgError::Sxg)? <= not_after {
+            return Ok((date, expires));
+        }
+        let message = format!(
+            "The signature would expire after the certificate, which expires at {}.",
+            DateTime::<Utc>::from(not_after)
+        );
+        if self.config.error_on_expiry_clamp {
+            return Err(SxgError::Crypto(Error::msg(message)));
+        }
+        utils::console_log(&format!(
+            "Warning: {} Clamping the signature expiry.",
+            message
+        ));
+        Ok((date, Some(not_after)))
+    }
+    // Returns the `cert-url` and `validity-url` of the signature. `ocsp_der` is
+    // only used by the `data:` form, which embeds the cert chain.
+    #[cfg_attr(not(feature = "test-utils"), allow(unused_variables))]
+    fn signature_urls(
+        &self,
+        fallback_base: &Url,
+        cert_origin: &str,
+        certificate: &CertificateChain,
+        ocsp_der: Option<&[u8]>,
+    ) -> Result<(String, String)> {
+        let cert_base = Url::parse(cert_origin)
+            .map_err(|e| Error::new(e).context("Failed to parse cert origin"))?;
+        let cert_url = cert_base
+            .join(&format!(
+                "{}{}",
+                &self.config.cert_url_dirname, &certificate.basename
+            ))
+            .map_err(|e| Error::new(e).context("Failed to parse cert_url_dirname"))?;
+        let validity_url = fallback_base
+            .join(&format!(
+                "{}{}",
+                &self.config.validity_url_dirname, "validity"
+            ))
+            .map_err(|e| Error::new(e).context("Failed to parse validity_url_dirname"))?;
+        // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-signature-validity
+        if validity_url.origin() != fallback_base.origin() {
+            return Err(anyhow!(
+                "The validity URL {} is not same-origin with the signed URL {}.",
+                validity_url,
+                fallback_base
+            ));
+        }
+        let cert_url = match self.config.cert_url_form {
+            #[cfg(feature = "test-utils")]
+            UrlForm::Data => format!(
+                "{}{}",
+                verify::DATA_CERT_URL_PREFIX,
+                base64::encode(certificate.create_cert_cbor(ocsp_der))
+            ),
+            form => format_signature_url(&cert_url, form, fallback_base)?,
+        };
+        let validity_url =
+            format_signature_url(&validity_url, self.config.validity_url_form, fallback_base)?;
+        Ok((cert_url, validity_url))
+    }
+    /// Responds to a `HEAD` request with the headers of the signed exchange
+    /// recently created for `fallback_url`, without signing it again.
+    /// Returns `None` if `runtime` has no `signed_exchange_cache`, or if the
+    /// cached signature has expired.
+    pub fn create_signed_exchange_head(
+        &self,
+        runtime: &Runtime,
+        fallback_url: &str,
+    ) -> Option<HttpResponse> {
+        runtime
+            .signed_exchange_cache
+            .as_ref()?
+            .get_head_response(fallback_url, runtime.now)
     }
     fn create_validity(&self) -> Vec<u8> {
         let validity = cbor::DataItem::Map(vec![]);
         validity.serialize()
     }
-    pub async fn get_unexpired_ocsp(&self, runtime: &Runtime) -> Result<Vec<u8>> {
+    pub async fn get_unexpired_ocsp<S: ?Sized, F: Fetcher + ?Sized>(
+        &self,
+        runtime: &GenericRuntime<S, F>,
+    ) -> Result<Vec<u8>, SxgError> {
         if let Some(certificate) = &self.certificates.back() {
-            ocsp::read_and_update_ocsp_in_storage(
-                certificate,
+            self.read_ocsp(
                 runtime,
+                certificate,
                 ocsp::OcspUpdateStrategy::LazyIfUnexpired,
             )
             .await
         } else {
-            Err(Error::msg("OCSP requires certificate chain"))
+            Err(SxgError::Ocsp(Error::msg(
+                "OCSP requires certificate chain",
+            )))
         }
     }
-    pub async fn update_oscp_in_storage(&self, runtime: &Runtime) -> Result<()> {
-        if let Some(certificate) = &self.certificates.back() {
-            ocsp::read_and_update_ocsp_in_storage(
+    // Returns the OCSP response of `certificate` in storage, which is fetched
+    // from the CA if it needs an update per `strategy`.
+    async fn read_ocsp<S: ?Sized, F: Fetcher + ?Sized>(
+        &self,
+        runtime: &GenericRuntime<S, F>,
+        certificate: &CertificateChain,
+        strategy: ocsp::OcspUpdateStrategy,
+    ) -> Result<Vec<u8>, SxgError> {
+        let storage_key = ocsp::storage_key(certificate);
+        ocsp::read_and_update_ocsp_in_storage(certificate, runtime, strategy, &storage_key).await
+    }
+    // Returns an error if there is no OCSP response to staple, or if it is past
+    // its `nextUpdate` by more than `ocsp_grace_period_seconds`, because the
+    // browser rejects a signed exchange whose OCSP response is stale. With
+    // `allow_missing_ocsp`, it is `None` instead if the certificate has no
+    // OCSP responder or the responder can't be reached.
+    async fn check_ocsp<S: ?Sized, F: Fetcher + ?Sized>(
+        &self,
+        runtime: &GenericRuntime<S, F>,
+        certificate: &CertificateChain,
+    ) -> Result<Option<Vec<u8>>> {
+        let ocsp_der = match self
+            .read_ocsp(
+                runtime,
                 certificate,
+                ocsp::OcspUpdateStrategy::LazyIfUnexpired,
+            )
+            .await
+        {
+            Ok(ocsp_der) => ocsp_der,
+            Err(e) if self.config.allow_missing_ocsp && ocsp::is_unavailable(&e) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(Error::new(e).context("No OCSP response to staple")),
+        };
+        self.check_ocsp_freshness(&ocsp_der, runtime.now)?;
+        Ok(Some(ocsp_der))
+    }
+    fn check_ocsp_freshness(&self, ocsp_der: &[u8], now: SystemTime) -> Result<()> {
+        // The stub OCSP response of a self-signed certificate can't be parsed,
+        // so its freshness is not checked.
+        if let Ok(ocsp::OcspValidity {
+            next_update: Some(next_update),
+            ..
+        }) = ocsp::parse_validity(ocsp_der)
+        {
+            let grace_period = Duration::from_secs(self.config.ocsp_grace_period_seconds);
+            if next_update + grace_period < now {
+                return Err(anyhow!(
+                    "The OCSP response expired at {}.",
+                    DateTime::<Utc>::from(next_update)
+                ));
+            }
+        }
+        Ok(())
+    }
+    /// Updates the OCSP responses in storage of the latest certificate, and of
+    /// the earlier certificates that have not expired, which may still be
+    /// used to sign during a rotation.
+    pub async fn update_oscp_in_storage(&self, runtime: &Runtime) -> Result<(), SxgError> {
+        for certificate in self.certificates.iter().rev() {
+            let has_expired = matches!(
+                certificate.end_entity.not_after(),
+                Ok(not_after) if not_after <= runtime.now
+            );
+            if has_expired && !self.is_latest_certificate(certificate) {
+                continue;
+            }
+            self.read_ocsp(
                 runtime,
+                certificate,
                 ocsp::OcspUpdateStrategy::EarlyAsRecommended,
             )
             .await?;
         }
         Ok(())
     }
+    /// Replaces the OCSP response in storage of the certificate whose basename
+    /// is `certificate_basename`, from which signed exchanges and the
+    /// cert-chain read the staple at request time. This makes it possible to
+    /// rotate the staple, such as with the output of `tools refresh-ocsp`,
+    /// without redeploying the worker. If the certificate chain has an issuer,
+    /// the response must be about that certificate.
+    pub async fn set_ocsp(
+        &self,
+        runtime: &Runtime,
+        certificate_basename: &str,
+        ocsp_der: &[u8],
+    ) -> Result<(), SxgError> {
+        let certificate = self
+            .find_certificate_by_basename(certificate_basename)
+            .ok_or_else(|| {
+                SxgError::Ocsp(anyhow!(
+                    "There is no certificate whose basename is {}",
+                    certificate_basename
+                ))
+            })?;
+        if let Some(issuer) = certificate.issuers.first() {
+            ocsp::verify_response(
+                ocsp_der,
+                &certificate.end_entity.der,
+                &issuer.der,
+                runtime.now,
+            )?;
+        }
+        ocsp::write_ocsp_to_storage(runtime, certificate, ocsp_der.to_vec()).await
+    }
+    pub async fn debug_info(&self, runtime: &Runtime) -> DebugInfo {
+        let mut debug_info = DebugInfo {
+            certificate_basename: self.latest_certificate_basename().map(String::from),
+            ..Default::default()
+        };
+        let validity = self
+            .get_unexpired_ocsp(runtime)
+            .await
+            .and_then(|ocsp_der| ocsp::parse_validity(&ocsp_der));
+        match validity {
+            Ok(validity) => {
+                debug_info.ocsp_this_update = Some(validity.this_update.into());
+                if let Some(next_update) = validity.next_update {
+                    debug_info.ocsp_next_update = Some(next_update.into());
+                    debug_info.seconds_until_ocsp_expiry =
+                        Some(match next_update.duration_since(runtime.now) {
+                            Ok(duration) => duration.as_secs() as i64,
+                            Err(e) => -(e.duration().as_secs() as i64),
+                        });
+                }
+            }
+            Err(e) => debug_info.ocsp_error = Some(format!("{:#}", e)),
+        }
+        debug_info
+    }
+    // Returns the `cert_chain_max_age`, clamped to the remaining validity of
+    // the OCSP response stapled to the cert-chain.
+    fn cert_chain_max_age(&self, now: SystemTime, ocsp_der: Option<&[u8]>) -> Option<u64> {
+        let max_age = self.config.cert_chain_max_age?;
+        let next_update = ocsp_der
+            .and_then(|ocsp_der| ocsp::parse_validity(ocsp_der).ok())
+            .and_then(|validity| validity.next_update);
+        Some(match next_update {
+            Some(next_update) => {
+                let remaining = next_update
+                    .duration_since(now)
+                    .unwrap_or(Duration::ZERO)
+                    .as_secs();
+                max_age.min(remaining)
+            }
+            None => max_age,
+        })
+    }
+    /// Returns the content served by this worker itself for `req_url`, such as
+    /// the certificate chain. `accept_encoding` is the `Accept-Encoding`
+    /// request header, and the certificate chain is compressed with gzip if the
+    /// client accepts it. Returns `None` for most `Route::Content`, and for a
+    /// certificate chain whose OCSP response is unavailable; the caller should
+    /// only sign the former.
     pub async fn serve_preset_content(
         &self,
         runtime: &Runtime,
         req_url: &str,
+        accept_encoding: Option<&str>,
     ) -> Option<PresetContent> {
         let req_url = url::Url::parse(req_url).ok()?;
         let path = req_url.path();
-        if let Some(basename) = path.strip_prefix(&self.config.reserved_path) {
-            match basename {
-                "test.html" => Some(PresetContent::Direct(HttpResponse {
-                    headers: vec![(String::from("content-type"), String::from("text/html"))],
-                    status: 200,
-                    body: include_bytes!("./static/test.html").to_vec(),
-                })),
-                "prefetch.html" => Some(PresetContent::Direct(HttpResponse {
-                    headers: vec![(String::from("content-type"), String::from("text/html"))],
-                    status: 200,
-                    body: include_bytes!("./static/prefetch.html").to_vec(),
-                })),
-                "fallback.html" => Some(PresetContent::Direct(HttpResponse {
-                    headers: vec![(String::from("content-type"), String::from("text/html"))],
-                    status: 200,
-                    body: include_bytes!("./static/fallback.html").to_vec(),
-                })),
-                "test.sxg" => {
-                    let mut fallback_url = req_url;
-                    fallback_url
-                        .set_path(&fallback_url.path().replace("test.sxg", "fallback.html"));
-                    Some(PresetContent::ToBeSigned {
-                        url: fallback_url.to_string(),
-                        payload: HttpResponse {
-                            headers: vec![(
-                                String::from("content-type"),
-                                String::from("text/html"),
-                            )],
-                            status: 200,
-                            body: include_bytes!("./static/success.html").to_vec(),
-                        },
-                        fallback: HttpResponse {
-                            headers: vec![(
-                                String::from("content-type"),
-                                String::from("text/html"),
-                            )],
-                            status: 200,
-                            body: include_bytes!("./static/fallback.html").to_vec(),
-                        },
-                    })
+        match self.config.route_path(path) {
+            Route::CertChain => {
+                let certificate = path
+                    .strip_prefix(&self.config.cert_url_dirname)
+                    .and_then(|name| self.find_certificate_by_basename(name));
+                if let Some(certificate) = certificate {
+                    let ocsp_der = match self
+                        .read_ocsp(
+                            runtime,
+                            certificate,
+                            ocsp::OcspUpdateStrategy::LazyIfUnexpired,
+                        )
+                        .await
+                    {
+                        Ok(ocsp_der) => Some(ocsp_der),
+                        Err(e) if self.config.allow_missing_ocsp && ocsp::is_unavailable(&e) => {
+                            None
+                        }
+                        Err(_) => return None,
+                    };
+                    let max_age = self.cert_chain_max_age(runtime.now, ocsp_der.as_deref());
+                    return Some(PresetContent::Direct(cert_chain_response(
+                        certificate.create_cert_cbor(ocsp_der.as_deref()),
+                        accept_encoding,
+                        max_age,
+                    )));
                 }
-                _ => None,
             }
-        } else if let Some(cert_name) = path.strip_prefix(&self.config.cert_url_dirname) {
-            if let Some(certificate) = self.find_certificate_by_basename(cert_name) {
-                let ocsp_der = self.get_unexpired_ocsp(runtime).await.ok()?;
-                Some(PresetContent::Direct(HttpResponse {
-                    body: certificate.create_cert_cbor(&ocsp_der),
+            Route::Validity => {
+                return Some(PresetContent::Direct(HttpResponse {
+                    body: self.create_validity(),
                     headers: vec![(
                         String::from("content-type"),
-                        String::from("application/cert-chain+cbor"),
+                        String::from("application/cbor"),
                     )],
                     status: 200,
                 }))
-            } else {
-                Some(PresetContent::Direct(HttpResponse {
-                    headers: vec![(String::from("content-type"), String::from("text/plain"))],
-                    status: 404,
-                    body: format!("Unknown path {}", req_url).into_bytes(),
-                }))
             }
-        } else if let Some(validity_name) = path.strip_prefix(&self.config.validity_url_dirname) {
-            if validity_name == "validity" {
-                Some(PresetContent::Direct(HttpResponse {
-                    body: self.create_validity(),
+            Route::Reserved => {
+                if let Some(basename) = path.strip_prefix(&self.config.reserved_path) {
+                    let content = self
+                        .serve_reserved_content(runtime, &req_url, basename)
+                        .await;
+                    if content.is_some() {
+                        return content;
+                    }
+                }
+            }
+            Route::Content => {
+                let actual_token = path.strip_prefix("/.well-known/acme-challenge/")?;
+                let answer = self.find_challenge_answer(runtime, actual_token).await?;
+                return Some(PresetContent::Direct(HttpResponse {
+                    status: 200,
                     headers: vec![(
                         String::from("content-type"),
-                        String::from("application/cbor"),
+                        String::from("application/octet-stream"),
                     )],
-                    status: 200,
-                }))
-            } else {
+                    body: answer.into_bytes(),
+                }));
+            }
+        }
+        // The cert, validity and reserved URLs may share a directory, so a
+        // basename is only unknown if none of them matches.
+        Some(PresetContent::Direct(HttpResponse {
+            headers: vec![(String::from("content-type"), String::from("text/plain"))],
+            status: 404,
+            body: format!("Unknown path {}", req_url).into_bytes(),
+        }))
+    }
+    // Returns the answer to the ACME HTTP-01 challenge of `token`. The answer is
+    // looked up by token, so that it is found even if another instance sharing
+    // the storage placed the order, and otherwise in the ACME state, which
+    // orders placed by older versions only stored it in.
+    async fn find_challenge_answer(&self, runtime: &Runtime, token: &str) -> Option<String> {
+        use crate::acme::state_machine;
+        if let Ok(Some(answer)) =
+            state_machine::get_challenge_answer(runtime.storage.as_ref(), token).await
+        {
+            return Some(answer);
+        }
+        match state_machine::get_challenge_token_and_answer(runtime).await {
+            Ok(Some((expected_token, answer))) if token == expected_token => Some(answer),
+            _ => None,
+        }
+    }
+    // Returns the content served at `<reserved_path><basename>`, or `None` if
+    // `basename` is unknown.
+    async fn serve_reserved_content(
+        &self,
+        runtime: &Runtime,
+        req_url: &url::Url,
+        basename: &str,
+    ) -> Option<PresetContent> {
+        match basename {
+            "test.html" => Some(PresetContent::Direct(HttpResponse {
+                headers: vec![(String::from("content-type"), String::from("text/html"))],
+                status: 200,
+                body: include_bytes!("./static/test.html").to_vec(),
+            })),
+            "prefetch.html" => Some(PresetContent::Direct(HttpResponse {
+                headers: vec![(String::from("content-type"), String::from("text/html"))],
+                status: 200,
+                body: include_bytes!("./static/prefetch.html").to_vec(),
+            })),
+            "fallback.html" => Some(PresetContent::Direct(HttpResponse {
+                headers: vec![(String::from("content-type"), String::from("text/html"))],
+                status: 200,
+                body: include_bytes!("./static/fallback.html").to_vec(),
+            })),
+            "debug.json" if self.config.respond_debug_info => {
+                let debug_info = self.debug_info(runtime).await;
                 Some(PresetContent::Direct(HttpResponse {
-                    headers: vec![(String::from("content-type"), String::from("text/plain"))],
-                    status: 404,
-                    body: format!("Unknown path {}", req_url).into_bytes(),
+                    headers: vec![
+                        (
+                            String::from("content-type"),
+                            String::from("application/json"),
+                        ),
+                        (String::from("cache-control"), String::from("no-store")),
+                    ],
+                    status: 200,
+                    body: serde_json::to_vec_pretty(&debug_info).ok()?,
                 }))
             }
-        } else if let Some(actual_token) = path.strip_prefix("/.well-known/acme-challenge/") {
-            match crate::acme::state_machine::get_challenge_token_and_answer(runtime).await {
-                Ok(Some((expected_token, answer))) => {
-                    if actual_token == expected_token {
-                        Some(PresetContent::Direct(HttpResponse {
-                            status: 200,
-                            headers: vec![(
-                                String::from("content-type"),
-                                String::from("application/octet-stream"),
-                            )],
-                            body: answer.into_bytes(),
-                        }))
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
+            "test.sxg" => {
+                let mut fallback_url = req_url.clone();
+                fallback_url.set_path(&fallback_url.path().replace("test.sxg", "fallback.html"));
+                Some(PresetContent::ToBeSigned {
+                    url: fallback_url.to_string(),
+                    payload: HttpResponse {
+                        headers: vec![(String::from("content-type"), String::from("text/html"))],
+                        status: 200,
+                        body: include_bytes!("./static/success.html").to_vec(),
+                    },
+                    fallback: HttpResponse {
+                        headers: vec![(String::from("content-type"), String::from("text/html"))],
+                        status: 200,
+                        body: include_bytes!("./static/fallback.html").to_vec(),
+                    },
+                })
             }
+            _ => None,
+        }
+    }
+    /// Returns the `AMP-Cache-Transform` response header for a request whose
+    /// `AMP-Cache-Transform` header is `request_header`. Returns `None` if
+    /// `amp_support` is disabled, or if no transform can be satisfied.
+    pub fn amp_cache_transform(&self, request_header: Option<&str>) -> Option<String> {
+        if self.config.amp_support {
+            amp::negotiate(request_header?)
         } else {
             None
         }
@@ -360,58 +1099,388 @@ impl SxgWorker {
         &self,
         fields: HeaderFields,
         accept_filter: AcceptFilter,
-    ) -> Result<HeaderFields> {
+    ) -> Result<HeaderFields, SxgError> {
+        self.check_required_request_header(&fields)
+            .map_err(SxgError::Sxg)?;
         let headers = Headers::new(fields, &self.config.strip_request_headers);
-        headers.forward_to_origin_server(accept_filter, &self.config.forward_request_headers)
+        headers
+            .forward_to_origin_server(accept_filter, |name| {
+                self.config.forwards_request_header(name)
+            })
+            .map_err(SxgError::Sxg)
+    }
+    // Checks that `fields` has the `require_request_header` of the config,
+    // before it may be stripped.
+    fn check_required_request_header(&self, fields: &HeaderFields) -> Result<()> {
+        let (name, required_value) = match &self.config.require_request_header {
+            Some(required) => required,
+            None => return Ok(()),
+        };
+        let value = fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim());
+        match (value, required_value) {
+            (None, _) => Err(anyhow!(
+                "The request does not have the {} header, which is required for signing.",
+                name
+            )),
+            (Some(value), Some(required_value)) if value != required_value => Err(anyhow!(
+                "The request header {} is \"{}\", but signing requires \"{}\".",
+                name,
+                value,
+                required_value
+            )),
+            _ => Ok(()),
+        }
     }
     /// Checks `fields` as response headers from backend server,
     /// and returns the reqsponse headers to be sent to browser.
-    pub fn transform_payload_headers(&self, fields: HeaderFields) -> Result<Headers> {
-        let headers = Headers::new(fields, &self.config.strip_response_headers);
+    pub fn transform_payload_headers(&self, fields: HeaderFields) -> Result<Headers, SxgError> {
+        let filter = self.config.response_header_filter();
+        // The signature max-age header is kept even if the filter drops it,
+        // because it is read and removed at signing.
+        let signature_max_age_header = self.config.signature_max_age_header.as_deref();
+        let keeps = |name: &str| filter.keeps(name) || Some(name) == signature_max_age_header;
+        // A denylist applies before validation, so that it can strip a header
+        // such as `set-cookie` that would make the response unsignable. An
+        // allowlist applies after, so that dropping such a header doesn't get
+        // a personalized response signed.
+        let is_allowlist = matches!(filter, ResponseHeaderFilter::Keep(_));
+        let mut headers = Headers::new(fields, &BTreeSet::new());
+        if !is_allowlist {
+            headers.retain(keeps);
+        }
+        headers
+            .apply_vary_mode(self.config.vary_mode)
+            .map_err(SxgError::Sxg)?;
         headers.validate_as_sxg_payload()?;
+        if is_allowlist {
+            headers.retain(keeps);
+            // The allowlist may have dropped `content-type`.
+            headers.validate_as_sxg_payload()?;
+        }
+        if self.config.add_nosniff_header && !headers.inner().contains_key("x-content-type-options")
+        {
+            headers.insert("x-content-type-options", "nosniff".to_string());
+        }
         Ok(headers)
     }
+    /// Creates a signer from `private_key_base64` in the config. The key is
+    /// parsed on every call, so the result should be created once and cloned
+    /// for each request.
     #[cfg(feature = "rust_signer")]
-    pub fn create_rust_signer(&self) -> Result<signature::rust_signer::RustSigner> {
-        let private_key_der = base64::decode(
-            self.config
-                .private_key_base64
-                .as_ref()
-                .ok_or_else(|| Error::msg("Config private_key_base64 is not set"))?,
-        )?;
-        signature::rust_signer::RustSigner::new(&private_key_der)
-            .map_err(|e| e.context("Failed to call RustSigner::new()."))
+    pub fn create_rust_signer(&self) -> Result<signature::rust_signer::RustSigner, SxgError> {
+        let private_key_base64 =
+            self.config.private_key_base64.as_ref().ok_or_else(|| {
+                SxgError::Config(Error::msg("Config private_key_base64 is not set"))
+            })?;
+        let private_key_der = config::decode_private_key(private_key_base64)
+            .map_err(|e| SxgError::Config(e.context("Config private_key_base64 is invalid")))?;
+        signature::rust_signer::RustSigner::new(&private_key_der).map_err(|e| {
+            SxgError::Crypto(e.into_inner().context("Failed to call RustSigner::new()."))
+        })
     }
     /// Replaces the host name to be the html_host in the config.
     // TODO: implement get_fallback_url_and_cert_origin, so that Cloudflare Worker can use it.
-    pub fn get_fallback_url(&self, original_url: &Url) -> Result<Url> {
+    pub fn get_fallback_url(&self, original_url: &Url) -> Result<Url, SxgError> {
         let mut fallback = original_url.clone();
         let html_host = &self.config.html_host;
         if !html_host.is_empty() {
-            fallback.set_host(Some(html_host)).map_err(Error::new)?;
+            fallback
+                .set_host(Some(html_host))
+                .map_err(|e| SxgError::Config(Error::new(e)))?;
         }
         Ok(fallback)
     }
 }
 
-pub struct CreateSignedExchangeParams<'a, C: HttpCache> {
-    pub fallback_url: &'a str,
-    pub cert_origin: &'a str,
-    pub payload_body: &'a [u8],
-    pub payload_headers: headers::Headers,
-    pub skip_process_link: bool,
-    pub status_code: u16,
-    pub header_integrity_cache: C,
+// Counts a failure to sign by `reason` if `result` is an error.
+fn count_failure<T, E, S: ?Sized, F: ?Sized>(
+    runtime: &GenericRuntime<S, F>,
+    reason: &str,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    if result.is_err() {
+        runtime
+            .metrics
+            .incr(&format!("{}{}", metrics::SIGNING_FAILURES_PREFIX, reason));
+    }
+    result
 }
 
-#[cfg(test)]
-mod lib_tests {
-    use super::*;
-    use utils::tests as util;
-    fn new_worker() -> SxgWorker {
-        let yaml = r#"
-cert_url_dirname: ".well-known/sxg-certs/"
-forward_request_headers:
+// Whether a `subjectAltName` entry covers `host`. A wildcard such as
+// `*.example.com` matches exactly one label, as in Section 6.4.3 of RFC 6125.
+pub(crate) fn san_covers_host(san: &str, host: &url::Host<&str>) -> bool {
+    match host {
+        url::Host::Domain(domain) => {
+            let domain = domain.trim_end_matches('.');
+            match san.strip_prefix("*.") {
+                Some(suffix) => domain
+                    .split_once('.')
+                    .map(|(label, rest)| !label.is_empty() && rest.eq_ignore_ascii_case(suffix))
+                    .unwrap_or(false),
+                None => domain.eq_ignore_ascii_case(san),
+            }
+        }
+        url::Host::Ipv4(ip) => san == ip.to_string(),
+        url::Host::Ipv6(ip) => san == ip.to_string(),
+    }
+}
+
+// Replaces the `transfer-encoding` of a buffered response with the
+// `content-length` of its body, which is no longer chunked.
+fn dechunk(mut response: HttpResponse) -> HttpResponse {
+    let is_chunked = response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"));
+    if is_chunked {
+        response.headers.retain(|(name, _)| {
+            !name.eq_ignore_ascii_case("transfer-encoding")
+                && !name.eq_ignore_ascii_case("content-length")
+        });
+        response
+            .headers
+            .push(("content-length".into(), response.body.len().to_string()));
+    }
+    response
+}
+
+fn unsignable_status_error(status_code: u16) -> Error {
+    let reason = if config::UNSIGNABLE_STATUSES.contains(&status_code) {
+        "which is never signed"
+    } else {
+        "which is not in signable_statuses"
+    };
+    SxgStatus::PassthroughUncacheable.error(format!(
+        "The resource status code is {}, {}.",
+        status_code, reason
+    ))
+}
+
+fn payload_too_large_error(size: usize) -> Error {
+    SxgStatus::PassthroughTooLarge.error(format!(
+        "Payload body size is {}, which exceeds the limit {}.",
+        size, MAX_PAYLOAD_SIZE
+    ))
+}
+
+fn build_signed_exchange(
+    fallback_url: &str,
+    signature: &signature::Signature,
+    signed_headers: &[u8],
+    payload_body: &[u8],
+) -> Result<SignedExchange> {
+    let sxg_body = sxg::build(
+        fallback_url,
+        &signature.serialize(),
+        signed_headers,
+        payload_body,
+    )
+    .map_err(|e| e.context("Failed to create SXG."))?;
+    Ok(SignedExchange {
+        bytes: sxg_body,
+        expires: signature.expires(),
+    })
+}
+
+// Caches the head response of a signed exchange, so that `HEAD` requests
+// don't need to sign again.
+fn cache_head_response<S: ?Sized, F: ?Sized>(
+    runtime: &GenericRuntime<S, F>,
+    fallback_url: &str,
+    expires: SystemTime,
+) {
+    if let Some(cache) = &runtime.signed_exchange_cache {
+        cache.put(fallback_url, &SignedExchange::head_response(), expires);
+    }
+}
+
+// The fields of a signed exchange, before they are framed by `sxg::write`.
+struct SignedParts {
+    signature: Vec<u8>,
+    signed_headers: Vec<u8>,
+    payload_body: Vec<u8>,
+    expires: SystemTime,
+}
+
+impl SignedParts {
+    fn write<W: std::io::Write>(&self, fallback_url: &str, writer: &mut W) -> Result<()> {
+        sxg::write(
+            writer,
+            fallback_url,
+            &self.signature,
+            &self.signed_headers,
+            &self.payload_body,
+        )
+        .map_err(|e| e.context("Failed to create SXG."))
+    }
+}
+
+// Returns whether the `Accept-Encoding` request header allows gzip.
+fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    let codings = match accept_encoding.map(http_parser::parse_accept_encoding_header) {
+        Some(Ok(codings)) => codings,
+        _ => return false,
+    };
+    let find = |name: &str| {
+        codings
+            .iter()
+            .find(|coding| coding.coding.eq_ignore_ascii_case(name))
+    };
+    match find("gzip").or_else(|| find("*")) {
+        Some(coding) => coding.q_millis > 0,
+        None => false,
+    }
+}
+
+// Creates the `application/cert-chain+cbor` response, which is compressed with
+// gzip if the client accepts it, and cacheable for `max_age` seconds if set.
+fn cert_chain_response(
+    cert_cbor: Vec<u8>,
+    accept_encoding: Option<&str>,
+    max_age: Option<u64>,
+) -> HttpResponse {
+    let mut headers = vec![
+        (
+            String::from("content-type"),
+            String::from("application/cert-chain+cbor"),
+        ),
+        (String::from("vary"), String::from("accept-encoding")),
+    ];
+    if let Some(max_age) = max_age {
+        headers.push((
+            String::from("cache-control"),
+            format!("public, max-age={}", max_age),
+        ));
+    }
+    let mut body = cert_cbor;
+    if accepts_gzip(accept_encoding) {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        if let Ok(compressed) = encoder.write_all(&body).and_then(|_| encoder.finish()) {
+            body = compressed;
+            headers.push((String::from("content-encoding"), String::from("gzip")));
+        }
+    }
+    HttpResponse {
+        body,
+        headers,
+        status: 200,
+    }
+}
+
+// Writes a URL of the `Signature` header in the given form. A relative URL is
+// resolved against the signed URL, so it must be same-origin with it.
+fn format_signature_url(url: &Url, form: UrlForm, fallback_base: &Url) -> Result<String> {
+    match form {
+        UrlForm::Absolute => Ok(url.to_string()),
+        UrlForm::Relative if url.origin() == fallback_base.origin() => {
+            Ok(url[url::Position::BeforePath..].to_string())
+        }
+        UrlForm::Relative => Err(anyhow!(
+            "{} can't be relative, because it is not same-origin with the signed URL {}.",
+            url,
+            fallback_base
+        )),
+        #[cfg(feature = "test-utils")]
+        UrlForm::Data => Err(anyhow!("{} can't be a data: URL.", url)),
+    }
+}
+
+/// A signed exchange created by `SxgWorker::create_signed_exchange`.
+#[derive(Debug)]
+pub struct SignedExchange {
+    /// The body of the `application/signed-exchange;v=b3` response.
+    pub bytes: Vec<u8>,
+    /// The time when the signature expires. The signed exchange should not be
+    /// cached for longer than this.
+    pub expires: SystemTime,
+}
+
+impl SignedExchange {
+    /// Returns the remaining validity of the signature at `now`, which is the
+    /// upper bound of the `max-age` in `Cache-Control`. Returns zero if the
+    /// signature has expired.
+    pub fn max_age(&self, now: SystemTime) -> Duration {
+        self.expires.duration_since(now).unwrap_or(Duration::ZERO)
+    }
+    /// Returns the `(name, value)` pairs of the signed headers, in the
+    /// canonical order they are signed in, such as for snapshot tests that
+    /// show changes to header handling as diffs.
+    pub fn signed_headers(&self) -> Result<Vec<(String, String)>, SxgError> {
+        let parts = sxg::parse(&self.bytes)?;
+        sxg::parse_signed_headers(parts.signed_headers)
+    }
+    /// Returns the response headers, with an empty body.
+    fn head_response() -> HttpResponse {
+        HttpResponse {
+            body: vec![],
+            headers: vec![
+                ("content-type".into(), SXG_CONTENT_TYPE.into()),
+                ("x-content-type-options".into(), "nosniff".into()),
+            ],
+            status: 200,
+        }
+    }
+    /// Returns the response to be sent to the browser.
+    pub fn into_response(self) -> HttpResponse {
+        let head_response = Self::head_response();
+        HttpResponse {
+            body: self.bytes,
+            ..head_response
+        }
+    }
+}
+
+pub struct CreateSignedExchangeParams<'a, C: HttpCache> {
+    pub fallback_url: &'a str,
+    pub cert_origin: &'a str,
+    pub payload_body: &'a [u8],
+    pub payload_headers: headers::Headers,
+    pub skip_process_link: bool,
+    pub status_code: u16,
+    pub header_integrity_cache: C,
+}
+
+pub struct CreateSignedExchangeBlockingParams<'a> {
+    /// The time of signing. The signature is backdated from it.
+    pub now: SystemTime,
+    pub fallback_url: &'a str,
+    pub cert_origin: &'a str,
+    pub payload_body: &'a [u8],
+    pub payload_headers: headers::Headers,
+    pub status_code: u16,
+    /// The OCSP response to staple to the certificate that
+    /// `signing_certificate_basename` returns.
+    pub ocsp_der: &'a [u8],
+    pub signer: &'a dyn signature::BlockingSigner,
+}
+
+pub struct CreateSignedExchangeFromResponseParams<'a> {
+    /// The time of signing. The signature is backdated from it.
+    pub now: SystemTime,
+    pub fallback_url: &'a str,
+    pub cert_origin: &'a str,
+    /// The unprocessed origin response.
+    pub response: &'a HttpResponse,
+    /// The OCSP response to staple to the certificate that
+    /// `signing_certificate_basename` returns.
+    pub ocsp_der: &'a [u8],
+    pub signer: &'a dyn signature::BlockingSigner,
+}
+
+#[cfg(test)]
+mod lib_tests {
+    use super::*;
+    use utils::tests as util;
+    fn new_worker() -> SxgWorker {
+        // The test certificate covers example.org rather than my_domain.com.
+        let yaml = r#"
+allow_uncovered_hosts: true
+cert_url_dirname: ".well-known/sxg-certs/"
+forward_request_headers:
   - "cf-IPCOUNTRY"
   - "USER-agent"
 html_host: my_domain.com
@@ -445,19 +1514,19 @@ validity_url_dirname: "//.well-known/sxg-validity"
         let runtime = Runtime::default();
         assert_eq!(
             worker
-                .serve_preset_content(&runtime, "https://my_domain.com/unknown",)
+                .serve_preset_content(&runtime, "https://my_domain.com/unknown", None)
                 .await,
             None
         );
         assert!(matches!(
             worker
-                .serve_preset_content(&runtime, "https://my_domain.com/.sxg/test.html",)
+                .serve_preset_content(&runtime, "https://my_domain.com/.sxg/test.html", None)
                 .await,
             Some(PresetContent::Direct(HttpResponse { status: 200, .. }))
         ));
         assert!(matches!(
             worker
-                .serve_preset_content(&runtime, "https://my_domain.com/.sxg/test.sxg",)
+                .serve_preset_content(&runtime, "https://my_domain.com/.sxg/test.sxg", None)
                 .await,
             Some(PresetContent::ToBeSigned { .. })
         ));
@@ -469,6 +1538,7 @@ validity_url_dirname: "//.well-known/sxg-validity"
                         "https://my_domain.com/.well-known/sxg-certs/{}",
                         util::SELF_SIGNED_CERT_SHA256
                     ),
+                    None,
                 )
                 .await,
             Some(PresetContent::Direct(HttpResponse { status: 200, .. }))
@@ -478,6 +1548,7 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 .serve_preset_content(
                     &runtime,
                     "https://my_domain.com/.well-known/sxg-certs/unknown",
+                    None,
                 )
                 .await,
             Some(PresetContent::Direct(HttpResponse { status: 404, .. }))
@@ -487,6 +1558,7 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 .serve_preset_content(
                     &runtime,
                     "https://my_domain.com/.well-known/sxg-validity/validity",
+                    None,
                 )
                 .await,
             Some(PresetContent::Direct(HttpResponse { status: 200, .. }))
@@ -496,9 +1568,1351 @@ validity_url_dirname: "//.well-known/sxg-validity"
                 .serve_preset_content(
                     &runtime,
                     "https://my_domain.com/.well-known/sxg-validity/unknown",
+                    None,
                 )
                 .await,
             Some(PresetContent::Direct(HttpResponse { status: 404, .. }))
         ));
     }
+    #[tokio::test]
+    async fn serve_preset_content_under_reserved_path() {
+        let mut worker = new_worker();
+        worker.config.cert_url_dirname = worker.config.reserved_path.clone();
+        worker.config.validity_url_dirname = worker.config.reserved_path.clone();
+        let runtime = Runtime::default();
+        let status = |path: String| {
+            let (worker, runtime) = (&worker, &runtime);
+            async move {
+                let url = format!("https://my_domain.com/.sxg/{}", path);
+                match worker.serve_preset_content(runtime, &url, None).await {
+                    Some(PresetContent::Direct(response)) => Some(response.status),
+                    Some(PresetContent::ToBeSigned { .. }) => Some(200),
+                    None => None,
+                }
+            }
+        };
+        assert_eq!(status("test.html".to_string()).await, Some(200));
+        assert_eq!(status("test.sxg".to_string()).await, Some(200));
+        assert_eq!(
+            status(util::SELF_SIGNED_CERT_SHA256.to_string()).await,
+            Some(200)
+        );
+        assert_eq!(status("validity".to_string()).await, Some(200));
+        assert_eq!(status("unknown".to_string()).await, Some(404));
+    }
+    #[tokio::test]
+    async fn serve_compressed_cert_chain() {
+        let worker = new_worker();
+        let runtime = Runtime::default();
+        let url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        let serve = |accept_encoding| {
+            let (worker, runtime, url) = (&worker, &runtime, &url);
+            async move {
+                match worker
+                    .serve_preset_content(runtime, url, accept_encoding)
+                    .await
+                {
+                    Some(PresetContent::Direct(response)) => response,
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+        };
+        let content_encoding = |response: &HttpResponse| {
+            response
+                .headers
+                .iter()
+                .find(|(name, _)| name == "content-encoding")
+                .map(|(_, value)| value.clone())
+        };
+        let plain = serve(None).await;
+        assert_eq!(content_encoding(&plain), None);
+        assert_eq!(serve(Some("br, gzip;q=0")).await.body, plain.body);
+        let compressed = serve(Some("deflate, gzip;q=0.8")).await;
+        assert_eq!(content_encoding(&compressed), Some("gzip".to_string()));
+        let mut decompressed = vec![];
+        std::io::Read::read_to_end(
+            &mut flate2::read::GzDecoder::new(compressed.body.as_slice()),
+            &mut decompressed,
+        )
+        .unwrap();
+        assert_eq!(decompressed, plain.body);
+    }
+    #[tokio::test]
+    async fn serve_debug_info() {
+        let mut worker = new_worker();
+        let runtime = Runtime::default();
+        let url = "https://my_domain.com/.sxg/debug.json";
+        assert!(matches!(
+            worker.serve_preset_content(&runtime, url, None).await,
+            Some(PresetContent::Direct(HttpResponse { status: 404, .. }))
+        ));
+        worker.config.respond_debug_info = true;
+        let body = match worker.serve_preset_content(&runtime, url, None).await {
+            Some(PresetContent::Direct(HttpResponse {
+                status: 200, body, ..
+            })) => body,
+            other => panic!("unexpected {:?}", other),
+        };
+        let debug_info: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            debug_info["certificate_basename"],
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        // The self-signed certificate is stapled with a stub OCSP response.
+        assert_eq!(debug_info["ocsp_next_update"], serde_json::Value::Null);
+        assert!(debug_info["ocsp_error"].is_string());
+    }
+    #[tokio::test]
+    async fn requires_fresh_ocsp() {
+        let mut worker = new_worker();
+        // A certificate that is unexpired at the `nextUpdate`.
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::MULTI_SAN_CERT_PEM, util::MULTI_SAN_CERT_PEM])
+                .unwrap(),
+        );
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1792745179 + 10);
+        let storage = runtime::store::InMemoryStore::new(now);
+        // The `nextUpdate` of this OCSP response is 1792745179.
+        let ocsp = serde_json::json!({
+            "expiration_time": {"secs_since_epoch": 1792745179 + 3600, "nanos_since_epoch": 0},
+            "recommended_update_time": {"secs_since_epoch": 1792745179, "nanos_since_epoch": 0},
+            "value": base64::encode_config(
+                include_bytes!("./static/ocsp.der"),
+                base64::URL_SAFE_NO_PAD,
+            ),
+        });
+        let storage_key = ocsp::storage_key(worker.certificates.back().unwrap());
+        runtime::store::Store::put(&storage, &storage_key, &ocsp.to_string())
+            .await
+            .unwrap();
+        let runtime = Runtime {
+            now,
+            storage: Box::new(storage),
+            ..Default::default()
+        };
+        let err = sign_with_runtime(&worker, &runtime, 200).await.unwrap_err();
+        assert!(format!("{}", err).contains("OCSP response expired"));
+        worker.config.ocsp_grace_period_seconds = 60;
+        assert!(sign_with_runtime(&worker, &runtime, 200).await.is_ok());
+    }
+    #[tokio::test]
+    async fn sets_ocsp() {
+        let ocsp_der = include_bytes!("./static/ocsp.der");
+        // The OCSP response is about another certificate, which is rejected
+        // when the chain has an issuer to verify it with.
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1792745179 - 10);
+        let runtime = Runtime {
+            now,
+            storage: Box::new(runtime::store::InMemoryStore::new(now)),
+            ..Default::default()
+        };
+        let worker = new_worker();
+        let basename = worker.latest_certificate_basename().unwrap();
+        let err = worker
+            .set_ocsp(&runtime, basename, ocsp_der)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("The OCSP response is for serial number"));
+
+        let mut worker = new_worker();
+        worker.certificates.clear();
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::SELF_SIGNED_CERT_PEM]).unwrap(),
+        );
+        let err = worker
+            .set_ocsp(&runtime, "unknown", ocsp_der)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("no certificate whose basename is unknown"));
+        let basename = worker.latest_certificate_basename().unwrap();
+        worker.set_ocsp(&runtime, basename, ocsp_der).await.unwrap();
+        assert_eq!(worker.get_unexpired_ocsp(&runtime).await.unwrap(), ocsp_der);
+        let url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        let cert_cbor = match worker.serve_preset_content(&runtime, &url, None).await {
+            Some(PresetContent::Direct(response)) => response.body,
+            _ => panic!("Expecting the cert-chain"),
+        };
+        let items = crypto::parse_cert_cbor(&cert_cbor).unwrap();
+        assert_eq!(items[0].ocsp.as_deref(), Some(&ocsp_der[..]));
+
+        let expired = Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1792745179 + 10),
+            ..Default::default()
+        };
+        let err = worker
+            .set_ocsp(&expired, basename, ocsp_der)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("The OCSP response has expired."));
+    }
+    #[tokio::test]
+    async fn clamps_cert_chain_max_age() {
+        let ocsp_der = include_bytes!("./static/ocsp.der");
+        // The OCSP response expires in 600 seconds.
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1792745179 - 600);
+        let runtime = Runtime {
+            now,
+            storage: Box::new(runtime::store::InMemoryStore::new(now)),
+            ..Default::default()
+        };
+        let mut worker = new_worker();
+        worker.certificates.clear();
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::SELF_SIGNED_CERT_PEM]).unwrap(),
+        );
+        let basename = worker.latest_certificate_basename().unwrap();
+        worker.set_ocsp(&runtime, basename, ocsp_der).await.unwrap();
+        let url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        async fn cache_control(worker: &SxgWorker, runtime: &Runtime, url: &str) -> Option<String> {
+            match worker.serve_preset_content(runtime, url, None).await {
+                Some(PresetContent::Direct(response)) => response
+                    .headers
+                    .into_iter()
+                    .find(|(name, _)| name == "cache-control")
+                    .map(|(_, value)| value),
+                _ => panic!("Expecting the cert-chain"),
+            }
+        }
+        assert_eq!(cache_control(&worker, &runtime, &url).await, None);
+        worker.config.cert_chain_max_age = Some(60);
+        assert_eq!(
+            cache_control(&worker, &runtime, &url).await.as_deref(),
+            Some("public, max-age=60")
+        );
+        // Clamped to the OCSP freshness.
+        worker.config.cert_chain_max_age = Some(3600);
+        assert_eq!(
+            cache_control(&worker, &runtime, &url).await.as_deref(),
+            Some("public, max-age=600")
+        );
+    }
+    #[tokio::test]
+    async fn allows_missing_ocsp() {
+        let mut worker = new_worker();
+        // Without an issuer, no OCSP response can be fetched.
+        worker.certificates.clear();
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::SELF_SIGNED_CERT_PEM]).unwrap(),
+        );
+        let runtime = signing_runtime();
+        let err = sign_with_runtime(&worker, &runtime, 200).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("No OCSP response to staple"));
+        let url = format!(
+            "https://my_domain.com/.well-known/sxg-certs/{}",
+            util::SELF_SIGNED_CERT_SHA256
+        );
+        assert!(worker
+            .serve_preset_content(&runtime, &url, None)
+            .await
+            .is_none());
+
+        worker.config.allow_missing_ocsp = true;
+        assert!(sign_with_runtime(&worker, &runtime, 200).await.is_ok());
+        let cert_cbor = match worker.serve_preset_content(&runtime, &url, None).await {
+            Some(PresetContent::Direct(response)) => response.body,
+            _ => panic!("Expecting the cert-chain"),
+        };
+        let items = crypto::parse_cert_cbor(&cert_cbor).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].ocsp, None);
+    }
+    #[test]
+    fn amp_cache_transform() {
+        let mut worker = new_worker();
+        assert_eq!(worker.amp_cache_transform(Some("google")), None);
+        worker.config.amp_support = true;
+        assert_eq!(
+            worker.amp_cache_transform(Some("google")),
+            Some(r#"google;v="1""#.to_string())
+        );
+        assert_eq!(worker.amp_cache_transform(None), None);
+    }
+    #[test]
+    fn negotiates_serve_mode() {
+        let negotiate_prefers = |accept| negotiate(accept, AcceptFilter::PrefersSxg);
+        let negotiate_accepts = |accept| negotiate(accept, AcceptFilter::AcceptsSxg);
+        assert_eq!(negotiate_prefers(None), ServeMode::Plain);
+        assert_eq!(negotiate_prefers(Some("text/html")), ServeMode::Plain);
+        assert_eq!(
+            negotiate_prefers(Some("application/signed-exchange;v=b3")),
+            ServeMode::Sxg
+        );
+        assert_eq!(
+            negotiate_prefers(Some("text/html,application/signed-exchange;v=b3;q=0.9")),
+            ServeMode::Plain
+        );
+        assert_eq!(
+            negotiate_accepts(Some("text/html,application/signed-exchange;v=b3;q=0.9")),
+            ServeMode::Sxg
+        );
+        assert_eq!(
+            negotiate_accepts(Some("application/signed-exchange;v=b3;q=0")),
+            ServeMode::Plain
+        );
+        assert_eq!(
+            negotiate_accepts(Some("application/signed-exchange;v=b2")),
+            ServeMode::Plain
+        );
+        assert_eq!(
+            negotiate_accepts(Some("application/signed-exchange")),
+            ServeMode::Plain
+        );
+        assert_eq!(
+            negotiate_accepts(Some(
+                "application/signed-exchange;v=b2,application/signed-exchange;v=b3;q=0.5"
+            )),
+            ServeMode::Sxg
+        );
+    }
+    #[test]
+    fn requires_request_header() {
+        fn transform(
+            worker: &SxgWorker,
+            experiment: Option<&str>,
+        ) -> Result<HeaderFields, SxgError> {
+            let mut fields = vec![(
+                "accept".to_string(),
+                "application/signed-exchange;v=b3".to_string(),
+            )];
+            if let Some(experiment) = experiment {
+                fields.push(("X-SXG-Experiment".to_string(), experiment.to_string()));
+            }
+            worker.transform_request_headers(fields, AcceptFilter::PrefersSxg)
+        }
+        let mut worker = new_worker();
+        assert!(transform(&worker, None).is_ok());
+        worker.config.require_request_header = Some(("x-sxg-experiment".to_string(), None));
+        assert!(transform(&worker, Some("off")).is_ok());
+        assert_eq!(
+            transform(&worker, None).unwrap_err().to_string(),
+            "The request does not have the x-sxg-experiment header, which is required for signing."
+        );
+        worker.config.require_request_header =
+            Some(("x-sxg-experiment".to_string(), Some("on".to_string())));
+        assert!(transform(&worker, Some("on")).is_ok());
+        assert!(transform(&worker, None).is_err());
+        assert_eq!(
+            transform(&worker, Some("off")).unwrap_err().to_string(),
+            r#"The request header x-sxg-experiment is "off", but signing requires "on"."#
+        );
+    }
+    #[test]
+    fn always_forwards_host() {
+        fn transform(worker: &SxgWorker) -> std::collections::HashMap<String, String> {
+            let fields = vec![
+                (
+                    "accept".to_string(),
+                    "application/signed-exchange;v=b3".to_string(),
+                ),
+                ("Host".to_string(), "my_domain.com".to_string()),
+                ("X-Forwarded-Host".to_string(), "evil.com".to_string()),
+                ("X-Other".to_string(), "1".to_string()),
+            ];
+            worker
+                .transform_request_headers(fields, AcceptFilter::PrefersSxg)
+                .unwrap()
+                .into_iter()
+                .collect()
+        }
+        let mut worker = new_worker();
+        assert!(!worker.config.forward_request_headers.contains("host"));
+        let forwarded = transform(&worker);
+        assert_eq!(forwarded.get("host").unwrap(), "my_domain.com");
+        assert_eq!(forwarded.get("x-other"), None);
+        assert_eq!(forwarded.get("x-forwarded-host"), None);
+        worker
+            .config
+            .strip_request_headers
+            .insert("host".to_string());
+        assert_eq!(transform(&worker).get("host"), None);
+    }
+    fn signing_runtime() -> Runtime {
+        Runtime {
+            now: SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+            ..Default::default()
+        }
+    }
+    async fn sign_with_status(
+        worker: &SxgWorker,
+        status_code: u16,
+    ) -> Result<SignedExchange, SxgError> {
+        sign_with_runtime(worker, &signing_runtime(), status_code).await
+    }
+    async fn sign_with_runtime<S: Signer + ?Sized, F: Fetcher + ?Sized>(
+        worker: &SxgWorker,
+        runtime: &GenericRuntime<S, F>,
+        status_code: u16,
+    ) -> Result<SignedExchange, SxgError> {
+        let payload_headers = worker
+            .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+            .unwrap();
+        worker
+            .create_signed_exchange(
+                runtime,
+                CreateSignedExchangeParams {
+                    fallback_url: "https://my_domain.com/",
+                    cert_origin: "https://my_domain.com",
+                    payload_body: b"This is a test.",
+                    payload_headers,
+                    skip_process_link: false,
+                    status_code,
+                    header_integrity_cache: http_cache::NullCache {},
+                },
+            )
+            .await
+    }
+    #[tokio::test]
+    async fn signs_with_generic_runtime() {
+        let worker = new_worker();
+        let runtime = signing_runtime().into_generic(
+            signature::mock_signer::MockSigner,
+            crate::fetcher::NullFetcher,
+        );
+        assert_eq!(
+            sign_with_runtime(&worker, &runtime, 200)
+                .await
+                .unwrap()
+                .bytes,
+            sign_with_status(&worker, 200).await.unwrap().bytes
+        );
+    }
+    fn origin_request() -> http::HttpRequest {
+        http::HttpRequest {
+            body: vec![],
+            headers: vec![],
+            method: http::Method::Get,
+            url: "https://my_domain.com/".into(),
+        }
+    }
+    #[tokio::test]
+    async fn fetch_origin_limits_body_size() {
+        let mut worker = new_worker();
+        worker.config.max_origin_body_bytes = 10;
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let runtime = Runtime {
+            fetcher: Box::new(fetcher),
+            ..Default::default()
+        };
+        let response = |body: &[u8]| HttpResponse {
+            body: body.to_vec(),
+            headers: vec![],
+            status: 200,
+        };
+        let server_thread = async {
+            for body in [&b"0123456789"[..], &b"0123456789a"[..]] {
+                server
+                    .handle_next_request(origin_request(), response(body))
+                    .await
+                    .unwrap();
+            }
+        };
+        let client_thread = async {
+            let ok = worker.fetch_origin(&runtime, origin_request()).await;
+            assert_eq!(ok.unwrap().body, b"0123456789");
+            let err = worker
+                .fetch_origin(&runtime, origin_request())
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), SxgStatus::PassthroughTooLarge);
+            assert_eq!(
+                err.inner().downcast_ref::<OriginFetchError>(),
+                Some(&OriginFetchError::TooLarge { limit: 10 })
+            );
+        };
+        tokio::join!(server_thread, client_thread);
+    }
+    #[tokio::test]
+    async fn fetch_origin_dechunks() {
+        let worker = new_worker();
+        let (fetcher, mut server) = crate::fetcher::mock_fetcher::create();
+        let runtime = Runtime {
+            fetcher: Box::new(fetcher),
+            ..signing_runtime()
+        };
+        let body = b"This is a streamed test.";
+        let server_thread = async {
+            let response = HttpResponse {
+                body: body.to_vec(),
+                headers: vec![
+                    ("content-type".into(), "text/html".into()),
+                    ("Transfer-Encoding".into(), "chunked".into()),
+                ],
+                status: 200,
+            };
+            server
+                .handle_next_request(origin_request(), response)
+                .await
+                .unwrap();
+        };
+        let client_thread = async {
+            let response = worker
+                .fetch_origin(&runtime, origin_request())
+                .await
+                .unwrap();
+            assert_eq!(
+                response.headers,
+                vec![
+                    ("content-type".to_string(), "text/html".to_string()),
+                    ("content-length".to_string(), body.len().to_string()),
+                ]
+            );
+            let signed_exchange = worker
+                .create_signed_exchange(
+                    &runtime,
+                    CreateSignedExchangeParams {
+                        fallback_url: "https://my_domain.com/",
+                        cert_origin: "https://my_domain.com",
+                        payload_body: &response.body,
+                        payload_headers: worker
+                            .transform_payload_headers(response.headers)
+                            .unwrap(),
+                        skip_process_link: false,
+                        status_code: 200,
+                        header_integrity_cache: http_cache::NullCache {},
+                    },
+                )
+                .await
+                .unwrap();
+            let signed_headers = signed_exchange.signed_headers().unwrap();
+            assert!(
+                signed_headers.contains(&("content-length".to_string(), body.len().to_string()))
+            );
+            assert!(signed_headers
+                .iter()
+                .all(|(name, _)| name != "transfer-encoding"));
+        };
+        tokio::join!(server_thread, client_thread);
+    }
+    #[tokio::test]
+    async fn fetch_origin_times_out() {
+        let mut worker = new_worker();
+        worker.config.origin_fetch_timeout_seconds = Some(0);
+        // The server never responds.
+        let (fetcher, _server) = crate::fetcher::mock_fetcher::create();
+        let runtime = Runtime {
+            fetcher: Box::new(fetcher),
+            ..Default::default()
+        };
+        let err = worker
+            .fetch_origin(&runtime, origin_request())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, SxgError::Network(_)));
+        assert_eq!(
+            err.inner().downcast_ref::<OriginFetchError>(),
+            Some(&OriginFetchError::Timeout(Duration::from_secs(0)))
+        );
+    }
+    #[tokio::test]
+    async fn writes_signed_exchange() {
+        let worker = new_worker();
+        let runtime = signing_runtime();
+        let signed_exchange = sign_with_runtime(&worker, &runtime, 200).await.unwrap();
+        let mut bytes = vec![];
+        let expires = worker
+            .create_signed_exchange_to_writer(
+                &runtime,
+                CreateSignedExchangeParams {
+                    fallback_url: "https://my_domain.com/",
+                    cert_origin: "https://my_domain.com",
+                    payload_body: b"This is a test.",
+                    payload_headers: worker
+                        .transform_payload_headers(vec![(
+                            "content-type".into(),
+                            "text/html".into(),
+                        )])
+                        .unwrap(),
+                    skip_process_link: false,
+                    status_code: 200,
+                    header_integrity_cache: http_cache::NullCache {},
+                },
+                &mut bytes,
+            )
+            .await
+            .unwrap();
+        assert_eq!(bytes, signed_exchange.bytes);
+        assert_eq!(expires, signed_exchange.expires);
+    }
+    #[test]
+    fn signs_deterministically() {
+        let worker = new_worker();
+        let fields: Vec<(String, String)> = (0..20)
+            .map(|i| (format!("x-header-{}", i), format!("value-{}", i)))
+            .chain(std::iter::once(("content-type".into(), "text/html".into())))
+            .collect();
+        // Each `Headers` has its own hash state, so inserting the same fields
+        // in a different order is likely to iterate them in another order.
+        let sign = |fields: Vec<(String, String)>| {
+            worker
+                .create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+                    now: SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+                    fallback_url: "https://my_domain.com/",
+                    cert_origin: "https://my_domain.com",
+                    payload_body: b"This is a test.",
+                    payload_headers: worker.transform_payload_headers(fields).unwrap(),
+                    status_code: 200,
+                    ocsp_der: b"ocsp",
+                    signer: &signature::mock_signer::MockSigner,
+                })
+                .unwrap()
+                .bytes
+        };
+        let reversed = fields.iter().rev().cloned().collect();
+        assert_eq!(sign(fields), sign(reversed));
+    }
+    #[test]
+    fn returns_certificate_info() {
+        let mut worker = SxgWorker::from_parsed(new_worker().config);
+        assert!(worker.certificate_info().is_none());
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::SELF_SIGNED_CERT_PEM]).unwrap(),
+        );
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::MULTI_SAN_CERT_PEM]).unwrap(),
+        );
+        assert_eq!(
+            worker.certificate_info().unwrap().unwrap(),
+            CertificateInfo {
+                subject: "CN=example.com, O=Test, C=US".to_string(),
+                subject_alt_names: vec![
+                    "example.com".to_string(),
+                    "www.example.com".to_string(),
+                    "*.cdn.example.com".to_string(),
+                    "192.0.2.1".to_string(),
+                ],
+                serial_number: "1f:95:b6:1b:28:89:8f:77:2f:20:a3:16:2e:0b:ee:30:7c:3a:11:c9"
+                    .to_string(),
+                issuer: "CN=example.com, O=Test, C=US".to_string(),
+                // 2020-09-01 and 2030-09-01.
+                not_before: SystemTime::UNIX_EPOCH + Duration::from_secs(1_598_918_400),
+                not_after: SystemTime::UNIX_EPOCH + Duration::from_secs(1_914_451_200),
+            }
+        );
+    }
+    #[test]
+    fn refuses_uncovered_hosts() {
+        let mut worker = new_worker();
+        worker.config.allow_uncovered_hosts = false;
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[util::MULTI_SAN_CERT_PEM]).unwrap(),
+        );
+        assert_eq!(
+            worker.certificate_sans(),
+            vec![
+                "example.com",
+                "www.example.com",
+                "*.cdn.example.com",
+                "192.0.2.1"
+            ]
+        );
+        let sign = |fallback_url: &str| {
+            let cert_origin = Url::parse(fallback_url)
+                .unwrap()
+                .origin()
+                .ascii_serialization();
+            worker.create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+                now: SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000),
+                fallback_url,
+                cert_origin: &cert_origin,
+                payload_body: b"This is a test.",
+                payload_headers: worker
+                    .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+                    .unwrap(),
+                status_code: 200,
+                ocsp_der: b"ocsp",
+                signer: &signature::mock_signer::MockSigner,
+            })
+        };
+        for url in [
+            "https://example.com/",
+            "https://WWW.Example.com/page",
+            "https://img.cdn.example.com/",
+            "https://192.0.2.1/",
+        ] {
+            assert!(sign(url).is_ok(), "{}", url);
+        }
+        for url in [
+            "https://my_domain.com/",
+            "https://api.example.com/",
+            "https://cdn.example.com/",
+            "https://a.b.cdn.example.com/",
+            "https://192.0.2.2/",
+        ] {
+            let err = sign(url).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("The certificate does not cover host"),
+                "{}",
+                url
+            );
+        }
+    }
+    #[tokio::test]
+    async fn returns_signed_headers() {
+        let sxg = sign_with_status(&new_worker(), 200).await.unwrap();
+        let expected = [
+            (
+                "digest",
+                "mi-sha256-03=0QggSLnO5bDukDUwVpNeq59ySlMstijCCBl/Dd5S1HU=",
+            ),
+            (":status", "200"),
+            ("content-type", "text/html"),
+            ("content-encoding", "mi-sha256-03"),
+            ("x-content-type-options", "nosniff"),
+        ];
+        assert_eq!(
+            sxg.signed_headers().unwrap(),
+            expected
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+    #[tokio::test]
+    async fn blocking_signing_matches_async() {
+        let worker = new_worker();
+        let runtime = signing_runtime();
+        let sign_blocking = |status_code| {
+            worker.create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+                now: runtime.now,
+                fallback_url: "https://my_domain.com/",
+                cert_origin: "https://my_domain.com",
+                payload_body: b"This is a test.",
+                payload_headers: worker
+                    .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+                    .unwrap(),
+                status_code,
+                ocsp_der: b"ocsp",
+                signer: &signature::mock_signer::MockSigner,
+            })
+        };
+        let blocking = sign_blocking(200).unwrap();
+        let non_blocking = sign_with_runtime(&worker, &runtime, 200).await.unwrap();
+        assert_eq!(blocking.bytes, non_blocking.bytes);
+        assert_eq!(blocking.expires, non_blocking.expires);
+        let err = sign_blocking(302).unwrap_err();
+        assert!(matches!(err, SxgError::Sxg(_)));
+        assert_eq!(err.status(), SxgStatus::PassthroughUncacheable);
+    }
+    #[test]
+    fn signs_provided_response() {
+        let worker = new_worker();
+        let now = signing_runtime().now;
+        let sign_response = |headers: Vec<(&str, &str)>| {
+            let response = HttpResponse {
+                body: b"This is a test.".to_vec(),
+                headers: headers
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                status: 200,
+            };
+            worker.create_signed_exchange_from_response(CreateSignedExchangeFromResponseParams {
+                now,
+                fallback_url: "https://my_domain.com/",
+                cert_origin: "https://my_domain.com",
+                response: &response,
+                ocsp_der: b"ocsp",
+                signer: &signature::mock_signer::MockSigner,
+            })
+        };
+        let expected = worker
+            .create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+                now,
+                fallback_url: "https://my_domain.com/",
+                cert_origin: "https://my_domain.com",
+                payload_body: b"This is a test.",
+                payload_headers: worker
+                    .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+                    .unwrap(),
+                status_code: 200,
+                ocsp_der: b"ocsp",
+                signer: &signature::mock_signer::MockSigner,
+            })
+            .unwrap();
+        // set-cookie is in strip_response_headers.
+        let signed_exchange =
+            sign_response(vec![("content-type", "text/html"), ("set-cookie", "id=1")]).unwrap();
+        assert_eq!(signed_exchange.bytes, expected.bytes);
+        let err = sign_response(vec![
+            ("content-type", "text/html"),
+            ("cache-control", "no-store"),
+        ])
+        .unwrap_err();
+        assert_eq!(err.status(), SxgStatus::PassthroughUncacheable);
+    }
+    #[tokio::test]
+    async fn transforms_payload_before_signing() {
+        let worker = new_worker();
+        let runtime = Runtime {
+            payload_transform: Some(Box::new(|body: &[u8], headers: &Headers| {
+                assert_eq!(headers.inner()["content-type"], "text/html");
+                let body = std::str::from_utf8(body).unwrap();
+                Cow::Owned(body.replace("a test", "a transformed test").into_bytes())
+            })),
+            ..signing_runtime()
+        };
+        let payload_headers = worker
+            .transform_payload_headers(vec![
+                ("content-type".into(), "text/html".into()),
+                ("content-length".into(), "15".into()),
+            ])
+            .unwrap();
+        let signed_exchange = worker
+            .create_signed_exchange(
+                &runtime,
+                CreateSignedExchangeParams {
+                    fallback_url: "https://my_domain.com/",
+                    cert_origin: "https://my_domain.com",
+                    payload_body: b"This is a test.",
+                    payload_headers,
+                    skip_process_link: false,
+                    status_code: 200,
+                    header_integrity_cache: http_cache::NullCache {},
+                },
+            )
+            .await
+            .unwrap();
+        // The MICE-encoded payload is at the end, after the record size.
+        assert!(signed_exchange
+            .bytes
+            .ends_with(b"This is a transformed test."));
+        // The signed headers are a CBOR map, in which "content-length" is a
+        // 14-byte string and "27" a 2-byte string.
+        let content_length = b"\x4econtent-length\x4227";
+        assert!(signed_exchange
+            .bytes
+            .windows(content_length.len())
+            .any(|window| window == content_length));
+    }
+    #[tokio::test]
+    async fn clamps_expiry_to_certificate() {
+        let mut worker = new_worker();
+        worker.add_certificate(
+            CertificateChain::from_pem_files(&[
+                util::NEAR_EXPIRY_CERT_PEM,
+                util::NEAR_EXPIRY_CERT_PEM,
+            ])
+            .unwrap(),
+        );
+        // The signature would otherwise expire 7 days after 1600000000.
+        let signed_exchange = sign_with_status(&worker, 200).await.unwrap();
+        assert_eq!(
+            signed_exchange.expires,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1600128000)
+        );
+        worker.config.error_on_expiry_clamp = true;
+        let err = sign_with_status(&worker, 200).await.unwrap_err();
+        assert!(format!("{}", err).contains("expire after the certificate"));
+        // An expired certificate is never used, whether or not it's clamped.
+        worker.config.error_on_expiry_clamp = false;
+        let mut runtime = signing_runtime();
+        runtime.now = SystemTime::UNIX_EPOCH + Duration::from_secs(1600200000);
+        let err = sign_with_runtime(&worker, &runtime, 200).await.unwrap_err();
+        assert!(format!("{}", err).contains("certificate expired"));
+    }
+    #[tokio::test]
+    async fn signs_with_freshest_covering_certificate() {
+        let mut worker = SxgWorker::from_parsed(new_worker().config);
+        worker.config.allow_uncovered_hosts = false;
+        for pem in [
+            util::FRESH_CERT_PEM,
+            // Expires later, but doesn't cover example.org.
+            util::MULTI_SAN_CERT_PEM,
+            // Expires 2 days after the signing time.
+            util::NEAR_EXPIRY_CERT_PEM,
+        ] {
+            worker.add_certificate(CertificateChain::from_pem_files(&[pem, pem]).unwrap());
+        }
+        let payload_headers = worker
+            .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+            .unwrap();
+        let signed_exchange = worker
+            .create_signed_exchange(
+                &signing_runtime(),
+                CreateSignedExchangeParams {
+                    fallback_url: "https://example.org/",
+                    cert_origin: "https://example.org",
+                    payload_body: b"This is a test.",
+                    payload_headers,
+                    skip_process_link: false,
+                    status_code: 200,
+                    header_integrity_cache: http_cache::NullCache {},
+                },
+            )
+            .await
+            .unwrap();
+        let parts = sxg::parse(&signed_exchange.bytes).unwrap();
+        let signature = std::str::from_utf8(parts.signature).unwrap();
+        assert!(signature.contains(util::FRESH_CERT_SHA256));
+        assert!(signed_exchange.expires > SystemTime::UNIX_EPOCH + Duration::from_secs(1600128000));
+    }
+    #[tokio::test]
+    async fn blocking_signing_selects_same_certificate() {
+        let mut worker = SxgWorker::from_parsed(new_worker().config);
+        worker.config.allow_uncovered_hosts = false;
+        for pem in [
+            util::FRESH_CERT_PEM,
+            util::MULTI_SAN_CERT_PEM,
+            util::NEAR_EXPIRY_CERT_PEM,
+        ] {
+            worker.add_certificate(CertificateChain::from_pem_files(&[pem, pem]).unwrap());
+        }
+        let runtime = signing_runtime();
+        let payload_headers = || {
+            worker
+                .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+                .unwrap()
+        };
+        let non_blocking = worker
+            .create_signed_exchange(
+                &runtime,
+                CreateSignedExchangeParams {
+                    fallback_url: "https://example.org/",
+                    cert_origin: "https://example.org",
+                    payload_body: b"This is a test.",
+                    payload_headers: payload_headers(),
+                    skip_process_link: false,
+                    status_code: 200,
+                    header_integrity_cache: http_cache::NullCache {},
+                },
+            )
+            .await
+            .unwrap();
+        let blocking = worker
+            .create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+                now: runtime.now,
+                fallback_url: "https://example.org/",
+                cert_origin: "https://example.org",
+                payload_body: b"This is a test.",
+                payload_headers: payload_headers(),
+                status_code: 200,
+                ocsp_der: b"ocsp",
+                signer: &signature::mock_signer::MockSigner,
+            })
+            .unwrap();
+        let non_blocking = parse_signature_header(&non_blocking);
+        let blocking = parse_signature_header(&blocking);
+        assert_eq!(blocking.cert_url, non_blocking.cert_url);
+        assert_eq!(blocking.cert_sha256, non_blocking.cert_sha256);
+        assert!(blocking.cert_url.ends_with(util::FRESH_CERT_SHA256));
+        let url = Url::parse("https://example.org/").unwrap();
+        assert_eq!(
+            worker.signing_certificate_basename(&url, runtime.now),
+            Some(util::FRESH_CERT_SHA256)
+        );
+    }
+    #[tokio::test]
+    async fn serves_staple_of_each_rotated_certificate() {
+        let mut worker = SxgWorker::from_parsed(new_worker().config);
+        worker.config.allow_uncovered_hosts = false;
+        let storage = runtime::store::InMemoryStore::new(signing_runtime().now);
+        let mut staples = vec![];
+        for (pem, staple) in [
+            (util::FRESH_CERT_PEM, "fresh staple"),
+            (util::NEAR_EXPIRY_CERT_PEM, "near expiry staple"),
+        ] {
+            let certificate = CertificateChain::from_pem_files(&[pem, pem]).unwrap();
+            // The staples can't be parsed, so their freshness is not checked.
+            let ocsp = serde_json::json!({
+                "expiration_time": {"secs_since_epoch": 1700000000, "nanos_since_epoch": 0},
+                "recommended_update_time": {"secs_since_epoch": 1700000000, "nanos_since_epoch": 0},
+                "value": base64::encode_config(staple, base64::URL_SAFE_NO_PAD),
+            });
+            runtime::store::Store::put(
+                &storage,
+                &ocsp::storage_key(&certificate),
+                &ocsp.to_string(),
+            )
+            .await
+            .unwrap();
+            staples.push((certificate.basename.clone(), staple));
+            worker.add_certificate(certificate);
+        }
+        let runtime = Runtime {
+            storage: Box::new(storage),
+            ..signing_runtime()
+        };
+        // The latest certificate expires first, so the earlier one signs.
+        let payload_headers = worker
+            .transform_payload_headers(vec![("content-type".into(), "text/html".into())])
+            .unwrap();
+        let signed_exchange = worker
+            .create_signed_exchange(
+                &runtime,
+                CreateSignedExchangeParams {
+                    fallback_url: "https://example.org/",
+                    cert_origin: "https://example.org",
+                    payload_body: b"This is a test.",
+                    payload_headers,
+                    skip_process_link: false,
+                    status_code: 200,
+                    header_integrity_cache: http_cache::NullCache {},
+                },
+            )
+            .await
+            .unwrap();
+        let parts = sxg::parse(&signed_exchange.bytes).unwrap();
+        let signature = std::str::from_utf8(parts.signature).unwrap();
+        assert!(signature.contains(util::FRESH_CERT_SHA256));
+        for (basename, staple) in staples {
+            let url = format!("https://example.org/.well-known/sxg-certs/{}", basename);
+            let cert_cbor = match worker.serve_preset_content(&runtime, &url, None).await {
+                Some(PresetContent::Direct(response)) => response.body,
+                _ => panic!("Expecting the cert-chain"),
+            };
+            let items = crypto::parse_cert_cbor(&cert_cbor).unwrap();
+            assert_eq!(items[0].ocsp.as_deref(), Some(staple.as_bytes()));
+        }
+    }
+    #[tokio::test]
+    async fn signature_max_age_header() {
+        let mut worker = new_worker();
+        worker.config.signature_max_age_header = Some("x-sxg-max-age".into());
+        let runtime = signing_runtime();
+        for (max_age, expected_expires) in [
+            ("3600", Some(runtime.now + Duration::from_secs(3600))),
+            // Capped by cache-control.
+            ("864000", Some(runtime.now + Duration::from_secs(7200))),
+            // Ignored, so cache-control applies.
+            ("1h", Some(runtime.now + Duration::from_secs(7200))),
+            // Too short to sign.
+            ("60", None),
+        ] {
+            let payload_headers = worker
+                .transform_payload_headers(vec![
+                    ("content-type".into(), "text/html".into()),
+                    ("cache-control".into(), "max-age=7200".into()),
+                    ("X-SXG-Max-Age".into(), max_age.into()),
+                ])
+                .unwrap();
+            let signed_exchange = worker
+                .create_signed_exchange(
+                    &runtime,
+                    CreateSignedExchangeParams {
+                        fallback_url: "https://my_domain.com/",
+                        cert_origin: "https://my_domain.com",
+                        payload_body: b"This is a test.",
+                        payload_headers,
+                        skip_process_link: false,
+                        status_code: 200,
+                        header_integrity_cache: http_cache::NullCache {},
+                    },
+                )
+                .await;
+            let expected_expires = match expected_expires {
+                Some(expires) => expires,
+                None => {
+                    assert!(signed_exchange.is_err(), "{}", max_age);
+                    continue;
+                }
+            };
+            let signed_exchange = signed_exchange.unwrap();
+            assert_eq!(signed_exchange.expires, expected_expires, "{}", max_age);
+            assert!(signed_exchange
+                .signed_headers()
+                .unwrap()
+                .iter()
+                .all(|(name, _)| name != "x-sxg-max-age"));
+        }
+    }
+    #[tokio::test]
+    async fn signs_only_signable_statuses() {
+        let mut worker = new_worker();
+        assert!(sign_with_status(&worker, 200).await.is_ok());
+        assert!(!worker.is_signable_status(302));
+        assert!(sign_with_status(&worker, 302).await.is_err());
+        assert!(sign_with_status(&worker, 203).await.is_err());
+        worker.config.signable_statuses = vec![200, 203];
+        assert!(sign_with_status(&worker, 203).await.is_ok());
+    }
+    #[tokio::test]
+    async fn passes_through_partial_and_not_modified() {
+        let mut worker = new_worker();
+        // Even if a config bypasses validation.
+        worker.config.signable_statuses = vec![200, 206, 304];
+        for status_code in [206, 304] {
+            assert!(!worker.is_signable_status(status_code));
+            let err = sign_with_status(&worker, status_code).await.unwrap_err();
+            assert_eq!(err.status(), SxgStatus::PassthroughUncacheable);
+            assert!(format!("{}", err).contains("never signed"));
+            let err = worker
+                .create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+                    now: signing_runtime().now,
+                    fallback_url: "https://my_domain.com/",
+                    cert_origin: "https://my_domain.com",
+                    payload_body: b"",
+                    payload_headers: worker
+                        .transform_payload_headers(vec![(
+                            "content-type".into(),
+                            "text/html".into(),
+                        )])
+                        .unwrap(),
+                    status_code,
+                    ocsp_der: b"",
+                    signer: &signature::mock_signer::MockSigner,
+                })
+                .unwrap_err();
+            assert_eq!(err.status(), SxgStatus::PassthroughUncacheable);
+        }
+    }
+    #[tokio::test]
+    async fn sxg_status() {
+        let mut worker = new_worker();
+        assert_eq!(worker.sxg_status_header(SxgStatus::Signed), None);
+        worker.config.add_sxg_status_header = true;
+        assert_eq!(
+            worker.sxg_status_header(SxgStatus::PassthroughTooLarge),
+            Some(("x-sxg", "passthrough-too-large"))
+        );
+        let status_of = |error: Option<SxgError>| error.unwrap().status();
+        assert_eq!(
+            status_of(sign_with_status(&worker, 302).await.err()),
+            SxgStatus::PassthroughUncacheable
+        );
+        assert_eq!(
+            status_of(
+                worker
+                    .transform_payload_headers(vec![
+                        ("content-type".into(), "text/html".into()),
+                        ("cache-control".into(), "no-store".into()),
+                    ])
+                    .err()
+            ),
+            SxgStatus::PassthroughUncacheable
+        );
+        assert_eq!(
+            status_of(
+                worker
+                    .transform_payload_headers(vec![
+                        ("content-type".into(), "text/html".into()),
+                        ("content-length".into(), "9000000".into()),
+                    ])
+                    .err()
+            ),
+            SxgStatus::PassthroughTooLarge
+        );
+        assert_eq!(
+            status_of(worker.transform_payload_headers(vec![]).err()),
+            SxgStatus::Error
+        );
+    }
+    #[test]
+    fn filters_payload_headers_by_mode() {
+        let fields = || {
+            vec![
+                ("content-type".into(), "text/html".into()),
+                ("set-cookie".into(), "id=1".into()),
+                ("x-custom".into(), "1".into()),
+            ]
+        };
+        let header_names = |headers: Headers| {
+            let mut names: Vec<String> = headers.inner().keys().cloned().collect();
+            names.sort();
+            names
+        };
+        let mut worker = new_worker();
+        assert_eq!(
+            header_names(worker.transform_payload_headers(fields()).unwrap()),
+            ["content-type", "x-content-type-options", "x-custom"]
+        );
+        worker.config.response_header_mode = config::ResponseHeaderMode::Allowlist;
+        worker.config.signed_response_headers =
+            ["content-type"].iter().map(|s| s.to_string()).collect();
+        let fields = || {
+            vec![
+                ("content-type".into(), "text/html".into()),
+                ("cache-control".into(), "max-age=600".into()),
+                ("x-custom".into(), "1".into()),
+            ]
+        };
+        let headers = worker.transform_payload_headers(fields()).unwrap();
+        // The dropped cache-control header still sets the signature duration.
+        assert_eq!(
+            headers.signature_duration().unwrap(),
+            Duration::from_secs(600)
+        );
+        assert_eq!(
+            header_names(headers),
+            ["content-type", "x-content-type-options"]
+        );
+        worker.config.signed_response_headers.clear();
+        assert!(worker.transform_payload_headers(fields()).is_err());
+    }
+    #[test]
+    fn allowlist_does_not_sign_personalized_response() {
+        let mut worker = new_worker();
+        worker.config.response_header_mode = config::ResponseHeaderMode::Allowlist;
+        worker.config.signed_response_headers =
+            ["content-type"].iter().map(|s| s.to_string()).collect();
+        for (name, value) in [("set-cookie", "id=1"), ("cache-control", "private")] {
+            let fields = vec![
+                ("content-type".into(), "text/html".into()),
+                (name.into(), value.into()),
+            ];
+            assert_eq!(
+                worker
+                    .transform_payload_headers(fields)
+                    .err()
+                    .unwrap()
+                    .status(),
+                SxgStatus::PassthroughUncacheable
+            );
+        }
+    }
+    #[tokio::test]
+    async fn adds_nosniff_header() {
+        let mut worker = new_worker();
+        let nosniff = ("x-content-type-options".to_string(), "nosniff".to_string());
+        let sxg = sign_with_status(&worker, 200).await.unwrap();
+        assert!(sxg.signed_headers().unwrap().contains(&nosniff));
+        // The origin's value is kept.
+        let headers = worker
+            .transform_payload_headers(vec![
+                ("content-type".into(), "text/html".into()),
+                ("X-Content-Type-Options".into(), "NOSNIFF".into()),
+            ])
+            .unwrap();
+        assert_eq!(
+            headers.inner().get("x-content-type-options").unwrap(),
+            "NOSNIFF"
+        );
+        worker.config.add_nosniff_header = false;
+        let sxg = sign_with_status(&worker, 200).await.unwrap();
+        assert!(!sxg.signed_headers().unwrap().contains(&nosniff));
+    }
+    fn parse_signature_header(signed_exchange: &SignedExchange) -> signature::SignatureHeader {
+        let bytes = &signed_exchange.bytes;
+        let url_len = u16::from_be_bytes([bytes[8], bytes[9]]) as usize;
+        let sig_start = 10 + url_len + 6;
+        let sig_len = u32::from_be_bytes([
+            0,
+            bytes[10 + url_len],
+            bytes[11 + url_len],
+            bytes[12 + url_len],
+        ]);
+        let sig = &bytes[sig_start..sig_start + sig_len as usize];
+        signature::SignatureHeader::parse(std::str::from_utf8(sig).unwrap()).unwrap()
+    }
+    #[tokio::test]
+    async fn signature_url_forms() {
+        let mut worker = new_worker();
+        let header = parse_signature_header(&sign_with_status(&worker, 200).await.unwrap());
+        assert_eq!(
+            header.cert_url,
+            format!(
+                "https://my_domain.com/.well-known/sxg-certs/{}",
+                util::SELF_SIGNED_CERT_SHA256
+            )
+        );
+        assert_eq!(
+            header.validity_url,
+            "https://my_domain.com/.well-known/sxg-validity/validity"
+        );
+        worker.config.cert_url_form = UrlForm::Relative;
+        worker.config.validity_url_form = UrlForm::Relative;
+        let header = parse_signature_header(&sign_with_status(&worker, 200).await.unwrap());
+        assert_eq!(
+            header.cert_url,
+            format!("/.well-known/sxg-certs/{}", util::SELF_SIGNED_CERT_SHA256)
+        );
+        assert_eq!(header.validity_url, "/.well-known/sxg-validity/validity");
+    }
+    #[cfg(feature = "test-utils")]
+    #[tokio::test]
+    async fn data_cert_url() {
+        let mut worker = new_worker();
+        worker.config.cert_url_form = UrlForm::Data;
+        let runtime = signing_runtime();
+        let header =
+            parse_signature_header(&sign_with_runtime(&worker, &runtime, 200).await.unwrap());
+        let cert_cbor = verify::decode_data_cert_url(&header.cert_url)
+            .unwrap()
+            .unwrap();
+        let ocsp_der = worker.get_unexpired_ocsp(&runtime).await.unwrap();
+        assert_eq!(
+            cert_cbor,
+            worker.create_cert_cbor(util::SELF_SIGNED_CERT_SHA256, &ocsp_der)
+        );
+    }
+    #[tokio::test]
+    async fn signature_label() {
+        let mut worker = new_worker();
+        let header = parse_signature_header(&sign_with_status(&worker, 200).await.unwrap());
+        assert_eq!(header.label, "sig");
+        worker.config.signature_label = Some("label".to_string());
+        let header = parse_signature_header(&sign_with_status(&worker, 200).await.unwrap());
+        assert_eq!(header.label, "label");
+    }
+    #[tokio::test]
+    async fn validity_url_must_be_same_origin() {
+        let mut worker = new_worker();
+        // A backslash is parsed as a slash, so this resolves to
+        // `https://other.example/sxg-validity/validity`.
+        worker.config.validity_url_dirname = "/\\other.example/sxg-validity/".to_string();
+        let err = sign_with_status(&worker, 200).await.unwrap_err();
+        assert!(format!("{}", err).contains("not same-origin"));
+    }
+    #[tokio::test]
+    async fn reports_metrics() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        #[derive(Clone, Default)]
+        struct RecordingMetrics(Rc<RefCell<Vec<String>>>);
+        impl runtime::metrics::Metrics for RecordingMetrics {
+            fn incr(&self, name: &str) {
+                self.0.borrow_mut().push(name.to_string());
+            }
+            fn observe(&self, name: &str, value: f64) {
+                assert!(value >= 0.0);
+                self.0.borrow_mut().push(name.to_string());
+            }
+        }
+        let recorded = RecordingMetrics::default();
+        let runtime = Runtime {
+            metrics: Box::new(recorded.clone()),
+            signing_limiter: Some(std::sync::Arc::new(
+                runtime::signing_limiter::SigningLimiter::new(0),
+            )),
+            ..signing_runtime()
+        };
+        let worker = new_worker();
+        assert!(sign_with_runtime(&worker, &runtime, 302).await.is_err());
+        assert!(sign_with_runtime(&worker, &runtime, 200).await.is_err());
+        let runtime = Runtime {
+            metrics: Box::new(recorded.clone()),
+            ..signing_runtime()
+        };
+        assert!(sign_with_runtime(&worker, &runtime, 200).await.is_ok());
+        assert_eq!(
+            *recorded.0.borrow(),
+            vec![
+                "sxg_passed_through",
+                "sxg_signing_failures_overloaded",
+                "sxg_signed",
+                "sxg_signing_latency_ms",
+            ]
+        );
+    }
+    #[test]
+    fn signed_exchange_max_age() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let signed_exchange = SignedExchange {
+            bytes: vec![],
+            expires: now + Duration::from_secs(60),
+        };
+        assert_eq!(signed_exchange.max_age(now), Duration::from_secs(60));
+        assert_eq!(
+            signed_exchange.max_age(now + Duration::from_secs(120)),
+            Duration::ZERO
+        );
+    }
 }
@@ -1,20 +1,24 @@
+use crate::config::LinkHint;
 use crate::header_integrity::HeaderIntegrityFetcher;
 use crate::http_parser::{link::Link, parse_link_header, srcset};
+use crate::utils::console_log;
 use futures::{stream, stream::StreamExt};
 use once_cell::sync::Lazy;
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::iter::once;
 use url::{Origin, Url};
 
 // Filters the link header to comply with
 // https://github.com/google/webpackager/blob/main/docs/cache_requirements.md,
-// and adds allowed-alt-sxg with header-integrity if not present.
+// and adds allowed-alt-sxg with header-integrity if not present. Preloads that
+// refer to a cross-origin URL outside allowed_origins are dropped.
 pub(crate) async fn process_link_header(
     value: &str,
     fallback_url: &Url,
     header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
+    allowed_origins: &BTreeSet<String>,
 ) -> String {
     let links = match parse_link_header(value) {
         Ok(links) => links,
@@ -30,16 +34,6 @@ pub(crate) async fn process_link_header(
     stream::iter(preloads)
         .for_each_concurrent(None, |link| async {
             let link = link;
-            let srcset = get_param(&link.params, "imagesrcset");
-            let images = match &srcset {
-                Some(srcset) => srcset::parse(srcset).unwrap_or_default(),
-                None => vec![],
-            };
-            // Convert image hrefs from srcset to absolute, for comparison with
-            // allowed_alt_sxgs.
-            let images = images
-                .iter()
-                .filter_map(|url| fallback_url.join(url).ok().map(String::from));
 
             // Collect allowed-alt-sxg directives for all URLs referred to by this preload.
             // We will only output the preload if all of them can be found or computed.
@@ -48,8 +42,27 @@ pub(crate) async fn process_link_header(
 
             // Iterate over all URLs referred to by this preload.
             // TODO: Make these concurrent also. (Not critical because imagesrcset is rare.)
-            let urls = once(link.uri.clone()).chain(images);
+            let urls = match preload_urls(&link, fallback_url) {
+                Some(urls) => urls,
+                None => {
+                    console_log(&format!(
+                        "Warning: Skipping the preload of {}, because its imagesrcset \
+                        can't be checked without the srcset feature.",
+                        link.uri
+                    ));
+                    return;
+                }
+            };
             for url in urls {
+                if !is_allowed_origin(&fallback_origin, allowed_origins, &url) {
+                    console_log(&format!(
+                        "Warning: Skipping the preload of {}, whose origin is not in \
+                        allowed_subresource_origins.",
+                        url
+                    ));
+                    all_allow_sxg = false;
+                    break;
+                }
                 let mut allow_sxg = false;
                 match allowed_alt_sxgs.get(&url) {
                     Some(allowed_alt_sxg) => {
@@ -57,19 +70,17 @@ pub(crate) async fn process_link_header(
                         allow_sxg = true;
                     }
                     None => {
-                        // Fetch and compute header-integrity only for same-origin preloads.
-                        // Cross-origin preloads are assumed unlikely to be SXG.
-                        if origin_is(&fallback_origin, &url) {
-                            if let Ok(integrity) = header_integrity_fetcher.fetch(&url).await {
-                                allow_directives.push(Link {
-                                    uri: url,
-                                    params: vec![
-                                        (Cow::Borrowed("rel"), Some("allowed-alt-sxg".into())),
-                                        (Cow::Borrowed("header-integrity"), Some(integrity)),
-                                    ],
-                                });
-                                allow_sxg = true;
-                            }
+                        // Fetch and compute header-integrity, which is only
+                        // done for same-origin or allowlisted preloads.
+                        if let Ok(integrity) = header_integrity_fetcher.fetch(&url).await {
+                            allow_directives.push(Link {
+                                uri: url,
+                                params: vec![
+                                    (Cow::Borrowed("rel"), Some("allowed-alt-sxg".into())),
+                                    (Cow::Borrowed("header-integrity"), Some(integrity)),
+                                ],
+                            });
+                            allow_sxg = true;
                         }
                     }
                 };
@@ -97,6 +108,125 @@ pub(crate) async fn process_link_header(
         .join(",")
 }
 
+// Drops the preloads and prefetches that refer to a cross-origin URL outside
+// allowed_origins, like process_link_header, but leaves the other directives
+// as they are, so it needs no I/O. Used for the configured prefetch hints, and
+// for the whole link header when it isn't processed.
+pub(crate) fn drop_disallowed_hints(
+    value: &str,
+    fallback_url: &Url,
+    allowed_origins: &BTreeSet<String>,
+) -> String {
+    let links = match parse_link_header(value) {
+        Ok(links) => links,
+        Err(_) => {
+            return "".into();
+        }
+    };
+    let fallback_origin = fallback_url.origin();
+    let (allowed, disallowed): (Vec<Link>, Vec<Link>) = links.into_iter().partition(|link| {
+        !matches!(
+            get_param(&link.params, "rel").as_deref(),
+            Some("preload" | "prefetch")
+        ) || preload_urls(link, fallback_url).map_or(false, |urls| {
+            urls.iter()
+                .all(|url| is_allowed_origin(&fallback_origin, allowed_origins, url))
+        })
+    });
+    if disallowed.is_empty() {
+        return value.to_string();
+    }
+    for link in disallowed {
+        let reason = if preload_urls(&link, fallback_url).is_some() {
+            "whose origin is not in allowed_subresource_origins"
+        } else {
+            "because its imagesrcset can't be checked without the srcset feature"
+        };
+        console_log(&format!(
+            "Warning: Skipping the {} of {}, {}.",
+            get_param(&link.params, "rel").unwrap_or_default(),
+            link.uri,
+            reason
+        ));
+    }
+    allowed
+        .iter()
+        .map(|link| link.serialize())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Serializes the configured link hints, resolving their URLs against
+// fallback_url. Hints whose URL can't be resolved are skipped.
+pub(crate) fn serialize_link_hints<'a>(
+    link_hints: impl IntoIterator<Item = &'a LinkHint>,
+    fallback_url: &Url,
+) -> String {
+    link_hints
+        .into_iter()
+        .filter_map(|hint| {
+            let link = Link {
+                uri: fallback_url.join(&hint.url).ok()?.into(),
+                params: vec![
+                    (Cow::Borrowed("rel"), Some(hint.rel.clone())),
+                    (Cow::Borrowed("as"), Some(hint.r#as.clone())),
+                ],
+            };
+            Some(link.serialize())
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Returns the link_hints that aren't already in the given link header, so
+// that a directive emitted by the origin takes precedence over a generated
+// one for the same URL and rel. Both are resolved against fallback_url before
+// comparison.
+pub(crate) fn link_hints_not_in<'a>(
+    link_hints: impl IntoIterator<Item = &'a LinkHint>,
+    link: &str,
+    fallback_url: &Url,
+) -> Vec<&'a LinkHint> {
+    let existing: HashSet<(String, String)> = parse_link_header(link)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|link| {
+            let uri: String = fallback_url.join(&link.uri).ok()?.into();
+            Some((uri, get_param(&link.params, "rel")?))
+        })
+        .collect();
+    link_hints
+        .into_iter()
+        .filter(|hint| match fallback_url.join(&hint.url) {
+            Ok(uri) => !existing.contains(&(uri.into(), hint.rel.clone())),
+            Err(_) => true,
+        })
+        .collect()
+}
+
+// Fetches each preloaded subresource through header_integrity_fetcher, which
+// caches the computed header-integrity, and returns the `rel=preload` and
+// `rel=allowed-alt-sxg` directives for it. As with process_link_header,
+// preloads whose header-integrity can't be computed are dropped.
+pub(crate) async fn subresource_preload<'a>(
+    preloads: impl IntoIterator<Item = &'a LinkHint>,
+    fallback_url: &Url,
+    header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
+    allowed_origins: &BTreeSet<String>,
+) -> String {
+    let value = serialize_link_hints(preloads, fallback_url);
+    if value.is_empty() {
+        return value;
+    }
+    process_link_header(
+        &value,
+        fallback_url,
+        header_integrity_fetcher,
+        allowed_origins,
+    )
+    .await
+}
+
 // Attributes allowed on Link headers by
 // https://github.com/google/webpackager/blob/main/docs/cache_requirements.md.
 pub static ALLOWED_PARAM_NAMES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
@@ -137,7 +267,7 @@ fn preloads_and_allowed_alt_sxgs<'a>(
         })
     });
 
-    let (mut preloads, allowed_alt_sxgs) = links
+    let (preloads, allowed_alt_sxgs) = links
         .filter_map(|link| {
             let uri: String = fallback_url.join(&link.uri).ok()?.into();
             match get_param(&link.params, "rel") {
@@ -148,9 +278,18 @@ fn preloads_and_allowed_alt_sxgs<'a>(
             }
         })
         .partition::<Vec<(bool, Link)>, _>(|(is_preload, _)| *is_preload);
+
+    // Drop repeated preloads, so they don't count twice against the limit.
+    let mut preloads: Vec<Link> = preloads
+        .into_iter()
+        .fold(vec![], |mut preloads, (_, link)| {
+            if !preloads.contains(&link) {
+                preloads.push(link);
+            }
+            preloads
+        });
     preloads.truncate(20);
 
-    let preloads: Vec<Link> = preloads.into_iter().map(|(_, link)| link).collect();
     let allowed_alt_sxgs: HashMap<String, Link> = allowed_alt_sxgs
         .into_iter()
         .map(|(_, link)| (link.uri.clone(), link))
@@ -171,18 +310,56 @@ fn get_param(params: &[(Cow<'_, str>, Option<String>)], name: &str) -> Option<St
     }
 }
 
-fn origin_is(origin: &Origin, uri: &str) -> bool {
-    Url::parse(uri).map_or(false, |u| &u.origin() == origin)
+// Returns the absolute URLs referred to by the preload: its uri, followed by
+// the images in its imagesrcset. Returns None if it has an imagesrcset but the
+// srcset feature is disabled, because the images can't be checked then.
+fn preload_urls(link: &Link, fallback_url: &Url) -> Option<Vec<String>> {
+    let srcset = get_param(&link.params, "imagesrcset");
+    let images = match &srcset {
+        Some(_) if !cfg!(feature = "srcset") => return None,
+        Some(srcset) => srcset::parse(srcset).unwrap_or_default(),
+        None => vec![],
+    };
+    // An unresolvable uri is kept as is, and then fails the origin check.
+    let uri = fallback_url
+        .join(&link.uri)
+        .map_or_else(|_| link.uri.clone(), String::from);
+    Some(
+        once(uri)
+            .chain(
+                images
+                    .iter()
+                    .filter_map(|url| fallback_url.join(url).ok().map(String::from)),
+            )
+            .collect(),
+    )
+}
+
+// Whether uri is same-origin with fallback_origin, or its origin is one of
+// allowed_origins.
+fn is_allowed_origin(
+    fallback_origin: &Origin,
+    allowed_origins: &BTreeSet<String>,
+    uri: &str,
+) -> bool {
+    match Url::parse(uri) {
+        Ok(url) => {
+            let origin = url.origin();
+            &origin == fallback_origin || allowed_origins.contains(&origin.ascii_serialization())
+        }
+        Err(_) => false,
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
     use crate::header_integrity::tests::null_integrity_fetcher;
     use anyhow::{anyhow, Result};
     use async_trait::async_trait;
 
-    struct FakeIntegrityFetcher(std::result::Result<String, String>);
+    // For use in other modules' tests.
+    pub struct FakeIntegrityFetcher(pub std::result::Result<String, String>);
 
     #[async_trait(?Send)]
     impl HeaderIntegrityFetcher for FakeIntegrityFetcher {
@@ -198,7 +375,8 @@ mod tests {
             process_link_header(
                 r#"<https://foo.com/> ; rel = "preload",</>;rel=allowed-alt-sxg;header-integrity=blah"#,
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             "<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity=blah"
@@ -208,7 +386,13 @@ mod tests {
             .map(|n| format!("<https://foo.com/{}.js>;rel=preload", n))
             .collect();
         assert_eq!(
-            process_link_header(&preloads.join(","), &url, &mut null_integrity_fetcher()).await,
+            process_link_header(
+                &preloads.join(","),
+                &url,
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
+            )
+            .await,
             ""
         );
         let allowed_alt_sxgs: Vec<String> = (0..20).map(|n|
@@ -222,21 +406,29 @@ mod tests {
             process_link_header(
                 &preloads_mixed.join(","),
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             preloads_mixed.join(",")
         );
 
         assert_eq!(
-            process_link_header("</foo>;rel=preload,<https://foo.com/foo>;rel=allowed-alt-sxg;header-integrity=blah", &url, &mut null_integrity_fetcher()).await,
+            process_link_header(
+                "</foo>;rel=preload,<https://foo.com/foo>;rel=allowed-alt-sxg;header-integrity=blah",
+                &url,
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
+            )
+            .await,
             "<https://foo.com/foo>;rel=preload,<https://foo.com/foo>;rel=allowed-alt-sxg;header-integrity=blah"
         );
         assert_eq!(
             process_link_header(
                 "<../quux>;rel=preload,<../quux>;rel=allowed-alt-sxg;header-integrity=blah",
                 &url.join("/bar/baz/").unwrap(),
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             "<https://foo.com/bar/quux>;rel=preload,<https://foo.com/bar/quux>;rel=allowed-alt-sxg;header-integrity=blah"
@@ -245,20 +437,28 @@ mod tests {
             process_link_header(
                 "<https://foo.com/>;rel=prefetch,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity=blah",
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             ""
         );
         assert_eq!(
-            process_link_header("</foo>", &url, &mut null_integrity_fetcher()).await,
+            process_link_header(
+                "</foo>",
+                &url,
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
+            )
+            .await,
             ""
         );
         assert_eq!(
             process_link_header(
                 "<https://foo.com/>;other",
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             ""
@@ -267,7 +467,8 @@ mod tests {
             process_link_header(
                 "<https://foo.com/>;rel=preload,<https://foo.com/>;rel=prefetch,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity=blah",
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             "<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity=blah"
@@ -282,7 +483,8 @@ mod tests {
             process_link_header(
                 r#"<img.jpg>;rel=preload;as=image;imagesizes=800px;imagesrcset="img2.jpg 800w",<img.jpg>;rel=allowed-alt-sxg;header-integrity=blah"#,
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             ""
@@ -294,7 +496,8 @@ mod tests {
                  <img2.jpg>;rel=allowed-alt-sxg;header-integrity=blah2,\
                  <img3.jpg>;rel=allowed-alt-sxg;header-integrity=blah3",
                 &url,
-                &mut null_integrity_fetcher()
+                &mut null_integrity_fetcher(),
+                &BTreeSet::new()
             )
             .await,
             "<https://foo.com/img.jpg>;rel=preload;as=image;imagesizes=800px;imagesrcset=\"img2.jpg 800w, img3.jpg\",\
@@ -308,6 +511,7 @@ mod tests {
                 "<img.jpg>;rel=preload;as=image;imagesizes=800px;imagesrcset=\"img2.jpg 800w, img3.jpg\"",
                 &url,
                 &mut fetcher,
+                &BTreeSet::new(),
             )
             .await,
             "<https://foo.com/img.jpg>;rel=preload;as=image;imagesizes=800px;imagesrcset=\"img2.jpg 800w, img3.jpg\",\
@@ -324,14 +528,14 @@ mod tests {
         ));
         let url = Url::parse("https://foo.com").unwrap();
         assert_eq!(
-            process_link_header("</>;rel=preload", &url, &mut fetcher).await,
+            process_link_header("</>;rel=preload", &url, &mut fetcher, &BTreeSet::new()).await,
             r#"<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#
         );
         assert_eq!(process_link_header(r#"</>;rel=preload,</>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#,
-        &url, &mut fetcher).await,
+        &url, &mut fetcher, &BTreeSet::new()).await,
                    r#"<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#);
         assert_eq!(process_link_header(r#"</>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#,
-        &url, &mut fetcher).await,
+        &url, &mut fetcher, &BTreeSet::new()).await,
                    r#"<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#);
     }
 
@@ -342,7 +546,13 @@ mod tests {
         ));
         let url = Url::parse("https://foo.com").unwrap();
         assert_eq!(
-            process_link_header("</a>;rel=preload,</b>;rel=preload", &url, &mut fetcher).await,
+            process_link_header(
+                "</a>;rel=preload,</b>;rel=preload",
+                &url,
+                &mut fetcher,
+                &BTreeSet::new()
+            )
+            .await,
             concat!(
                 r#"<https://foo.com/a>;rel=preload,<https://foo.com/a>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=","#,
                 r#"<https://foo.com/b>;rel=preload,<https://foo.com/b>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#
@@ -372,7 +582,13 @@ mod tests {
         });
         let url = Url::parse("https://foo.com").unwrap();
         assert_eq!(
-            process_link_header("</a>;rel=preload,</b>;rel=preload", &url, &mut fetcher).await,
+            process_link_header(
+                "</a>;rel=preload,</b>;rel=preload",
+                &url,
+                &mut fetcher,
+                &BTreeSet::new()
+            )
+            .await,
             concat!(
                 r#"<https://foo.com/b>;rel=preload,<https://foo.com/b>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=","#,
                 r#"<https://foo.com/a>;rel=preload,"#,
@@ -386,14 +602,205 @@ mod tests {
         let mut fetcher = FakeIntegrityFetcher(Err("some error".into()));
         let url = Url::parse("https://foo.com").unwrap();
         assert_eq!(
-            process_link_header("</>;rel=preload", &url, &mut fetcher).await,
+            process_link_header("</>;rel=preload", &url, &mut fetcher, &BTreeSet::new()).await,
             ""
         );
         assert_eq!(process_link_header(r#"</>;rel=preload,</>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#,
-        &url, &mut fetcher).await,
+        &url, &mut fetcher, &BTreeSet::new()).await,
                    r#"<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#);
         assert_eq!(process_link_header(r#"</>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#,
-        &url, &mut fetcher).await,
+        &url, &mut fetcher, &BTreeSet::new()).await,
                    r#"<https://foo.com/>;rel=preload,<https://foo.com/>;rel=allowed-alt-sxg;header-integrity="sha256-OcpYAC5zFQtAXUURzXkMDDxMbxuEeWVjdRCDcLcBhBY=""#);
     }
+    #[test]
+    fn serializes_link_hints() {
+        let url = Url::parse("https://foo.com/bar/").unwrap();
+        let link_hints = vec![
+            LinkHint {
+                url: "next.html".to_string(),
+                rel: "prefetch".to_string(),
+                r#as: "document".to_string(),
+            },
+            LinkHint {
+                url: "https://cdn.foo.com/app.js".to_string(),
+                rel: "preload".to_string(),
+                r#as: "script".to_string(),
+            },
+        ];
+        assert_eq!(
+            serialize_link_hints(&link_hints, &url),
+            "<https://foo.com/bar/next.html>;rel=prefetch;as=document,<https://cdn.foo.com/app.js>;rel=preload;as=script"
+        );
+        assert_eq!(serialize_link_hints(&[], &url), "");
+    }
+    #[tokio::test]
+    async fn allowlists_subresource_origins() {
+        let mut fetcher = FakeIntegrityFetcher(Ok("sha256-blah".into()));
+        let url = Url::parse("https://foo.com").unwrap();
+        let allowed_origins = BTreeSet::from(["https://cdn.foo.com".to_string()]);
+        // Same-origin preloads are always allowed.
+        assert_eq!(
+            process_link_header("</a.js>;rel=preload", &url, &mut fetcher, &allowed_origins).await,
+            "<https://foo.com/a.js>;rel=preload,<https://foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+        );
+        // Listed cross-origin preloads are allowed.
+        assert_eq!(
+            process_link_header(
+                "<https://cdn.foo.com/a.js>;rel=preload",
+                &url,
+                &mut fetcher,
+                &allowed_origins
+            )
+            .await,
+            "<https://cdn.foo.com/a.js>;rel=preload,<https://cdn.foo.com/a.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+        );
+        // Unlisted cross-origin preloads are skipped, even with an explicit
+        // allowed-alt-sxg.
+        assert_eq!(
+            process_link_header(
+                "<https://bar.com/a.js>;rel=preload,<https://bar.com/a.js>;rel=allowed-alt-sxg;header-integrity=blah",
+                &url,
+                &mut fetcher,
+                &allowed_origins
+            )
+            .await,
+            ""
+        );
+        assert_eq!(
+            process_link_header(
+                "<https://cdn.foo.com:8443/a.js>;rel=preload,</b.js>;rel=preload",
+                &url,
+                &mut fetcher,
+                &allowed_origins
+            )
+            .await,
+            "<https://foo.com/b.js>;rel=preload,<https://foo.com/b.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+        );
+    }
+    #[test]
+    fn drops_disallowed_hints_without_processing() {
+        let url = Url::parse("https://foo.com").unwrap();
+        let allowed_origins = BTreeSet::from(["https://cdn.foo.com".to_string()]);
+        let value = "</a.js>;rel=preload,<https://cdn.foo.com/b.js>;rel=preload";
+        assert_eq!(drop_disallowed_hints(value, &url, &allowed_origins), value);
+        assert_eq!(
+            drop_disallowed_hints(
+                "<https://bar.com/a.js>;rel=preload,<https://bar.com/b.html>;rel=prefetch,\
+                 <https://cdn.foo.com/c.html>;rel=prefetch,<https://bar.com/>;rel=dns-prefetch",
+                &url,
+                &allowed_origins
+            ),
+            "<https://cdn.foo.com/c.html>;rel=prefetch,<https://bar.com/>;rel=dns-prefetch"
+        );
+        assert_eq!(
+            drop_disallowed_hints(
+                r#"<a.jpg>;rel=preload;as=image;imagesrcset="https://bar.com/b.jpg 800w""#,
+                &url,
+                &allowed_origins
+            ),
+            ""
+        );
+    }
+    #[test]
+    fn drops_unchecked_imagesrcset() {
+        let url = Url::parse("https://foo.com").unwrap();
+        let value = r#"<a.jpg>;rel=preload;as=image;imagesrcset="b.jpg 800w""#;
+        let expected = if cfg!(feature = "srcset") { value } else { "" };
+        assert_eq!(
+            drop_disallowed_hints(value, &url, &BTreeSet::new()),
+            expected
+        );
+    }
+    #[cfg(not(feature = "srcset"))]
+    #[tokio::test]
+    async fn skips_imagesrcset_preloads_without_srcset() {
+        let mut fetcher = FakeIntegrityFetcher(Ok("sha256-blah".into()));
+        let url = Url::parse("https://foo.com").unwrap();
+        assert_eq!(
+            process_link_header(
+                r#"<a.jpg>;rel=preload;as=image;imagesrcset="b.jpg 800w""#,
+                &url,
+                &mut fetcher,
+                &BTreeSet::new()
+            )
+            .await,
+            ""
+        );
+    }
+    #[tokio::test]
+    async fn dedupes_preloads() {
+        let mut fetcher = FakeIntegrityFetcher(Ok("sha256-blah".into()));
+        let url = Url::parse("https://foo.com").unwrap();
+        assert_eq!(
+            process_link_header(
+                "</a>;rel=preload,<https://foo.com/a>;rel=preload",
+                &url,
+                &mut fetcher,
+                &BTreeSet::new()
+            )
+            .await,
+            "<https://foo.com/a>;rel=preload,<https://foo.com/a>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+        );
+    }
+    #[test]
+    fn filters_link_hints_in_link_header() {
+        let url = Url::parse("https://foo.com/bar/").unwrap();
+        let link_hints = vec![
+            LinkHint {
+                url: "app.js".to_string(),
+                rel: "preload".to_string(),
+                r#as: "script".to_string(),
+            },
+            LinkHint {
+                url: "/bar/app.js".to_string(),
+                rel: "prefetch".to_string(),
+                r#as: "script".to_string(),
+            },
+            LinkHint {
+                url: "app.css".to_string(),
+                rel: "preload".to_string(),
+                r#as: "style".to_string(),
+            },
+        ];
+        assert_eq!(
+            link_hints_not_in(
+                &link_hints,
+                "<https://foo.com/bar/app.js>;rel=preload",
+                &url
+            ),
+            vec![&link_hints[1], &link_hints[2]]
+        );
+        assert_eq!(
+            link_hints_not_in(&link_hints, "", &url),
+            link_hints.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            link_hints_not_in(&link_hints, "<invalid", &url),
+            link_hints.iter().collect::<Vec<_>>()
+        );
+    }
+    #[tokio::test]
+    async fn preloads_subresources() {
+        let url = Url::parse("https://foo.com/bar/").unwrap();
+        let preloads = vec![LinkHint {
+            url: "app.js".to_string(),
+            rel: "preload".to_string(),
+            r#as: "script".to_string(),
+        }];
+        let mut fetcher = FakeIntegrityFetcher(Ok("sha256-blah".into()));
+        assert_eq!(
+            subresource_preload(&preloads, &url, &mut fetcher, &BTreeSet::new()).await,
+            "<https://foo.com/bar/app.js>;rel=preload;as=script,\
+             <https://foo.com/bar/app.js>;rel=allowed-alt-sxg;header-integrity=sha256-blah"
+        );
+        let mut fetcher = FakeIntegrityFetcher(Err("not found".into()));
+        assert_eq!(
+            subresource_preload(&preloads, &url, &mut fetcher, &BTreeSet::new()).await,
+            ""
+        );
+        assert_eq!(
+            subresource_preload(&[], &url, &mut fetcher, &BTreeSet::new()).await,
+            ""
+        );
+    }
 }
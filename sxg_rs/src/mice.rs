@@ -15,9 +15,16 @@
 // https://tools.ietf.org/html/draft-thomson-http-mice-03
 
 use crate::crypto::HashAlgorithm;
+use crate::SxgError;
 use ::sha2::{Digest, Sha256};
+use anyhow::{anyhow, Result};
 use std::collections::VecDeque;
+use std::convert::TryFrom;
 
+const PROOF_SIZE: usize = 32;
+
+/// Encodes `input` with `mi-sha256-03` in records of `record_size` bytes, and
+/// returns the integrity proof of the first record and the encoded message.
 pub fn calculate(input: &[u8], record_size: usize) -> (Vec<u8>, Vec<u8>) {
     if input.is_empty() {
         return (HashAlgorithm::Sha256.digest(&[0]), vec![]);
@@ -34,9 +41,9 @@ pub fn calculate(input: &[u8], record_size: usize) -> (Vec<u8>, Vec<u8>) {
         hasher.update(record);
         if let Some(f) = proofs.front() {
             hasher.update(f);
-            hasher.update(&[1u8]);
+            hasher.update([1u8]);
         } else {
-            hasher.update(&[0u8]);
+            hasher.update([0u8]);
         }
         proofs.push_front(hasher.finalize().to_vec());
     }
@@ -52,9 +59,102 @@ pub fn calculate(input: &[u8], record_size: usize) -> (Vec<u8>, Vec<u8>) {
     (integrity, message)
 }
 
+/// Returns the value of the `Digest` header of `input` encoded with
+/// `mi-sha256-03` in records of `record_size` bytes, such as
+/// `mi-sha256-03=dcRDgR2GM35DluAV13PzgnG6+pvQwPywfFvAu1UeFrs=`. Unlike
+/// `calculate`, it doesn't build the encoded message.
+pub fn integrity(input: &[u8], record_size: usize) -> String {
+    let record_size = std::cmp::min(record_size, input.len());
+    let mut proof: Option<Vec<u8>> = None;
+    let records: Vec<_> = if record_size > 0 {
+        input.chunks(record_size).collect()
+    } else {
+        vec![input]
+    };
+    for record in records.iter().rev() {
+        let mut hasher = Sha256::new();
+        hasher.update(record);
+        if let Some(p) = &proof {
+            hasher.update(p);
+            hasher.update([1u8]);
+        } else {
+            hasher.update([0u8]);
+        }
+        proof = Some(hasher.finalize().to_vec());
+    }
+    digest_header_value(&proof.unwrap())
+}
+
+/// Returns the value of the `Digest` header for the integrity proof returned
+/// by `calculate`.
+pub(crate) fn digest_header_value(integrity: &[u8]) -> String {
+    format!("mi-sha256-03={}", ::base64::encode(integrity))
+}
+
+/// Decodes a `mi-sha256-03` message created by `calculate`, and returns the
+/// payload. Returns an error if any record doesn't match its integrity proof,
+/// starting with `integrity` for the first record. The record size isn't
+/// covered by the proofs, so a message of a single record decodes the same
+/// with any record size at least as large as the record.
+pub fn decode(integrity: &[u8], message: &[u8]) -> Result<Vec<u8>, SxgError> {
+    decode_impl(integrity, message).map_err(SxgError::Sxg)
+}
+
+fn decode_impl(integrity: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+    if message.is_empty() {
+        return if integrity == HashAlgorithm::Sha256.digest(&[0]) {
+            Ok(vec![])
+        } else {
+            Err(anyhow!("The integrity proof of the empty payload is wrong"))
+        };
+    }
+    if message.len() < 8 {
+        return Err(anyhow!("The MICE message is missing the record size"));
+    }
+    let (record_size, mut rest) = message.split_at(8);
+    let record_size = u64::from_be_bytes(<[u8; 8]>::try_from(record_size)?);
+    let record_size = match usize::try_from(record_size) {
+        Ok(0) | Err(_) => return Err(anyhow!("Invalid MICE record size {}", record_size)),
+        Ok(record_size) => record_size,
+    };
+    if rest.is_empty() {
+        return Err(anyhow!("The MICE message has no records"));
+    }
+    let mut payload = Vec::with_capacity(rest.len());
+    let mut expected_proof = integrity;
+    for index in 0.. {
+        let mut hasher = Sha256::new();
+        if rest.len() <= record_size {
+            hasher.update(rest);
+            hasher.update([0u8]);
+        } else if rest.len() > record_size + PROOF_SIZE {
+            hasher.update(&rest[..record_size]);
+            hasher.update(&rest[record_size..record_size + PROOF_SIZE]);
+            hasher.update([1u8]);
+        } else {
+            return Err(anyhow!("Record {} of the MICE message is truncated", index));
+        }
+        if hasher.finalize().as_slice() != expected_proof {
+            return Err(anyhow!(
+                "Record {} of the MICE message does not match its integrity proof",
+                index
+            ));
+        }
+        if rest.len() <= record_size {
+            payload.extend_from_slice(rest);
+            break;
+        }
+        payload.extend_from_slice(&rest[..record_size]);
+        expected_proof = &rest[record_size..record_size + PROOF_SIZE];
+        rest = &rest[record_size + PROOF_SIZE..];
+    }
+    Ok(payload)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck::{quickcheck, TestResult};
     #[test]
     fn it_works() {
         // https://tools.ietf.org/html/draft-thomson-http-mice-03#section-4.1
@@ -84,6 +184,64 @@ mod tests {
         );
     }
     #[test]
+    fn computes_integrity() {
+        let input = "When I grow up, I want to be a watermelon".as_bytes();
+        assert_eq!(
+            integrity(input, 16),
+            "mi-sha256-03=IVa9shfs0nyKEhHqtB3WVNANJ2Njm5KjQLjRtnbkYJ4="
+        );
+        assert_eq!(
+            integrity(b"", 16384),
+            "mi-sha256-03=bjQLnP+zepicpUTmu3gKLHiQHT+zNzh2hRGjBhevoB0="
+        );
+        fn prop(input: Vec<u8>, record_size: u8) -> bool {
+            let record_size = record_size as usize;
+            integrity(&input, record_size) == digest_header_value(&calculate(&input, record_size).0)
+        }
+        quickcheck(prop as fn(Vec<u8>, u8) -> bool);
+    }
+    #[test]
+    fn decodes() {
+        let input = "When I grow up, I want to be a watermelon".as_bytes();
+        let (integrity, message) = calculate(input, 16);
+        assert_eq!(decode(&integrity, &message).unwrap(), input);
+        let (integrity, message) = calculate(b"", 16);
+        assert_eq!(decode(&integrity, &message).unwrap(), b"");
+        assert!(decode(&integrity, &0_u64.to_be_bytes()).is_err());
+        assert!(decode(b"", b"").is_err());
+    }
+    #[test]
+    fn round_trips() {
+        fn prop(input: Vec<u8>, record_size: u16) -> TestResult {
+            if record_size == 0 {
+                return TestResult::discard();
+            }
+            let (integrity, message) = calculate(&input, record_size as usize);
+            TestResult::from_bool(decode(&integrity, &message).unwrap() == input)
+        }
+        quickcheck(prop as fn(Vec<u8>, u16) -> TestResult);
+        // Small record sizes, so that most payloads have many records.
+        quickcheck(
+            (|input: Vec<u8>, record_size: u8| prop(input, record_size as u16 % 8))
+                as fn(Vec<u8>, u8) -> TestResult,
+        );
+    }
+    #[test]
+    fn rejects_flipped_byte() {
+        // The record size isn't covered by the integrity proofs, so only the
+        // bytes after it are flipped.
+        fn prop(input: Vec<u8>, record_size: u8, index: usize, bit: u8) -> TestResult {
+            if input.is_empty() || record_size == 0 {
+                return TestResult::discard();
+            }
+            let (integrity, mut message) = calculate(&input, record_size as usize);
+            let index = 8 + index % (message.len() - 8);
+            message[index] ^= 1 << (bit % 8);
+            TestResult::from_bool(decode(&integrity, &message).is_err())
+        }
+        quickcheck(prop as fn(Vec<u8>, u8, usize, u8) -> TestResult);
+    }
+    #[test]
     fn empty_payload() {
         assert_eq!(
             calculate(b"", 16384),
@@ -17,24 +17,27 @@
 //
 // OCSP over http is defined in
 // https://tools.ietf.org/html/rfc2560#appendix-A.1
+// https://tools.ietf.org/html/rfc6960#appendix-A.1
 
 use crate::crypto::{CertificateChain, HashAlgorithm};
 use crate::fetcher::Fetcher;
 use crate::http::{HttpRequest, Method};
-use crate::runtime::Runtime;
-use anyhow::{anyhow, Error, Result};
+use crate::runtime::{GenericRuntime, Runtime};
+use crate::SxgError;
+use anyhow::{anyhow, Context, Error, Result};
 use der_parser::{
     ber::{BerObject, BerObjectContent},
     oid,
     oid::Oid,
 };
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::time::{Duration, SystemTime};
 use tokio::sync::Mutex;
-use x509_parser::{
-    certificate::X509Certificate,
-    extensions::{GeneralName, ParsedExtension},
-};
+use x509_parser::certificate::X509Certificate;
+
+#[cfg(feature = "verify_ocsp_signature")]
+mod signature;
 
 fn create_ocsp_request(cert: &X509Certificate, issuer: &X509Certificate) -> Vec<u8> {
     let hash_algorithm = HashAlgorithm::Sha1;
@@ -78,13 +81,98 @@ fn create_ocsp_request(cert: &X509Certificate, issuer: &X509Certificate) -> Vec<
 // https://datatracker.ietf.org/doc/html/rfc4325#section-2
 // https://datatracker.ietf.org/doc/html/rfc3280#section-4.2.2.1
 const AIA: Oid<'static> = oid!(1.3.6 .1 .5 .5 .7 .1 .1);
-// https://www.iana.org/assignments/smi-numbers/smi-numbers.xhtml#smi-numbers-1.3.6.1.5.5.7.48.1
-const AIA_OCSP: Oid<'static> = oid!(1.3.6 .1 .5 .5 .7 .48 .1);
 
-pub async fn fetch_from_ca(
+// https://datatracker.ietf.org/doc/html/rfc5019#section-5
+// Requests whose GET form is shorter than 255 bytes should be sent via GET,
+// which lets intermediaries cache the response; larger ones use POST.
+const MAX_GET_URL_LENGTH: usize = 255;
+
+// Returns the URL of the GET form of an OCSP request, which is
+// `{url}/{url-encoding of base-64 encoding of the DER encoding of the
+// OCSPRequest}`, or `None` if the URL would be too long.
+fn ocsp_get_url(responder_url: &str, request_der: &[u8]) -> Option<String> {
+    let encoded = base64::encode(request_der)
+        .replace('+', "%2B")
+        .replace('/', "%2F")
+        .replace('=', "%3D");
+    let separator = if responder_url.ends_with('/') {
+        ""
+    } else {
+        "/"
+    };
+    let url = format!("{}{}{}", responder_url, separator, encoded);
+    if url.len() < MAX_GET_URL_LENGTH {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Why no OCSP response can be fetched for a certificate. It is found in the
+/// chain of the `SxgError::Ocsp` returned by `fetch_from_ca` and
+/// `read_and_update_ocsp_in_storage`.
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum NoOcspResponder {
+    #[error("Certificate chain contains no issuer.")]
+    NoIssuer,
+    #[error("AIA OCSP responder with type of URI is not found.")]
+    NoResponderUrl,
+}
+
+/// Whether `error` means that the certificate has no OCSP responder, or that
+/// the responder couldn't be reached, as opposed to a storage failure or a
+/// response that is rejected.
+pub fn is_unavailable(error: &SxgError) -> bool {
+    std::iter::once(error)
+        .chain(
+            error
+                .inner()
+                .chain()
+                .filter_map(|e| e.downcast_ref::<SxgError>()),
+        )
+        .any(|e| {
+            matches!(e, SxgError::Network(_))
+                || e.inner().downcast_ref::<NoOcspResponder>().is_some()
+        })
+}
+
+fn create_ocsp_http_request(responder_url: &str, request_der: Vec<u8>) -> HttpRequest {
+    if let Some(url) = ocsp_get_url(responder_url, &request_der) {
+        HttpRequest {
+            body: vec![],
+            headers: vec![],
+            method: Method::Get,
+            url,
+        }
+    } else {
+        HttpRequest {
+            body: request_der,
+            headers: vec![(
+                String::from("content-type"),
+                String::from("application/ocsp-request"),
+            )],
+            method: Method::Post,
+            url: responder_url.into(),
+        }
+    }
+}
+
+pub async fn fetch_from_ca<F: Fetcher + ?Sized>(
     cert_der: &[u8],
     issuer_der: &[u8],
-    fetcher: &dyn Fetcher,
+    fetcher: &F,
+    now: SystemTime,
+) -> Result<Vec<u8>, SxgError> {
+    fetch_from_ca_impl(cert_der, issuer_der, fetcher, now)
+        .await
+        .map_err(SxgError::Ocsp)
+}
+
+async fn fetch_from_ca_impl<F: Fetcher + ?Sized>(
+    cert_der: &[u8],
+    issuer_der: &[u8],
+    fetcher: &F,
+    now: SystemTime,
 ) -> Result<Vec<u8>> {
     let cert = x509_parser::parse_x509_certificate(cert_der)
         .map_err(|e| Error::from(e).context("Failed to parse cert DER"))?
@@ -92,51 +180,250 @@ pub async fn fetch_from_ca(
     let issuer = x509_parser::parse_x509_certificate(issuer_der)
         .map_err(|e| Error::from(e).context("Failed to parse issuer DER"))?
         .1;
-    let aia = cert.extensions().iter().find(|ext| ext.oid == AIA);
-    let aia = if let Some(aia) = aia {
-        aia
-    } else {
+    if !cert.extensions().iter().any(|ext| ext.oid == AIA) {
         // If the certificate doesn't include an AIA section, it is probably a
         // self-signed certificate. Return a stub OCSP response.
         return Ok(b"ocsp".to_vec());
-    };
-    let aia = if let ParsedExtension::AuthorityInfoAccess(aia) = aia.parsed_extension() {
-        aia
+    }
+    let url = crate::crypto::ocsp_responder_url(cert_der)
+        .ok_or_else(|| Error::new(NoOcspResponder::NoResponderUrl))?;
+    let req = create_ocsp_http_request(&url, create_ocsp_request(&cert, &issuer));
+    let rsp = fetcher.fetch(req).await.context("Failed to fetch OCSP")?;
+    verify_response(&rsp.body, cert_der, issuer_der, now)
+        .context("The OCSP response from the CA is rejected")?;
+    Ok(rsp.body)
+}
+
+/// Checks that the OCSP response is about the certificate, by comparing the
+/// `CertID` of its first `SingleResponse` with the serial number of the
+/// certificate and the hashes of the name and public key of the issuer. With
+/// the `verify_ocsp_signature` feature, also checks that the response is
+/// signed by the issuer or by a responder that the issuer delegated to, which
+/// is valid at `now`.
+pub fn verify_response(
+    ocsp_der: &[u8],
+    cert_der: &[u8],
+    issuer_der: &[u8],
+    now: SystemTime,
+) -> Result<(), SxgError> {
+    verify_response_impl(ocsp_der, cert_der, issuer_der, now).map_err(SxgError::Ocsp)
+}
+
+#[cfg_attr(not(feature = "verify_ocsp_signature"), allow(unused_variables))]
+fn verify_response_impl(
+    ocsp_der: &[u8],
+    cert_der: &[u8],
+    issuer_der: &[u8],
+    now: SystemTime,
+) -> Result<()> {
+    let cert = x509_parser::parse_x509_certificate(cert_der)
+        .map_err(|e| Error::from(e).context("Failed to parse cert DER"))?
+        .1;
+    let issuer = x509_parser::parse_x509_certificate(issuer_der)
+        .map_err(|e| Error::from(e).context("Failed to parse issuer DER"))?
+        .1;
+    let single_response = parse_single_response(ocsp_der)?;
+    // https://tools.ietf.org/html/rfc6960#section-4.1.1
+    // CertID          ::=     SEQUENCE {
+    //     hashAlgorithm       AlgorithmIdentifier,
+    //     issuerNameHash      OCTET STRING, -- Hash of issuer's DN
+    //     issuerKeyHash       OCTET STRING, -- Hash of issuer's public key
+    //     serialNumber        CertificateSerialNumber }
+    let cert_id = single_response
+        .first()
+        .ok_or_else(|| anyhow!("SingleResponse contains no certID"))?
+        .as_sequence()?;
+    if cert_id.len() != 4 {
+        return Err(anyhow!("CertID has {} fields instead of 4", cert_id.len()));
+    }
+    let hash_algorithm = cert_id[0]
+        .as_sequence()?
+        .first()
+        .ok_or_else(|| anyhow!("CertID contains no hash algorithm"))?
+        .as_oid()?;
+    let hash_algorithm = HashAlgorithm::from_oid(hash_algorithm)
+        .ok_or_else(|| anyhow!("CertID uses unsupported hash algorithm {}", hash_algorithm))?;
+    let serial_number = cert_id[3].as_slice()?;
+    if serial_number != cert.tbs_certificate.raw_serial() {
+        return Err(anyhow!(
+            "The OCSP response is for serial number {}, but the certificate has serial number {}",
+            format_serial_number(serial_number),
+            cert.tbs_certificate.raw_serial_as_string(),
+        ));
+    }
+    let issuer_name_hash = hash_algorithm.digest(issuer.tbs_certificate.subject.as_raw());
+    let issuer_key_hash =
+        hash_algorithm.digest(issuer.tbs_certificate.subject_pki.subject_public_key.data);
+    if cert_id[1].as_slice()? != issuer_name_hash || cert_id[2].as_slice()? != issuer_key_hash {
+        return Err(anyhow!(
+            "The OCSP response is for a certificate from another issuer than {}",
+            issuer.tbs_certificate.subject
+        ));
+    }
+    #[cfg(feature = "verify_ocsp_signature")]
+    signature::verify_signature(basic_ocsp_response(ocsp_der)?, &issuer, now)?;
+    Ok(())
+}
+
+// Formats the serial number like `X509Certificate::raw_serial_as_string`.
+fn format_serial_number(serial_number: &[u8]) -> String {
+    serial_number
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The period in which an OCSP response is valid.
+#[derive(Debug, PartialEq)]
+pub struct OcspValidity {
+    pub this_update: SystemTime,
+    pub next_update: Option<SystemTime>,
+}
+
+fn parse_generalized_time(obj: &BerObject) -> Result<SystemTime> {
+    if let BerObjectContent::GeneralizedTime(time) = &obj.content {
+        let timestamp = time
+            .to_datetime()
+            .map_err(|e| anyhow!("{:?}", e))?
+            .unix_timestamp();
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(timestamp)?))
     } else {
-        return Err(anyhow!("Failed to parse AIA extension"));
+        Err(anyhow!("Expecting GeneralizedTime"))
+    }
+}
+
+fn parse_explicit_tagged<'a>(obj: &BerObject<'a>) -> Result<BerObject<'a>> {
+    Ok(der_parser::parse_der(obj.as_slice()?)?.1)
+}
+
+/// The revocation status of the certificate in an OCSP response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// Parses `thisUpdate` and `nextUpdate` of the first `SingleResponse` in an
+/// OCSP response.
+pub fn parse_validity(ocsp_der: &[u8]) -> Result<OcspValidity, SxgError> {
+    parse_validity_impl(ocsp_der).map_err(SxgError::Ocsp)
+}
+
+fn parse_validity_impl(ocsp_der: &[u8]) -> Result<OcspValidity> {
+    let single_response = parse_single_response(ocsp_der)?;
+    // SingleResponse ::= SEQUENCE {
+    //    certID                       CertID,
+    //    certStatus                   CertStatus,
+    //    thisUpdate                   GeneralizedTime,
+    //    nextUpdate         [0]       EXPLICIT GeneralizedTime OPTIONAL,
+    //    singleExtensions   [1]       EXPLICIT Extensions OPTIONAL }
+    let this_update = single_response
+        .get(2)
+        .ok_or_else(|| anyhow!("SingleResponse contains no thisUpdate"))?;
+    let next_update = match single_response.get(3) {
+        Some(obj) if obj.is_contextspecific() && obj.tag().0 == 0 => {
+            Some(parse_generalized_time(&parse_explicit_tagged(obj)?)?)
+        }
+        _ => None,
     };
-    let url = aia
-        .accessdescs
+    Ok(OcspValidity {
+        this_update: parse_generalized_time(this_update)?,
+        next_update,
+    })
+}
+
+/// Parses `certStatus` of the first `SingleResponse` in an OCSP response.
+pub fn parse_cert_status(ocsp_der: &[u8]) -> Result<CertStatus, SxgError> {
+    parse_cert_status_impl(ocsp_der).map_err(SxgError::Ocsp)
+}
+
+fn parse_cert_status_impl(ocsp_der: &[u8]) -> Result<CertStatus> {
+    let single_response = parse_single_response(ocsp_der)?;
+    // CertStatus ::= CHOICE {
+    //    good        [0]     IMPLICIT NULL,
+    //    revoked     [1]     IMPLICIT RevokedInfo,
+    //    unknown     [2]     IMPLICIT UnknownInfo }
+    let cert_status = single_response
+        .get(1)
+        .ok_or_else(|| anyhow!("SingleResponse contains no certStatus"))?;
+    match cert_status.tag().0 {
+        0 => Ok(CertStatus::Good),
+        1 => Ok(CertStatus::Revoked),
+        2 => Ok(CertStatus::Unknown),
+        tag => Err(anyhow!("Unexpected certStatus tag {}", tag)),
+    }
+}
+
+// Returns the DER of the `BasicOCSPResponse` in an OCSP response.
+fn basic_ocsp_response(ocsp_der: &[u8]) -> Result<&[u8]> {
+    // https://tools.ietf.org/html/rfc6960#section-4.2.1
+    // OCSPResponse ::= SEQUENCE {
+    //    responseStatus         OCSPResponseStatus,
+    //    responseBytes          [0] EXPLICIT ResponseBytes OPTIONAL }
+    // ResponseBytes ::=       SEQUENCE {
+    //    responseType   OBJECT IDENTIFIER,
+    //    response       OCTET STRING }
+    let ocsp_response = der_parser::parse_der(ocsp_der)?.1;
+    let response_bytes = ocsp_response
+        .as_sequence()?
+        .get(1)
+        .ok_or_else(|| anyhow!("OCSP response contains no responseBytes"))?;
+    let response_bytes = parse_explicit_tagged(response_bytes)?;
+    let response = response_bytes
+        .as_sequence()?
+        .get(1)
+        .ok_or_else(|| anyhow!("responseBytes contains no response"))?
+        .as_slice()?;
+    Ok(response)
+}
+
+// Returns the fields of the first `SingleResponse` in an OCSP response.
+fn parse_single_response(ocsp_der: &[u8]) -> Result<Vec<BerObject<'_>>> {
+    // BasicOCSPResponse       ::= SEQUENCE {
+    //    tbsResponseData      ResponseData,
+    //    signatureAlgorithm   AlgorithmIdentifier,
+    //    signature            BIT STRING,
+    //    certs            [0] EXPLICIT SEQUENCE OF Certificate OPTIONAL }
+    // ResponseData ::= SEQUENCE {
+    //    version              [0] EXPLICIT Version DEFAULT v1,
+    //    responderID              ResponderID,
+    //    producedAt               GeneralizedTime,
+    //    responses                SEQUENCE OF SingleResponse,
+    //    responseExtensions   [1] EXPLICIT Extensions OPTIONAL }
+    let basic_response = der_parser::parse_der(basic_ocsp_response(ocsp_der)?)?.1;
+    let response_data = basic_response
+        .as_sequence()?
+        .first()
+        .ok_or_else(|| anyhow!("BasicOCSPResponse contains no tbsResponseData"))?
+        .as_sequence()?;
+    // `responses` follows `producedAt`, which is the only GeneralizedTime.
+    let single_response = response_data
         .iter()
-        .find_map(|access_desc| {
-            if access_desc.access_method == AIA_OCSP {
-                match access_desc.access_location {
-                    GeneralName::URI(url) => Some(url),
-                    _ => None,
-                }
-            } else {
-                None
-            }
-        })
-        .ok_or_else(|| anyhow!("AIA OCSP responder with type of URI is not found."))?;
-    let req = HttpRequest {
-        body: create_ocsp_request(&cert, &issuer),
-        headers: vec![(
-            String::from("content-type"),
-            String::from("application/ocsp-request"),
-        )],
-        method: Method::Post,
-        url: url.into(),
-    };
-    let rsp = fetcher
-        .fetch(req)
-        .await
-        .map_err(|e| e.context("Failed to fetch OCSP"))?;
-    Ok(rsp.body)
+        .skip_while(|obj| !matches!(obj.content, BerObjectContent::GeneralizedTime(_)))
+        .nth(1)
+        .ok_or_else(|| anyhow!("ResponseData contains no responses"))?
+        .as_sequence()?
+        .first()
+        .ok_or_else(|| anyhow!("OCSP response contains no SingleResponse"))?
+        .as_sequence()?
+        .to_vec();
+    Ok(single_response)
 }
 
 const OCSP_KEY: &str = "OCSP";
 
+/// Returns the key under which the OCSP response of `certificate_chain` is
+/// stored. Each certificate has a key of its own, so that while certificates
+/// are rotated, the staple matches the certificate that signed.
+pub fn storage_key(certificate_chain: &CertificateChain) -> String {
+    format!("{}/{}", OCSP_KEY, certificate_chain.basename)
+}
+const SIX_DAYS: Duration = Duration::from_secs(3600 * 24 * 6);
+const ONE_DAY: Duration = Duration::from_secs(3600 * 24);
+
 #[derive(Serialize, Deserialize)]
 struct OcspData {
     pub expiration_time: SystemTime,
@@ -154,13 +441,25 @@ pub enum OcspUpdateStrategy {
 /// If OCSP in storage needs update, fetches it from the server and writes it
 /// into storage. The outging traffic to the server is throttled to be a
 /// single task.
-pub async fn read_and_update_ocsp_in_storage(
+pub async fn read_and_update_ocsp_in_storage<S: ?Sized, F: Fetcher + ?Sized>(
     certificate_chain: &CertificateChain,
-    runtime: &Runtime,
+    runtime: &GenericRuntime<S, F>,
     strategy: OcspUpdateStrategy,
+    storage_key: &str,
+) -> Result<Vec<u8>, SxgError> {
+    read_and_update_ocsp_in_storage_impl(certificate_chain, runtime, strategy, storage_key)
+        .await
+        .map_err(SxgError::Ocsp)
+}
+
+async fn read_and_update_ocsp_in_storage_impl<S: ?Sized, F: Fetcher + ?Sized>(
+    certificate_chain: &CertificateChain,
+    runtime: &GenericRuntime<S, F>,
+    strategy: OcspUpdateStrategy,
+    storage_key: &str,
 ) -> Result<Vec<u8>> {
     // Checks whether we can directly return the existing OCSP in storage.
-    if let Some(old_ocsp) = runtime.storage.read(OCSP_KEY).await? {
+    if let Some(old_ocsp) = runtime.storage.get(storage_key).await? {
         if let Ok(old_ocsp) = serde_json::from_str::<OcspData>(&old_ocsp) {
             match strategy {
                 OcspUpdateStrategy::EarlyAsRecommended => {
@@ -181,19 +480,18 @@ pub async fn read_and_update_ocsp_in_storage(
         // There is no OCSP in storage.
     }
     if certificate_chain.issuers.is_empty() {
-        return Err(Error::msg("Certiciate chain contains no issuer."));
+        return Err(Error::new(NoOcspResponder::NoIssuer));
     }
     let cert_der = &certificate_chain.end_entity.der;
     let issuer_der = &certificate_chain.issuers[0].der;
     let new_ocsp_value = {
         static SINGLE_TASK: Mutex<()> = Mutex::const_new(());
         let guard = SINGLE_TASK.lock().await;
-        let ocsp = fetch_from_ca(cert_der, issuer_der, runtime.fetcher.as_ref()).await?;
+        let ocsp =
+            fetch_from_ca(cert_der, issuer_der, runtime.fetcher.as_ref(), runtime.now).await?;
         std::mem::drop(guard);
         ocsp
     };
-    const SIX_DAYS: Duration = Duration::from_secs(3600 * 24 * 6);
-    const ONE_DAY: Duration = Duration::from_secs(3600 * 24);
     let new_ocsp = OcspData {
         expiration_time: runtime.now + SIX_DAYS,
         recommended_update_time: runtime.now + ONE_DAY,
@@ -201,7 +499,171 @@ pub async fn read_and_update_ocsp_in_storage(
     };
     runtime
         .storage
-        .write(OCSP_KEY, &serde_json::to_string(&new_ocsp)?)
+        .put_with_ttl(storage_key, &serde_json::to_string(&new_ocsp)?, SIX_DAYS)
         .await?;
     Ok(new_ocsp.value)
 }
+
+/// Writes an OCSP response obtained elsewhere, such as by `tools
+/// refresh-ocsp`, into storage, where `read_and_update_ocsp_in_storage` reads
+/// it at request time. The response is used until its `nextUpdate`, and is
+/// recommended to be updated from the CA halfway through its validity period.
+/// Does not check that the response is about `certificate_chain`.
+pub async fn write_ocsp_to_storage(
+    runtime: &Runtime,
+    certificate_chain: &CertificateChain,
+    ocsp_der: Vec<u8>,
+) -> Result<(), SxgError> {
+    write_ocsp_to_storage_impl(runtime, certificate_chain, ocsp_der)
+        .await
+        .map_err(SxgError::Ocsp)
+}
+
+async fn write_ocsp_to_storage_impl(
+    runtime: &Runtime,
+    certificate_chain: &CertificateChain,
+    ocsp_der: Vec<u8>,
+) -> Result<()> {
+    let OcspValidity {
+        this_update,
+        next_update,
+    } = parse_validity(&ocsp_der)?;
+    let expiration_time = next_update.unwrap_or(runtime.now + SIX_DAYS);
+    let ttl = expiration_time
+        .duration_since(runtime.now)
+        .map_err(|_| anyhow!("The OCSP response has expired."))?;
+    let validity_period = expiration_time
+        .duration_since(this_update)
+        .unwrap_or_default();
+    let ocsp = OcspData {
+        expiration_time,
+        recommended_update_time: this_update + validity_period / 2,
+        value: ocsp_der,
+    };
+    runtime
+        .storage
+        .put_with_ttl(
+            &storage_key(certificate_chain),
+            &serde_json::to_string(&ocsp)?,
+            ttl,
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpResponse;
+    use async_trait::async_trait;
+    #[tokio::test]
+    async fn tells_unavailable_responder() {
+        use crate::fetcher::NullFetcher;
+        use crate::utils::tests::{AIA_CERT_PEM, SELF_SIGNED_CERT_PEM};
+        // A responder that answers something other than an OCSP response.
+        struct BadResponder;
+        #[async_trait(?Send)]
+        impl Fetcher for BadResponder {
+            async fn fetch(&self, _request: HttpRequest) -> Result<HttpResponse, SxgError> {
+                Ok(HttpResponse {
+                    body: b"not ocsp".to_vec(),
+                    headers: vec![],
+                    status: 200,
+                })
+            }
+        }
+        let cert_der = crate::crypto::first_certificate(AIA_CERT_PEM).unwrap().der;
+        let now = SystemTime::UNIX_EPOCH;
+        let e = fetch_from_ca(&cert_der, &cert_der, &NullFetcher, now)
+            .await
+            .unwrap_err();
+        assert!(is_unavailable(&e));
+        let e = fetch_from_ca(&cert_der, &cert_der, &BadResponder, now)
+            .await
+            .unwrap_err();
+        assert!(!is_unavailable(&e));
+
+        let certificate = CertificateChain::from_pem_files(&[SELF_SIGNED_CERT_PEM]).unwrap();
+        let e = read_and_update_ocsp_in_storage(
+            &certificate,
+            &Runtime::default(),
+            OcspUpdateStrategy::LazyIfUnexpired,
+            &storage_key(&certificate),
+        )
+        .await
+        .unwrap_err();
+        assert!(is_unavailable(&e));
+        let e = SxgError::Ocsp(Error::new(SxgError::Storage(anyhow!("Disk full"))));
+        assert!(!is_unavailable(&e));
+    }
+    #[test]
+    fn parses_validity() {
+        let validity = parse_validity(include_bytes!("../static/ocsp.der")).unwrap();
+        assert_eq!(
+            validity,
+            OcspValidity {
+                this_update: SystemTime::UNIX_EPOCH + Duration::from_secs(1792140379),
+                next_update: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1792745179)),
+            }
+        );
+        assert!(parse_validity(b"ocsp").is_err());
+    }
+    #[test]
+    fn parses_cert_status() {
+        assert_eq!(
+            parse_cert_status(include_bytes!("../static/ocsp.der")).unwrap(),
+            CertStatus::Good
+        );
+        assert!(parse_cert_status(b"ocsp").is_err());
+    }
+    #[test]
+    fn verifies_cert_id() {
+        let issuer = include_bytes!("../static/ocsp_verification/issuer.der");
+        let leaf = include_bytes!("../static/ocsp_verification/leaf.der");
+        let other_leaf = include_bytes!("../static/ocsp_verification/other_leaf.der");
+        let ocsp = include_bytes!("../static/ocsp_verification/by_issuer.der");
+        let verify_response = |ocsp: &[u8], leaf: &[u8], issuer: &[u8]| {
+            verify_response(ocsp, leaf, issuer, SystemTime::UNIX_EPOCH)
+        };
+        verify_response(ocsp, leaf, issuer).unwrap();
+        assert_eq!(
+            verify_response(ocsp, other_leaf, issuer)
+                .unwrap_err()
+                .to_string(),
+            "The OCSP response is for serial number 12:34, but the certificate has serial number 56:78"
+        );
+        assert_eq!(
+            verify_response(ocsp, leaf, leaf).unwrap_err().to_string(),
+            "The OCSP response is for a certificate from another issuer than CN=example.org"
+        );
+        assert!(verify_response(b"ocsp", leaf, issuer).is_err());
+    }
+    #[test]
+    fn get_url_of_small_request() {
+        // Base64 of these bytes is "MEIwQDA+/z8=", which contains all of
+        // the characters that need to be url-encoded.
+        let request = [0x30, 0x42, 0x30, 0x40, 0x30, 0x3e, 0xff, 0x3f];
+        let expected = "http://r3.o.lencr.org/MEIwQDA%2B%2Fz8%3D";
+        assert_eq!(
+            ocsp_get_url("http://r3.o.lencr.org", &request).unwrap(),
+            expected
+        );
+        assert_eq!(
+            ocsp_get_url("http://r3.o.lencr.org/", &request).unwrap(),
+            expected
+        );
+        let req = create_ocsp_http_request("http://r3.o.lencr.org", request.to_vec());
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.url, expected);
+        assert!(req.body.is_empty());
+    }
+    #[test]
+    fn posts_large_request() {
+        let request = vec![0; 200];
+        assert_eq!(ocsp_get_url("http://r3.o.lencr.org", &request), None);
+        let req = create_ocsp_http_request("http://r3.o.lencr.org", request.clone());
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.url, "http://r3.o.lencr.org");
+        assert_eq!(req.body, request);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The signature of an OCSP response is defined in
+// https://tools.ietf.org/html/rfc6960#section-4.2.1
+//
+// Delegated responders are defined in
+// https://tools.ietf.org/html/rfc6960#section-4.2.2.2
+
+use anyhow::{anyhow, Result};
+use der_parser::{oid, oid::Oid};
+use sha2::digest::{const_oid::AssociatedOid, Digest};
+use std::time::SystemTime;
+use x509_parser::{certificate::X509Certificate, x509::SubjectPublicKeyInfo};
+
+// https://datatracker.ietf.org/doc/html/rfc5758#section-3.2
+const ECDSA_WITH_SHA256: Oid<'static> = oid!(1.2.840 .10045 .4 .3 .2);
+const ECDSA_WITH_SHA384: Oid<'static> = oid!(1.2.840 .10045 .4 .3 .3);
+// https://datatracker.ietf.org/doc/html/rfc4055#section-5
+const SHA256_WITH_RSA_ENCRYPTION: Oid<'static> = oid!(1.2.840 .113549 .1 .1 .11);
+const SHA384_WITH_RSA_ENCRYPTION: Oid<'static> = oid!(1.2.840 .113549 .1 .1 .12);
+const SHA512_WITH_RSA_ENCRYPTION: Oid<'static> = oid!(1.2.840 .113549 .1 .1 .13);
+
+/// Checks that the `BasicOCSPResponse` is signed by `issuer`, or by a
+/// certificate in its `certs` field that `issuer` authorized to sign OCSP
+/// responses, and that is valid at `now`.
+pub(super) fn verify_signature(
+    basic_response_der: &[u8],
+    issuer: &X509Certificate,
+    now: SystemTime,
+) -> Result<()> {
+    // BasicOCSPResponse       ::= SEQUENCE {
+    //    tbsResponseData      ResponseData,
+    //    signatureAlgorithm   AlgorithmIdentifier,
+    //    signature            BIT STRING,
+    //    certs            [0] EXPLICIT SEQUENCE OF Certificate OPTIONAL }
+    // The signature is computed over the DER of `tbsResponseData`, so it is
+    // sliced out of the input rather than re-encoded.
+    let (fields, _) = der_parser::der::der_read_element_header(basic_response_der)?;
+    let (rest, _) = der_parser::parse_der(fields)?;
+    let tbs_response_data = &fields[..fields.len() - rest.len()];
+    let (rest, signature_algorithm) = der_parser::parse_der(rest)?;
+    let signature_algorithm = signature_algorithm
+        .as_sequence()?
+        .first()
+        .ok_or_else(|| anyhow!("BasicOCSPResponse contains no signature algorithm"))?
+        .as_oid()?
+        .clone();
+    let (rest, signature) = der_parser::parse_der(rest)?;
+    let signature = signature.as_bitstring()?.data;
+
+    let issuer_error = match verify_signed_data(
+        issuer.public_key(),
+        &signature_algorithm,
+        tbs_response_data,
+        signature,
+    ) {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    for responder in parse_certs(rest)? {
+        if is_delegated_responder(&responder, issuer, now)
+            && verify_signed_data(
+                responder.public_key(),
+                &signature_algorithm,
+                tbs_response_data,
+                signature,
+            )
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err(issuer_error.context(format!(
+        "The OCSP response is signed by neither {} nor a responder it delegated to",
+        issuer.tbs_certificate.subject
+    )))
+}
+
+// Parses the optional `certs` field of a `BasicOCSPResponse`.
+fn parse_certs(input: &[u8]) -> Result<Vec<X509Certificate<'_>>> {
+    if input.is_empty() {
+        return Ok(vec![]);
+    }
+    let certs = der_parser::parse_der(input)?.1;
+    let (mut rest, _) = der_parser::der::der_read_element_header(certs.as_slice()?)?;
+    let mut certs = vec![];
+    while !rest.is_empty() {
+        let (next, cert) = x509_parser::parse_x509_certificate(rest)?;
+        certs.push(cert);
+        rest = next;
+    }
+    Ok(certs)
+}
+
+// Returns whether `responder` is issued by `issuer` for signing OCSP
+// responses, and is valid at `now`.
+fn is_delegated_responder(
+    responder: &X509Certificate,
+    issuer: &X509Certificate,
+    now: SystemTime,
+) -> bool {
+    let ocsp_signing = matches!(
+        responder.tbs_certificate.extended_key_usage(),
+        Ok(Some(eku)) if eku.value.ocsp_signing
+    );
+    let now = match now.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(now) => now.as_secs() as i64,
+        Err(_) => return false,
+    };
+    let validity = responder.validity();
+    ocsp_signing
+        && validity.not_before.timestamp() <= now
+        && now <= validity.not_after.timestamp()
+        && responder.tbs_certificate.issuer.as_raw() == issuer.tbs_certificate.subject.as_raw()
+        && verify_signed_data(
+            issuer.public_key(),
+            &responder.signature_algorithm.algorithm,
+            responder.tbs_certificate.as_ref(),
+            responder.signature_value.data,
+        )
+        .is_ok()
+}
+
+fn verify_signed_data(
+    public_key: &SubjectPublicKeyInfo,
+    algorithm: &Oid,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let public_key = public_key.subject_public_key.data;
+    if *algorithm == ECDSA_WITH_SHA256 {
+        use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+        let public_key = VerifyingKey::from_sec1_bytes(public_key)?;
+        public_key.verify(message, &Signature::from_der(signature)?)?;
+        Ok(())
+    } else if *algorithm == ECDSA_WITH_SHA384 {
+        use p384::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+        let public_key = VerifyingKey::from_sec1_bytes(public_key)?;
+        public_key.verify(message, &Signature::from_der(signature)?)?;
+        Ok(())
+    } else if *algorithm == SHA256_WITH_RSA_ENCRYPTION {
+        verify_rsa_signature::<sha2::Sha256>(public_key, message, signature)
+    } else if *algorithm == SHA384_WITH_RSA_ENCRYPTION {
+        verify_rsa_signature::<sha2::Sha384>(public_key, message, signature)
+    } else if *algorithm == SHA512_WITH_RSA_ENCRYPTION {
+        verify_rsa_signature::<sha2::Sha512>(public_key, message, signature)
+    } else {
+        Err(anyhow!("Unsupported signature algorithm {}", algorithm))
+    }
+}
+
+fn verify_rsa_signature<D: Digest + AssociatedOid>(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    use rsa::{pkcs1::DecodeRsaPublicKey, PaddingScheme, PublicKey, RsaPublicKey};
+    let public_key = RsaPublicKey::from_pkcs1_der(public_key)?;
+    public_key.verify(
+        PaddingScheme::new_pkcs1v15_sign::<D>(),
+        &D::digest(message),
+        signature,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const ISSUER: &[u8] = include_bytes!("../static/ocsp_verification/issuer.der");
+    const LEAF: &[u8] = include_bytes!("../static/ocsp_verification/leaf.der");
+    const BY_ISSUER: &[u8] = include_bytes!("../static/ocsp_verification/by_issuer.der");
+    const BY_RESPONDER: &[u8] = include_bytes!("../static/ocsp_verification/by_responder.der");
+    // An hour after the fixtures were generated, from when the responder is
+    // valid for 90 days.
+    const GENERATED_AT: u64 = 1792145239 + 3600;
+    fn verify_at(ocsp_der: &[u8], issuer_der: &[u8], now: u64) -> Result<()> {
+        let issuer = x509_parser::parse_x509_certificate(issuer_der).unwrap().1;
+        let now = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(now);
+        verify_signature(super::super::basic_ocsp_response(ocsp_der)?, &issuer, now)
+    }
+    fn verify(ocsp_der: &[u8], issuer_der: &[u8]) -> Result<()> {
+        verify_at(ocsp_der, issuer_der, GENERATED_AT)
+    }
+    #[test]
+    fn accepts_response_signed_by_issuer() {
+        verify(BY_ISSUER, ISSUER).unwrap();
+    }
+    #[test]
+    fn accepts_response_signed_by_delegated_responder() {
+        verify(BY_RESPONDER, ISSUER).unwrap();
+    }
+    #[test]
+    fn rejects_response_signed_by_expired_responder() {
+        assert!(verify_at(BY_RESPONDER, ISSUER, GENERATED_AT + 91 * 86400).is_err());
+        assert!(verify_at(BY_RESPONDER, ISSUER, GENERATED_AT - 2 * 3600).is_err());
+    }
+    #[test]
+    fn rejects_tampered_response() {
+        // `by_issuer.der` has no `certs`, so it ends with the signature.
+        let mut ocsp_der = BY_ISSUER.to_vec();
+        *ocsp_der.last_mut().unwrap() ^= 1;
+        assert!(verify(&ocsp_der, ISSUER).is_err());
+    }
+    #[test]
+    fn rejects_response_signed_by_others() {
+        assert!(verify(BY_ISSUER, LEAF).is_err());
+        // The responder is not delegated by the leaf.
+        assert!(verify(BY_RESPONDER, LEAF).is_err());
+    }
+}
@@ -19,6 +19,7 @@
 use crate::http::HttpResponse;
 use crate::http_parser::link::Link;
 use crate::link::ALLOWED_PARAM_NAMES;
+use crate::SxgError;
 use anyhow::Result;
 use serde::Deserialize;
 use std::borrow::Cow;
@@ -56,8 +57,16 @@ fn parse_content_type(content_type_header_value: &str) -> ContentType {
 /// - For `<script data-issxg-var>` elements, they are replaced with
 ///   `<script>window.isSXG=...</script>`, where `...` is true or false.
 /// - The `content-length` header is updated to the new value.
+///
 /// If input charset is not UTF8, the input will be returned back without any modification.
-pub fn process_html(input: HttpResponse, option: ProcessHtmlOption) -> Result<HttpResponse> {
+pub fn process_html(
+    input: HttpResponse,
+    option: ProcessHtmlOption,
+) -> Result<HttpResponse, SxgError> {
+    process_html_impl(input, option).map_err(SxgError::Sxg)
+}
+
+fn process_html_impl(input: HttpResponse, option: ProcessHtmlOption) -> Result<HttpResponse> {
     let content_type_header = input.headers.iter().find_map(|(name, value)| {
         if name.eq_ignore_ascii_case("content-type") {
             Some(value)
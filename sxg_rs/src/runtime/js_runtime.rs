@@ -12,10 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::metrics::NullMetrics;
 use super::Runtime;
 use crate::fetcher::{js_fetcher::JsFetcher, Fetcher, NullFetcher};
+use crate::runtime::store::{js_store::JsStore, Store};
 use crate::signature::{js_signer::JsSigner, mock_signer::MockSigner, Signer};
-use crate::storage::{js_storage::JsStorage, Storage};
 use anyhow::{Error, Result};
 use js_sys::Function as JsFunction;
 use std::time::{Duration, SystemTime};
@@ -32,6 +33,8 @@ extern "C" {
     fn storage_read(this: &JsRuntimeInitParams) -> Option<JsFunction>;
     #[wasm_bindgen(method, getter, js_name = "storageWrite")]
     fn storage_write(this: &JsRuntimeInitParams) -> Option<JsFunction>;
+    #[wasm_bindgen(method, getter, js_name = "storageDelete")]
+    fn storage_delete(this: &JsRuntimeInitParams) -> Option<JsFunction>;
     #[wasm_bindgen(method, getter, js_name = "sxgAsn1Signer")]
     fn sxg_asn1_signer(this: &JsRuntimeInitParams) -> Option<JsFunction>;
     #[wasm_bindgen(method, getter, js_name = "sxgRawSigner")]
@@ -47,8 +50,11 @@ impl std::convert::TryFrom<JsRuntimeInitParams> for Runtime {
         let fetcher = input
             .fetcher()
             .map(|f| Box::new(JsFetcher::new(f)) as Box<dyn Fetcher>);
-        let storage = Box::new(JsStorage::new(input.storage_read(), input.storage_write()))
-            as Box<dyn Storage>;
+        let storage = Box::new(JsStore::new(
+            input.storage_read(),
+            input.storage_write(),
+            input.storage_delete(),
+        )) as Box<dyn Store>;
         let sxg_asn1_signer = input
             .sxg_asn1_signer()
             .map(|f| Box::new(JsSigner::from_asn1_signer(f)) as Box<dyn Signer>);
@@ -65,6 +71,10 @@ impl std::convert::TryFrom<JsRuntimeInitParams> for Runtime {
             storage,
             sxg_signer: sxg_signer.unwrap_or_else(|| Box::new(MockSigner)),
             acme_signer: acme_signer.unwrap_or_else(|| Box::new(MockSigner)),
+            signing_limiter: None,
+            signed_exchange_cache: None,
+            metrics: Box::new(NullMetrics),
+            payload_transform: None,
         })
     }
 }
@@ -0,0 +1,53 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Receives counters and measurements from the signing pipeline, so that they
+/// can be exported to a metrics system without this crate depending on it.
+pub trait Metrics {
+    /// Increments the counter `name` by one.
+    fn incr(&self, name: &str);
+    /// Records `value` into the distribution `name`.
+    fn observe(&self, name: &str, value: f64);
+}
+
+/// Discards all metrics.
+pub struct NullMetrics;
+
+impl Metrics for NullMetrics {
+    fn incr(&self, _name: &str) {}
+    fn observe(&self, _name: &str, _value: f64) {}
+}
+
+/// The number of signed exchanges created.
+pub const SIGNED: &str = "sxg_signed";
+/// The number of origin responses not signed because of their status code.
+pub const PASSED_THROUGH: &str = "sxg_passed_through";
+/// The prefix of the number of failures to sign, followed by the reason, such
+/// as `sxg_signing_failures_overloaded`.
+pub const SIGNING_FAILURES_PREFIX: &str = "sxg_signing_failures_";
+/// The time in milliseconds to create a signed exchange.
+pub const SIGNING_LATENCY_MS: &str = "sxg_signing_latency_ms";
+
+// `std::time::SystemTime::now` panics in browsers and Cloudflare Workers.
+#[cfg(all(target_family = "wasm", feature = "wasm"))]
+pub(crate) fn now_millis() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(all(target_family = "wasm", feature = "wasm")))]
+pub(crate) fn now_millis() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |duration| duration.as_secs_f64() * 1000.0)
+}
@@ -14,18 +14,74 @@
 
 #[cfg(feature = "wasm")]
 pub mod js_runtime;
+pub mod metrics;
+pub mod signed_exchange_cache;
+pub mod signing_limiter;
+pub mod store;
 
 use crate::fetcher::{Fetcher, NullFetcher};
+use crate::headers::Headers;
 use crate::signature::{mock_signer::MockSigner, Signer};
-use crate::storage::{InMemoryStorage, Storage};
+use metrics::{Metrics, NullMetrics};
+use signed_exchange_cache::SignedExchangeCache;
+use signing_limiter::SigningLimiter;
+use std::borrow::Cow;
+use std::sync::Arc;
 use std::time::SystemTime;
+use store::{InMemoryStore, Store};
 
-pub struct Runtime {
+/// Rewrites the payload body of a signed exchange, given its payload headers.
+pub type PayloadTransform = dyn for<'a> Fn(&'a [u8], &Headers) -> Cow<'a, [u8]>;
+
+/// The I/O and clock used by `SxgWorker`. `S` is the type of `sxg_signer`
+/// and `F` the type of `fetcher`. Setting them to concrete types, as with
+/// `Runtime::into_generic`, lets the compiler monomorphize the signing path
+/// instead of dispatching through vtables. Most embedders use the boxed
+/// `Runtime`.
+pub struct GenericRuntime<S: ?Sized = dyn Signer, F: ?Sized = dyn Fetcher> {
     pub now: SystemTime,
-    pub fetcher: Box<dyn Fetcher>,
-    pub storage: Box<dyn Storage>,
-    pub sxg_signer: Box<dyn Signer>,
+    pub fetcher: Box<F>,
+    pub storage: Box<dyn Store>,
+    pub sxg_signer: Box<S>,
     pub acme_signer: Box<dyn Signer>,
+    /// If set, signed exchanges are not created when there are already
+    /// `max_concurrent_signings` being created.
+    pub signing_limiter: Option<Arc<SigningLimiter>>,
+    /// If set, the headers of created signed exchanges are cached to respond
+    /// to `HEAD` requests.
+    pub signed_exchange_cache: Option<Arc<SignedExchangeCache>>,
+    /// Receives the counters and latencies of creating signed exchanges.
+    pub metrics: Box<dyn Metrics>,
+    /// If set, the payload body is passed through it before being signed,
+    /// such as to inject `<link rel=preload>` tags. The `content-length`
+    /// header, if any, is updated to the transformed length.
+    pub payload_transform: Option<Box<PayloadTransform>>,
+}
+
+/// A runtime whose signer and fetcher are trait objects, which can be
+/// chosen at run time.
+pub type Runtime = GenericRuntime<dyn Signer, dyn Fetcher>;
+
+impl Runtime {
+    /// Replaces `sxg_signer` and `fetcher` with concrete types, keeping the
+    /// other fields.
+    pub fn into_generic<S: Signer, F: Fetcher>(
+        self,
+        sxg_signer: S,
+        fetcher: F,
+    ) -> GenericRuntime<S, F> {
+        GenericRuntime {
+            now: self.now,
+            fetcher: Box::new(fetcher),
+            storage: self.storage,
+            sxg_signer: Box::new(sxg_signer),
+            acme_signer: self.acme_signer,
+            signing_limiter: self.signing_limiter,
+            signed_exchange_cache: self.signed_exchange_cache,
+            metrics: self.metrics,
+            payload_transform: self.payload_transform,
+        }
+    }
 }
 
 impl Default for Runtime {
@@ -33,9 +89,13 @@ impl Default for Runtime {
         Runtime {
             now: SystemTime::UNIX_EPOCH,
             fetcher: Box::new(NullFetcher),
-            storage: Box::new(InMemoryStorage::default()),
+            storage: Box::new(InMemoryStore::new(SystemTime::UNIX_EPOCH)),
             sxg_signer: Box::new(MockSigner),
             acme_signer: Box::new(MockSigner),
+            signing_limiter: None,
+            signed_exchange_cache: None,
+            metrics: Box::new(NullMetrics),
+            payload_transform: None,
         }
     }
 }
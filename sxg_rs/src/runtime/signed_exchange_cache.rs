@@ -0,0 +1,128 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::http::{HeaderFields, HttpResponse};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// A least-recently-used cache of the signed exchanges created recently,
+/// keyed by the inner URL. It is used to respond to `HEAD` requests without
+/// signing the same URL again. Only the response headers are stored.
+/// Like `SigningLimiter`, it needs to be shared via `Arc` by all runtimes.
+pub struct SignedExchangeCache {
+    capacity: usize,
+    // The most recently used entry is at the back. Lookups are linear, which
+    // is fast enough for the small capacity this cache is intended for.
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+struct Entry {
+    url: String,
+    status: u16,
+    headers: HeaderFields,
+    expires: SystemTime,
+}
+
+impl SignedExchangeCache {
+    pub fn new(capacity: usize) -> Self {
+        SignedExchangeCache {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+    /// Stores the headers of a signed exchange, whose signature expires at
+    /// `expires`.
+    pub fn put(&self, url: &str, response: &HttpResponse, expires: SystemTime) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.url != url);
+        if entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            url: url.to_string(),
+            status: response.status,
+            headers: response.headers.clone(),
+            expires,
+        });
+    }
+    /// Returns a response with the headers of the cached signed exchange and
+    /// an empty body, if the signature is still valid at `now`.
+    pub fn get_head_response(&self, url: &str, now: SystemTime) -> Option<HttpResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let index = entries.iter().position(|entry| entry.url == url)?;
+        let entry = entries.remove(index)?;
+        if now >= entry.expires {
+            return None;
+        }
+        let response = HttpResponse {
+            body: vec![],
+            headers: entry.headers.clone(),
+            status: entry.status,
+        };
+        entries.push_back(entry);
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    fn response() -> HttpResponse {
+        HttpResponse {
+            body: b"sxg".to_vec(),
+            headers: vec![(
+                "content-type".to_string(),
+                "application/signed-exchange;v=b3".to_string(),
+            )],
+            status: 200,
+        }
+    }
+    #[test]
+    fn serves_fresh_entries_without_body() {
+        let cache = SignedExchangeCache::new(2);
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        cache.put(
+            "https://foo.com/",
+            &response(),
+            now + Duration::from_secs(10),
+        );
+        let head = cache.get_head_response("https://foo.com/", now).unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.headers, response().headers);
+        assert!(head.body.is_empty());
+        assert!(cache
+            .get_head_response("https://foo.com/", now + Duration::from_secs(10))
+            .is_none());
+        // The expired entry has been removed.
+        assert!(cache.get_head_response("https://foo.com/", now).is_none());
+    }
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = SignedExchangeCache::new(2);
+        let now = SystemTime::UNIX_EPOCH;
+        let expires = now + Duration::from_secs(10);
+        cache.put("https://foo.com/1", &response(), expires);
+        cache.put("https://foo.com/2", &response(), expires);
+        assert!(cache.get_head_response("https://foo.com/1", now).is_some());
+        cache.put("https://foo.com/3", &response(), expires);
+        assert!(cache.get_head_response("https://foo.com/1", now).is_some());
+        assert!(cache.get_head_response("https://foo.com/2", now).is_none());
+        assert!(cache.get_head_response("https://foo.com/3", now).is_some());
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::SxgError;
+use anyhow::anyhow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Limits the number of signed exchanges being created at the same time.
+/// Because a new `Runtime` is usually created for each request, the same
+/// `SigningLimiter` needs to be shared via `Arc` by all runtimes.
+pub struct SigningLimiter {
+    semaphore: Semaphore,
+    shed_count: AtomicU64,
+}
+
+impl SigningLimiter {
+    pub fn new(max_concurrent_signings: usize) -> Self {
+        SigningLimiter {
+            semaphore: Semaphore::new(max_concurrent_signings),
+            shed_count: AtomicU64::new(0),
+        }
+    }
+    /// Returns a permit which must be held while signing. Returns an error
+    /// without waiting if the limit is reached, so that the caller can serve
+    /// the unsigned response instead.
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, SxgError> {
+        self.semaphore.try_acquire().map_err(|_| {
+            self.shed_count.fetch_add(1, Ordering::Relaxed);
+            SxgError::Sxg(anyhow!(
+                "Too many signed exchanges are being created concurrently."
+            ))
+        })
+    }
+    /// Returns how many times `try_acquire` has rejected a signing.
+    pub fn shed_count(&self) -> u64 {
+        self.shed_count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn sheds_excess_signings() {
+        let limiter = SigningLimiter::new(2);
+        let permit_1 = limiter.try_acquire().unwrap();
+        let _permit_2 = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_err());
+        assert_eq!(limiter.shed_count(), 1);
+        drop(permit_1);
+        assert!(limiter.try_acquire().is_ok());
+        assert_eq!(limiter.shed_count(), 1);
+    }
+}
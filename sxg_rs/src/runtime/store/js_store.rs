@@ -0,0 +1,101 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Store;
+use crate::utils::await_js_promise;
+use crate::SxgError;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use js_sys::Function as JsFunction;
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct JsStore {
+    read: Option<JsFunction>,
+    write: Option<JsFunction>,
+    delete: Option<JsFunction>,
+}
+
+#[wasm_bindgen]
+impl JsStore {
+    /// Creates a store by three JavaScript async functions.
+    /// `read` must be of type `(key: string) => Promise<string | null>`;
+    /// `write` must be of type
+    /// `(key: string, value: string, ttlSeconds?: number) => Promise<void>`;
+    /// `delete` must be of type `(key: string) => Promise<void>`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        read: Option<JsFunction>,
+        write: Option<JsFunction>,
+        delete: Option<JsFunction>,
+    ) -> Self {
+        JsStore {
+            read,
+            write,
+            delete,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for JsStore {
+    async fn get(&self, k: &str) -> Result<Option<String>, SxgError> {
+        if let Some(read) = &self.read {
+            let k = JsValue::from_str(k);
+            let v = await_js_promise(read.call1(&JsValue::NULL, &k))
+                .await
+                .map_err(SxgError::Storage)?;
+            if v.is_null() {
+                return Ok(None);
+            }
+            let v = v.as_string().ok_or_else(|| {
+                SxgError::Storage(anyhow!("Expecting JavaScript function to return a string"))
+            })?;
+            Ok(Some(v))
+        } else {
+            Ok(None)
+        }
+    }
+    async fn put(&self, k: &str, v: &str) -> Result<(), SxgError> {
+        if let Some(write) = &self.write {
+            let k = JsValue::from_str(k);
+            let v = JsValue::from_str(v);
+            await_js_promise(write.call2(&JsValue::NULL, &k, &v))
+                .await
+                .map_err(SxgError::Storage)?;
+        }
+        Ok(())
+    }
+    async fn delete(&self, k: &str) -> Result<(), SxgError> {
+        if let Some(delete) = &self.delete {
+            let k = JsValue::from_str(k);
+            await_js_promise(delete.call1(&JsValue::NULL, &k))
+                .await
+                .map_err(SxgError::Storage)?;
+        }
+        Ok(())
+    }
+    async fn put_with_ttl(&self, k: &str, v: &str, ttl: Duration) -> Result<(), SxgError> {
+        if let Some(write) = &self.write {
+            let k = JsValue::from_str(k);
+            let v = JsValue::from_str(v);
+            let ttl = JsValue::from_f64(ttl.as_secs_f64());
+            await_js_promise(write.call3(&JsValue::NULL, &k, &v, &ttl))
+                .await
+                .map_err(SxgError::Storage)?;
+        }
+        Ok(())
+    }
+}
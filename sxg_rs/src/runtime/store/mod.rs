@@ -0,0 +1,129 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistence for the state that workers keep across requests, such as the
+//! OCSP response, the ACME state machine and cached header integrities.
+//!
+//! Each platform supplies its own `Store` as `Runtime::storage`. The
+//! JavaScript workers pass `storageRead`, `storageWrite` and `storageDelete`
+//! functions in `JsRuntimeInitParams`, which the Cloudflare worker backs with
+//! a KV namespace. The CLI keeps each key in a file. Other platforms implement
+//! `Store` on top of their own key-value storage.
+
+#[cfg(feature = "wasm")]
+pub mod js_store;
+
+use crate::SxgError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+
+/// A key-value store. Failures are reported as `SxgError::Storage`.
+#[async_trait(?Send)]
+pub trait Store {
+    /// Returns the value of `key`, or `None` if it is absent or has expired.
+    async fn get(&self, key: &str) -> Result<Option<String>, SxgError>;
+    async fn put(&self, key: &str, value: &str) -> Result<(), SxgError>;
+    /// Removes `key`. Removing an absent key is not an error.
+    async fn delete(&self, key: &str) -> Result<(), SxgError>;
+    /// Like `put`, but `get` stops returning the value after `ttl`. Stores
+    /// may expire the value late, so callers that need a precise expiration
+    /// must still check it themselves.
+    async fn put_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SxgError>;
+}
+
+struct Entry {
+    value: String,
+    expiration: Option<SystemTime>,
+}
+
+/// A store that keeps the values in memory, which is as long as the runtime
+/// that owns it.
+pub struct InMemoryStore {
+    entries: Arc<RwLock<HashMap<String, Entry>>>,
+    now: SystemTime,
+}
+
+impl InMemoryStore {
+    /// Creates an empty store, whose TTLs are measured from `now`. It should
+    /// be the `now` of the runtime that owns the store, so that values expire
+    /// consistently with the expiration checks of the callers.
+    pub fn new(now: SystemTime) -> Self {
+        InMemoryStore {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            now,
+        }
+    }
+    async fn insert(&self, key: &str, value: &str, expiration: Option<SystemTime>) {
+        let mut guard = self.entries.write().await;
+        guard.insert(
+            key.to_string(),
+            Entry {
+                value: value.to_string(),
+                expiration,
+            },
+        );
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for InMemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, SxgError> {
+        let guard = self.entries.read().await;
+        Ok(guard
+            .get(key)
+            .filter(|entry| match entry.expiration {
+                Some(expiration) => self.now < expiration,
+                None => true,
+            })
+            .map(|entry| entry.value.clone()))
+    }
+    async fn put(&self, key: &str, value: &str) -> Result<(), SxgError> {
+        self.insert(key, value, None).await;
+        Ok(())
+    }
+    async fn delete(&self, key: &str) -> Result<(), SxgError> {
+        let mut guard = self.entries.write().await;
+        guard.remove(key);
+        Ok(())
+    }
+    async fn put_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SxgError> {
+        self.insert(key, value, Some(self.now + ttl)).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn in_memory_store() {
+        let store = InMemoryStore::new(SystemTime::UNIX_EPOCH);
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.put("a", "1").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some("1".to_string()));
+        store.delete("a").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.delete("a").await.unwrap();
+        store
+            .put_with_ttl("b", "2", Duration::from_secs(3600))
+            .await
+            .unwrap();
+        assert_eq!(store.get("b").await.unwrap(), Some("2".to_string()));
+        store.put_with_ttl("b", "2", Duration::ZERO).await.unwrap();
+        assert_eq!(store.get("b").await.unwrap(), None);
+    }
+}
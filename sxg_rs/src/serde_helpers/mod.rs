@@ -26,3 +26,4 @@
 //! Rust value `Container { value: vec![1, 2, 3] }` will be serialized as JSON `{ "value": "AQID" }`.
 
 pub mod base64;
+pub mod one_or_many;
@@ -0,0 +1,70 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Module to deserialize `Vec<T>` from either a single value or a sequence,
+//! so that a field can be changed from one value to many without breaking
+//! existing files. It is always serialized as a sequence.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+pub fn serialize<S, T>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize,
+{
+    values.serialize(serializer)
+}
+
+pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[derive(Serialize, Deserialize)]
+    struct Data {
+        #[serde(with = "super")]
+        values: Vec<String>,
+    }
+    #[test]
+    fn serialize() {
+        let x = Data {
+            values: vec!["a".to_string()],
+        };
+        assert_eq!(serde_json::to_string(&x).unwrap(), r#"{"values":["a"]}"#);
+    }
+    #[test]
+    fn deserialize() {
+        let x: Data = serde_json::from_str(r#"{"values":"a"}"#).unwrap();
+        assert_eq!(x.values, vec!["a"]);
+        let x: Data = serde_json::from_str(r#"{"values":["a","b"]}"#).unwrap();
+        assert_eq!(x.values, vec!["a", "b"]);
+        assert!(serde_json::from_str::<Data>(r#"{"values":1}"#).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structured_header::{parse_param_list, ParamItem, ParsedItem, ShItem, ShParamList};
+use crate::SxgError;
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// The `Signature` header of a signed exchange, as defined in
+/// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-the-signature-header
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SignatureHeader {
+    pub label: String,
+    pub sig: Vec<u8>,
+    pub integrity: String,
+    pub cert_url: String,
+    pub cert_sha256: Vec<u8>,
+    pub validity_url: String,
+    /// Seconds since the Unix epoch.
+    pub date: u64,
+    /// Seconds since the Unix epoch.
+    pub expires: u64,
+}
+
+impl SignatureHeader {
+    /// Parses a `Signature` header. If the header contains multiple
+    /// signatures, only the first one is returned.
+    pub fn parse(s: &str) -> Result<SignatureHeader, SxgError> {
+        Self::parse_impl(s).map_err(SxgError::Sxg)
+    }
+    fn parse_impl(s: &str) -> Result<SignatureHeader> {
+        let signature = parse_param_list(s)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Signature header is empty"))?;
+        let mut sig = None;
+        let mut integrity = None;
+        let mut cert_url = None;
+        let mut cert_sha256 = None;
+        let mut validity_url = None;
+        let mut date = None;
+        let mut expires = None;
+        for (name, value) in signature.parameters {
+            match (name.as_str(), value) {
+                ("sig", Some(ParsedItem::ByteSequence(x))) => sig = Some(x),
+                ("integrity", Some(ParsedItem::String(x))) => integrity = Some(x),
+                ("cert-url", Some(ParsedItem::String(x))) => cert_url = Some(x),
+                ("cert-sha256", Some(ParsedItem::ByteSequence(x))) => cert_sha256 = Some(x),
+                ("validity-url", Some(ParsedItem::String(x))) => validity_url = Some(x),
+                ("date", Some(ParsedItem::Integer(x))) => date = Some(x),
+                ("expires", Some(ParsedItem::Integer(x))) => expires = Some(x),
+                (
+                    "sig" | "integrity" | "cert-url" | "cert-sha256" | "validity-url" | "date"
+                    | "expires",
+                    value,
+                ) => {
+                    return Err(anyhow!(
+                        "Signature parameter {} has an unexpected value {:?}",
+                        name,
+                        value
+                    ))
+                }
+                // Unknown parameters are ignored.
+                _ => (),
+            }
+        }
+        let missing = |name: &str| anyhow!("Signature parameter {} is missing", name);
+        Ok(SignatureHeader {
+            label: signature.primary_id,
+            sig: sig.ok_or_else(|| missing("sig"))?,
+            integrity: integrity.ok_or_else(|| missing("integrity"))?,
+            cert_url: cert_url.ok_or_else(|| missing("cert-url"))?,
+            cert_sha256: cert_sha256.ok_or_else(|| missing("cert-sha256"))?,
+            validity_url: validity_url.ok_or_else(|| missing("validity-url"))?,
+            date: date.ok_or_else(|| missing("date"))?,
+            expires: expires.ok_or_else(|| missing("expires"))?,
+        })
+    }
+}
+
+// Serializes the header as a parameterised list with a single member.
+impl fmt::Display for SignatureHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = ShParamList::new();
+        let mut param = ParamItem::new(&self.label);
+        param.push(("sig", Some(ShItem::ByteSequence(&self.sig))));
+        param.push(("integrity", Some(ShItem::String(&self.integrity))));
+        param.push(("cert-url", Some(ShItem::String(&self.cert_url))));
+        param.push(("cert-sha256", Some(ShItem::ByteSequence(&self.cert_sha256))));
+        param.push(("validity-url", Some(ShItem::String(&self.validity_url))));
+        param.push(("date", Some(ShItem::Integer(self.date))));
+        param.push(("expires", Some(ShItem::Integer(self.expires))));
+        list.push(param);
+        write!(f, "{}", list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::quickcheck;
+    // The first signature of the example in
+    // https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#section-3.1,
+    // with the `b3` integrity, and the parameters in the order Chrome has
+    // accepted from this crate.
+    const EXAMPLE: &str = "sig1;\
+        sig=*MEUCIQDXlI2gN3RNBlgFiuRNFpZXcDIaUpX6HIEwcZEc0cZYLAIga9DsVOMM+g5YpwEBdGW3sS+bvnmAJJiSMwhuBdqp5UY=*;\
+        integrity=\"digest/mi-sha256-03\";\
+        cert-url=\"https://example.com/oldcerts\";\
+        cert-sha256=*W7uB969dFW3Mb5ZefPS9Tq5ZbH5iSmOILpjv2qEArmI=*;\
+        validity-url=\"https://example.com/resource.validity.1511128380\";\
+        date=1511128380;\
+        expires=1511733180";
+    fn example() -> SignatureHeader {
+        SignatureHeader {
+            label: "sig1".to_string(),
+            sig: base64::decode("MEUCIQDXlI2gN3RNBlgFiuRNFpZXcDIaUpX6HIEwcZEc0cZYLAIga9DsVOMM+g5YpwEBdGW3sS+bvnmAJJiSMwhuBdqp5UY=").unwrap(),
+            integrity: "digest/mi-sha256-03".to_string(),
+            cert_url: "https://example.com/oldcerts".to_string(),
+            cert_sha256: base64::decode("W7uB969dFW3Mb5ZefPS9Tq5ZbH5iSmOILpjv2qEArmI=").unwrap(),
+            validity_url: "https://example.com/resource.validity.1511128380".to_string(),
+            date: 1511128380,
+            expires: 1511733180,
+        }
+    }
+    #[test]
+    fn serializes_example() {
+        assert_eq!(example().to_string(), EXAMPLE);
+    }
+    #[test]
+    fn parses_example() {
+        assert_eq!(SignatureHeader::parse(EXAMPLE).unwrap(), example());
+        // Optional whitespace and unknown parameters are allowed.
+        assert_eq!(
+            SignatureHeader::parse(&format!(" {} ; foo, sig2;bar ", EXAMPLE)).unwrap(),
+            example()
+        );
+        assert!(SignatureHeader::parse(&EXAMPLE.replace("date=", "date=\"")).is_err());
+        assert!(SignatureHeader::parse(&EXAMPLE.replace(";date=1511128380", "")).is_err());
+    }
+    #[test]
+    fn parse_reverses_to_string() {
+        // Strings are limited to the printable ASCII that sh-string allows,
+        // and the label to an sh-key.
+        fn printable(s: String) -> String {
+            s.chars().filter(|c| (' '..='~').contains(c)).collect()
+        }
+        #[allow(clippy::too_many_arguments)]
+        fn prop(
+            label: String,
+            sig: Vec<u8>,
+            integrity: String,
+            cert_url: String,
+            cert_sha256: Vec<u8>,
+            validity_url: String,
+            date: u64,
+            expires: u64,
+        ) -> bool {
+            let header = SignatureHeader {
+                label: format!(
+                    "s{}",
+                    label
+                        .chars()
+                        .filter(char::is_ascii_lowercase)
+                        .collect::<String>()
+                ),
+                sig,
+                integrity: printable(integrity),
+                cert_url: printable(cert_url),
+                cert_sha256,
+                validity_url: printable(validity_url),
+                date,
+                expires,
+            };
+            SignatureHeader::parse(&header.to_string()).ok() == Some(header)
+        }
+        quickcheck(prop as fn(String, Vec<u8>, String, String, Vec<u8>, String, u64, u64) -> bool);
+    }
+}
@@ -14,7 +14,7 @@
 
 use super::{Format, Signer};
 use crate::utils::await_js_promise;
-use anyhow::Result;
+use crate::SxgError;
 use async_trait::async_trait;
 use js_sys::{Function as JsFunction, Uint8Array};
 use wasm_bindgen::JsValue;
@@ -51,17 +51,19 @@ impl JsSigner {
 
 #[async_trait(?Send)]
 impl Signer for JsSigner {
-    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>> {
+    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>, SxgError> {
         let a = Uint8Array::new_with_length(message.len() as u32);
         a.copy_from(message);
-        let sig = await_js_promise(self.js_function.call1(&JsValue::NULL, &a)).await?;
+        let sig = await_js_promise(self.js_function.call1(&JsValue::NULL, &a))
+            .await
+            .map_err(SxgError::Crypto)?;
         let sig = Uint8Array::from(sig);
         let sig = sig.to_vec();
         match (self.js_sig_format, format) {
             (Format::Raw, Format::Raw) => Ok(sig),
             (Format::EccAsn1, Format::EccAsn1) => Ok(sig),
-            (Format::Raw, Format::EccAsn1) => super::raw_sig_to_asn1(sig),
-            (Format::EccAsn1, Format::Raw) => super::parse_asn1_sig(&sig),
+            (Format::Raw, Format::EccAsn1) => super::raw_sig_to_asn1(sig).map_err(SxgError::Crypto),
+            (Format::EccAsn1, Format::Raw) => super::parse_asn1_sig(&sig).map_err(SxgError::Crypto),
         }
     }
 }
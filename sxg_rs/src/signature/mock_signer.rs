@@ -12,17 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Format, Signer};
-use anyhow::Result;
+use super::{BlockingSigner, Format, Signer};
+use crate::SxgError;
 use async_trait::async_trait;
 
 pub struct MockSigner;
 
 #[async_trait(?Send)]
 impl Signer for MockSigner {
-    async fn sign(&self, _message: &[u8], format: Format) -> Result<Vec<u8>> {
+    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>, SxgError> {
+        self.sign_blocking(message, format)
+    }
+}
+
+impl BlockingSigner for MockSigner {
+    fn sign_blocking(&self, _message: &[u8], format: Format) -> Result<Vec<u8>, SxgError> {
         match format {
-            Format::EccAsn1 => super::raw_sig_to_asn1([0].repeat(64)),
+            Format::EccAsn1 => super::raw_sig_to_asn1([0].repeat(64)).map_err(SxgError::Crypto),
             Format::Raw => Ok([0].repeat(64)),
         }
     }
@@ -12,32 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod header;
 #[cfg(feature = "wasm")]
 pub mod js_signer;
 pub mod mock_signer;
 #[cfg(feature = "rust_signer")]
 pub mod rust_signer;
 
-use crate::structured_header::{ParamItem, ShItem, ShParamList};
-use anyhow::{anyhow, Error, Result};
+use crate::SxgError;
+use anyhow::{anyhow, Context, Error, Result};
 use async_trait::async_trait;
 use der_parser::ber::{BerObject, BerObjectContent};
 use std::cmp::min;
 use std::time::Duration;
 
+pub use header::SignatureHeader;
+
 #[derive(Clone, Copy)]
 pub enum Format {
     Raw,
     EccAsn1,
 }
 
+/// Signs the signed exchanges and the ACME requests. Failures are reported as
+/// `SxgError::Crypto`.
 #[async_trait(?Send)]
 pub trait Signer {
     /// Signs the message, and returns in the given format.
-    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>>;
+    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>, SxgError>;
+}
+
+/// A signer that signs without I/O, such as by a private key in memory. It is
+/// used by `SxgWorker::create_signed_exchange_blocking`.
+pub trait BlockingSigner {
+    /// Signs the message, and returns in the given format.
+    fn sign_blocking(&self, message: &[u8], format: Format) -> Result<Vec<u8>, SxgError>;
 }
 
-pub struct SignatureParams<'a> {
+pub struct SignatureParams<'a, S: ?Sized = dyn Signer> {
     pub cert_url: &'a str,
     pub cert_sha256: &'a [u8],
     pub date: std::time::SystemTime,
@@ -45,7 +57,7 @@ pub struct SignatureParams<'a> {
     pub headers: &'a [u8],
     pub id: &'a str,
     pub request_url: &'a str,
-    pub signer: &'a dyn Signer,
+    pub signer: &'a S,
     pub validity_url: &'a str,
 }
 
@@ -68,8 +80,41 @@ fn seven_days_from(date: &std::time::SystemTime) -> Result<std::time::SystemTime
         .ok_or_else(|| anyhow!("Overflow computing expires"))
 }
 
+/// Returns the `expires` of a signature created with the given `date` and
+/// requested `expires`, which is at most seven days after `date`.
+pub fn effective_expires(
+    date: std::time::SystemTime,
+    expires: Option<std::time::SystemTime>,
+) -> Result<std::time::SystemTime> {
+    Ok(match expires {
+        None => seven_days_from(&date)?,
+        Some(expires) => min(expires, seven_days_from(&date)?),
+    })
+}
+
 impl<'a> Signature<'a> {
-    pub async fn new(params: SignatureParams<'a>) -> Result<Signature<'a>> {
+    pub async fn new<S: Signer + ?Sized>(params: SignatureParams<'a, S>) -> Result<Signature<'a>> {
+        let (mut signature, message, signer) = Self::unsigned(params)?;
+        signature.sig = signer
+            .sign(&message, Format::EccAsn1)
+            .await
+            .context("Failed to sign the message.")?;
+        Ok(signature)
+    }
+    /// Like `new`, but signs synchronously.
+    pub fn new_blocking(
+        params: SignatureParams<'a, dyn BlockingSigner + 'a>,
+    ) -> Result<Signature<'a>> {
+        let (mut signature, message, signer) = Self::unsigned(params)?;
+        signature.sig = signer
+            .sign_blocking(&message, Format::EccAsn1)
+            .context("Failed to sign the message.")?;
+        Ok(signature)
+    }
+    // Returns the signature with an empty `sig`, and the message to sign.
+    fn unsigned<S: ?Sized>(
+        params: SignatureParams<'a, S>,
+    ) -> Result<(Signature<'a>, Vec<u8>, &'a S)> {
         let SignatureParams {
             cert_url,
             cert_sha256,
@@ -81,58 +126,77 @@ impl<'a> Signature<'a> {
             signer,
             validity_url,
         } = params;
-        let expires = match expires {
-            None => seven_days_from(&date)?,
-            Some(expires) => min(expires, seven_days_from(&date)?),
-        };
+        let expires = effective_expires(date, expires)?;
         let date = time_to_number(date);
         let expires = time_to_number(expires);
-        // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-signature-validity
-        let message = [
-            &[32u8; 64],
-            "HTTP Exchange 1 b3".as_bytes(),
-            &[0u8],
-            &[32u8],
+        let message = signed_message(
             cert_sha256,
-            &(validity_url.len() as u64).to_be_bytes(),
-            validity_url.as_bytes(),
-            &date.to_be_bytes(),
-            &expires.to_be_bytes(),
-            &(request_url.len() as u64).to_be_bytes(),
-            request_url.as_bytes(),
-            &(headers.len() as u64).to_be_bytes(),
+            validity_url,
+            date,
+            expires,
+            request_url,
             headers,
-        ]
-        .concat();
-        let sig = signer
-            .sign(&message, Format::EccAsn1)
-            .await
-            .map_err(|e| e.context("Failed to sign the message."))?;
-        Ok(Signature {
+        );
+        let signature = Signature {
             cert_url,
             cert_sha256,
             date,
             expires,
             id,
-            sig,
+            sig: vec![],
             validity_url,
-        })
+        };
+        Ok((signature, message, signer))
+    }
+    /// Returns the time when the signature expires.
+    pub fn expires(&self) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + Duration::from_secs(self.expires)
+    }
+    pub fn header(&self) -> SignatureHeader {
+        SignatureHeader {
+            label: self.id.to_string(),
+            sig: self.sig.clone(),
+            integrity: "digest/mi-sha256-03".to_string(),
+            cert_url: self.cert_url.to_string(),
+            cert_sha256: self.cert_sha256.to_vec(),
+            validity_url: self.validity_url.to_string(),
+            date: self.date,
+            expires: self.expires,
+        }
     }
     pub fn serialize(&self) -> Vec<u8> {
-        let mut list = ShParamList::new();
-        let mut param = ParamItem::new(self.id);
-        param.push(("sig", Some(ShItem::ByteSequence(&self.sig))));
-        param.push(("integrity", Some(ShItem::String("digest/mi-sha256-03"))));
-        param.push(("cert-url", Some(ShItem::String(self.cert_url))));
-        param.push(("cert-sha256", Some(ShItem::ByteSequence(self.cert_sha256))));
-        param.push(("validity-url", Some(ShItem::String(self.validity_url))));
-        param.push(("date", Some(ShItem::Integer(self.date))));
-        param.push(("expires", Some(ShItem::Integer(self.expires))));
-        list.push(param);
-        format!("{}", list).into_bytes()
+        self.header().to_string().into_bytes()
     }
 }
 
+/// Returns the message that the signature of a signed exchange signs.
+/// https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#name-signature-validity
+pub(crate) fn signed_message(
+    cert_sha256: &[u8],
+    validity_url: &str,
+    date: u64,
+    expires: u64,
+    request_url: &str,
+    headers: &[u8],
+) -> Vec<u8> {
+    [
+        &[32u8; 64],
+        "HTTP Exchange 1 b3".as_bytes(),
+        &[0u8],
+        &[32u8],
+        cert_sha256,
+        &(validity_url.len() as u64).to_be_bytes(),
+        validity_url.as_bytes(),
+        &date.to_be_bytes(),
+        &expires.to_be_bytes(),
+        &(request_url.len() as u64).to_be_bytes(),
+        request_url.as_bytes(),
+        &(headers.len() as u64).to_be_bytes(),
+        headers,
+    ]
+    .concat()
+}
+
 fn time_to_number(t: std::time::SystemTime) -> u64 {
     t.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
 }
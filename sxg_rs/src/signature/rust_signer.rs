@@ -12,27 +12,43 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Format, Signer};
-use anyhow::Result;
+use super::{BlockingSigner, Format, Signer};
+use crate::SxgError;
+use anyhow::{Error, Result};
 use async_trait::async_trait;
 use p256::ecdsa::SigningKey;
 
+/// A signer holding a parsed private key. Parsing the key is much slower than
+/// cloning, so a `RustSigner` should be created once and cloned for each
+/// request.
+#[derive(Clone)]
 pub struct RustSigner {
     private_key: SigningKey,
 }
 
 impl RustSigner {
-    pub fn new(private_key: &[u8]) -> Result<Self> {
-        let private_key = SigningKey::from_bytes(private_key)?;
+    /// Parses the private key, which is the big-endian scalar of a P-256 key.
+    pub fn new(private_key: &[u8]) -> Result<Self, SxgError> {
+        let private_key =
+            SigningKey::from_bytes(private_key).map_err(|e| SxgError::Crypto(Error::new(e)))?;
         Ok(RustSigner { private_key })
     }
 }
 
 #[async_trait(?Send)]
 impl Signer for RustSigner {
-    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>> {
+    async fn sign(&self, message: &[u8], format: Format) -> Result<Vec<u8>, SxgError> {
+        self.sign_blocking(message, format)
+    }
+}
+
+impl BlockingSigner for RustSigner {
+    fn sign_blocking(&self, message: &[u8], format: Format) -> Result<Vec<u8>, SxgError> {
         use p256::ecdsa::signature::Signer as _;
-        let sig = self.private_key.try_sign(message)?;
+        let sig = self
+            .private_key
+            .try_sign(message)
+            .map_err(|e| SxgError::Crypto(Error::new(e)))?;
         match format {
             Format::Raw => Ok(sig.to_vec()),
             Format::EccAsn1 => Ok(sig.to_der().as_bytes().to_vec()),
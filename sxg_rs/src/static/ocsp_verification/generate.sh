@@ -0,0 +1,60 @@
+#!/bin/bash
+#
+# Copyright 2022 Google LLC
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     https://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+
+# Regenerates the test fixtures for OCSP response verification:
+#   issuer.der        an RSA issuer
+#   leaf.der          a leaf with serial 0x1234
+#   other_leaf.der    a leaf with serial 0x5678
+#   by_issuer.der     an OCSP response for leaf.der, signed by the issuer
+#   by_responder.der  an OCSP response for leaf.der, signed by a P-384
+#                     responder that the issuer delegated to
+
+set -e
+cd "$(dirname "$0")"
+TMP=$(mktemp -d)
+trap 'rm -rf "$TMP"' EXIT
+
+openssl req -x509 -newkey rsa:2048 -nodes -keyout "$TMP/issuer.key" \
+  -out "$TMP/issuer.pem" -subj /CN=issuer -days 3650 \
+  -addext basicConstraints=critical,CA:TRUE \
+  -addext keyUsage=critical,keyCertSign,cRLSign
+openssl req -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+  -keyout /dev/null -subj /CN=example.org -out "$TMP/leaf.csr"
+for serial in 1234 5678; do
+  openssl x509 -req -in "$TMP/leaf.csr" -CA "$TMP/issuer.pem" \
+    -CAkey "$TMP/issuer.key" -set_serial "0x$serial" -days 90 \
+    -out "$TMP/$serial.pem"
+done
+openssl req -newkey ec -pkeyopt ec_paramgen_curve:secp384r1 -nodes \
+  -keyout "$TMP/responder.key" -subj /CN=responder -out "$TMP/responder.csr"
+openssl x509 -req -in "$TMP/responder.csr" -CA "$TMP/issuer.pem" \
+  -CAkey "$TMP/issuer.key" -set_serial 0x9abc -days 90 \
+  -out "$TMP/responder.pem" \
+  -extfile <(echo "extendedKeyUsage=OCSPSigning")
+
+printf 'V\t300101000000Z\t\t1234\tunknown\t/CN=example.org\n' > "$TMP/index.txt"
+openssl ocsp -issuer "$TMP/issuer.pem" -cert "$TMP/1234.pem" -no_nonce \
+  -reqout "$TMP/request.der"
+openssl ocsp -index "$TMP/index.txt" -CA "$TMP/issuer.pem" \
+  -rsigner "$TMP/issuer.pem" -rkey "$TMP/issuer.key" -resp_no_certs \
+  -reqin "$TMP/request.der" -ndays 7 -respout by_issuer.der
+openssl ocsp -index "$TMP/index.txt" -CA "$TMP/issuer.pem" \
+  -rsigner "$TMP/responder.pem" -rkey "$TMP/responder.key" -rmd sha384 \
+  -reqin "$TMP/request.der" -ndays 7 -respout by_responder.der
+
+openssl x509 -in "$TMP/issuer.pem" -outform DER -out issuer.der
+openssl x509 -in "$TMP/1234.pem" -outform DER -out leaf.der
+openssl x509 -in "$TMP/5678.pem" -outform DER -out other_leaf.der
@@ -12,39 +12,65 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(feature = "wasm")]
-pub mod js_storage;
+//! The former storage interface, which is superseded by `runtime::store`,
+//! and will be removed in a future release. `Runtime::storage` is now a
+//! `Store`, so an existing `Storage` implementation is passed to it wrapped
+//! in `StorageAdapter`.
 
+#![allow(deprecated)]
+
+use crate::runtime::store::{InMemoryStore, Store};
+use crate::SxgError;
 use anyhow::Result;
 use async_trait::async_trait;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, SystemTime};
 
+#[deprecated(note = "use `sxg_rs::runtime::store::Store` instead")]
 #[async_trait(?Send)]
 pub trait Storage {
     async fn read(&self, k: &str) -> Result<Option<String>>;
     async fn write(&self, k: &str, v: &str) -> Result<()>;
 }
 
-pub struct InMemoryStorage(Arc<RwLock<HashMap<String, String>>>);
-
-impl InMemoryStorage {
-    pub fn new() -> Self {
-        InMemoryStorage(Arc::new(RwLock::new(HashMap::new())))
+#[async_trait(?Send)]
+impl<T: Store + ?Sized> Storage for T {
+    async fn read(&self, k: &str) -> Result<Option<String>> {
+        Ok(self.get(k).await?)
+    }
+    async fn write(&self, k: &str, v: &str) -> Result<()> {
+        Ok(self.put(k, v).await?)
     }
 }
 
+/// Adapts a `Storage` into a `Store`, such as for `Runtime::storage`.
+/// `Storage` can't remove keys or expire values, so `delete` writes an empty
+/// value, which `get` reads as absent, and `put_with_ttl` keeps the value
+/// until it is overwritten.
+pub struct StorageAdapter<S: Storage>(pub S);
+
 #[async_trait(?Send)]
-impl Storage for InMemoryStorage {
-    async fn read(&self, k: &str) -> Result<Option<String>> {
-        let guard = self.0.read().await;
-        Ok(guard.get(k).map(|v| v.to_string()))
+impl<S: Storage> Store for StorageAdapter<S> {
+    async fn get(&self, key: &str) -> Result<Option<String>, SxgError> {
+        let value = self.0.read(key).await.map_err(SxgError::Storage)?;
+        Ok(value.filter(|value| !value.is_empty()))
     }
-    async fn write(&self, k: &str, v: &str) -> Result<()> {
-        let mut guard = self.0.write().await;
-        guard.insert(k.to_string(), v.to_string());
-        Ok(())
+    async fn put(&self, key: &str, value: &str) -> Result<(), SxgError> {
+        self.0.write(key, value).await.map_err(SxgError::Storage)
+    }
+    async fn delete(&self, key: &str) -> Result<(), SxgError> {
+        self.0.write(key, "").await.map_err(SxgError::Storage)
+    }
+    async fn put_with_ttl(&self, key: &str, value: &str, _ttl: Duration) -> Result<(), SxgError> {
+        self.put(key, value).await
+    }
+}
+
+#[deprecated(note = "use `sxg_rs::runtime::store::InMemoryStore` instead")]
+pub struct InMemoryStorage(InMemoryStore);
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        InMemoryStorage(InMemoryStore::new(SystemTime::UNIX_EPOCH))
     }
 }
 
@@ -53,3 +79,66 @@ impl std::default::Default for InMemoryStorage {
         Self::new()
     }
 }
+
+#[async_trait(?Send)]
+impl Store for InMemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<String>, SxgError> {
+        self.0.get(key).await
+    }
+    async fn put(&self, key: &str, value: &str) -> Result<(), SxgError> {
+        self.0.put(key, value).await
+    }
+    async fn delete(&self, key: &str) -> Result<(), SxgError> {
+        self.0.delete(key).await
+    }
+    async fn put_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SxgError> {
+        self.0.put_with_ttl(key, value, ttl).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Runtime;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    #[tokio::test]
+    async fn reads_and_writes_store() {
+        let storage = InMemoryStorage::new();
+        assert_eq!(storage.read("a").await.unwrap(), None);
+        storage.write("a", "1").await.unwrap();
+        assert_eq!(storage.read("a").await.unwrap(), Some("1".to_string()));
+        assert_eq!(storage.get("a").await.unwrap(), Some("1".to_string()));
+        assert_eq!(InMemoryStorage::default().get("a").await.unwrap(), None);
+    }
+    #[derive(Default)]
+    struct MapStorage(RefCell<HashMap<String, String>>);
+    #[async_trait(?Send)]
+    impl Storage for MapStorage {
+        async fn read(&self, k: &str) -> Result<Option<String>> {
+            Ok(self.0.borrow().get(k).cloned())
+        }
+        async fn write(&self, k: &str, v: &str) -> Result<()> {
+            self.0.borrow_mut().insert(k.to_string(), v.to_string());
+            Ok(())
+        }
+    }
+    #[tokio::test]
+    async fn adapts_storage_to_store() {
+        let runtime = Runtime {
+            storage: Box::new(StorageAdapter(MapStorage::default())),
+            ..Default::default()
+        };
+        let store = &runtime.storage;
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.put("a", "1").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some("1".to_string()));
+        store
+            .put_with_ttl("b", "2", Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(store.get("b").await.unwrap(), Some("2".to_string()));
+        store.delete("a").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+    }
+}
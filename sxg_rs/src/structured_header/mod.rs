@@ -16,6 +16,9 @@
 
 mod item;
 mod parameterised_list;
+mod parser;
+pub mod rfc8941;
 
 pub use item::ShItem;
 pub use parameterised_list::{ParamItem, ShParamList};
+pub use parser::{is_identifier, parse_param_list, ParsedItem};
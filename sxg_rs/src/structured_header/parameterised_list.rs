@@ -22,6 +22,7 @@ pub struct ParamItem<'a> {
     parameters: Vec<(&'a str, Option<ShItem<'a>>)>,
 }
 
+#[derive(Default)]
 pub struct ShParamList<'a>(Vec<ParamItem<'a>>);
 
 impl<'a> ParamItem<'a> {
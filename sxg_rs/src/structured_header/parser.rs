@@ -0,0 +1,172 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{anyhow, Result};
+use nom::{
+    branch::alt,
+    bytes::complete::take_while,
+    character::complete::{char as char1, digit1, satisfy, space0},
+    combinator::{eof, map, map_res, opt, recognize},
+    multi::{many0, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+
+/// An item parsed from a header. Unlike `ShItem`, it owns its data, because
+/// a string may contain escaped characters.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ParsedItem {
+    ByteSequence(Vec<u8>),
+    Integer(u64),
+    String(String),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ParsedParamItem {
+    pub primary_id: String,
+    pub parameters: Vec<(String, Option<ParsedItem>)>,
+}
+
+// https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.5
+pub fn parse_param_list(input: &str) -> Result<Vec<ParsedParamItem>> {
+    terminated(
+        delimited(
+            space0,
+            separated_list1(tuple((space0, char1(','), space0)), param_item),
+            space0,
+        ),
+        eof,
+    )(input)
+    .map(|(_, items)| items)
+    .map_err(|e| anyhow!("Failed to parse parameterised list: {}", e))
+}
+
+fn param_item(input: &str) -> IResult<&str, ParsedParamItem> {
+    map(
+        pair(
+            identifier,
+            many0(preceded(
+                tuple((space0, char1(';'), space0)),
+                pair(identifier, opt(preceded(char1('='), item))),
+            )),
+        ),
+        |(primary_id, parameters)| ParsedParamItem {
+            primary_id: primary_id.to_string(),
+            parameters: parameters
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        },
+    )(input)
+}
+
+/// Returns whether `input` can be used as an identifier, such as the label of
+/// a parameterised item.
+pub fn is_identifier(input: &str) -> bool {
+    terminated(identifier, eof)(input).is_ok()
+}
+
+// https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.7
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_lowercase()),
+        take_while(|c: char| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '*' | '/')
+        }),
+    ))(input)
+}
+
+fn item(input: &str) -> IResult<&str, ParsedItem> {
+    alt((
+        map(byte_sequence, ParsedItem::ByteSequence),
+        map(integer, ParsedItem::Integer),
+        map(string, ParsedItem::String),
+    ))(input)
+}
+
+// https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.8
+fn integer(input: &str) -> IResult<&str, u64> {
+    map_res(digit1, str::parse)(input)
+}
+
+// https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.10
+fn string(input: &str) -> IResult<&str, String> {
+    let error = |rest| nom::Err::Error(nom::error::Error::new(rest, nom::error::ErrorKind::Char));
+    let (mut rest, _) = char1('"')(input)?;
+    let mut output = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('"') => return Ok((chars.as_str(), output)),
+            Some('\\') => match chars.next() {
+                Some(c @ ('"' | '\\')) => output.push(c),
+                _ => return Err(error(rest)),
+            },
+            Some(c @ '\u{20}'..='\u{7e}') => output.push(c),
+            _ => return Err(error(rest)),
+        }
+        rest = chars.as_str();
+    }
+}
+
+// https://tools.ietf.org/html/draft-ietf-httpbis-header-structure-10#section-4.2.11
+fn byte_sequence(input: &str) -> IResult<&str, Vec<u8>> {
+    map_res(
+        delimited(
+            char1('*'),
+            take_while(|c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=')),
+            char1('*'),
+        ),
+        base64::decode,
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parses_items() {
+        assert_eq!(
+            parse_param_list(r#"a;b=1;c="x\"\\y";d=*AQI=*;e, f"#).unwrap(),
+            vec![
+                ParsedParamItem {
+                    primary_id: "a".to_string(),
+                    parameters: vec![
+                        ("b".to_string(), Some(ParsedItem::Integer(1))),
+                        (
+                            "c".to_string(),
+                            Some(ParsedItem::String(r#"x"\y"#.to_string()))
+                        ),
+                        ("d".to_string(), Some(ParsedItem::ByteSequence(vec![1, 2]))),
+                        ("e".to_string(), None),
+                    ],
+                },
+                ParsedParamItem {
+                    primary_id: "f".to_string(),
+                    parameters: vec![],
+                },
+            ]
+        );
+    }
+    #[test]
+    fn rejects_malformed() {
+        assert!(parse_param_list("").is_err());
+        assert!(parse_param_list("A").is_err());
+        assert!(parse_param_list(r#"a;b="unterminated"#).is_err());
+        assert!(parse_param_list(r#"a;b="bad \escape""#).is_err());
+        assert!(parse_param_list("a;b=*not base64*").is_err());
+        assert!(parse_param_list("a;b=-1").is_err());
+        assert!(parse_param_list("a,").is_err());
+    }
+}
@@ -0,0 +1,650 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured field values, as defined in
+//! https://www.rfc-editor.org/rfc/rfc8941.html. The `Signature` header of a
+//! `b3` signed exchange uses the older syntax of draft 10 instead, which is
+//! implemented by the parent module.
+
+use anyhow::{anyhow, Result};
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1},
+    character::complete::{char as char1, digit1, satisfy},
+    combinator::{eof, map, map_res, opt, recognize, value},
+    multi::{fold_many0, separated_list0},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+
+/// https://www.rfc-editor.org/rfc/rfc8941.html#section-3.3
+#[derive(Clone, Debug, PartialEq)]
+pub enum BareItem {
+    Integer(i64),
+    Decimal(f64),
+    String(String),
+    Token(String),
+    ByteSequence(Vec<u8>),
+    Boolean(bool),
+}
+
+/// A map that keeps the order in which keys are first inserted, as used for
+/// parameters and dictionaries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderedMap<V>(Vec<(String, V)>);
+
+impl<V> Default for OrderedMap<V> {
+    fn default() -> Self {
+        OrderedMap(Vec::new())
+    }
+}
+
+impl<V> OrderedMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Inserts a value, overwriting the value of an existing key in place.
+    pub fn insert(&mut self, key: impl Into<String>, value: V) {
+        let key = key.into();
+        match self.0.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((key, value)),
+        }
+    }
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v))
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// https://www.rfc-editor.org/rfc/rfc8941.html#section-3.1.2
+pub type Parameters = OrderedMap<BareItem>;
+
+/// https://www.rfc-editor.org/rfc/rfc8941.html#section-3.3
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub bare_item: BareItem,
+    pub params: Parameters,
+}
+
+impl Item {
+    pub fn new(bare_item: BareItem) -> Self {
+        Item {
+            bare_item,
+            params: Parameters::new(),
+        }
+    }
+    pub fn serialize(&self) -> Result<String> {
+        let mut output = String::new();
+        write_item(&mut output, self)?;
+        Ok(output)
+    }
+}
+
+/// https://www.rfc-editor.org/rfc/rfc8941.html#section-3.1.1
+#[derive(Clone, Debug, PartialEq)]
+pub struct InnerList {
+    pub items: Vec<Item>,
+    pub params: Parameters,
+}
+
+/// A member of a list, or a value of a dictionary.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListEntry {
+    Item(Item),
+    InnerList(InnerList),
+}
+
+/// https://www.rfc-editor.org/rfc/rfc8941.html#section-3.1
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct List(pub Vec<ListEntry>);
+
+impl List {
+    /// Serializes the list. An empty list is serialized as an empty string,
+    /// in which case the field should be omitted.
+    pub fn serialize(&self) -> Result<String> {
+        let mut output = String::new();
+        for (i, entry) in self.0.iter().enumerate() {
+            if i > 0 {
+                output.push_str(", ");
+            }
+            write_list_entry(&mut output, entry)?;
+        }
+        Ok(output)
+    }
+}
+
+/// https://www.rfc-editor.org/rfc/rfc8941.html#section-3.2
+pub type Dictionary = OrderedMap<ListEntry>;
+
+impl Dictionary {
+    /// Serializes the dictionary. An empty dictionary is serialized as an
+    /// empty string, in which case the field should be omitted.
+    pub fn serialize(&self) -> Result<String> {
+        let mut output = String::new();
+        for (i, (key, entry)) in self.iter().enumerate() {
+            if i > 0 {
+                output.push_str(", ");
+            }
+            write_key(&mut output, key)?;
+            match entry {
+                ListEntry::Item(Item {
+                    bare_item: BareItem::Boolean(true),
+                    params,
+                }) => write_params(&mut output, params)?,
+                entry => {
+                    output.push('=');
+                    write_list_entry(&mut output, entry)?;
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.1
+fn write_list_entry(output: &mut String, entry: &ListEntry) -> Result<()> {
+    match entry {
+        ListEntry::Item(item) => write_item(output, item),
+        ListEntry::InnerList(inner_list) => {
+            output.push('(');
+            for (i, item) in inner_list.items.iter().enumerate() {
+                if i > 0 {
+                    output.push(' ');
+                }
+                write_item(output, item)?;
+            }
+            output.push(')');
+            write_params(output, &inner_list.params)
+        }
+    }
+}
+
+fn write_item(output: &mut String, item: &Item) -> Result<()> {
+    write_bare_item(output, &item.bare_item)?;
+    write_params(output, &item.params)
+}
+
+fn write_params(output: &mut String, params: &Parameters) -> Result<()> {
+    for (key, value) in params.iter() {
+        output.push(';');
+        write_key(output, key)?;
+        if *value != BareItem::Boolean(true) {
+            output.push('=');
+            write_bare_item(output, value)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_key(output: &mut String, key: &str) -> Result<()> {
+    if !is_key(key) {
+        return Err(anyhow!("\"{}\" is not a valid structured field key", key));
+    }
+    output.push_str(key);
+    Ok(())
+}
+
+const MAX_INTEGER: i64 = 999_999_999_999_999;
+const MAX_DECIMAL_INTEGER_DIGITS: usize = 12;
+
+fn write_bare_item(output: &mut String, bare_item: &BareItem) -> Result<()> {
+    match bare_item {
+        BareItem::Integer(x) => {
+            if !(-MAX_INTEGER..=MAX_INTEGER).contains(x) {
+                return Err(anyhow!("Integer {} is out of range", x));
+            }
+            output.push_str(&x.to_string());
+        }
+        BareItem::Decimal(x) => output.push_str(&serialize_decimal(*x)?),
+        BareItem::String(x) => {
+            output.push('"');
+            for c in x.chars() {
+                match c {
+                    '\\' | '"' => {
+                        output.push('\\');
+                        output.push(c);
+                    }
+                    '\u{20}'..='\u{7e}' => output.push(c),
+                    _ => return Err(anyhow!("String {:?} has a non-printable character", x)),
+                }
+            }
+            output.push('"');
+        }
+        BareItem::Token(x) => {
+            if terminated(token, eof)(x.as_str()).is_err() {
+                return Err(anyhow!("\"{}\" is not a valid token", x));
+            }
+            output.push_str(x);
+        }
+        BareItem::ByteSequence(x) => {
+            output.push(':');
+            output.push_str(&::base64::encode(x));
+            output.push(':');
+        }
+        BareItem::Boolean(x) => output.push_str(if *x { "?1" } else { "?0" }),
+    }
+    Ok(())
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.1.5
+fn serialize_decimal(x: f64) -> Result<String> {
+    // Rounds to thousandths, with ties to even.
+    let scaled = x * 1000.0;
+    let mut thousandths = scaled.round();
+    if (scaled - scaled.trunc()).abs() == 0.5 && thousandths % 2.0 != 0.0 {
+        thousandths -= scaled.signum();
+    }
+    let integer = (thousandths / 1000.0).trunc().abs();
+    if !integer.is_finite() || integer >= 10f64.powi(MAX_DECIMAL_INTEGER_DIGITS as i32) {
+        return Err(anyhow!("Decimal {} is out of range", x));
+    }
+    let thousandths = thousandths as i64;
+    let fraction = format!("{:03}", (thousandths % 1000).abs());
+    let fraction = fraction.trim_end_matches('0');
+    Ok(format!(
+        "{}{}.{}",
+        if thousandths < 0 { "-" } else { "" },
+        (thousandths / 1000).abs(),
+        if fraction.is_empty() { "0" } else { fraction }
+    ))
+}
+
+/// Parses a list field.
+pub fn parse_list(input: &str) -> Result<List> {
+    parse_field(
+        input,
+        map(
+            separated_list0(tuple((ows, char1(','), ows)), list_entry),
+            List,
+        ),
+    )
+}
+
+/// Parses a dictionary field. A key that appears again overwrites the
+/// earlier value.
+pub fn parse_dictionary(input: &str) -> Result<Dictionary> {
+    parse_field(
+        input,
+        map(
+            separated_list0(tuple((ows, char1(','), ows)), dictionary_member),
+            |members| {
+                let mut dictionary = Dictionary::new();
+                for (key, entry) in members {
+                    dictionary.insert(key, entry);
+                }
+                dictionary
+            },
+        ),
+    )
+}
+
+/// Parses an item field.
+pub fn parse_item(input: &str) -> Result<Item> {
+    parse_field(input, item)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2
+fn parse_field<'a, T>(
+    input: &'a str,
+    parser: impl FnMut(&'a str) -> IResult<&'a str, T>,
+) -> Result<T> {
+    terminated(delimited(sp, parser, sp), eof)(input)
+        .map(|(_, output)| output)
+        .map_err(|e| anyhow!("Failed to parse structured field: {}", e))
+}
+
+fn sp(input: &str) -> IResult<&str, &str> {
+    take_while(|c| c == ' ')(input)
+}
+
+fn ows(input: &str) -> IResult<&str, &str> {
+    take_while(|c| c == ' ' || c == '\t')(input)
+}
+
+fn dictionary_member(input: &str) -> IResult<&str, (&str, ListEntry)> {
+    pair(
+        key,
+        alt((
+            preceded(char1('='), list_entry),
+            map(params, |params| {
+                ListEntry::Item(Item {
+                    bare_item: BareItem::Boolean(true),
+                    params,
+                })
+            }),
+        )),
+    )(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.1.1
+fn list_entry(input: &str) -> IResult<&str, ListEntry> {
+    alt((
+        map(inner_list, ListEntry::InnerList),
+        map(item, ListEntry::Item),
+    ))(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.1.2
+fn inner_list(input: &str) -> IResult<&str, InnerList> {
+    map(
+        pair(
+            delimited(
+                pair(char1('('), sp),
+                separated_list0(take_while1(|c| c == ' '), item),
+                pair(sp, char1(')')),
+            ),
+            params,
+        ),
+        |(items, params)| InnerList { items, params },
+    )(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.3
+fn item(input: &str) -> IResult<&str, Item> {
+    map(pair(bare_item, params), |(bare_item, params)| Item {
+        bare_item,
+        params,
+    })(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.3.2
+fn params(input: &str) -> IResult<&str, Parameters> {
+    fold_many0(
+        preceded(
+            pair(char1(';'), sp),
+            pair(key, opt(preceded(char1('='), bare_item))),
+        ),
+        Parameters::new,
+        |mut params, (key, value)| {
+            params.insert(key, value.unwrap_or(BareItem::Boolean(true)));
+            params
+        },
+    )(input)
+}
+
+fn is_key(input: &str) -> bool {
+    terminated(key, eof)(input).is_ok()
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.3.3
+fn key(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_lowercase() || c == '*'),
+        take_while(|c: char| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.' | '*')
+        }),
+    ))(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.3.1
+fn bare_item(input: &str) -> IResult<&str, BareItem> {
+    alt((
+        number,
+        map(string, BareItem::String),
+        map(token, |x| BareItem::Token(x.to_string())),
+        map(byte_sequence, BareItem::ByteSequence),
+        map(boolean, BareItem::Boolean),
+    ))(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.4
+fn number(input: &str) -> IResult<&str, BareItem> {
+    map_res(
+        tuple((
+            recognize(pair(opt(char1('-')), digit1)),
+            opt(preceded(char1('.'), digit1)),
+        )),
+        |(integer, fraction): (&str, Option<&str>)| {
+            let digits = integer.trim_start_matches('-').len();
+            match fraction {
+                None if digits <= 15 => integer.parse().map(BareItem::Integer).map_err(|_| ()),
+                Some(fraction) if digits <= MAX_DECIMAL_INTEGER_DIGITS && fraction.len() <= 3 => {
+                    format!("{}.{}", integer, fraction)
+                        .parse()
+                        .map(BareItem::Decimal)
+                        .map_err(|_| ())
+                }
+                _ => Err(()),
+            }
+        },
+    )(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.5
+fn string(input: &str) -> IResult<&str, String> {
+    let error = |rest| nom::Err::Error(nom::error::Error::new(rest, nom::error::ErrorKind::Char));
+    let (mut rest, _) = char1('"')(input)?;
+    let mut output = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('"') => return Ok((chars.as_str(), output)),
+            Some('\\') => match chars.next() {
+                Some(c @ ('"' | '\\')) => output.push(c),
+                _ => return Err(error(rest)),
+            },
+            Some(c @ '\u{20}'..='\u{7e}') => output.push(c),
+            _ => return Err(error(rest)),
+        }
+        rest = chars.as_str();
+    }
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.6
+fn token(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '*'),
+        take_while(|c| crate::http_parser::is_tchar(c) || matches!(c, ':' | '/')),
+    ))(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.7
+fn byte_sequence(input: &str) -> IResult<&str, Vec<u8>> {
+    map_res(
+        delimited(
+            char1(':'),
+            take_while(|c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=')),
+            char1(':'),
+        ),
+        base64::decode,
+    )(input)
+}
+
+// https://www.rfc-editor.org/rfc/rfc8941.html#section-4.2.8
+fn boolean(input: &str) -> IResult<&str, bool> {
+    alt((value(true, tag("?1")), value(false, tag("?0"))))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn token_item(x: &str) -> Item {
+        Item::new(BareItem::Token(x.to_string()))
+    }
+    fn string_item(x: &str) -> Item {
+        Item::new(BareItem::String(x.to_string()))
+    }
+    // Checks that `input` parses, and serializes to `canonical`.
+    fn round_trips_list(input: &str, canonical: &str) -> List {
+        let list = parse_list(input).unwrap();
+        assert_eq!(list.serialize().unwrap(), canonical);
+        list
+    }
+    fn round_trips_dictionary(input: &str, canonical: &str) -> Dictionary {
+        let dictionary = parse_dictionary(input).unwrap();
+        assert_eq!(dictionary.serialize().unwrap(), canonical);
+        dictionary
+    }
+    // The examples in https://www.rfc-editor.org/rfc/rfc8941.html#section-3.
+    #[test]
+    fn parses_lists() {
+        assert_eq!(
+            round_trips_list("sugar, tea, rum", "sugar, tea, rum"),
+            List(vec![
+                ListEntry::Item(token_item("sugar")),
+                ListEntry::Item(token_item("tea")),
+                ListEntry::Item(token_item("rum")),
+            ])
+        );
+        let list = round_trips_list(
+            r#"("foo" "bar"), ("baz"), ("bat" "one"), ()"#,
+            r#"("foo" "bar"), ("baz"), ("bat" "one"), ()"#,
+        );
+        assert_eq!(
+            list.0[0],
+            ListEntry::InnerList(InnerList {
+                items: vec![string_item("foo"), string_item("bar")],
+                params: Parameters::new(),
+            })
+        );
+        let list = round_trips_list(
+            r#"("foo"; a=1;b=2);lvl=5, ("bar" "baz");lvl=1"#,
+            r#"("foo";a=1;b=2);lvl=5, ("bar" "baz");lvl=1"#,
+        );
+        match &list.0[0] {
+            ListEntry::InnerList(inner_list) => {
+                assert_eq!(inner_list.params.get("lvl"), Some(&BareItem::Integer(5)));
+                assert_eq!(
+                    inner_list.items[0].params.get("b"),
+                    Some(&BareItem::Integer(2))
+                );
+            }
+            entry => panic!("Unexpected {:?}", entry),
+        }
+        round_trips_list(
+            r#"abc;a=1;b=2; cde_456, (ghi;jk=4 l);q="9";r=w"#,
+            r#"abc;a=1;b=2;cde_456, (ghi;jk=4 l);q="9";r=w"#,
+        );
+        assert_eq!(parse_list("").unwrap(), List(vec![]));
+    }
+    #[test]
+    fn parses_dictionaries() {
+        let dictionary = round_trips_dictionary(
+            r#"en="Applepie", da=:w4ZibGV0w6ZydGU=:"#,
+            r#"en="Applepie", da=:w4ZibGV0w6ZydGU=:"#,
+        );
+        assert_eq!(
+            dictionary.get("da"),
+            Some(&ListEntry::Item(Item::new(BareItem::ByteSequence(
+                "Æbletærte".as_bytes().to_vec()
+            ))))
+        );
+        let dictionary = round_trips_dictionary("a=?0, b, c; foo=bar", "a=?0, b, c;foo=bar");
+        assert_eq!(
+            dictionary.get("b"),
+            Some(&ListEntry::Item(Item::new(BareItem::Boolean(true))))
+        );
+        round_trips_dictionary(
+            "rating=1.5, feelings=(joy sadness)",
+            "rating=1.5, feelings=(joy sadness)",
+        );
+        round_trips_dictionary(
+            "a=(1 2), b=3, c=4;aa=bb, d=(5 6);valid",
+            "a=(1 2), b=3, c=4;aa=bb, d=(5 6);valid",
+        );
+        // A repeated key overwrites the earlier value in place.
+        round_trips_dictionary("a=1, b=2, a=3", "a=3, b=2");
+    }
+    #[test]
+    fn parses_items() {
+        let item = parse_item("5; foo=bar").unwrap();
+        assert_eq!(item.bare_item, BareItem::Integer(5));
+        assert_eq!(
+            item.params.get("foo"),
+            Some(&BareItem::Token("bar".to_string()))
+        );
+        assert_eq!(item.serialize().unwrap(), "5;foo=bar");
+        for (input, bare_item) in [
+            ("42", BareItem::Integer(42)),
+            ("-42", BareItem::Integer(-42)),
+            ("4.5", BareItem::Decimal(4.5)),
+            (
+                r#""hello world""#,
+                BareItem::String("hello world".to_string()),
+            ),
+            ("foo123/456", BareItem::Token("foo123/456".to_string())),
+            (
+                ":cHJldGVuZCB0aGlzIGlzIGJpbmFyeSBjb250ZW50Lg==:",
+                BareItem::ByteSequence(b"pretend this is binary content.".to_vec()),
+            ),
+            ("?1", BareItem::Boolean(true)),
+        ] {
+            let item = parse_item(input).unwrap();
+            assert_eq!(item, Item::new(bare_item), "{}", input);
+            assert_eq!(item.serialize().unwrap(), input);
+        }
+    }
+    #[test]
+    fn rejects_malformed() {
+        for input in [
+            r#""unterminated"#,
+            r#""bad \escape""#,
+            "a,",
+            "1234567890123456",
+            "1.2345",
+            "1234567890123.4",
+            "Key=1",
+            "(a b",
+            ":not base64:",
+            "?2",
+        ] {
+            assert!(
+                parse_list(input).is_err() && parse_dictionary(input).is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+    #[test]
+    fn serializes_decimals() {
+        for (x, expected) in [
+            (1.5, "1.5"),
+            (-0.25, "-0.25"),
+            (2.0, "2.0"),
+            (1.23456, "1.235"),
+            (0.0001, "0.0"),
+        ] {
+            assert_eq!(
+                Item::new(BareItem::Decimal(x)).serialize().unwrap(),
+                expected
+            );
+        }
+        assert!(Item::new(BareItem::Decimal(1e12)).serialize().is_err());
+        assert!(Item::new(BareItem::Decimal(f64::NAN)).serialize().is_err());
+    }
+    #[test]
+    fn rejects_unserializable() {
+        assert!(Item::new(BareItem::Integer(MAX_INTEGER + 1))
+            .serialize()
+            .is_err());
+        assert!(Item::new(BareItem::String("é".to_string()))
+            .serialize()
+            .is_err());
+        assert!(Item::new(BareItem::Token("1a".to_string()))
+            .serialize()
+            .is_err());
+        let mut dictionary = Dictionary::new();
+        dictionary.insert("Key", ListEntry::Item(token_item("a")));
+        assert!(dictionary.serialize().is_err());
+    }
+}
@@ -12,7 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::{Error, Result};
+use crate::SxgError;
+use anyhow::{anyhow, Error, Result};
+use std::io::Write;
+
+const MAGIC: &[u8] = b"sxg1-b3\0";
+// https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#name-application-signed-exchange
+const SIG_LEN_LIMIT: usize = 16384;
+const HEADER_LEN_LIMIT: usize = 524288;
 
 // https://wicg.github.io/webpackage/draft-yasskin-httpbis-origin-signed-exchanges-impl.html#application-signed-exchange
 pub fn build(
@@ -21,8 +28,26 @@ pub fn build(
     signed_headers: &[u8],
     payload_body: &[u8],
 ) -> Result<Vec<u8>> {
-    // https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#name-application-signed-exchange
-    const SIG_LEN_LIMIT: usize = 16384;
+    let mut sxg = vec![];
+    write(
+        &mut sxg,
+        fallback_url,
+        signature,
+        signed_headers,
+        payload_body,
+    )?;
+    Ok(sxg)
+}
+
+/// Like `build`, but writes the signed exchange to `writer`, so that the
+/// payload is not copied into another buffer.
+pub fn write<W: Write>(
+    writer: &mut W,
+    fallback_url: &str,
+    signature: &[u8],
+    signed_headers: &[u8],
+    payload_body: &[u8],
+) -> Result<()> {
     let sig_len = signature.len();
     if sig_len > SIG_LEN_LIMIT {
         return Err(Error::msg(format!(
@@ -30,7 +55,6 @@ pub fn build(
             sig_len, SIG_LEN_LIMIT
         )));
     }
-    const HEADER_LEN_LIMIT: usize = 524288;
     let header_len = signed_headers.len();
     if header_len > HEADER_LEN_LIMIT {
         return Err(Error::msg(format!(
@@ -38,8 +62,8 @@ pub fn build(
             header_len, HEADER_LEN_LIMIT
         )));
     }
-    Ok([
-        "sxg1-b3\0".as_bytes(),
+    for part in [
+        MAGIC,
         &(fallback_url.len() as u16).to_be_bytes(),
         fallback_url.as_bytes(),
         (sig_len as u32).to_be_bytes().get(1..4).unwrap(),
@@ -47,6 +71,222 @@ pub fn build(
         signature,
         signed_headers,
         payload_body,
-    ]
-    .concat())
+    ] {
+        writer.write_all(part)?;
+    }
+    Ok(())
+}
+
+/// The fields of a signed exchange, borrowed from its serialization.
+#[derive(Debug, Eq, PartialEq)]
+pub struct SignedExchangeParts<'a> {
+    pub fallback_url: &'a str,
+    /// The `Signature` header.
+    pub signature: &'a [u8],
+    /// The CBOR-encoded signed headers.
+    pub signed_headers: &'a [u8],
+    /// The MICE-encoded payload body.
+    pub payload_body: &'a [u8],
+}
+
+/// Splits a signed exchange created by `build` into its fields. Only the
+/// framing is checked; the fields themselves are not parsed.
+pub fn parse(sxg: &[u8]) -> Result<SignedExchangeParts<'_>, SxgError> {
+    parse_impl(sxg).map_err(SxgError::Sxg)
+}
+
+fn parse_impl(sxg: &[u8]) -> Result<SignedExchangeParts<'_>> {
+    let input = sxg
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| Error::msg("The signed exchange does not start with sxg1-b3"))?;
+    let (fallback_url_len, input) = split_at(input, 2)?;
+    let (fallback_url, input) = split_at(input, read_length(fallback_url_len))?;
+    let fallback_url = std::str::from_utf8(fallback_url)?;
+    let (sig_len, input) = split_at(input, 3)?;
+    let sig_len = read_length(sig_len);
+    if sig_len > SIG_LEN_LIMIT {
+        return Err(Error::msg(format!(
+            "sigLength {} is larger than the limit {}",
+            sig_len, SIG_LEN_LIMIT
+        )));
+    }
+    let (header_len, input) = split_at(input, 3)?;
+    let header_len = read_length(header_len);
+    if header_len > HEADER_LEN_LIMIT {
+        return Err(Error::msg(format!(
+            "headerLength {} is larger than the limit {}",
+            header_len, HEADER_LEN_LIMIT
+        )));
+    }
+    let (signature, input) = split_at(input, sig_len)?;
+    let (signed_headers, payload_body) = split_at(input, header_len)?;
+    Ok(SignedExchangeParts {
+        fallback_url,
+        signature,
+        signed_headers,
+        payload_body,
+    })
+}
+
+/// Decodes the CBOR-encoded signed headers of a signed exchange into
+/// `(name, value)` pairs, in the canonical order they are encoded in.
+pub fn parse_signed_headers(signed_headers: &[u8]) -> Result<Vec<(String, String)>, SxgError> {
+    parse_signed_headers_impl(signed_headers).map_err(SxgError::Sxg)
+}
+
+fn parse_signed_headers_impl(signed_headers: &[u8]) -> Result<Vec<(String, String)>> {
+    use crate::cbor::DataItem;
+    let fields = match DataItem::parse(signed_headers)? {
+        DataItem::Map(fields) => fields,
+        _ => return Err(Error::msg("The signed headers are not a CBOR map")),
+    };
+    fields
+        .into_iter()
+        .map(|field| match field {
+            (DataItem::ByteString(name), DataItem::ByteString(value)) => Ok((
+                std::str::from_utf8(name)?.to_string(),
+                std::str::from_utf8(value)?.to_string(),
+            )),
+            _ => Err(Error::msg(
+                "The signed header is not a pair of byte strings",
+            )),
+        })
+        .collect()
+}
+
+/// Builds the CBOR-encoded response map of a signed exchange, which holds
+/// the `:status` pseudo-header and the response headers, in the canonical
+/// order of CBOR map keys. This is the signed headers of `sxg1-b3`.
+pub fn build_response_map(status_code: u16, headers: &[(&str, &str)]) -> Vec<u8> {
+    let status_code = status_code.to_string();
+    let mut fields = headers.to_vec();
+    fields.push((":status", &status_code));
+    build_header_map(fields)
+}
+
+/// Reads the status code and the other headers of a response map built by
+/// `build_response_map`.
+pub fn read_response_map(response_map: &[u8]) -> Result<(u16, Vec<(String, String)>), SxgError> {
+    read_response_map_impl(response_map).map_err(SxgError::Sxg)
+}
+
+fn read_response_map_impl(response_map: &[u8]) -> Result<(u16, Vec<(String, String)>)> {
+    let mut status_code = None;
+    let mut headers = vec![];
+    for (name, value) in parse_signed_headers_impl(response_map)? {
+        if name == ":status" {
+            status_code = Some(value.parse()?);
+        } else if name.starts_with(':') {
+            return Err(anyhow!("Unexpected pseudo-header {}", name));
+        } else {
+            headers.push((name, value));
+        }
+    }
+    let status_code = status_code.ok_or_else(|| Error::msg("The response map has no :status"))?;
+    Ok((status_code, headers))
+}
+
+// Serializes the header fields, including pseudo-headers, into a CBOR map of
+// byte strings.
+pub(crate) fn build_header_map(mut fields: Vec<(&str, &str)>) -> Vec<u8> {
+    use crate::cbor::DataItem;
+    // `DataItem::Map` also sorts its keys, but sorting here keeps the order
+    // explicit rather than relying on the CBOR encoder.
+    crate::headers::sort_canonically(&mut fields);
+    let cbor_data = DataItem::Map(
+        fields
+            .iter()
+            .map(|(key, value)| {
+                (
+                    DataItem::ByteString(key.as_bytes()),
+                    DataItem::ByteString(value.as_bytes()),
+                )
+            })
+            .collect(),
+    );
+    cbor_data.serialize()
+}
+
+// Reads a big-endian integer of at most 3 bytes.
+fn read_length(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |length, &byte| (length << 8) | byte as usize)
+}
+
+fn split_at(input: &[u8], len: usize) -> Result<(&[u8], &[u8])> {
+    if len <= input.len() {
+        Ok(input.split_at(len))
+    } else {
+        Err(Error::msg("Unexpected end of the signed exchange"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parses_built_sxg() {
+        let sxg = build("https://foo.com/", b"sig", b"\xa0", b"body").unwrap();
+        assert_eq!(
+            parse(&sxg).unwrap(),
+            SignedExchangeParts {
+                fallback_url: "https://foo.com/",
+                signature: b"sig",
+                signed_headers: b"\xa0",
+                payload_body: b"body",
+            }
+        );
+    }
+    #[test]
+    fn parses_signed_headers() {
+        // {h':status': h'200', h'digest': h'mi-sha256-03=x'}
+        let signed_headers = b"\xa2\x46digest\x4emi-sha256-03=x\x47:status\x43200";
+        assert_eq!(
+            parse_signed_headers(signed_headers).unwrap(),
+            vec![
+                ("digest".to_string(), "mi-sha256-03=x".to_string()),
+                (":status".to_string(), "200".to_string()),
+            ]
+        );
+        assert!(parse_signed_headers(b"\xa1\x00\x00").is_err());
+        assert!(parse_signed_headers(b"\x80").is_err());
+    }
+    #[test]
+    fn builds_response_map() {
+        let response_map = build_response_map(
+            404,
+            &[("content-type", "text/html"), ("digest", "mi-sha256-03=x")],
+        );
+        // Shorter keys come first, so `:status` is between the others.
+        assert_eq!(
+            parse_signed_headers(&response_map).unwrap(),
+            vec![
+                ("digest".to_string(), "mi-sha256-03=x".to_string()),
+                (":status".to_string(), "404".to_string()),
+                ("content-type".to_string(), "text/html".to_string()),
+            ]
+        );
+        assert_eq!(
+            read_response_map(&response_map).unwrap(),
+            (
+                404,
+                vec![
+                    ("digest".to_string(), "mi-sha256-03=x".to_string()),
+                    ("content-type".to_string(), "text/html".to_string()),
+                ]
+            )
+        );
+        assert!(read_response_map(&build_header_map(vec![(":method", "GET")])).is_err());
+        assert!(read_response_map(&build_header_map(vec![])).is_err());
+    }
+    #[test]
+    fn rejects_malformed() {
+        let sxg = build("https://foo.com/", b"sig", b"\xa0", b"").unwrap();
+        assert!(parse(b"sxg1-b2\0").is_err());
+        for len in 0..sxg.len() {
+            assert!(parse(&sxg[..len]).is_err());
+        }
+        assert!(parse(&sxg).is_ok());
+    }
 }
@@ -12,9 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::LinkHint;
 use crate::header_integrity::HeaderIntegrityFetcher;
 use crate::headers::Headers;
-use anyhow::{anyhow, Result};
+use crate::SxgError;
+use anyhow::{Error, Result};
+use std::collections::BTreeSet;
+use std::io::Read;
 use url::Url;
 
 #[cfg(all(target_family = "wasm", feature = "wasm"))]
@@ -22,9 +26,53 @@ pub fn console_log(msg: &str) {
     web_sys::console::log_1(&msg.into());
 }
 
+// Logs go to stderr, so they don't mix with the output of the command-line
+// tools, such as `--output json`.
 #[cfg(not(all(target_family = "wasm", feature = "wasm")))]
 pub fn console_log(msg: &str) {
-    println!("{}", msg);
+    eprintln!("{}", msg);
+}
+
+// JavaScript workers have no tokio timer, so this waits on `setTimeout`.
+#[cfg(all(target_family = "wasm", feature = "wasm"))]
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    use wasm_bindgen::{JsCast, JsValue};
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let set_timeout = js_sys::Reflect::get(&js_sys::global(), &"setTimeout".into())
+            .ok()
+            .and_then(|f| f.dyn_into::<js_sys::Function>().ok());
+        let _ = match set_timeout {
+            Some(set_timeout) => set_timeout.call2(
+                &JsValue::NULL,
+                &resolve,
+                &JsValue::from_f64(duration.as_secs_f64() * 1000.0),
+            ),
+            None => resolve.call0(&JsValue::NULL),
+        };
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+// Fastly Compute runs a tokio runtime without the timer driver, so this
+// yields to the other futures until `duration` has passed, instead of
+// blocking the only thread.
+#[cfg(all(target_family = "wasm", not(feature = "wasm")))]
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    let deadline = std::time::Instant::now() + duration;
+    futures::future::poll_fn(|cx| {
+        if std::time::Instant::now() < deadline {
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        } else {
+            std::task::Poll::Ready(())
+        }
+    })
+    .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    tokio::time::sleep(duration).await;
 }
 
 // This is unused in main branch, but it is useful during development.
@@ -45,6 +93,34 @@ macro_rules! console_dbg {
 #[allow(unused_imports)]
 pub(crate) use console_dbg;
 
+/// Removes a leading UTF-8 byte order mark and surrounding whitespace, which
+/// some text editors add when saving a file.
+pub fn trim_text_file(text: &str) -> &str {
+    text.trim_start_matches('\u{feff}').trim()
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Decodes the content of a text file as UTF-8, after decompressing it if it
+/// starts with the gzip magic number, as some secrets pipelines store files
+/// compressed.
+pub fn decode_text_file(content: &[u8]) -> Result<String, SxgError> {
+    decode_text_file_impl(content).map_err(SxgError::Config)
+}
+
+fn decode_text_file_impl(content: &[u8]) -> Result<String> {
+    let content = if content.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(content)
+            .read_to_end(&mut decompressed)
+            .map_err(|e| Error::new(e).context("Failed to decompress gzip file"))?;
+        decompressed
+    } else {
+        content.to_vec()
+    };
+    String::from_utf8(content).map_err(|e| Error::new(e).context("The file is not UTF-8"))
+}
+
 #[cfg(feature = "wasm")]
 pub fn to_js_error<E: std::fmt::Debug>(e: E) -> wasm_bindgen::JsValue {
     // TODO: The `JsValue::from_str()` constructs a `string` in JavaScript.
@@ -53,23 +129,31 @@ pub fn to_js_error<E: std::fmt::Debug>(e: E) -> wasm_bindgen::JsValue {
     wasm_bindgen::JsValue::from_str(&format!("{:?}", e))
 }
 
+/// Like `to_js_error`, but throws a JavaScript `Error` whose `sxgStatus`
+/// property is the `SxgStatus` of `e`.
+#[cfg(feature = "wasm")]
+pub fn to_js_sxg_error(e: crate::SxgError) -> wasm_bindgen::JsValue {
+    let error = js_sys::Error::new(&format!("{:?}", e.inner()));
+    let _ = js_sys::Reflect::set(&error, &"sxgStatus".into(), &e.status().as_str().into());
+    error.into()
+}
+
 /// Given the return value from a JavaScript async function, waits for the
 /// JavaScript Promise ,and returns the resolved value.
 #[cfg(feature = "wasm")]
 pub async fn await_js_promise(
     result: Result<wasm_bindgen::JsValue, wasm_bindgen::JsValue>,
 ) -> Result<wasm_bindgen::JsValue> {
-    use std::convert::TryFrom;
+    use anyhow::anyhow;
     let value =
         result.map_err(|e| anyhow!("{:?}", e).context("JavaScript function throws an error"))?;
-    let promise = js_sys::Promise::try_from(value)
-        .map_err(|e| anyhow::Error::new(e).context("Expecting a JavaScript Promise"))?;
+    let promise = js_sys::Promise::from(value);
     wasm_bindgen_futures::JsFuture::from(promise)
         .await
         .map_err(|e| anyhow!("{:?}", e).context("JavaScript throws an error asynchronously"))
 }
 
-// #[warn(clippy::too_many_arguments)]
+#[allow(clippy::too_many_arguments)]
 pub async fn signed_headers_and_payload(
     fallback_url: &Url,
     status_code: u16,
@@ -77,25 +161,49 @@ pub async fn signed_headers_and_payload(
     payload_body: &[u8],
     header_integrity_fetcher: &mut dyn HeaderIntegrityFetcher,
     skip_process_link: bool,
+    link_hints: &[LinkHint],
+    allowed_subresource_origins: &BTreeSet<String>,
 ) -> Result<(Vec<u8>, Vec<u8>)> {
-    if status_code != 200 {
-        return Err(anyhow!("The resource status code is {}.", status_code));
-    }
     // 16384 is the max mice record size allowed by SXG spec.
     // https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#section-3.5-7.9.1
-    let (mice_digest, payload_body) = crate::mice::calculate(payload_body, 16384);
-    let signed_headers = payload_headers
-        .get_signed_headers_bytes(
+    // The link header may wait on subresource fetches, so start those before
+    // the CPU-bound MICE encoding, and join the two afterwards.
+    let (link, (mice_digest, payload_body)) = futures::join!(
+        payload_headers.get_signed_link(
             fallback_url,
-            status_code,
-            &mice_digest,
             header_integrity_fetcher,
             skip_process_link,
-        )
-        .await;
+            link_hints,
+            allowed_subresource_origins,
+        ),
+        async { crate::mice::calculate(payload_body, 16384) },
+    );
+    let signed_headers =
+        payload_headers.get_signed_headers_bytes_with_link(&link, status_code, &mice_digest);
     Ok((signed_headers, payload_body))
 }
 
+/// Like `signed_headers_and_payload` with `skip_process_link`, but without
+/// I/O, so it doesn't need to be async.
+pub fn signed_headers_and_payload_blocking(
+    fallback_url: &Url,
+    status_code: u16,
+    payload_headers: &Headers,
+    payload_body: &[u8],
+    link_hints: &[LinkHint],
+    allowed_subresource_origins: &BTreeSet<String>,
+) -> (Vec<u8>, Vec<u8>) {
+    let link = payload_headers.get_unprocessed_signed_link(
+        fallback_url,
+        link_hints,
+        allowed_subresource_origins,
+    );
+    let (mice_digest, payload_body) = crate::mice::calculate(payload_body, 16384);
+    let signed_headers =
+        payload_headers.get_signed_headers_bytes_with_link(&link, status_code, &mice_digest);
+    (signed_headers, payload_body)
+}
+
 #[cfg(test)]
 pub mod tests {
     use futures::{
@@ -129,6 +237,92 @@ SFfkmh8Fc2QXpbbaK5AQfnQpkDHV
     //   openssl x509 -in - -outform DER | openssl dgst -sha256 -binary | base64 | tr /+ _- | tr -d =
     pub const SELF_SIGNED_CERT_SHA256: &str = "Lz2EMcys4NR9FP0yYnuS5Uw8xM3gbVAOM2lwSBU9qX0";
 
+    // A self-signed certificate that expires at 2020-09-15T00:00:00Z (1600128000).
+    // Generated like `SELF_SIGNED_CERT_PEM`, but with
+    //   openssl req -x509 -new -key "$KEY" -subj '/CN=example.org/O=Test/C=US' \
+    //     -not_before 20200901000000Z -not_after 20200915000000Z \
+    //     -addext "1.3.6.1.4.1.11129.2.1.22 = ASN1:NULL" -addext "subjectAltName=DNS:example.org"
+    pub const NEAR_EXPIRY_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIB4jCCAYigAwIBAgITT4+k5PHy2xF64+W/xiaScSEV+TAKBggqhkjOPQQDAjAy
+MRQwEgYDVQQDDAtleGFtcGxlLm9yZzENMAsGA1UECgwEVGVzdDELMAkGA1UEBhMC
+VVMwHhcNMjAwOTAxMDAwMDAwWhcNMjAwOTE1MDAwMDAwWjAyMRQwEgYDVQQDDAtl
+eGFtcGxlLm9yZzENMAsGA1UECgwEVGVzdDELMAkGA1UEBhMCVVMwWTATBgcqhkjO
+PQIBBggqhkjOPQMBBwNCAARHa3G6hbcUpNiPtpSQj1/VYDhh4nXhKDAPks72UH7w
+naG6vuu9kN7MaYkB1Jt2RCuRmFRmSSyR7ntfnPGvpRnlo30wezAdBgNVHQ4EFgQU
+YJsXhd8I8GgPvfzX0eWC83N6vyswHwYDVR0jBBgwFoAUYJsXhd8I8GgPvfzX0eWC
+83N6vyswDwYDVR0TAQH/BAUwAwEB/zAQBgorBgEEAdZ5AgEWBAIFADAWBgNVHREE
+DzANggtleGFtcGxlLm9yZzAKBggqhkjOPQQDAgNIADBFAiEA0aSJ7YFOfHCO6H55
+mr5GB1PwTJvwt5n1AWwS99KVR+kCIBykDCszVixu0hj+lmocE5YK2Lt3zUAbBsWp
+fFwqQs9y
+-----END CERTIFICATE-----
+    ";
+
+    // A self-signed certificate, valid from 2020-09-01 to 2030-09-01, whose
+    // subjectAltName covers several hosts.
+    // Generated like `NEAR_EXPIRY_CERT_PEM`, but with
+    //   openssl req -x509 -new -key "$KEY" -subj '/CN=example.com/O=Test/C=US' \
+    //     -not_before 20200901000000Z -not_after 20300901000000Z \
+    //     -addext "1.3.6.1.4.1.11129.2.1.22 = ASN1:NULL" \
+    //     -addext "subjectAltName=DNS:example.com,DNS:www.example.com,DNS:*.cdn.example.com,IP:192.0.2.1"
+    pub const MULTI_SAN_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIICDzCCAbWgAwIBAgIUH5W2GyiJj3cvIKMWLgvuMHw6EckwCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5jb20xDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTIwMDkwMTAwMDAwMFoXDTMwMDkwMTAwMDAwMFowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5jb20xDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEWfr/Hrspzf2QT88U1CZX+fU84SyNBAzjdsR33Dfj
+5zLsYpWCA6F07URhr5TFwRtPz74VvUbXzfNGbNfsKU/VQKOBqDCBpTAdBgNVHQ4E
+FgQUAuEorOFt/toQBHh4tnwP44tiSQkwHwYDVR0jBBgwFoAUAuEorOFt/toQBHh4
+tnwP44tiSQkwDwYDVR0TAQH/BAUwAwEB/zAQBgorBgEEAdZ5AgEWBAIFADBABgNV
+HREEOTA3ggtleGFtcGxlLmNvbYIPd3d3LmV4YW1wbGUuY29tghEqLmNkbi5leGFt
+cGxlLmNvbYcEwAACATAKBggqhkjOPQQDAgNIADBFAiBBSZLVNIjhJR0TF8BIuWDm
+oNgCli1P8ju+Vp5NzsEBSQIhAPZryi5EgdUrDVd1gdweLXJ9Bw/5pCIvDWkHqK3h
+s8CD
+-----END CERTIFICATE-----
+    ";
+
+    // A self-signed certificate for example.org, valid from 2020-09-01 to
+    // 2020-11-30, which outlives `NEAR_EXPIRY_CERT_PEM`.
+    // Generated like `NEAR_EXPIRY_CERT_PEM`, but with
+    //   -not_before 20200901000000Z -not_after 20201130000000Z
+    pub const FRESH_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIB4zCCAYmgAwIBAgIUdKySeMcDC2iULjjU+4BGOMlGR7owCgYIKoZIzj0EAwIw
+MjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYT
+AlVTMB4XDTIwMDkwMTAwMDAwMFoXDTIwMTEzMDAwMDAwMFowMjEUMBIGA1UEAwwL
+ZXhhbXBsZS5vcmcxDTALBgNVBAoMBFRlc3QxCzAJBgNVBAYTAlVTMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAEXYtpTB0uW/4eleI1tOPJFH8+BDdId6S4P1NoaL4e
+t5JElNsmgUIC4R2C3CWvTb7sJxmDeeFLBn89zI4Ug1Ho9qN9MHswHQYDVR0OBBYE
+FIdnt6BhhK7qT3GBcLwgu1ejnkZMMB8GA1UdIwQYMBaAFIdnt6BhhK7qT3GBcLwg
+u1ejnkZMMA8GA1UdEwEB/wQFMAMBAf8wEAYKKwYBBAHWeQIBFgQCBQAwFgYDVR0R
+BA8wDYILZXhhbXBsZS5vcmcwCgYIKoZIzj0EAwIDSAAwRQIga5xKACS50wkoJlMI
+MqyHAnt1iT4/kvDKE9/r8O7GzwcCIQCTHbWbxJduNcfSnjQgxq8I3T3JIAYB/8XC
+kTXnRhlLdw==
+-----END CERTIFICATE-----
+    ";
+    pub const FRESH_CERT_SHA256: &str = "UfMtB26y4QrD9dR96R0dn0j8raMD_GE9rDqcq3EpbNE";
+
+    // A self-signed certificate whose authorityInfoAccess names an OCSP
+    // responder. Generated with:
+    //   openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+    //     -keyout /dev/null -subj /CN=example.org -days 365 \
+    //     -addext "authorityInfoAccess=OCSP;URI:http://ocsp.example.org,caIssuers;URI:http://ca.example.org/issuer.der"
+    pub const AIA_CERT_PEM: &str = "
+-----BEGIN CERTIFICATE-----
+MIIB5zCCAYygAwIBAgIUaTNMluQ8ncnda5ACJzHvFzHzqTkwCgYIKoZIzj0EAwIw
+FjEUMBIGA1UEAwwLZXhhbXBsZS5vcmcwHhcNMjYxMDE2MTAwNDAyWhcNMjcxMDE2
+MTAwNDAyWjAWMRQwEgYDVQQDDAtleGFtcGxlLm9yZzBZMBMGByqGSM49AgEGCCqG
+SM49AwEHA0IABCTKdlXIGxGT7h7YzNwetw/AKrRBcCbA2ckgUJN9TwU1bYa1QH5o
+XtLFNl7MZijrP9aQ1Kf49Iz4mDVWIH1aKBmjgbcwgbQwHQYDVR0OBBYEFCzTb9a8
+ZOmaqAflw6CEEa4awd9qMB8GA1UdIwQYMBaAFCzTb9a8ZOmaqAflw6CEEa4awd9q
+MA8GA1UdEwEB/wQFMAMBAf8wYQYIKwYBBQUHAQEEVTBTMCMGCCsGAQUFBzABhhdo
+dHRwOi8vb2NzcC5leGFtcGxlLm9yZzAsBggrBgEFBQcwAoYgaHR0cDovL2NhLmV4
+YW1wbGUub3JnL2lzc3Vlci5kZXIwCgYIKoZIzj0EAwIDSQAwRgIhAKXGUwbO1tFd
+XMjKulkf0uZgA9kxdMtMEO4JHSqhrPuoAiEAvR64BCuge5J0TDM2gxHorftXlWaT
+TralbHKGHxJo8Ew=
+-----END CERTIFICATE-----";
+
     // Returns a future for the given state object. If multiple futures are created from the same
     // shared state, the first to be polled resolves after the second.
     pub fn out_of_order<'a, T: 'a, F: 'a + Fn() -> T + Send>(
@@ -160,7 +354,7 @@ SFfkmh8Fc2QXpbbaK5AQfnQpkDHV
     impl<T, F: Fn() -> T> Future for OutOfOrderFuture<T, F> {
         type Output = T;
         fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            let mut state = &mut *self.state.lock().unwrap();
+            let state = &mut *self.state.lock().unwrap();
             let first = state.first;
             state.first = false;
             println!("first = {}", first);
@@ -14,11 +14,11 @@
 
 use crate::crypto::CertificateChain;
 use crate::headers::AcceptFilter;
-use crate::http::HttpResponse;
+use crate::http::{HttpRequest, HttpResponse};
 use crate::process_html::ProcessHtmlOption;
 use crate::runtime::{js_runtime::JsRuntimeInitParams, Runtime};
-use crate::utils::to_js_error;
-use crate::SxgWorker;
+use crate::utils::{to_js_error, to_js_sxg_error};
+use crate::{SxgStatus, SxgWorker};
 use anyhow::Result;
 use js_sys::Function as JsFunction;
 use js_sys::Promise as JsPromise;
@@ -92,11 +92,39 @@ impl WasmWorker {
             Ok(JsValue::UNDEFINED)
         })
     }
+    #[wasm_bindgen(js_name=setOcsp)]
+    pub fn set_ocsp(
+        &self,
+        js_runtime: JsRuntimeInitParams,
+        certificate_basename: String,
+        ocsp_der: Vec<u8>,
+    ) -> JsPromise {
+        let worker = self.0.clone();
+        future_to_promise(async move {
+            let runtime = Runtime::try_from(js_runtime).map_err(to_js_error)?;
+            worker
+                .read()
+                .await
+                .set_ocsp(&runtime, &certificate_basename, &ocsp_der)
+                .await
+                .map_err(to_js_error)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+    #[wasm_bindgen(js_name=route)]
+    pub fn route(&self, req_url: String) -> JsPromise {
+        let worker = self.0.clone();
+        future_to_promise(async move {
+            let route = worker.read().await.config().route(&req_url);
+            JsValue::from_serde(&route).map_err(to_js_error)
+        })
+    }
     #[wasm_bindgen(js_name=servePresetContent)]
     pub fn serve_preset_content(
         &self,
         js_runtime: JsRuntimeInitParams,
         req_url: String,
+        accept_encoding: Option<String>,
     ) -> JsPromise {
         let worker = self.0.clone();
         future_to_promise(async move {
@@ -104,7 +132,7 @@ impl WasmWorker {
             Ok(worker
                 .read()
                 .await
-                .serve_preset_content(&runtime, &req_url)
+                .serve_preset_content(&runtime, &req_url, accept_encoding.as_deref())
                 .await
                 .map_or(JsValue::UNDEFINED, |preset_content| {
                     JsValue::from_serde(&preset_content).unwrap()
@@ -139,10 +167,19 @@ impl WasmWorker {
                 .read()
                 .await
                 .transform_payload_headers(fields)
-                .map_err(to_js_error)?;
+                .map_err(to_js_sxg_error)?;
             Ok(JsValue::UNDEFINED)
         })
     }
+    #[wasm_bindgen(js_name=sxgStatusHeader)]
+    pub fn sxg_status_header(&self, status: JsValue) -> JsPromise {
+        let worker = self.0.clone();
+        future_to_promise(async move {
+            let status: SxgStatus = status.into_serde().map_err(to_js_error)?;
+            let header = worker.read().await.sxg_status_header(status);
+            Ok(JsValue::from_serde(&header).unwrap())
+        })
+    }
     #[wasm_bindgen(js_name=processHtml)]
     pub fn process_html(&self, input: JsValue, option: JsValue) -> JsPromise {
         let worker = self.0.clone();
@@ -157,6 +194,21 @@ impl WasmWorker {
             JsValue::from_serde(&output).map_err(to_js_error)
         })
     }
+    #[wasm_bindgen(js_name=fetchOrigin)]
+    pub fn fetch_origin(&self, js_runtime: JsRuntimeInitParams, request: JsValue) -> JsPromise {
+        let worker = self.0.clone();
+        future_to_promise(async move {
+            let runtime = Runtime::try_from(js_runtime).map_err(to_js_error)?;
+            let request: HttpRequest = request.into_serde().map_err(to_js_error)?;
+            let response = worker
+                .read()
+                .await
+                .fetch_origin(&runtime, request)
+                .await
+                .map_err(to_js_sxg_error)?;
+            Ok(JsValue::from_serde(&response).unwrap())
+        })
+    }
     #[wasm_bindgen(js_name=createSignedExchange)]
     pub fn create_signed_exchange(
         &self,
@@ -173,12 +225,12 @@ impl WasmWorker {
             let worker = worker.read().await;
             let payload_headers = worker
                 .transform_payload_headers(payload_headers)
-                .map_err(to_js_error)?;
+                .map_err(to_js_sxg_error)?;
             let header_integrity_cache = crate::http_cache::js_http_cache::JsHttpCache {
                 get: options.header_integrity_get(),
                 put: options.header_integrity_put(),
             };
-            let sxg: HttpResponse = worker
+            let mut sxg: HttpResponse = worker
                 .create_signed_exchange(
                     &runtime,
                     crate::CreateSignedExchangeParams {
@@ -192,7 +244,11 @@ impl WasmWorker {
                     },
                 )
                 .await
-                .map_err(to_js_error)?;
+                .map_err(to_js_sxg_error)?
+                .into_response();
+            if let Some((name, value)) = worker.sxg_status_header(SxgStatus::Signed) {
+                sxg.headers.push((name.to_string(), value.to_string()));
+            }
             Ok(JsValue::from_serde(&sxg).unwrap())
         })
     }
@@ -0,0 +1,70 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use warp::Filter;
+
+/// Returns a filter that answers the HTTP-01 challenge of `token` at
+/// `<base_path>/.well-known/acme-challenge/<token>`, and rejects other paths.
+/// It can be mounted into an existing warp server, such as one behind a
+/// reverse proxy that routes the challenge through a path prefix.
+pub fn challenge_filter(
+    base_path: &str,
+    token: String,
+    answer: String,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    let base_path = base_path.trim_matches('/');
+    let expected_path = if base_path.is_empty() {
+        format!("/.well-known/acme-challenge/{}", token)
+    } else {
+        format!("/{}/.well-known/acme-challenge/{}", base_path, token)
+    };
+    warp::get()
+        .and(warp::path::full())
+        .and_then(move |path: warp::path::FullPath| {
+            let result = if path.as_str() == expected_path {
+                Ok(answer.clone())
+            } else {
+                Err(warp::reject::not_found())
+            };
+            async move { result }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn challenge_filter_matches_token() {
+        let filter = challenge_filter("/sxg/", "token".to_string(), "answer".to_string());
+        let get = |path: &'static str| warp::test::request().path(path).filter(&filter);
+        assert_eq!(
+            get("/sxg/.well-known/acme-challenge/token").await.unwrap(),
+            "answer"
+        );
+        assert!(get("/sxg/.well-known/acme-challenge/other").await.is_err());
+        assert!(get("/sxg/.well-known/acme-challenge/token/more")
+            .await
+            .is_err());
+        assert!(get("/.well-known/acme-challenge/token").await.is_err());
+        let filter = challenge_filter("", "token".to_string(), "answer".to_string());
+        assert_eq!(
+            warp::test::request()
+                .path("/.well-known/acme-challenge/token")
+                .filter(&filter)
+                .await
+                .unwrap(),
+            "answer"
+        );
+    }
+}
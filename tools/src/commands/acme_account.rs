@@ -0,0 +1,84 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::linux_commands::{key_passphrase, parse_acme_account_key, read_private_key_pem};
+use crate::runtime::hyper_fetcher::HyperFetcher;
+use anyhow::Result;
+use clap::Parser;
+use sxg_rs::acme::directory::AccountStatus;
+use sxg_rs::acme::jws::Algorithm;
+use sxg_rs::acme::Account;
+
+#[derive(Debug, Parser)]
+pub struct Opts {
+    /// Directory URL of ACME server
+    #[clap(long)]
+    acme_server: String,
+    /// The URL of the ACME account, as printed by
+    /// `apply-acme-cert --output json`.
+    #[clap(long)]
+    account_url: String,
+    #[clap(long, default_value_t=String::from("acme_account_private_key.pem"))]
+    acme_account_private_key_file: String,
+    /// The passphrase of the ACME account private key file, if it is
+    /// encrypted. Defaults to the environment variable
+    /// `SXG_ACME_KEY_PASSPHRASE`.
+    #[clap(long)]
+    key_passphrase: Option<String>,
+    /// Prints the status of the account.
+    #[clap(
+        long,
+        conflicts_with = "deactivate",
+        required_unless_present = "deactivate"
+    )]
+    status: bool,
+    /// Deactivates the account, such as when its private key is compromised.
+    /// A deactivated account can't be used again.
+    #[clap(long)]
+    deactivate: bool,
+}
+
+fn status_name(status: AccountStatus) -> &'static str {
+    match status {
+        AccountStatus::Valid => "valid",
+        AccountStatus::Deactivated => "deactivated",
+        AccountStatus::Revoked => "revoked",
+    }
+}
+
+pub async fn main(opts: Opts) -> Result<()> {
+    let private_key_pem = read_private_key_pem(
+        &opts.acme_account_private_key_file,
+        key_passphrase(&opts.key_passphrase).as_deref(),
+    )?;
+    let (public_key, signer) = parse_acme_account_key(&private_key_pem)?;
+    let fetcher = HyperFetcher::new();
+    // Only the URLs are used to manage the account.
+    let account = Account {
+        server_directory_url: opts.acme_server.clone(),
+        account_url: opts.account_url.clone(),
+        domain: String::new(),
+        cert_request_der: vec![],
+        public_key_thumbprint: String::new(),
+        key_algorithm: Algorithm::of_key(&public_key),
+    };
+    if opts.deactivate {
+        sxg_rs::acme::deactivate_account(&account, &fetcher, signer.as_ref()).await?;
+        println!("The account {} is deactivated.", opts.account_url);
+    } else {
+        let status = sxg_rs::acme::get_account_status(&account, &fetcher, signer.as_ref()).await?;
+        println!("{}", status_name(status));
+    }
+    Ok(())
+}
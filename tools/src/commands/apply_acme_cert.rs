@@ -12,15 +12,30 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::linux_commands::{create_certificate_request_pem, read_or_create_private_key_pem};
+use super::gen_config::{read_artifact, write_artifact};
+use super::output::{CertificateMetadata, OutputFormat};
+use crate::linux_commands::{
+    create_certificate_request_pem, key_passphrase, parse_acme_account_key,
+    read_or_create_private_key_pem, CsrSubject, Curve,
+};
+use crate::runtime::file_store::FileStore;
 use crate::runtime::hyper_fetcher::HyperFetcher;
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, FixedOffset, Utc};
 use clap::Parser;
-use sxg_rs::acme::directory::Directory;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use sxg_rs::acme::directory::{Directory, Status};
 use sxg_rs::acme::eab::create_external_account_binding;
+use sxg_rs::acme::retry_budget::RetryBudget;
 use sxg_rs::acme::state_machine::{
-    get_challenge_token_and_answer, update_state as update_acme_state_machine,
+    self, get_challenge_token_and_answer, update_state_with_budget as update_acme_state_machine,
+    TaskStep,
 };
+use sxg_rs::acme::{get_order_status, Account, OrderOptions};
+use sxg_rs::crypto::CertificateChain;
+use tools::acme_challenge::challenge_filter;
 use warp::Filter;
 
 #[derive(Debug, Parser)]
@@ -31,28 +46,138 @@ pub struct Opts {
     /// Directory URL of ACME server
     #[clap(long)]
     acme_server: String,
-    #[clap(long)]
-    email: String,
+    /// The contact email of the ACME account. Repeat to register several
+    /// contacts, such as a shared alias and an individual.
+    #[clap(long, required = true)]
+    email: Vec<String>,
+    /// The domain name, or the IPv4 or IPv6 address, to request a certificate
+    /// for.
     #[clap(long)]
     domain: String,
     #[clap(long, default_value_t=String::from("acme_account_private_key.pem"))]
     acme_account_private_key_file: String,
+    /// The passphrase of the ACME account private key file. A new key file is
+    /// encrypted by it, and an existing encrypted one is decrypted by it.
+    /// Defaults to the environment variable `SXG_ACME_KEY_PASSPHRASE`.
+    #[clap(long)]
+    key_passphrase: Option<String>,
+    /// Generates an Ed25519 ACME account private key, instead of a P-256 one,
+    /// if the key file does not exist. Not all ACME servers accept Ed25519
+    /// keys. An existing key file is used whichever type it is.
+    #[clap(long)]
+    ed25519_account_key: bool,
     #[clap(long, default_value_t=String::from("privkey.pem"))]
     sxg_private_key_file: String,
     #[clap(long, default_value_t=String::from("cert.csr"))]
     sxg_cert_request_file: String,
+    /// A PEM file of the certificate signing request for `domain`. If
+    /// provided, the SXG private key is not read, and no CSR is generated, so
+    /// the key can be held elsewhere, such as in an HSM.
+    #[clap(long, value_name = "FILE_NAME")]
+    csr: Option<String>,
+    /// The organization (O) in the subject of the generated CSR.
+    #[clap(long)]
+    csr_organization: Option<String>,
+    /// The organizational unit (OU) in the subject of the generated CSR.
+    #[clap(long)]
+    csr_organizational_unit: Option<String>,
+    /// The locality (L) in the subject of the generated CSR.
+    #[clap(long)]
+    csr_locality: Option<String>,
+    /// The state or province (ST) in the subject of the generated CSR.
+    #[clap(long)]
+    csr_state: Option<String>,
+    /// The two-letter country code (C) in the subject of the generated CSR.
+    #[clap(long, value_parser = parse_country_code)]
+    csr_country: Option<String>,
     #[clap(long)]
     agreed_terms_of_service: String,
     #[clap(long)]
     eab_mac_key: Option<String>,
     #[clap(long)]
     eab_key_id: Option<String>,
+    /// The path prefix under which a reverse proxy forwards
+    /// `/.well-known/acme-challenge/` to this server, such as `/acme`.
+    #[clap(long, default_value = "")]
+    challenge_base_path: String,
+    /// Prints the endpoints in the directory of the ACME server, and exits
+    /// without applying for a certificate.
+    #[clap(long)]
+    print_directory: bool,
+    /// Places a new order, instead of continuing an unfinished order of the
+    /// same account and domain, such as one left by a previous crashed run.
+    #[clap(long)]
+    new_order: bool,
+    /// Downloads the alternate certificate chain whose topmost certificate is
+    /// issued by this common name, such as "ISRG Root X1", if the ACME server
+    /// offers one. Defaults to the primary chain.
+    #[clap(long, value_name = "ISSUER_CN")]
+    preferred_chain: Option<String>,
+    /// The start of the validity period to request for the certificate, in
+    /// RFC 3339, such as "2022-01-01T00:00:00Z". Some ACME servers ignore it.
+    #[clap(long, value_name = "TIME", value_parser = parse_rfc3339)]
+    not_before: Option<DateTime<FixedOffset>>,
+    /// The end of the validity period to request for the certificate, in RFC
+    /// 3339. It must be at most 90 days after `--not-before`, or after now if
+    /// that is not given. Some ACME servers ignore it.
+    #[clap(long, value_name = "TIME", value_parser = parse_rfc3339)]
+    not_after: Option<DateTime<FixedOffset>>,
+    /// A directory to keep the ACME order state in. If provided, an
+    /// interrupted run can be resumed by running again with the same
+    /// directory. By default, the state is kept in memory only.
+    #[clap(long, value_name = "DIR")]
+    store_dir: Option<String>,
+    /// The number of seconds to wait for the certificate, before failing with
+    /// the step at which the ACME order is stalled.
+    #[clap(long, default_value_t = 300)]
+    timeout: u64,
+    /// The largest number of requests the ACME order may send, including
+    /// retries, before failing. Along with `--timeout`, this bounds the worst
+    /// case in automated pipelines. By default, there is no limit.
+    #[clap(long, value_name = "COUNT")]
+    max_requests: Option<u32>,
+    /// The `User-Agent` of the requests to the ACME server. Defaults to
+    /// `sxg-rs/<version>`.
+    #[clap(long)]
+    user_agent: Option<String>,
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+    /// Prints only the PEM of the issued certificate, without the summary of
+    /// its subject, validity and serial number above it.
+    #[clap(long)]
+    quiet: bool,
+    /// An artifact file generated by `gen-config`. If provided, the issued
+    /// certificate and its expiration time are saved into this file.
+    #[clap(long, value_name = "FILE_NAME")]
+    artifact: Option<String>,
 }
 
-fn start_warp_server(port: u16, answer: String) -> tokio::sync::oneshot::Sender<()> {
+fn parse_country_code(value: &str) -> Result<String> {
+    if value.len() == 2 && value.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(value.to_ascii_uppercase())
+    } else {
+        Err(anyhow!("{:?} is not a two-letter country code", value))
+    }
+}
+
+fn parse_rfc3339(value: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|e| anyhow!("{:?} is not an RFC 3339 time: {}", value, e))
+}
+
+#[derive(Serialize)]
+struct JsonOutput {
+    certificate_pem: String,
+    #[serde(flatten)]
+    certificate_metadata: CertificateMetadata,
+    acme_account_url: String,
+}
+
+fn start_warp_server(
+    port: u16,
+    routes: impl Filter<Extract = (String,), Error = warp::Rejection> + Clone + Send + Sync + 'static,
+) -> tokio::sync::oneshot::Sender<()> {
     let (tx, rx) = tokio::sync::oneshot::channel();
-    let routes =
-        warp::path!(".well-known" / "acme-challenge" / String).map(move |_name| answer.to_string());
     let (_addr, server) =
         warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], port), async {
             rx.await.ok();
@@ -61,25 +186,173 @@ fn start_warp_server(port: u16, answer: String) -> tokio::sync::oneshot::Sender<
     tx
 }
 
+// Prints a summary of the issued certificate, so that it can be checked to
+// cover the intended domains.
+fn print_certificate_summary(certificate_pem: &str) -> Result<()> {
+    let certificate = CertificateChain::from_pem_files(&[certificate_pem])?.end_entity;
+    let not_after: DateTime<Utc> = certificate.not_after()?.into();
+    let renew_after =
+        not_after - chrono::Duration::from_std(state_machine::RENEW_BEFORE_EXPIRATION)?;
+    println!("Subject: {}", certificate.subject()?);
+    println!(
+        "Subject alternative names: {}",
+        certificate.subject_alt_names()?.join(", ")
+    );
+    println!(
+        "Not before: {}",
+        DateTime::<Utc>::from(certificate.not_before()?)
+    );
+    println!("Not after: {}", not_after);
+    println!("Renew after: {}", renew_after);
+    println!("Serial number: {}", certificate.serial_number()?);
+    println!();
+    Ok(())
+}
+
+fn print_directory(directory: &Directory, output: OutputFormat) -> Result<()> {
+    match output {
+        OutputFormat::Text => {
+            let optional = |url: &Option<String>| url.clone().unwrap_or_else(|| "-".to_string());
+            println!("newNonce: {}", directory.new_nonce);
+            println!("newAccount: {}", directory.new_account);
+            println!("newOrder: {}", directory.new_order);
+            println!("newAuthz: {}", optional(&directory.new_authz));
+            println!("revokeCert: {}", directory.revoke_cert);
+            println!("keyChange: {}", optional(&directory.key_change));
+            println!("termsOfService: {}", directory.meta.terms_of_service);
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(directory)?),
+    }
+    Ok(())
+}
+
+// Whether the CSR requests `domain` and nothing else. IP addresses are
+// compared after parsing, because the subject of a CSR may spell an IPv6
+// address differently from its canonical form.
+fn requests_only(domains: &[String], domain: &str) -> bool {
+    let ip = domain.parse::<IpAddr>().ok();
+    let is_same = |requested: &str| match (requested.parse::<IpAddr>().ok(), ip) {
+        (Some(requested), Some(ip)) => requested == ip,
+        _ => requested == domain,
+    };
+    !domains.is_empty() && domains.iter().all(|requested| is_same(requested))
+}
+
+/// The error when the ACME order doesn't finish within `--timeout`. It carries
+/// the step that the order stalled at, and the status of the order on the
+/// ACME server, if they could be read.
+#[derive(Debug)]
+struct AcmeTimeout {
+    timeout: Duration,
+    next_step: Option<TaskStep>,
+    order_status: Option<Status>,
+}
+
+impl std::fmt::Display for AcmeTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Timed out after {} seconds; ", self.timeout.as_secs())?;
+        match &self.next_step {
+            Some(next_step) => write!(f, "the next step of the ACME order is {:?}", next_step)?,
+            None => write!(f, "there is no ongoing ACME order")?,
+        }
+        match &self.order_status {
+            Some(order_status) => write!(f, ", and its status is {:?}.", order_status),
+            None => write!(f, "."),
+        }
+    }
+}
+
+impl std::error::Error for AcmeTimeout {}
+
+// Returns an `AcmeTimeout` if `deadline` has passed.
+async fn check_deadline(
+    deadline: Instant,
+    opts: &Opts,
+    runtime: &sxg_rs::runtime::Runtime,
+    account: &Account,
+) -> Result<()> {
+    if Instant::now() < deadline {
+        return Ok(());
+    }
+    let state = state_machine::read_current_state(runtime).await?;
+    let order_status = match state.order_url() {
+        Some(order_url) => get_order_status(
+            account,
+            order_url,
+            runtime.fetcher.as_ref(),
+            runtime.acme_signer.as_ref(),
+        )
+        .await
+        .ok(),
+        None => None,
+    };
+    Err(Error::new(AcmeTimeout {
+        timeout: Duration::from_secs(opts.timeout),
+        next_step: state.next_step().cloned(),
+        order_status,
+    }))
+}
+
 pub async fn main(opts: Opts) -> Result<()> {
-    let acme_private_key = {
-        let private_key_pem = read_or_create_private_key_pem(&opts.acme_account_private_key_file)?;
-        sxg_rs::crypto::EcPrivateKey::from_sec1_pem(&private_key_pem)?
+    let fetcher = match &opts.user_agent {
+        Some(user_agent) => HyperFetcher::new().with_user_agent(user_agent),
+        None => HyperFetcher::new(),
+    };
+    if opts.print_directory {
+        let (directory, _) = Directory::from_url(&opts.acme_server, &fetcher).await?;
+        return print_directory(&directory, opts.output);
+    }
+    let (acme_public_key, acme_signer) = {
+        let curve = if opts.ed25519_account_key {
+            Curve::Ed25519
+        } else {
+            Curve::default()
+        };
+        let private_key_pem = read_or_create_private_key_pem(
+            &opts.acme_account_private_key_file,
+            curve,
+            key_passphrase(&opts.key_passphrase).as_deref(),
+        )?;
+        parse_acme_account_key(&private_key_pem)?
     };
-    let sxg_cert_request_der = {
-        read_or_create_private_key_pem(&opts.sxg_private_key_file)?;
+    let sxg_cert_request_der = if let Some(csr_file) = &opts.csr {
+        let cert_request_pem = std::fs::read_to_string(csr_file)?;
+        let der = sxg_rs::crypto::get_der_from_pem(&cert_request_pem, "CERTIFICATE REQUEST")?;
+        let domains = sxg_rs::crypto::certificate_request_domains(&der)?;
+        if !requests_only(&domains, &opts.domain) {
+            return Err(anyhow!(
+                "The CSR {} requests {:?}, but it should only request {}.",
+                csr_file,
+                domains,
+                opts.domain
+            ));
+        }
+        der
+    } else {
+        read_or_create_private_key_pem(&opts.sxg_private_key_file, Curve::default(), None)?;
+        let subject = CsrSubject {
+            organization: opts.csr_organization.clone(),
+            organizational_unit: opts.csr_organizational_unit.clone(),
+            locality: opts.csr_locality.clone(),
+            state: opts.csr_state.clone(),
+            country: opts.csr_country.clone(),
+        };
         let cert_request_pem = create_certificate_request_pem(
             &opts.domain,
+            &subject,
             &opts.sxg_private_key_file,
             &opts.sxg_cert_request_file,
         )?;
         sxg_rs::crypto::get_der_from_pem(&cert_request_pem, "CERTIFICATE REQUEST")?
     };
     let mut runtime = sxg_rs::runtime::Runtime {
-        acme_signer: Box::new(acme_private_key.create_signer()?),
-        fetcher: Box::new(HyperFetcher::new()),
+        acme_signer,
+        fetcher: Box::new(fetcher),
         ..Default::default()
     };
+    if let Some(store_dir) = &opts.store_dir {
+        runtime.storage = Box::new(FileStore::new(store_dir)?);
+    }
     let external_account_binding = match (&opts.eab_key_id, &opts.eab_mac_key) {
         (Some(eab_key_id), Some(eab_mac_key)) => {
             let eab_mac_key = base64::decode_config(eab_mac_key, base64::URL_SAFE_NO_PAD)?;
@@ -92,7 +365,7 @@ pub async fn main(opts: Opts) -> Result<()> {
                 sxg_rs::acme::jws::Algorithm::HS256,
                 eab_key_id,
                 &new_account_url,
-                &acme_private_key.public_key,
+                &acme_public_key,
                 &eab_signer,
             )
             .await?;
@@ -106,39 +379,136 @@ pub async fn main(opts: Opts) -> Result<()> {
             ))
         }
     };
+    let order_options = OrderOptions {
+        always_place_new_order: opts.new_order,
+        preferred_chain: opts.preferred_chain.clone(),
+        not_before: opts.not_before,
+        not_after: opts.not_after,
+    };
+    // Fails before registering an account.
+    order_options.validate(std::time::SystemTime::now())?;
     let acme_account = sxg_rs::acme::create_account(
         sxg_rs::acme::AccountSetupParams {
             directory_url: opts.acme_server.clone(),
             agreed_terms_of_service: &opts.agreed_terms_of_service,
             external_account_binding,
-            email: &opts.email,
+            emails: &opts.email,
             domain: opts.domain.clone(),
-            public_key: acme_private_key.public_key,
+            public_key: acme_public_key,
             cert_request_der: sxg_cert_request_der,
         },
         runtime.fetcher.as_ref(),
         runtime.acme_signer.as_ref(),
     )
     .await?;
-    let challenge_answer = loop {
+    let deadline = Instant::now() + Duration::from_secs(opts.timeout);
+    let budget = RetryBudget::new(
+        opts.max_requests.unwrap_or(u32::MAX),
+        Duration::from_secs(opts.timeout),
+        std::time::SystemTime::now(),
+    );
+    let (challenge_token, challenge_answer) = loop {
         runtime.now = std::time::SystemTime::now();
-        update_acme_state_machine(&runtime, &acme_account).await?;
-        if let Some((_token, answer)) = get_challenge_token_and_answer(&runtime).await? {
-            break answer;
+        update_acme_state_machine(&runtime, &acme_account, &order_options, &budget).await?;
+        if let Some(token_and_answer) = get_challenge_token_and_answer(&runtime).await? {
+            break token_and_answer;
         }
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        check_deadline(deadline, &opts, &runtime, &acme_account).await?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
     };
-    let tx = start_warp_server(opts.port, challenge_answer);
+    let tx = start_warp_server(
+        opts.port,
+        challenge_filter(
+            &opts.challenge_base_path,
+            challenge_token.clone(),
+            challenge_answer.clone(),
+        ),
+    );
+    // The state machine asks the ACME server to validate the challenge at its
+    // next update, so the answer has to be publicly reachable before that.
+    if let Err(e) = sxg_rs::acme::check_challenge_reachable(
+        &opts.domain,
+        &challenge_token,
+        &challenge_answer,
+        runtime.fetcher.as_ref(),
+    )
+    .await
+    {
+        let _ = tx.send(());
+        return Err(e.into());
+    }
     let certificate_pem = loop {
         runtime.now = std::time::SystemTime::now();
-        update_acme_state_machine(&runtime, &acme_account).await?;
+        update_acme_state_machine(&runtime, &acme_account, &order_options, &budget).await?;
         let state = sxg_rs::acme::state_machine::read_current_state(&runtime).await?;
         if let Some(cert) = state.certificates.last() {
             break cert.clone();
         }
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        check_deadline(deadline, &opts, &runtime, &acme_account).await?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
     };
     let _ = tx.send(());
-    println!("{}", certificate_pem);
+    if let Some(artifact_file) = &opts.artifact {
+        let mut artifact = read_artifact(artifact_file)?;
+        artifact.set_sxg_certificate(certificate_pem.clone())?;
+        write_artifact(artifact_file, &artifact)?;
+    }
+    match opts.output {
+        OutputFormat::Text => {
+            if !opts.quiet {
+                print_certificate_summary(&certificate_pem)?;
+            }
+            println!("{}", certificate_pem);
+        }
+        OutputFormat::Json => {
+            let output = JsonOutput {
+                certificate_metadata: CertificateMetadata::from_pem(&certificate_pem)?,
+                certificate_pem,
+                acme_account_url: acme_account.account_url,
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn compares_requested_ip_addresses() {
+        let domains = |domains: &[&str]| domains.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+        assert!(requests_only(&domains(&["example.com"]), "example.com"));
+        assert!(!requests_only(
+            &domains(&["example.com", "www.example.com"]),
+            "example.com"
+        ));
+        assert!(!requests_only(&domains(&[]), "example.com"));
+        assert!(requests_only(&domains(&["0:0:0:0:0:0:0:1", "::1"]), "::1"));
+        assert!(requests_only(&domains(&["2001:db8::1"]), "2001:DB8:0::1"));
+        assert!(!requests_only(&domains(&["2001:db8::2"]), "2001:db8::1"));
+    }
+    #[test]
+    fn acme_timeout_has_step_and_status() {
+        let timeout = Duration::from_secs(300);
+        assert_eq!(
+            AcmeTimeout {
+                timeout,
+                next_step: Some(TaskStep::DownloadCertificate),
+                order_status: Some(Status::Processing),
+            }
+            .to_string(),
+            "Timed out after 300 seconds; the next step of the ACME order is \
+            DownloadCertificate, and its status is Processing."
+        );
+        assert_eq!(
+            AcmeTimeout {
+                timeout,
+                next_step: None,
+                order_status: None,
+            }
+            .to_string(),
+            "Timed out after 300 seconds; there is no ongoing ACME order."
+        );
+    }
+}
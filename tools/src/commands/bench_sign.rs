@@ -0,0 +1,132 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::output::OutputFormat;
+use anyhow::{Error, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+use sxg_rs::crypto::{CertificateChain, EcPrivateKey};
+use sxg_rs::{CreateSignedExchangeBlockingParams, SxgWorker};
+
+/// Measures how fast signed exchanges are created with the given certificate
+/// and private key on this machine. Only the signing path is timed; nothing is
+/// fetched, and a stub OCSP response is stapled.
+#[derive(Debug, Parser)]
+pub struct Opts {
+    /// The PEM file of the certificate, optionally followed by its issuers.
+    #[clap(long, value_name = "FILE_NAME")]
+    cert: String,
+    /// The PEM file of the EC private key of the certificate.
+    #[clap(long, value_name = "FILE_NAME")]
+    key: String,
+    /// The size in bytes of the payload body to sign.
+    #[clap(long, default_value_t = 16384)]
+    payload_size: usize,
+    /// How many signed exchanges to create.
+    #[clap(long, default_value_t = 1000)]
+    iterations: usize,
+    /// The URL of the signed exchanges.
+    #[clap(long, default_value = "https://example.com/")]
+    url: String,
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+const CONFIG: &str = r#"
+allow_uncovered_hosts: true
+cert_url_dirname: ".well-known/sxg-certs"
+forward_request_headers: []
+html_host: ""
+reserved_path: ".sxg"
+strip_request_headers: []
+strip_response_headers: []
+suppress_config_warnings: true
+validity_url_dirname: ".well-known/sxg-validity"
+"#;
+
+#[derive(Serialize)]
+struct Report {
+    iterations: usize,
+    payload_size: usize,
+    p50_ms: f64,
+    p99_ms: f64,
+    exchanges_per_second: f64,
+}
+
+// Returns the given percentile of the sorted, non-empty durations.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let index = sorted.len() * percent / 100;
+    sorted[index.min(sorted.len() - 1)]
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub fn main(opts: Opts) -> Result<()> {
+    if opts.iterations == 0 {
+        return Err(Error::msg("--iterations must be positive."));
+    }
+    let mut worker = SxgWorker::new(CONFIG)?;
+    worker.add_certificate(CertificateChain::from_pem_files(&[&fs::read_to_string(
+        &opts.cert,
+    )?])?);
+    let signer = EcPrivateKey::from_sec1_pem(&fs::read_to_string(&opts.key)?)?.create_signer()?;
+    let cert_origin = url::Url::parse(&opts.url)?.origin().ascii_serialization();
+    let payload_body = vec![b'a'; opts.payload_size];
+    let mut durations = Vec::with_capacity(opts.iterations);
+    for _ in 0..opts.iterations {
+        let payload_headers =
+            worker.transform_payload_headers(vec![("content-type".into(), "text/html".into())])?;
+        let start = Instant::now();
+        worker.create_signed_exchange_blocking(CreateSignedExchangeBlockingParams {
+            now: SystemTime::now(),
+            fallback_url: &opts.url,
+            cert_origin: &cert_origin,
+            payload_body: &payload_body,
+            payload_headers,
+            status_code: 200,
+            ocsp_der: b"ocsp",
+            signer: &signer,
+        })?;
+        durations.push(start.elapsed());
+    }
+    let total: Duration = durations.iter().sum();
+    durations.sort();
+    let report = Report {
+        iterations: opts.iterations,
+        payload_size: opts.payload_size,
+        p50_ms: as_millis(percentile(&durations, 50)),
+        p99_ms: as_millis(percentile(&durations, 99)),
+        exchanges_per_second: opts.iterations as f64 / total.as_secs_f64(),
+    };
+    match opts.output {
+        OutputFormat::Text => {
+            println!(
+                "Signed {} exchanges of {} bytes.",
+                report.iterations, report.payload_size
+            );
+            println!("  p50 latency: {:.3} ms", report.p50_ms);
+            println!("  p99 latency: {:.3} ms", report.p99_ms);
+            println!(
+                "  Throughput: {:.1} exchanges/s",
+                report.exchanges_per_second
+            );
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+    }
+    Ok(())
+}
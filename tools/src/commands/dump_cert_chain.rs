@@ -0,0 +1,161 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::output::OutputFormat;
+use crate::runtime::hyper_fetcher::HyperFetcher;
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use sxg_rs::crypto::{parse_cert_cbor, parse_sct_list, SignedCertificateTimestamp};
+use sxg_rs::fetcher::Fetcher;
+use sxg_rs::ocsp::{parse_cert_status, parse_validity, CertStatus};
+
+/// Prints the certificates, OCSP response and SCTs in an
+/// `application/cert-chain+cbor` file, such as the response of a `cert-url`.
+#[derive(Debug, Parser)]
+pub struct Opts {
+    /// The path of the cert-chain+cbor file.
+    #[clap(long, conflicts_with = "url", required_unless_present = "url")]
+    file: Option<String>,
+    /// The URL to fetch the cert-chain+cbor from.
+    #[clap(long)]
+    url: Option<String>,
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+#[derive(Serialize)]
+struct Sct {
+    log_id: String,
+    timestamp: DateTime<Utc>,
+}
+
+impl From<SignedCertificateTimestamp> for Sct {
+    fn from(sct: SignedCertificateTimestamp) -> Self {
+        Sct {
+            log_id: base64::encode(&sct.log_id),
+            timestamp: sct.timestamp.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Ocsp {
+    cert_status: CertStatus,
+    this_update: DateTime<Utc>,
+    next_update: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct Certificate {
+    subject: String,
+    issuer: String,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
+    embedded_scts: Vec<Sct>,
+    /// Set if the item has an `ocsp` field, which may fail to parse.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ocsp: Option<Result<Ocsp, String>>,
+    /// Set if the item has an `sct` field, which may fail to parse.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scts: Option<Result<Vec<Sct>, String>>,
+}
+
+fn parse_ocsp(ocsp_der: &[u8]) -> Result<Ocsp> {
+    let validity = parse_validity(ocsp_der)?;
+    Ok(Ocsp {
+        cert_status: parse_cert_status(ocsp_der)?,
+        this_update: validity.this_update.into(),
+        next_update: validity.next_update.map(Into::into),
+    })
+}
+
+fn parse_scts(sct_list: &[u8]) -> Result<Vec<Sct>> {
+    Ok(parse_sct_list(sct_list)?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+}
+
+fn print_scts(label: &str, scts: &[Sct]) {
+    println!("  {}: {}", label, scts.len());
+    for sct in scts {
+        println!("    log {} at {}", sct.log_id, sct.timestamp);
+    }
+}
+
+fn print_text(certificates: &[Certificate]) {
+    for (i, certificate) in certificates.iter().enumerate() {
+        println!("Certificate #{}", i);
+        println!("  Subject: {}", certificate.subject);
+        println!("  Issuer: {}", certificate.issuer);
+        println!("  Not before: {}", certificate.not_before);
+        println!("  Not after: {}", certificate.not_after);
+        print_scts("Embedded SCTs", &certificate.embedded_scts);
+        match &certificate.ocsp {
+            Some(Ok(ocsp)) => {
+                println!("  OCSP status: {:?}", ocsp.cert_status);
+                println!("  OCSP this update: {}", ocsp.this_update);
+                match &ocsp.next_update {
+                    Some(next_update) => println!("  OCSP next update: {}", next_update),
+                    None => println!("  OCSP next update: -"),
+                }
+            }
+            Some(Err(e)) => println!("  OCSP: {}", e),
+            None => (),
+        }
+        match &certificate.scts {
+            Some(Ok(scts)) => print_scts("SCTs", scts),
+            Some(Err(e)) => println!("  SCTs: {}", e),
+            None => (),
+        }
+    }
+}
+
+pub async fn main(opts: Opts) -> Result<()> {
+    let cert_cbor = match (&opts.file, &opts.url) {
+        (Some(file), _) => std::fs::read(file)?,
+        (None, Some(url)) => HyperFetcher::new().get(url).await?,
+        (None, None) => return Err(Error::msg("Please provide either --file or --url.")),
+    };
+    let certificates = parse_cert_cbor(&cert_cbor)?
+        .into_iter()
+        .map(|item| {
+            Ok(Certificate {
+                subject: item.cert.subject()?,
+                issuer: item.cert.issuer()?,
+                not_before: item.cert.not_before()?.into(),
+                not_after: item.cert.not_after()?.into(),
+                embedded_scts: item
+                    .cert
+                    .embedded_scts()?
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                ocsp: item
+                    .ocsp
+                    .map(|ocsp| parse_ocsp(&ocsp).map_err(|e| format!("{:#}", e))),
+                scts: item
+                    .sct
+                    .map(|sct| parse_scts(&sct).map_err(|e| format!("{:#}", e))),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    match opts.output {
+        OutputFormat::Text => print_text(&certificates),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&certificates)?),
+    }
+    Ok(())
+}
@@ -12,11 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{create_acme_key_and_account, read_certificate_pem_file, Artifact, SxgCertConfig};
+use super::{
+    create_acme_account, read_certificate_pem_file, Artifact, StorageArtifact, SxgCertConfig,
+};
+use crate::linux_commands::{
+    decrypt_private_key_pem, encrypt_private_key_pem, generate_private_key_pem, Curve,
+};
 use crate::tokio_block_on;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use sxg_rs::config::Config as SxgConfig;
+use sxg_rs::crypto::EcPrivateKey;
 use wrangler::settings::global_user::GlobalUser;
 use wrangler::settings::toml::ConfigKvNamespace;
 
@@ -67,16 +73,16 @@ struct WranglerManifest {
 // Get the Cloudflare user.
 // If there is no active user, the terminal will display a login link.
 // This function will wait for the login process before returning.
-fn get_global_user() -> GlobalUser {
-    println!("Checking Cloudflare login state");
+fn get_global_user() -> Result<GlobalUser> {
+    eprintln!("Checking Cloudflare login state");
     let mut user = GlobalUser::new();
     if user.is_err() {
         wrangler::login::run(None).unwrap();
         user = GlobalUser::new();
     }
     let user = user.unwrap();
-    println!("Successful login to Cloudflare");
-    user
+    eprintln!("Successful login to Cloudflare");
+    Ok(user)
 }
 
 const STORAGE_NAME: &str = "OCSP";
@@ -103,24 +109,64 @@ fn create_wrangler_secret_instruction(name: &str, value: &str) -> String {
     )
 }
 
+fn acme_private_key_instruction(acme_private_key: &EcPrivateKey) -> Result<String> {
+    Ok(create_wrangler_secret_instruction(
+        "ACME_PRIVATE_KEY_JWK",
+        &serde_json::to_string(acme_private_key)?,
+    ))
+}
+
+// Prints the instruction instead of saving it, because it contains the
+// plaintext private key.
+fn print_acme_private_key_instruction(pem: &str) -> Result<()> {
+    let instruction = acme_private_key_instruction(&EcPrivateKey::from_sec1_pem(pem)?)?;
+    eprintln!(
+        "Run the following command to upload the ACME private key:\n{}",
+        instruction
+    );
+    Ok(())
+}
+
+fn kv_namespace_id(artifact: &Artifact) -> Option<String> {
+    artifact
+        .storage
+        .as_ref()
+        .map(|StorageArtifact::CloudflareKvNamespace { id }| id.clone())
+}
+
+// Sets the KV namespace of the artifact to the one returned by `create`, if
+// the artifact has none. `create` logs in to Cloudflare, which may prompt on
+// stdin, so it is skipped in CI mode.
+fn ensure_kv_namespace(
+    use_ci_mode: bool,
+    artifact: &mut Artifact,
+    create: impl FnOnce() -> Result<String>,
+) -> Result<()> {
+    if kv_namespace_id(artifact).is_none() {
+        if use_ci_mode {
+            eprintln!("Skipping KV namespace creation, because --use-ci-mode is set.")
+        } else {
+            artifact.storage = Some(StorageArtifact::CloudflareKvNamespace { id: create()? })
+        }
+    }
+    Ok(())
+}
+
 const WRANGLER_TOML: &str = "cloudflare_worker/wrangler.toml";
 
 pub fn main(
     use_ci_mode: bool,
+    force_acme: bool,
+    key_passphrase: Option<&str>,
     sxg_input: &SxgConfig,
     cert_input: &SxgCertConfig,
     cloudflare_input: &CloudlareSpecificInput,
     artifact: &mut Artifact,
 ) -> Result<()> {
-    if artifact.cloudflare_kv_namespace_id.is_none() {
-        if use_ci_mode {
-            println!("Skipping KV namespace creation, because --use-ci-mode is set.")
-        } else {
-            let user = get_global_user();
-            artifact.cloudflare_kv_namespace_id =
-                Some(get_ocsp_kv_id(&user, &cloudflare_input.account_id))
-        }
-    }
+    ensure_kv_namespace(use_ci_mode, artifact, || {
+        let user = get_global_user()?;
+        Ok(get_ocsp_kv_id(&user, &cloudflare_input.account_id))
+    })?;
     let mut wrangler_vars = WranglerVars {
         html_host: sxg_input.html_host.clone(),
         sxg_config: serde_yaml::to_string(&sxg_input)?,
@@ -135,15 +181,45 @@ pub fn main(
             wrangler_vars.issuer_pem = Some(read_certificate_pem_file(issuer_file)?);
         }
         SxgCertConfig::CreateAcmeAccount(acme_config) => {
-            if artifact.acme_account.is_none() {
-                let (acme_private_key, acme_account) = tokio_block_on(
-                    create_acme_key_and_account(acme_config, &sxg_input.html_host),
-                )?;
+            if artifact.acme_account.is_some() && !force_acme {
+                eprintln!(
+                    "Reusing the ACME account in the artifact; \
+                    use --force-acme to create a new one."
+                );
+                if let Some(encrypted_pem) = &artifact.acme_private_key_pem {
+                    match key_passphrase {
+                        Some(key_passphrase) => {
+                            let pem = decrypt_private_key_pem(encrypted_pem, key_passphrase)?;
+                            print_acme_private_key_instruction(&pem)?;
+                        }
+                        None => eprintln!(
+                            "The ACME private key in the artifact is encrypted; \
+                            use --key-passphrase to print the instruction to upload it."
+                        ),
+                    }
+                }
+            } else {
+                let pem = generate_private_key_pem(Curve::default())?;
+                let acme_private_key = EcPrivateKey::from_sec1_pem(&pem)?;
+                let acme_account = tokio_block_on(create_acme_account(
+                    acme_config,
+                    &sxg_input.html_host,
+                    &acme_private_key,
+                ))?;
                 artifact.acme_account = Some(acme_account);
-                artifact.acme_private_key_instruction = Some(create_wrangler_secret_instruction(
-                    "ACME_PRIVATE_KEY_JWK",
-                    &serde_json::to_string(&acme_private_key)?,
-                ))
+                match key_passphrase {
+                    Some(key_passphrase) => {
+                        artifact.acme_private_key_pem =
+                            Some(encrypt_private_key_pem(&pem, key_passphrase)?);
+                        artifact.acme_private_key_instruction = None;
+                        print_acme_private_key_instruction(&pem)?;
+                    }
+                    None => {
+                        artifact.acme_private_key_pem = None;
+                        artifact.acme_private_key_instruction =
+                            Some(acme_private_key_instruction(&acme_private_key)?);
+                    }
+                }
             }
             wrangler_vars.acme_account = Some(serde_json::to_string(&artifact.acme_account)?);
         }
@@ -162,9 +238,7 @@ pub fn main(
         routes,
         kv_namespaces: vec![ConfigKvNamespace {
             binding: STORAGE_NAME.to_string(),
-            id: artifact
-                .cloudflare_kv_namespace_id
-                .clone()
+            id: kv_namespace_id(artifact)
                 .or_else(|| Some("XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX".to_string())),
             preview_id: None,
         }],
@@ -189,6 +263,23 @@ pub fn main(
             toml::to_string_pretty(&wrangler_toml_output)?
         ),
     )?;
-    println!("Successfully wrote config to {}", WRANGLER_TOML);
+    eprintln!("Successfully wrote config to {}", WRANGLER_TOML);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn ci_mode_never_logs_in() {
+        // Logging in is the only step that reads stdin.
+        let mut artifact = Artifact::default();
+        ensure_kv_namespace(true, &mut artifact, || {
+            panic!("CI mode must not log in to Cloudflare")
+        })
+        .unwrap();
+        assert!(kv_namespace_id(&artifact).is_none());
+        ensure_kv_namespace(false, &mut artifact, || Ok("kv-id".to_string())).unwrap();
+        assert_eq!(kv_namespace_id(&artifact), Some("kv-id".to_string()));
+    }
+}
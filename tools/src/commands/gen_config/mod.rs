@@ -14,34 +14,62 @@
 
 mod cloudflare;
 
-use crate::linux_commands::generate_private_key_pem;
+use super::output::{CertificateMetadata, OutputFormat};
+use crate::linux_commands::key_passphrase;
 use crate::runtime::openssl_signer::OpensslSigner;
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use cloudflare::CloudlareSpecificInput;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::io::{Read, Write};
 use sxg_rs::acme::{directory::Directory as AcmeDirectory, Account as AcmeAccount};
 use sxg_rs::crypto::EcPrivateKey;
 
 #[derive(Debug, Parser)]
 pub struct Opts {
-    /// A YAML file containing all config values.
+    /// A YAML file containing all config values, or `-` to read from stdin.
     /// You can use the template
     /// 'tools/src/commands/gen_config/input.example.yaml'.
     #[clap(long, value_name = "FILE_NAME")]
     input: String,
-    /// A YAML file containing the generated values.
+    /// A YAML file containing the generated values, or `-` to write the
+    /// artifact to stdout.
     #[clap(long, value_name = "FILE_NAME")]
     artifact: String,
+    /// An existing artifact whose ACME account and key-value store are
+    /// reused, or `-` to read it from stdin. Defaults to `--artifact`, if that
+    /// file exists. Use this with `--artifact -`, which can't be read back.
+    #[clap(long, value_name = "FILE_NAME")]
+    previous_artifact: Option<String>,
     /// No longer log in to worker service providers.
+    /// Implied when the environment variable `CI` is set or `SXG_CI=1`,
+    /// unless `--no-ci-check` is set.
     #[clap(long)]
     use_ci_mode: bool,
+    /// Do not infer `--use-ci-mode` from the environment variables `CI`
+    /// and `SXG_CI`.
+    #[clap(long, conflicts_with = "use_ci_mode")]
+    no_ci_check: bool,
+    /// Create a new ACME account and private key, even if the artifact
+    /// already contains one. By default, the existing account is reused.
+    #[clap(long)]
+    force_acme: bool,
+    /// If set, a new ACME account private key is saved in the artifact,
+    /// encrypted by this passphrase, instead of in a plaintext instruction.
+    /// Defaults to the environment variable `SXG_ACME_KEY_PASSPHRASE`.
+    #[clap(long)]
+    key_passphrase: Option<String>,
+    /// The format of the summary printed to stdout after the artifact is
+    /// written. Progress messages are always printed to stderr.
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
-    sxg_worker: sxg_rs::config::Config,
-    certificates: SxgCertConfig,
+    pub sxg_worker: sxg_rs::config::Config,
+    pub certificates: SxgCertConfig,
     cloudflare: CloudlareSpecificInput,
 }
 
@@ -58,9 +86,11 @@ pub enum SxgCertConfig {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AcmeConfig {
     server_url: String,
-    contact_email: String,
+    /// Either a single email or a list of them.
+    #[serde(with = "sxg_rs::serde_helpers::one_or_many")]
+    contact_email: Vec<String>,
     agreed_terms_of_service: String,
-    sxg_cert_request_file: String,
+    pub sxg_cert_request_file: String,
     eab: Option<EabConfig>,
 }
 
@@ -74,7 +104,63 @@ pub struct EabConfig {
 pub struct Artifact {
     acme_account: Option<AcmeAccount>,
     acme_private_key_instruction: Option<String>,
-    cloudflare_kv_namespace_id: Option<String>,
+    /// The ACME account private key, as a PKCS#8 PEM encrypted by
+    /// `--key-passphrase`. When this is set, `acme_private_key_instruction`
+    /// is printed instead of saved.
+    acme_private_key_pem: Option<String>,
+    /// The key-value store created for the worker. Older artifacts stored a
+    /// Cloudflare KV namespace ID in `cloudflare_kv_namespace_id`.
+    #[serde(
+        alias = "cloudflare_kv_namespace_id",
+        default,
+        deserialize_with = "deserialize_storage_artifact"
+    )]
+    storage: Option<StorageArtifact>,
+    /// The latest certificate issued by the ACME server, in PEM format.
+    sxg_certificate_pem: Option<String>,
+    /// The expiration time of `sxg_certificate_pem`.
+    not_after: Option<DateTime<Utc>>,
+}
+
+/// Identifies the key-value store of a worker, per platform.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageArtifact {
+    CloudflareKvNamespace { id: String },
+}
+
+fn deserialize_storage_artifact<'de, D>(
+    deserializer: D,
+) -> Result<Option<StorageArtifact>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        CloudflareKvNamespaceId(String),
+        Storage(StorageArtifact),
+    }
+    Ok(
+        Option::<Repr>::deserialize(deserializer)?.map(|repr| match repr {
+            Repr::CloudflareKvNamespaceId(id) => StorageArtifact::CloudflareKvNamespace { id },
+            Repr::Storage(storage) => storage,
+        }),
+    )
+}
+
+impl Artifact {
+    /// Returns the certificate issued by the ACME server, if any.
+    pub fn sxg_certificate_pem(&self) -> Option<&str> {
+        self.sxg_certificate_pem.as_deref()
+    }
+    /// Stores the certificate issued by the ACME server, and updates
+    /// `not_after` from the certificate.
+    pub fn set_sxg_certificate(&mut self, certificate_pem: String) -> Result<()> {
+        self.not_after = Some(CertificateMetadata::from_pem(&certificate_pem)?.not_after);
+        self.sxg_certificate_pem = Some(certificate_pem);
+        Ok(())
+    }
 }
 
 // Set working directory to the root folder of the "sxg-rs" repository.
@@ -92,14 +178,16 @@ fn goto_repository_root() -> Result<(), std::io::Error> {
     Ok(())
 }
 
-fn read_certificate_pem_file(path: &str) -> Result<String> {
+pub fn read_certificate_pem_file(path: &str) -> Result<String> {
     let text = std::fs::read_to_string(path)
         .map_err(|_| Error::msg(format!(r#"Failed to read file "{}""#, path)))?;
     // Translate Windows-style line endings to Unix-style so the '\r' is
     // not rendered in the toml. This is purely cosmetic; '\r' is deserialized
-    // faithfully from toml and pem::parse_many is able to parse either style.
-    let text = text.replace("\r\n", "\n");
-    let certs = pem::parse_many(&text).map_err(Error::new)?;
+    // faithfully from toml and parse_pem_blocks is able to parse either style.
+    // A leading byte order mark is removed, because it would be copied into
+    // the toml as an invisible character.
+    let text = sxg_rs::utils::trim_text_file(&text).replace("\r\n", "\n") + "\n";
+    let certs = sxg_rs::crypto::parse_pem_blocks(&text)?;
     if certs.len() == 1 && certs[0].tag == "CERTIFICATE" {
         Ok(text)
     } else {
@@ -110,14 +198,11 @@ fn read_certificate_pem_file(path: &str) -> Result<String> {
     }
 }
 
-async fn create_acme_key_and_account(
+async fn create_acme_account(
     acme_config: &AcmeConfig,
     domain_name: &str,
-) -> Result<(EcPrivateKey, AcmeAccount)> {
-    let acme_private_key = {
-        let pem = generate_private_key_pem()?;
-        EcPrivateKey::from_sec1_pem(&pem)?
-    };
+    acme_private_key: &EcPrivateKey,
+) -> Result<AcmeAccount> {
     let runtime = sxg_rs::runtime::Runtime {
         acme_signer: Box::new(acme_private_key.create_signer()?),
         fetcher: Box::new(crate::runtime::hyper_fetcher::HyperFetcher::new()),
@@ -140,7 +225,7 @@ async fn create_acme_key_and_account(
             sxg_rs::acme::jws::Algorithm::HS256,
             &input_eab.key_id,
             &new_account_url,
-            &acme_private_key.public_key,
+            &acme_private_key.public_key.clone().into(),
             &eab_signer,
         )
         .await?;
@@ -153,51 +238,157 @@ async fn create_acme_key_and_account(
             directory_url: acme_config.server_url.clone(),
             agreed_terms_of_service: &acme_config.agreed_terms_of_service,
             external_account_binding: eab,
-            email: &acme_config.contact_email,
+            emails: &acme_config.contact_email,
             domain: domain_name.to_string(),
-            public_key: acme_private_key.public_key.clone(),
+            public_key: acme_private_key.public_key.clone().into(),
             cert_request_der: sxg_cert_request_der,
         },
         runtime.fetcher.as_ref(),
         runtime.acme_signer.as_ref(),
     )
     .await?;
-    Ok((acme_private_key, account))
+    Ok(account)
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    artifact: &'a Artifact,
+    certificate: Option<CertificateMetadata>,
+    acme_account_url: Option<&'a str>,
+}
+
+fn print_json_output(input: &Config, artifact: &Artifact) -> Result<()> {
+    let certificate = match &input.certificates {
+        SxgCertConfig::PreIssued { cert_file, .. } => Some(CertificateMetadata::from_pem(
+            &read_certificate_pem_file(cert_file)?,
+        )?),
+        SxgCertConfig::CreateAcmeAccount(_) => artifact
+            .sxg_certificate_pem
+            .as_deref()
+            .map(CertificateMetadata::from_pem)
+            .transpose()?,
+    };
+    let output = JsonOutput {
+        artifact,
+        certificate,
+        acme_account_url: artifact
+            .acme_account
+            .as_ref()
+            .map(|account| account.account_url.as_str()),
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// The file name that refers to stdin or stdout instead of a file.
+const STDIO_FILE_NAME: &str = "-";
+
+/// Reads a YAML file, which may be compressed with gzip.
+/// If `file_name` is `-`, reads from stdin.
+pub fn read_yaml_file(file_name: &str) -> Result<String> {
+    let content = if file_name == STDIO_FILE_NAME {
+        let mut content = vec![];
+        std::io::stdin().read_to_end(&mut content)?;
+        content
+    } else {
+        std::fs::read(file_name)?
+    };
+    Ok(sxg_rs::utils::decode_text_file(&content)?)
 }
 
-fn read_artifact(file_name: &str) -> Result<Artifact> {
-    let file_content = std::fs::read_to_string(file_name)?;
+pub fn read_artifact(file_name: &str) -> Result<Artifact> {
+    let file_content = read_yaml_file(file_name)?;
     let artifact = serde_yaml::from_str(&file_content)?;
     Ok(artifact)
 }
 
+/// Writes the artifact to a file. If `file_name` is `-`, writes to stdout.
+pub fn write_artifact(file_name: &str, artifact: &Artifact) -> Result<()> {
+    let content = format!(
+        "# This file is generated by command \"cargo run -p tools -- gen-config\".\n\
+        # Please do not modify.\n\
+        {}",
+        serde_yaml::to_string(artifact)?
+    );
+    if file_name == STDIO_FILE_NAME {
+        std::io::stdout().write_all(content.as_bytes())?;
+    } else {
+        std::fs::write(file_name, content)?;
+    }
+    Ok(())
+}
+
+// Returns whether to run in CI mode, in which nothing may prompt the user
+// for input. CI mode is implied by `$CI` or `SXG_CI=1`, because prompting
+// in CI blocks on stdin until the job times out.
+fn use_ci_mode(opts: &Opts) -> bool {
+    if opts.use_ci_mode {
+        return true;
+    }
+    if opts.no_ci_check {
+        return false;
+    }
+    if std::env::var_os("CI").is_some() {
+        eprintln!("Using CI mode, because the environment variable $CI is set.");
+        return true;
+    }
+    if std::env::var("SXG_CI").as_deref() == Ok("1") {
+        eprintln!("Using CI mode, because the environment variable $SXG_CI is 1.");
+        return true;
+    }
+    false
+}
+
 pub fn main(opts: Opts) -> Result<()> {
-    if std::env::var("CI").is_ok() && !opts.use_ci_mode {
-        println!("The environment variable $CI is set, but --use-ci-mode is not set.");
+    let use_ci_mode = use_ci_mode(&opts);
+    let artifact_to_stdout = opts.artifact == STDIO_FILE_NAME;
+    if artifact_to_stdout && matches!(opts.output, OutputFormat::Json) {
+        return Err(anyhow!(
+            "--output json cannot be used with --artifact -, because both write to stdout"
+        ));
+    }
+    if opts.input == STDIO_FILE_NAME && opts.previous_artifact.as_deref() == Some(STDIO_FILE_NAME) {
+        return Err(anyhow!(
+            "--input - cannot be used with --previous-artifact -, because both read from stdin"
+        ));
     }
     goto_repository_root()?;
-    let input: Config = serde_yaml::from_str(&std::fs::read_to_string(&opts.input)?)?;
-    let mut artifact: Artifact = read_artifact(&opts.artifact).unwrap_or_else(|_| {
-        println!("Creating a new artifact");
-        Default::default()
-    });
+    let input: Config = serde_yaml::from_str(&read_yaml_file(&opts.input)?)?;
+    let previous_artifact = match &opts.previous_artifact {
+        Some(file_name) => Some(file_name.as_str()),
+        None if !artifact_to_stdout && std::path::Path::new(&opts.artifact).exists() => {
+            Some(opts.artifact.as_str())
+        }
+        None => None,
+    };
+    // An unreadable artifact is an error instead of being silently replaced,
+    // because the replacement would register a second ACME account.
+    let mut artifact: Artifact = match previous_artifact {
+        Some(file_name) => read_artifact(file_name)
+            .map_err(|e| e.context(format!("Failed to parse artifact {}", file_name)))?,
+        None => {
+            eprintln!("Creating a new artifact");
+            Default::default()
+        }
+    };
 
     cloudflare::main(
-        opts.use_ci_mode,
+        use_ci_mode,
+        opts.force_acme,
+        key_passphrase(&opts.key_passphrase).as_deref(),
         &input.sxg_worker,
         &input.certificates,
         &input.cloudflare,
         &mut artifact,
     )?;
 
-    std::fs::write(
-        &opts.artifact,
-        format!(
-            "# This file is generated by command \"cargo run -p tools -- gen-config\".\n\
-            # Please do not modify.\n\
-            {}",
-            serde_yaml::to_string(&artifact)?
-        ),
-    )?;
+    write_artifact(&opts.artifact, &artifact)?;
+    match opts.output {
+        OutputFormat::Text if artifact_to_stdout => {
+            eprintln!("Successfully wrote artifact to stdout")
+        }
+        OutputFormat::Text => println!("Successfully wrote artifact to {}", opts.artifact),
+        OutputFormat::Json => print_json_output(&input, &artifact)?,
+    }
     Ok(())
 }
@@ -18,7 +18,7 @@ use std::fs::write;
 
 use crate::linux_commands::{
     create_certificate, create_certificate_request_pem, get_certificate_sha256,
-    read_or_create_private_key_pem, write_new_file,
+    read_or_create_private_key_pem, write_new_file, CsrSubject, Curve,
 };
 
 #[derive(Parser)]
@@ -34,8 +34,13 @@ pub fn main(opts: Opts) -> Result<()> {
     const CERT_FILE: &str = "cert.pem";
     const ISSUER_FILE: &str = "issuer.pem";
     const CERT_SHA256_FILE: &str = "cert_sha256.txt";
-    read_or_create_private_key_pem(PRIVKEY_FILE)?;
-    create_certificate_request_pem(&opts.domain, PRIVKEY_FILE, CERT_CSR_FILE)?;
+    read_or_create_private_key_pem(PRIVKEY_FILE, Curve::default(), None)?;
+    let subject = CsrSubject {
+        organization: Some("Test".to_string()),
+        country: Some("US".to_string()),
+        ..Default::default()
+    };
+    create_certificate_request_pem(&opts.domain, &subject, PRIVKEY_FILE, CERT_CSR_FILE)?;
     write(
         EXT_FILE,
         format!(
@@ -40,7 +40,7 @@ pub async fn main(opts: Opts) -> Result<()> {
     worker.add_certificate(certificate);
     fs::write(
         opts.out_cert_cbor,
-        &worker.create_cert_cbor(
+        worker.create_cert_cbor(
             worker.latest_certificate_basename().unwrap(),
             // TODO: Use a real OCSP
             b"ocsp",
@@ -68,6 +68,6 @@ pub async fn main(opts: Opts) -> Result<()> {
         },
     );
     let sxg = sxg.await;
-    fs::write(opts.out_sxg, &sxg.unwrap().body)?;
+    fs::write(opts.out_sxg, &sxg.unwrap().bytes)?;
     Ok(())
 }
@@ -0,0 +1,168 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::gen_config::SxgCertConfig;
+use super::validate_config::MAX_CERTIFICATE_VALIDITY;
+use crate::linux_commands::write_new_file;
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use sxg_rs::crypto::{certificate_chain, EcPrivateKey, SingleCertificate};
+
+/// Splits a certificate bundle issued by a CA into the `cert_file` and
+/// `issuer_file` used by `gen-config`, and writes the `certificates:`
+/// section of its input. Exits with an error if the certificate does not
+/// match the private key, has expired, or is valid for more than 90 days.
+#[derive(Debug, Parser)]
+pub struct Opts {
+    /// The PEM file of the certificate followed by its issuer, such as the
+    /// `fullchain.pem` of most CAs.
+    #[clap(long, value_name = "FILE_NAME")]
+    fullchain: String,
+    /// The PEM file of the EC private key of the certificate.
+    #[clap(long, value_name = "FILE_NAME")]
+    key: String,
+    /// Where to write the `certificates:` section, which can be pasted into
+    /// `input.yaml`.
+    #[clap(long, value_name = "FILE_NAME")]
+    out: String,
+    /// Where to write the certificate.
+    #[clap(long, value_name = "FILE_NAME", default_value = "credentials/cert.pem")]
+    cert_file: String,
+    /// Where to write the issuer of the certificate.
+    #[clap(
+        long,
+        value_name = "FILE_NAME",
+        default_value = "credentials/issuer.pem"
+    )]
+    issuer_file: String,
+}
+
+#[derive(Serialize)]
+struct InputFragment {
+    certificates: SxgCertConfig,
+}
+
+fn read_file(file_name: &str) -> Result<String> {
+    std::fs::read_to_string(file_name)
+        .map_err(|e| Error::new(e).context(format!(r#"Failed to read file "{}""#, file_name)))
+}
+
+fn to_pem(certificate: &SingleCertificate) -> String {
+    let base64 = base64::encode(&certificate.der);
+    let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+    for line in base64.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str("-----END CERTIFICATE-----\n");
+    pem
+}
+
+fn check_certificate(
+    certificate: &SingleCertificate,
+    private_key: &EcPrivateKey,
+    now: SystemTime,
+) -> Result<()> {
+    let public_key = certificate.public_key()?;
+    if public_key.x != private_key.public_key.x || public_key.y != private_key.public_key.y {
+        return Err(anyhow!("The certificate does not match the private key."));
+    }
+    let not_before = certificate.not_before()?;
+    let not_after = certificate.not_after()?;
+    if not_after <= now {
+        return Err(anyhow!(
+            "The certificate expired at {}.",
+            DateTime::<Utc>::from(not_after)
+        ));
+    }
+    let validity = not_after
+        .duration_since(not_before)
+        .unwrap_or(Duration::ZERO);
+    if validity > MAX_CERTIFICATE_VALIDITY {
+        return Err(anyhow!(
+            "The certificate is valid for {} days, but certificates for signed \
+            exchanges must be valid for at most 90 days.",
+            validity.as_secs() / (24 * 60 * 60)
+        ));
+    }
+    if !certificate.can_sign_http_exchanges()? {
+        println!(
+            "Warning: the certificate does not have the CanSignHttpExchanges \
+            extension, so browsers will reject the signed exchanges."
+        );
+    }
+    Ok(())
+}
+
+pub fn main(opts: Opts) -> Result<()> {
+    let chain = certificate_chain(&read_file(&opts.fullchain)?)
+        .with_context(|| format!("Failed to parse {}", opts.fullchain))?;
+    let private_key = EcPrivateKey::from_sec1_pem(&read_file(&opts.key)?)
+        .with_context(|| format!("Failed to parse {}", opts.key))?;
+    let (certificate, issuer) = match chain.as_slice() {
+        [] => return Err(anyhow!("{} contains no certificate.", opts.fullchain)),
+        // A self-signed certificate is its own issuer.
+        [certificate] if certificate.issuer()? == certificate.subject()? => {
+            (certificate, certificate)
+        }
+        [_] => {
+            return Err(anyhow!(
+                "{} contains no issuer of the certificate.",
+                opts.fullchain
+            ))
+        }
+        [certificate, issuer, rest @ ..] => {
+            if !rest.is_empty() {
+                println!(
+                    "Ignoring {} certificates after the issuer, which browsers do not need.",
+                    rest.len()
+                );
+            }
+            (certificate, issuer)
+        }
+    };
+    if certificate.issuer()? != issuer.subject()? {
+        return Err(anyhow!(
+            "The second certificate in {} is not the issuer of the first one.",
+            opts.fullchain
+        ));
+    }
+    check_certificate(certificate, &private_key, SystemTime::now())?;
+
+    // Checks all the files before writing any of them, so that a partial
+    // write doesn't block the next run.
+    for file_name in [&opts.cert_file, &opts.issuer_file, &opts.out] {
+        if Path::new(file_name).exists() {
+            return Err(anyhow!("Cowardly refuse to overwrite {:?}", file_name));
+        }
+    }
+    write_new_file(&opts.cert_file, to_pem(certificate))?;
+    write_new_file(&opts.issuer_file, to_pem(issuer))?;
+    let fragment = InputFragment {
+        certificates: SxgCertConfig::PreIssued {
+            cert_file: opts.cert_file.clone(),
+            issuer_file: opts.issuer_file.clone(),
+        },
+    };
+    write_new_file(&opts.out, serde_yaml::to_string(&fragment)?)?;
+    println!(
+        "Wrote {} and {}. Replace the certificates section of input.yaml with {}.",
+        opts.cert_file, opts.issuer_file, opts.out
+    );
+    Ok(())
+}
@@ -12,21 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod acme_account;
 mod apply_acme_cert;
+mod bench_sign;
+mod dump_cert_chain;
 mod gen_config;
 mod gen_dev_cert;
 mod gen_sxg;
+mod import_cert;
+mod output;
+mod refresh_ocsp;
+mod self_test;
+mod validate_config;
 
 use super::tokio_block_on as block_on;
 use anyhow::Result;
 use clap::Parser;
 
+// The options are only parsed once, so their size doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Parser)]
 enum SubCommand {
+    AcmeAccount(acme_account::Opts),
     ApplyAcmeCert(apply_acme_cert::Opts),
+    BenchSign(bench_sign::Opts),
+    DumpCertChain(dump_cert_chain::Opts),
     GenConfig(gen_config::Opts),
     GenDevCert(gen_dev_cert::Opts),
     GenSxg(gen_sxg::Opts),
+    ImportCert(import_cert::Opts),
+    RefreshOcsp(refresh_ocsp::Opts),
+    SelfTest(self_test::Opts),
+    ValidateConfig(validate_config::Opts),
 }
 
 #[derive(Parser)]
@@ -37,9 +54,16 @@ struct Opts {
 
 pub fn main() -> Result<()> {
     match Opts::parse().sub_command {
+        SubCommand::AcmeAccount(opts) => block_on(acme_account::main(opts)),
         SubCommand::ApplyAcmeCert(opts) => block_on(apply_acme_cert::main(opts)),
+        SubCommand::BenchSign(opts) => bench_sign::main(opts),
+        SubCommand::DumpCertChain(opts) => block_on(dump_cert_chain::main(opts)),
         SubCommand::GenConfig(opts) => gen_config::main(opts),
         SubCommand::GenSxg(opts) => block_on(gen_sxg::main(opts)),
         SubCommand::GenDevCert(opts) => gen_dev_cert::main(opts),
+        SubCommand::ImportCert(opts) => import_cert::main(opts),
+        SubCommand::RefreshOcsp(opts) => block_on(refresh_ocsp::main(opts)),
+        SubCommand::SelfTest(opts) => block_on(self_test::main(opts)),
+        SubCommand::ValidateConfig(opts) => validate_config::main(opts),
     }
 }
@@ -0,0 +1,44 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sxg_rs::crypto::CertificateChain;
+
+/// The format of the result that a command prints to stdout.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable output.
+    Text,
+    /// A single JSON object, for consumption by automation scripts.
+    Json,
+}
+
+/// Metadata of the end-entity certificate in a PEM file.
+#[derive(Debug, Serialize)]
+pub struct CertificateMetadata {
+    pub not_after: DateTime<Utc>,
+    pub serial_number: String,
+}
+
+impl CertificateMetadata {
+    pub fn from_pem(certificate_pem: &str) -> Result<Self> {
+        let certificate = CertificateChain::from_pem_files(&[certificate_pem])?.end_entity;
+        Ok(CertificateMetadata {
+            not_after: certificate.not_after()?.into(),
+            serial_number: certificate.serial_number()?,
+        })
+    }
+}
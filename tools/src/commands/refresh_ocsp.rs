@@ -0,0 +1,112 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::output::OutputFormat;
+use crate::runtime::hyper_fetcher::HyperFetcher;
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use std::time::SystemTime;
+use sxg_rs::crypto::{first_certificate, ocsp_responder_url};
+use sxg_rs::ocsp::{fetch_from_ca, parse_cert_status, parse_validity, CertStatus};
+
+/// Fetches a fresh OCSP response from the CA, regardless of when the current
+/// one expires, and writes its DER to a file. The response is verified
+/// against the certificate before it is written, and is only written if the
+/// certificate status is good.
+#[derive(Debug, Parser)]
+pub struct Opts {
+    /// The PEM file of the certificate.
+    #[clap(long, value_name = "FILE_NAME")]
+    cert: String,
+    /// The PEM file of the issuer of the certificate.
+    #[clap(long, value_name = "FILE_NAME")]
+    issuer: String,
+    /// The file to write the DER of the OCSP response to.
+    #[clap(long, value_name = "FILE_NAME")]
+    out: String,
+    #[clap(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+#[derive(Serialize)]
+struct Ocsp {
+    cert_status: CertStatus,
+    this_update: DateTime<Utc>,
+    next_update: Option<DateTime<Utc>>,
+}
+
+fn read_certificate_der(file_name: &str) -> Result<Vec<u8>> {
+    let pem = std::fs::read_to_string(file_name)
+        .map_err(|e| Error::from(e).context(format!(r#"Failed to read file "{}""#, file_name)))?;
+    Ok(first_certificate(&pem)?.der)
+}
+
+pub async fn main(opts: Opts) -> Result<()> {
+    let cert_der = read_certificate_der(&opts.cert)?;
+    let issuer_der = read_certificate_der(&opts.issuer)?;
+    // `fetch_from_ca` returns a stub for certificates without an OCSP
+    // responder, which must not be written as if it were a real response.
+    if ocsp_responder_url(&cert_der).is_none() {
+        return Err(anyhow!(
+            "The certificate does not specify an OCSP responder URL."
+        ));
+    }
+    let ocsp_der = fetch_from_ca(
+        &cert_der,
+        &issuer_der,
+        &HyperFetcher::new(),
+        SystemTime::now(),
+    )
+    .await?;
+    let validity = parse_validity(&ocsp_der)?;
+    if let Some(next_update) = validity.next_update {
+        if next_update <= SystemTime::now() {
+            return Err(anyhow!(
+                "The OCSP response from the CA expired at {}",
+                DateTime::<Utc>::from(next_update)
+            ));
+        }
+    }
+    let cert_status = parse_cert_status(&ocsp_der)?;
+    // Browsers reject signed exchanges stapled with a revoked or unknown
+    // status, so the existing response is kept.
+    if cert_status != CertStatus::Good {
+        return Err(anyhow!(
+            "The OCSP response from the CA has status {:?}; not writing it to {}",
+            cert_status,
+            opts.out
+        ));
+    }
+    let ocsp = Ocsp {
+        cert_status,
+        this_update: validity.this_update.into(),
+        next_update: validity.next_update.map(Into::into),
+    };
+    std::fs::write(&opts.out, &ocsp_der)?;
+    match opts.output {
+        OutputFormat::Text => {
+            println!("Wrote OCSP response to {}", opts.out);
+            println!("OCSP status: {:?}", ocsp.cert_status);
+            println!("OCSP this update: {}", ocsp.this_update);
+            match &ocsp.next_update {
+                Some(next_update) => println!("OCSP next update: {}", next_update),
+                None => println!("OCSP next update: -"),
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&ocsp)?),
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::gen_config::{read_certificate_pem_file, read_yaml_file, Config, SxgCertConfig};
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use std::time::{Duration, SystemTime};
+use sxg_rs::crypto::{certificate_request_domains, first_certificate, get_der_from_pem};
+
+/// Checks the input of `gen-config`, and exits with an error if it has any
+/// problem. Unlike `gen-config`, this neither deploys anything nor accesses
+/// the network, so it can run in CI before a deployment.
+#[derive(Debug, Parser)]
+pub struct Opts {
+    /// A YAML file in the same format as the input of `gen-config`.
+    /// Relative file names in it are resolved against the current directory.
+    #[clap(long, value_name = "FILE_NAME")]
+    input: String,
+}
+
+// The maximum validity period of a certificate that signs exchanges.
+// https://wicg.github.io/webpackage/draft-yasskin-http-origin-signed-responses.html#name-certificate-requirements
+pub(super) const MAX_CERTIFICATE_VALIDITY: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+fn check_certificate_file(file_name: &str, now: SystemTime) -> Result<Vec<String>> {
+    let certificate = first_certificate(&read_certificate_pem_file(file_name)?)?;
+    let mut problems = vec![];
+    if !certificate.can_sign_http_exchanges()? {
+        problems.push(format!(
+            "{} does not have the CanSignHttpExchanges extension.",
+            file_name
+        ));
+    }
+    let not_before = certificate.not_before()?;
+    let not_after = certificate.not_after()?;
+    if not_after <= now {
+        problems.push(format!(
+            "{} expired at {}.",
+            file_name,
+            DateTime::<Utc>::from(not_after)
+        ));
+    }
+    let validity = not_after
+        .duration_since(not_before)
+        .unwrap_or(Duration::ZERO);
+    if validity > MAX_CERTIFICATE_VALIDITY {
+        problems.push(format!(
+            "{} is valid for {} days, but certificates for signed exchanges \
+            must be valid for at most 90 days.",
+            file_name,
+            validity.as_secs() / (24 * 60 * 60)
+        ));
+    }
+    Ok(problems)
+}
+
+fn check_certificate_request_file(file_name: &str, html_host: &str) -> Result<Vec<String>> {
+    let pem = std::fs::read_to_string(file_name)
+        .map_err(|e| Error::new(e).context(format!("Failed to read {}", file_name)))?;
+    let domains = certificate_request_domains(&get_der_from_pem(&pem, "CERTIFICATE REQUEST")?)?;
+    if domains.iter().any(|domain| domain == html_host) {
+        Ok(vec![])
+    } else {
+        Ok(vec![format!(
+            "{} requests {:?}, which does not include html_host \"{}\".",
+            file_name, domains, html_host
+        )])
+    }
+}
+
+fn check_certificates(
+    certificates: &SxgCertConfig,
+    html_host: &str,
+    now: SystemTime,
+) -> Vec<String> {
+    let results = match certificates {
+        SxgCertConfig::PreIssued {
+            cert_file,
+            issuer_file,
+        } => vec![
+            check_certificate_file(cert_file, now),
+            read_certificate_pem_file(issuer_file).map(|_| vec![]),
+        ],
+        SxgCertConfig::CreateAcmeAccount(acme_config) => vec![check_certificate_request_file(
+            &acme_config.sxg_cert_request_file,
+            html_host,
+        )],
+    };
+    results
+        .into_iter()
+        .flat_map(|result| result.unwrap_or_else(|e| vec![format!("{:#}", e)]))
+        .collect()
+}
+
+pub fn main(opts: Opts) -> Result<()> {
+    let input: Config = serde_yaml::from_str(&read_yaml_file(&opts.input)?)
+        .map_err(|e| Error::new(e).context(format!("Failed to parse {}", opts.input)))?;
+    let mut sxg_config = input.sxg_worker;
+    sxg_config.normalize();
+    for warning in sxg_config.lint() {
+        println!("Warning: {}", warning);
+    }
+    let mut problems: Vec<String> = sxg_config
+        .validation_errors()
+        .iter()
+        .map(|e| format!("{:#}", e))
+        .collect();
+    problems.extend(check_certificates(
+        &input.certificates,
+        &sxg_config.html_host,
+        SystemTime::now(),
+    ));
+    for problem in &problems {
+        println!("Error: {}", problem);
+    }
+    if problems.is_empty() {
+        println!("{} is valid.", opts.input);
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Found {} problem(s) in {}.",
+            problems.len(),
+            opts.input
+        ))
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use sxg_rs::crypto::HashAlgorithm;
+use sxg_rs::runtime::store::Store;
+use sxg_rs::SxgError;
+
+/// A store that keeps each key in a JSON file under a directory, so that the
+/// state survives across runs of the CLI.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    value: String,
+    expires_at: Option<SystemTime>,
+}
+
+// The longest file name that most file systems allow.
+const MAX_FILE_NAME_BYTES: usize = 255;
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+    // Returns the file of `key`, whose name is the percent-encoded key. If that
+    // is too long, the name is the SHA-256 digest of the key instead, which
+    // starts with `=`, a character that is always encoded otherwise.
+    fn path(&self, key: &str) -> PathBuf {
+        let name: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c.to_string()
+                } else {
+                    let mut buf = [0; 4];
+                    c.encode_utf8(&mut buf)
+                        .bytes()
+                        .map(|b| format!("%{:02X}", b))
+                        .collect()
+                }
+            })
+            .collect();
+        let file_name = format!("{}.json", name);
+        if file_name.len() <= MAX_FILE_NAME_BYTES {
+            return self.dir.join(file_name);
+        }
+        let digest = HashAlgorithm::Sha256.digest(key.as_bytes());
+        self.dir.join(format!(
+            "=sha256-{}.json",
+            base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+    fn write(&self, key: &str, entry: &Entry) -> Result<()> {
+        std::fs::write(self.path(key), serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+    fn read(&self, key: &str) -> Result<Option<String>> {
+        let content = match std::fs::read_to_string(self.path(key)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let entry: Entry = serde_json::from_str(&content)?;
+        match entry.expires_at {
+            Some(expires_at) if expires_at <= SystemTime::now() => Ok(None),
+            _ => Ok(Some(entry.value)),
+        }
+    }
+    fn remove(&self, key: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Store for FileStore {
+    async fn get(&self, key: &str) -> Result<Option<String>, SxgError> {
+        self.read(key).map_err(SxgError::Storage)
+    }
+    async fn put(&self, key: &str, value: &str) -> Result<(), SxgError> {
+        self.write(
+            key,
+            &Entry {
+                value: value.to_string(),
+                expires_at: None,
+            },
+        )
+        .map_err(SxgError::Storage)
+    }
+    async fn delete(&self, key: &str) -> Result<(), SxgError> {
+        self.remove(key).map_err(SxgError::Storage)
+    }
+    async fn put_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<(), SxgError> {
+        self.write(
+            key,
+            &Entry {
+                value: value.to_string(),
+                expires_at: Some(SystemTime::now() + ttl),
+            },
+        )
+        .map_err(SxgError::Storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn stores_long_keys() {
+        let dir = std::env::temp_dir().join(format!("sxg-file-store-{}", std::process::id()));
+        let store = FileStore::new(&dir).unwrap();
+        let long_key = format!("http-cache:https://example.com/{}", "a".repeat(300));
+        for key in ["OCSP/abc", long_key.as_str()] {
+            store.put(key, "value").await.unwrap();
+            assert_eq!(store.get(key).await.unwrap(), Some("value".to_string()));
+            assert!(store.path(key).file_name().unwrap().len() <= MAX_FILE_NAME_BYTES);
+        }
+        assert_ne!(store.path(&long_key), store.path(&format!("{}b", long_key)));
+        store.delete(&long_key).await.unwrap();
+        assert_eq!(store.get(&long_key).await.unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -20,34 +20,43 @@ use async_trait::async_trait;
 use std::convert::TryInto;
 use sxg_rs::fetcher::Fetcher;
 use sxg_rs::http::{HttpRequest as SxgRsRequest, HttpResponse as SxgRsResponse};
+use sxg_rs::SxgError;
 
 /// A [`Fetcher`] implemented by the external `hyper` crate.
 pub struct HyperFetcher {
     client: Client<HttpsConnector<HttpConnector>>,
+    user_agent: String,
 }
 
+// Some ACME and OCSP servers rate-limit requests without a descriptive
+// `User-Agent`.
+const DEFAULT_USER_AGENT: &str = concat!("sxg-rs/", env!("CARGO_PKG_VERSION"));
+
 impl HyperFetcher {
     pub fn new() -> Self {
         let https = HttpsConnector::new();
         HyperFetcher {
             client: Client::builder().build(https),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
         }
     }
-}
-
-impl Default for HyperFetcher {
-    fn default() -> Self {
-        Self::new()
+    /// Sets the `User-Agent` header of the requests that don't have one.
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
     }
-}
-
-#[async_trait(?Send)]
-impl Fetcher for HyperFetcher {
-    async fn fetch(&self, request: SxgRsRequest) -> Result<SxgRsResponse> {
+    async fn fetch_impl(&self, request: SxgRsRequest) -> Result<SxgRsResponse> {
         let request: http::Request<Vec<u8>> = request
             .try_into()
             .map_err(|e: Error| e.context("Failed to convert sxg_rs::Request to http::Request"))?;
-        let request: http::Request<hyper::body::Body> = request.map(|body| body.into());
+        let mut request: http::Request<hyper::body::Body> = request.map(|body| body.into());
+        if !request.headers().contains_key(http::header::USER_AGENT) {
+            request.headers_mut().insert(
+                http::header::USER_AGENT,
+                http::HeaderValue::from_str(&self.user_agent)
+                    .map_err(|e| Error::new(e).context("Invalid User-Agent"))?,
+            );
+        }
         let response = self
             .client
             .request(request)
@@ -62,3 +71,16 @@ impl Fetcher for HyperFetcher {
         response.try_into()
     }
 }
+
+impl Default for HyperFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait(?Send)]
+impl Fetcher for HyperFetcher {
+    async fn fetch(&self, request: SxgRsRequest) -> Result<SxgRsResponse, SxgError> {
+        self.fetch_impl(request).await.map_err(SxgError::Network)
+    }
+}
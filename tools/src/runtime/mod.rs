@@ -12,5 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod file_store;
 pub mod hyper_fetcher;
 pub mod openssl_signer;
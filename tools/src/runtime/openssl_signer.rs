@@ -12,25 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::linux_commands::{execute, execute_and_parse_stdout};
+use crate::linux_commands::{execute, execute_and_parse_stdout, execute_with_stdin_bytes};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use std::process::Command;
 use sxg_rs::signature::{Format as SignatureFormat, Signer};
+use sxg_rs::SxgError;
 
 #[derive(Debug)]
 pub enum OpensslSigner<'a> {
     Hmac(&'a [u8]),
+    /// The PKCS#8 PEM of an Ed25519 private key.
+    Ed25519(String),
 }
 
-#[async_trait(?Send)]
-impl<'a> Signer for OpensslSigner<'a> {
-    async fn sign(&self, message: &[u8], format: SignatureFormat) -> Result<Vec<u8>> {
-        let tmp_file = execute_and_parse_stdout(&mut Command::new("mktemp"))?;
-        let tmp_file = tmp_file.trim();
-        std::fs::write(tmp_file, message)?;
+// A file created by `mktemp`, which is only readable by the current user, and
+// is removed when dropped, including when signing returns early.
+struct TempFile(String);
+
+impl TempFile {
+    fn new(content: &[u8]) -> Result<Self> {
+        let path = execute_and_parse_stdout(&mut Command::new("mktemp"))?;
+        let file = TempFile(path.trim().to_string());
+        std::fs::write(&file.0, content)?;
+        Ok(file)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl<'a> OpensslSigner<'a> {
+    fn sign_impl(&self, message: &[u8], format: SignatureFormat) -> Result<Vec<u8>> {
         match self {
             OpensslSigner::Hmac(private_key) => {
+                let message_file = TempFile::new(message)?;
                 let hexkey = private_key
                     .iter()
                     .map(|x| format!("{:02x}", x))
@@ -45,7 +64,7 @@ impl<'a> Signer for OpensslSigner<'a> {
                         .arg("-binary")
                         .arg("-macopt")
                         .arg(format!("hexkey:{}", hexkey))
-                        .arg(tmp_file),
+                        .arg(&message_file.0),
                 )
                 .map_err(|e| e.context("Failed to use openssl to create HMAC"))?;
                 match format {
@@ -55,6 +74,38 @@ impl<'a> Signer for OpensslSigner<'a> {
                     }
                 }
             }
+            OpensslSigner::Ed25519(private_key_pem) => {
+                if let SignatureFormat::EccAsn1 = format {
+                    return Err(anyhow!("Ed25519 signature can't be formatted as EccAsn1."));
+                }
+                // `-rawin` reads the message from `-in`, so the private key is
+                // piped through stdin instead, and is never written to disk.
+                let message_file = TempFile::new(message)?;
+                let sig = execute_with_stdin_bytes(
+                    Command::new("openssl")
+                        .arg("pkeyutl")
+                        .arg("-sign")
+                        .arg("-rawin")
+                        .arg("-inkey")
+                        .arg("/dev/stdin")
+                        .arg("-in")
+                        .arg(&message_file.0),
+                    private_key_pem.as_bytes(),
+                )
+                .map_err(|e| e.context("Failed to use openssl to sign by Ed25519"))?;
+                // https://datatracker.ietf.org/doc/html/rfc8032#section-5.1.6
+                if sig.len() != 64 {
+                    return Err(anyhow!("Failed to use openssl to sign by Ed25519"));
+                }
+                Ok(sig)
+            }
         }
     }
 }
+
+#[async_trait(?Send)]
+impl<'a> Signer for OpensslSigner<'a> {
+    async fn sign(&self, message: &[u8], format: SignatureFormat) -> Result<Vec<u8>, SxgError> {
+        self.sign_impl(message, format).map_err(SxgError::Crypto)
+    }
+}
@@ -30,6 +30,18 @@ type HeaderFields = Array<[string, string]>;
 
 export type AcceptFilter = 'PrefersSxg' | 'AcceptsSxg';
 
+export type Route = 'CertChain' | 'Validity' | 'Reserved' | 'Content';
+
+// The outcome of serving a response, which is written to the `x-sxg` header
+// when `add_sxg_status_header` is enabled in the config. Errors thrown by
+// `fetchOrigin`, `createSignedExchange` and `validatePayloadHeaders` carry it
+// as their `sxgStatus` property.
+export type SxgStatus =
+  | 'signed'
+  | 'passthrough-uncacheable'
+  | 'passthrough-too-large'
+  | 'error';
+
 export interface WasmRequest {
   body: number[];
   headers: HeaderFields;
@@ -54,9 +66,19 @@ export type PresetContent =
 
 export type JsRuntimeInitParams = {
   nowInSeconds: number;
-  fetcher: ((request: WasmRequest) => Promise<WasmResponse>) | undefined;
+  // If `maxBodyBytes` is given, the fetcher stops reading a body that exceeds
+  // it, and resolves to `null`.
+  fetcher:
+    | ((
+        request: WasmRequest,
+        maxBodyBytes?: number
+      ) => Promise<WasmResponse | null>)
+    | undefined;
   storageRead: ((k: string) => Promise<string | null>) | undefined;
-  storageWrite: ((k: string, v: string) => Promise<void>) | undefined;
+  storageWrite:
+    | ((k: string, v: string, ttlSeconds?: number) => Promise<void>)
+    | undefined;
+  storageDelete: ((k: string) => Promise<void>) | undefined;
   sxgAsn1Signer: ((input: Uint8Array) => Promise<Uint8Array>) | undefined;
   sxgRawSigner: ((input: Uint8Array) => Promise<Uint8Array>) | undefined;
   acmeRawSigner: ((input: Uint8Array) => Promise<Uint8Array>) | undefined;
@@ -89,16 +111,28 @@ export interface WasmWorker {
     input: WasmResponse,
     option: ProcessHtmlOption
   ): Promise<WasmResponse>;
+  fetchOrigin(
+    runtime: JsRuntimeInitParams,
+    request: WasmRequest
+  ): Promise<WasmResponse>;
   createSignedExchange(
     runtime: JsRuntimeInitParams,
     options: CreateSignedExchangedOptions
   ): Promise<WasmResponse>;
   updateOcspInStorage(runtime: JsRuntimeInitParams): Promise<void>;
+  setOcsp(
+    runtime: JsRuntimeInitParams,
+    certificateBasename: string,
+    ocspDer: Uint8Array
+  ): Promise<void>;
+  route(url: string): Promise<Route>;
   servePresetContent(
     runtime: JsRuntimeInitParams,
-    url: string
+    url: string,
+    acceptEncoding: string | undefined
   ): Promise<PresetContent | undefined>;
   validatePayloadHeaders(fields: HeaderFields): Promise<void>;
+  sxgStatusHeader(status: SxgStatus): Promise<[string, string] | null>;
   updateAcmeStateMachine: (
     runtime: JsRuntimeInitParams,
     acmeAccount: string